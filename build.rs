@@ -0,0 +1,35 @@
+use std::{env, process::Command};
+
+fn main() {
+    // The gRPC API is optional, so the protobuf definitions are only compiled if it is enabled
+    #[cfg(feature = "grpc")]
+    {
+        std::env::set_var(
+            "PROTOC",
+            protoc_bin_vendored::protoc_bin_path().expect("Could not find vendored protoc"),
+        );
+        tonic_build::compile_protos("proto/image_service.proto")
+            .expect("Could not compile protobuf definitions");
+    }
+
+    // Reported by `/info`. Docker builds have no git repository, so the commit can be passed as
+    // `GIT_COMMIT` instead.
+    let commit = env::var("GIT_COMMIT")
+        .ok()
+        .filter(|commit| !commit.is_empty())
+        .or_else(|| {
+            Command::new("git")
+                .args(["rev-parse", "HEAD"])
+                .output()
+                .ok()
+                .filter(|output| output.status.success())
+                .and_then(|output| String::from_utf8(output.stdout).ok())
+        })
+        .map(|commit| commit.trim().to_owned())
+        .unwrap_or_else(|| "unknown".to_owned());
+    println!("cargo:rustc-env=GIT_COMMIT={}", commit);
+    println!("cargo:rerun-if-env-changed=GIT_COMMIT");
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs/heads");
+    println!("cargo:rerun-if-changed=build.rs");
+}
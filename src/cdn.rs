@@ -0,0 +1,108 @@
+use config::Config;
+use reqwest::{Client, RequestBuilder};
+use uuid::Uuid;
+
+/// Returns the cache tag (Cloudflare) and surrogate key (Fastly) responses of the image `uuid`
+/// are tagged with, so all its renditions can be purged at once
+pub fn cache_tag(uuid: Uuid) -> String {
+    uuid.to_string()
+}
+
+/// A CDN whose API is used to purge images from the edge caches
+enum CdnProvider {
+    Cloudflare {
+        zone_id: String,
+        api_token: String,
+    },
+    Fastly {
+        service_id: String,
+        api_token: String,
+    },
+}
+
+impl CdnProvider {
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Cloudflare { .. } => "Cloudflare",
+            Self::Fastly { .. } => "Fastly",
+        }
+    }
+
+    /// Builds the request that purges all responses tagged with `tag`
+    fn purge_request(&self, client: &Client, tag: &str) -> RequestBuilder {
+        match self {
+            Self::Cloudflare { zone_id, api_token } => client
+                .post(format!(
+                    "https://api.cloudflare.com/client/v4/zones/{}/purge_cache",
+                    zone_id
+                ))
+                .bearer_auth(api_token)
+                .json(&serde_json::json!({ "tags": [tag] })),
+            Self::Fastly {
+                service_id,
+                api_token,
+            } => client
+                .post(format!(
+                    "https://api.fastly.com/service/{}/purge/{}",
+                    service_id, tag
+                ))
+                .header("Fastly-Key", api_token),
+        }
+    }
+}
+
+/// Purges images from the edge caches of the configured CDNs, after they were removed from
+/// the cache of the service (e.g. because they were rotated), so no stale rendition is served
+pub struct CdnPurger {
+    providers: Vec<CdnProvider>,
+}
+
+/// Parses the CDNs to purge from the config properties `CLOUDFLARE_ZONE_ID` and
+/// `CLOUDFLARE_API_TOKEN` as well as `FASTLY_SERVICE_ID` and `FASTLY_API_TOKEN`.
+/// A CDN is only purged if both of its properties are set.
+pub fn parse_cdn_purger(config: &Config) -> CdnPurger {
+    let mut providers = Vec::new();
+    if let (Ok(zone_id), Ok(api_token)) = (
+        config.get_string("CLOUDFLARE_ZONE_ID"),
+        config.get_string("CLOUDFLARE_API_TOKEN"),
+    ) {
+        providers.push(CdnProvider::Cloudflare {
+            zone_id: zone_id,
+            api_token: api_token,
+        });
+    }
+    if let (Ok(service_id), Ok(api_token)) = (
+        config.get_string("FASTLY_SERVICE_ID"),
+        config.get_string("FASTLY_API_TOKEN"),
+    ) {
+        providers.push(CdnProvider::Fastly {
+            service_id: service_id,
+            api_token: api_token,
+        });
+    }
+    CdnPurger {
+        providers: providers,
+    }
+}
+
+impl CdnPurger {
+    /// Names of the CDNs that are purged
+    pub fn provider_names(&self) -> Vec<&'static str> {
+        self.providers.iter().map(CdnProvider::name).collect()
+    }
+
+    /// Purges all renditions of the image `uuid` in the background.
+    /// Failures are only logged, as the image has already been changed.
+    pub fn purge(&self, client: &Client, uuid: Uuid) {
+        for provider in &self.providers {
+            let name = provider.name();
+            let request = provider.purge_request(client, &cache_tag(uuid));
+            tokio::spawn(async move {
+                match request.send().await.and_then(|res| res.error_for_status()) {
+                    Err(err) => log::error!("CDN: Purging {} from {} failed: {}", uuid, name, err),
+                    Ok(_) => log::debug!("CDN: Purged {} from {}", uuid, name),
+                }
+            });
+        }
+    }
+}
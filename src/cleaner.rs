@@ -1,42 +1,630 @@
 use std::{
-    fs::{read_dir, remove_file, DirEntry},
-    io, thread,
-    time::{Duration, SystemTime},
+    fs::{metadata, read_dir, remove_file, DirEntry},
+    io,
+    os::unix::fs::MetadataExt,
+    path::Path,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
-use crate::util::path::get_pending_path;
+use config::Config;
+use uuid::Uuid;
 
-pub fn delete_old_pending_images() {
-    loop {
-        // Get the current time
-        let current_time = SystemTime::now();
+use crate::{
+    constants::{
+        DEFAULT_CACHE_CLEANER_GRACE_SECS, DEFAULT_CACHE_CLEANER_INTERVAL_SECS,
+        DEFAULT_CACHE_EVICTION_INTERVAL_SECS, DEFAULT_CACHE_MAX_IDLE_SECS,
+        DEFAULT_CLEANER_INTERVAL_SECS, DEFAULT_OBJECT_CLEANER_GRACE_SECS,
+        DEFAULT_OBJECT_CLEANER_INTERVAL_SECS, DEFAULT_PENDING_MAX_AGE_SECS,
+        DEFAULT_PROXY_CACHE_MAX_AGE_SECS, DEFAULT_PROXY_CLEANER_INTERVAL_SECS,
+        DEFAULT_QUARANTINE_CLEANER_INTERVAL_SECS, DEFAULT_QUARANTINE_MAX_AGE_SECS,
+        DEFAULT_RAW_CLEANER_GRACE_SECS, DEFAULT_RAW_CLEANER_INTERVAL_SECS,
+        DEFAULT_TRASH_CLEANER_INTERVAL_SECS, DEFAULT_TRASH_MAX_AGE_SECS,
+        DEFAULT_UNAPPROVED_CLEANER_INTERVAL_SECS, DEFAULT_UNAPPROVED_MAX_AGE_SECS,
+    },
+    quarantine::enforce_quarantine_size,
+    scheduler::{parse_job_schedule, Job, Scheduler},
+    util::{
+        image::{determine_img_dir, determine_img_path, ImageSearchBehaviour, RemovalBehavior},
+        path::{
+            get_cache_path, get_objects_path, get_original_path, get_pending_path,
+            get_proxy_cache_path, get_quarantine_path, get_raw_path, get_trash_path,
+            get_unapproved_path, list_roots,
+        },
+    },
+    ServerState,
+};
+
+/// Parses whether maintenance jobs should only report what they would delete from the
+/// config property `MAINTENANCE_DRY_RUN`
+pub fn parse_maintenance_behavior(config: &Config) -> RemovalBehavior {
+    match config.get_bool("MAINTENANCE_DRY_RUN") {
+        Ok(true) => RemovalBehavior::DryRun,
+        _ => RemovalBehavior::Delete,
+    }
+}
+
+/// A job that regularly deletes old files, with its config keys and defaults
+pub struct Cleaner {
+    pub name: &'static str,
+    // State of the files that are deleted, used for reporting retention policies
+    pub state: &'static str,
+    // What is deleted, used for logging
+    pub description: &'static str,
+    pub enabled_key: &'static str,
+    // Whether the cleaner runs if `enabled_key` is not set
+    pub default_enabled: bool,
+    pub interval_key: &'static str,
+    // Optional cron expression that replaces the interval
+    pub schedule_key: &'static str,
+    pub max_age_key: &'static str,
+    // Optional max age of files of approved images, counted from their approval. Such files are
+    // kept forever if it is not set.
+    pub approved_max_age_key: Option<&'static str>,
+    pub default_interval_secs: u64,
+    pub default_max_age_secs: u64,
+    // Whether a max age of 0 is valid, i.e. files are deleted at the next run
+    pub allow_zero_max_age: bool,
+    pub run: fn(CleanerConfig, &ServerState) -> Result<usize, String>,
+}
+
+pub static CLEANERS: [Cleaner; 9] = [
+    // Deletes pending images that were never submitted
+    Cleaner {
+        name: "pending-cleaner",
+        state: "pending",
+        description: "pending images",
+        enabled_key: "CLEANER_ENABLED",
+        default_enabled: true,
+        interval_key: "CLEANER_INTERVAL_SECS",
+        schedule_key: "CLEANER_SCHEDULE",
+        max_age_key: "PENDING_MAX_AGE_SECS",
+        approved_max_age_key: None,
+        default_interval_secs: DEFAULT_CLEANER_INTERVAL_SECS,
+        default_max_age_secs: DEFAULT_PENDING_MAX_AGE_SECS,
+        allow_zero_max_age: false,
+        run: delete_old_pending_images,
+    },
+    // Deletes raw files whose image does not exist in any state anymore and, if
+    // `RAW_RETENTION_SECS` is set, those of images approved long enough ago. Raw files of approved
+    // images are kept by default, as images can't be regenerated without them.
+    Cleaner {
+        name: "raw-cleaner",
+        state: "raw",
+        description: "orphaned raw files",
+        enabled_key: "RAW_CLEANER_ENABLED",
+        default_enabled: true,
+        interval_key: "RAW_CLEANER_INTERVAL_SECS",
+        schedule_key: "RAW_CLEANER_SCHEDULE",
+        max_age_key: "RAW_CLEANER_GRACE_SECS",
+        approved_max_age_key: Some("RAW_RETENTION_SECS"),
+        default_interval_secs: DEFAULT_RAW_CLEANER_INTERVAL_SECS,
+        default_max_age_secs: DEFAULT_RAW_CLEANER_GRACE_SECS,
+        allow_zero_max_age: false,
+        run: delete_raw_files,
+    },
+    // Deletes images that were unapproved (i.e. rejected) long enough ago. Disabled by default,
+    // as they are kept until they are deleted otherwise.
+    Cleaner {
+        name: "unapproved-cleaner",
+        state: "unapproved",
+        description: "unapproved images",
+        enabled_key: "UNAPPROVED_CLEANER_ENABLED",
+        default_enabled: false,
+        interval_key: "UNAPPROVED_CLEANER_INTERVAL_SECS",
+        schedule_key: "UNAPPROVED_CLEANER_SCHEDULE",
+        max_age_key: "UNAPPROVED_MAX_AGE_SECS",
+        approved_max_age_key: None,
+        default_interval_secs: DEFAULT_UNAPPROVED_CLEANER_INTERVAL_SECS,
+        default_max_age_secs: DEFAULT_UNAPPROVED_MAX_AGE_SECS,
+        allow_zero_max_age: false,
+        run: delete_old_unapproved_images,
+    },
+    // Deletes images (and raw files) moved to `data/trash` by deletions with `TRASH_ENABLED`
+    Cleaner {
+        name: "trash-cleaner",
+        state: "trash",
+        description: "deleted images",
+        enabled_key: "TRASH_CLEANER_ENABLED",
+        default_enabled: true,
+        interval_key: "TRASH_CLEANER_INTERVAL_SECS",
+        schedule_key: "TRASH_CLEANER_SCHEDULE",
+        max_age_key: "TRASH_MAX_AGE_SECS",
+        approved_max_age_key: None,
+        default_interval_secs: DEFAULT_TRASH_CLEANER_INTERVAL_SECS,
+        default_max_age_secs: DEFAULT_TRASH_MAX_AGE_SECS,
+        allow_zero_max_age: false,
+        run: delete_old_trashed_files,
+    },
+    // Deletes cache entries whose original does not exist anymore
+    Cleaner {
+        name: "cache-cleaner",
+        state: "cache",
+        description: "orphaned cache entries",
+        enabled_key: "CACHE_CLEANER_ENABLED",
+        default_enabled: true,
+        interval_key: "CACHE_CLEANER_INTERVAL_SECS",
+        schedule_key: "CACHE_CLEANER_SCHEDULE",
+        max_age_key: "CACHE_CLEANER_GRACE_SECS",
+        approved_max_age_key: None,
+        default_interval_secs: DEFAULT_CACHE_CLEANER_INTERVAL_SECS,
+        default_max_age_secs: DEFAULT_CACHE_CLEANER_GRACE_SECS,
+        allow_zero_max_age: false,
+        run: delete_orphaned_cache_entries,
+    },
+    // Deletes cache entries that were not accessed for a long time
+    Cleaner {
+        name: "cache-eviction",
+        state: "cache",
+        description: "cache entries not accessed",
+        enabled_key: "CACHE_EVICTION_ENABLED",
+        default_enabled: true,
+        interval_key: "CACHE_EVICTION_INTERVAL_SECS",
+        schedule_key: "CACHE_EVICTION_SCHEDULE",
+        max_age_key: "CACHE_MAX_IDLE_SECS",
+        approved_max_age_key: None,
+        default_interval_secs: DEFAULT_CACHE_EVICTION_INTERVAL_SECS,
+        default_max_age_secs: DEFAULT_CACHE_MAX_IDLE_SECS,
+        allow_zero_max_age: false,
+        run: evict_idle_cache_entries,
+    },
+    // Deletes objects no image links to anymore, e.g. after their images were deleted by other
+    // cleaners, the consistency repair or a restore
+    Cleaner {
+        name: "object-cleaner",
+        state: "objects",
+        description: "objects no image links to",
+        enabled_key: "OBJECT_CLEANER_ENABLED",
+        default_enabled: true,
+        interval_key: "OBJECT_CLEANER_INTERVAL_SECS",
+        schedule_key: "OBJECT_CLEANER_SCHEDULE",
+        max_age_key: "OBJECT_CLEANER_GRACE_SECS",
+        approved_max_age_key: None,
+        default_interval_secs: DEFAULT_OBJECT_CLEANER_INTERVAL_SECS,
+        default_max_age_secs: DEFAULT_OBJECT_CLEANER_GRACE_SECS,
+        allow_zero_max_age: false,
+        run: delete_unreferenced_objects,
+    },
+    // Deletes images fetched by `/proxy` that were not fetched again for a long time, i.e. are
+    // no longer requested. Images that are still requested are fetched again after
+    // `PROXY_CACHE_TTL_SECS`, which renews their age.
+    Cleaner {
+        name: "proxy-cleaner",
+        state: "proxy_cache",
+        description: "images fetched by the proxy",
+        enabled_key: "PROXY_CLEANER_ENABLED",
+        default_enabled: true,
+        interval_key: "PROXY_CLEANER_INTERVAL_SECS",
+        schedule_key: "PROXY_CLEANER_SCHEDULE",
+        max_age_key: "PROXY_CACHE_MAX_AGE_SECS",
+        approved_max_age_key: None,
+        default_interval_secs: DEFAULT_PROXY_CLEANER_INTERVAL_SECS,
+        default_max_age_secs: DEFAULT_PROXY_CACHE_MAX_AGE_SECS,
+        allow_zero_max_age: false,
+        run: delete_old_proxied_images,
+    },
+    // Deletes quarantined files after they could be investigated, and the oldest ones beyond
+    // `QUARANTINE_MAX_BYTES`
+    Cleaner {
+        name: "quarantine-cleaner",
+        state: "quarantine",
+        description: "quarantined files",
+        enabled_key: "QUARANTINE_CLEANER_ENABLED",
+        default_enabled: true,
+        interval_key: "QUARANTINE_CLEANER_INTERVAL_SECS",
+        schedule_key: "QUARANTINE_CLEANER_SCHEDULE",
+        max_age_key: "QUARANTINE_MAX_AGE_SECS",
+        approved_max_age_key: None,
+        default_interval_secs: DEFAULT_QUARANTINE_CLEANER_INTERVAL_SECS,
+        default_max_age_secs: DEFAULT_QUARANTINE_MAX_AGE_SECS,
+        allow_zero_max_age: false,
+        run: delete_old_quarantined_files,
+    },
+];
+
+/// Settings of a cleaner that regularly deletes old files
+#[derive(Clone, Copy)]
+pub struct CleanerConfig {
+    pub enabled: bool,
+    // Time between two runs of the cleaner, unless a cron schedule is configured
+    pub interval: Duration,
+    // Files older than this are deleted
+    pub max_age: Duration,
+    // Files of approved images are deleted once they were approved longer ago than this, if set
+    pub approved_max_age: Option<Duration>,
+    pub removal_behavior: RemovalBehavior,
+}
+
+/// Parses the settings of `cleaner` from the config
+pub fn parse_cleaner_config(config: &Config, cleaner: &Cleaner) -> CleanerConfig {
+    CleanerConfig {
+        enabled: config
+            .get_bool(cleaner.enabled_key)
+            .unwrap_or(cleaner.default_enabled),
+        interval: Duration::from_secs(
+            config
+                .get::<u64>(cleaner.interval_key)
+                .unwrap_or(cleaner.default_interval_secs),
+        ),
+        max_age: Duration::from_secs(
+            config
+                .get::<u64>(cleaner.max_age_key)
+                .unwrap_or(cleaner.default_max_age_secs),
+        ),
+        approved_max_age: cleaner
+            .approved_max_age_key
+            .and_then(|key| config.get::<u64>(key).ok())
+            .map(Duration::from_secs),
+        removal_behavior: parse_maintenance_behavior(config),
+    }
+}
+
+/// How long files of a state are kept, as enforced by one of the cleaners
+pub struct RetentionPolicy {
+    pub cleaner: &'static Cleaner,
+    pub config: CleanerConfig,
+}
+
+/// Parses the retention policies of all states from the config.
+/// Each state can have multiple policies, e.g. the cache is cleaned of orphaned and idle entries.
+pub fn parse_retention_policies(config: &Config) -> Vec<RetentionPolicy> {
+    CLEANERS
+        .iter()
+        .map(|cleaner| RetentionPolicy {
+            cleaner: cleaner,
+            config: parse_cleaner_config(config, cleaner),
+        })
+        .collect()
+}
+
+/// Schedules the cleaners of all enabled retention policies and logs the policies
+pub fn schedule_cleaners(config: &Config, scheduler: &Scheduler, server_state: &ServerState) {
+    for policy in parse_retention_policies(config) {
+        let cleaner = policy.cleaner;
+        let cleaner_config = policy.config;
+        if !cleaner_config.enabled {
+            log::info!(
+                "RETENTION: {}: Keeping {} forever (cleaner disabled)",
+                cleaner.state,
+                cleaner.description
+            );
+            continue;
+        }
+
+        let schedule = parse_job_schedule(config, cleaner.schedule_key, cleaner_config.interval);
+        log::info!(
+            "RETENTION: {}: Deleting {} older than {:?} {}",
+            cleaner.state,
+            cleaner.description,
+            cleaner_config.max_age,
+            schedule
+        );
+        if let Some(approved_max_age) = cleaner_config.approved_max_age {
+            log::info!(
+                "RETENTION: {}: Deleting files of images approved more than {:?} ago",
+                cleaner.state,
+                approved_max_age
+            );
+        }
+        let run = cleaner.run;
+        let name = cleaner.name;
+        let server_state = server_state.clone();
+        scheduler.spawn(Job {
+            name: name,
+            schedule: schedule,
+            run: Arc::new(move || {
+                let res = run(cleaner_config, &server_state);
+                if let Err(err) = &res {
+                    server_state.notifier.notify_job_failure(name, err);
+                }
+                res
+            }),
+        });
+    }
+}
+
+/// Deletes all pending images older than the configured max age.
+/// Returns the number of deleted images.
+pub fn delete_old_pending_images(
+    cleaner_config: CleanerConfig,
+    _: &ServerState,
+) -> Result<usize, String> {
+    in_all_roots(|tenant| delete_old_files(&get_pending_path(tenant), cleaner_config, |_| false))
+}
+
+/// Deletes all unapproved images that were unapproved longer than the configured max age ago.
+/// Images keep their modification time when they are moved, so the time of the state change is
+/// taken from the metadata index; images without one are kept. Their raw files are deleted by
+/// the raw file cleaner afterwards.
+/// Returns the number of deleted images.
+pub fn delete_old_unapproved_images(
+    cleaner_config: CleanerConfig,
+    server_state: &ServerState,
+) -> Result<usize, String> {
+    let threshold = SystemTime::now()
+        .checked_sub(cleaner_config.max_age)
+        .unwrap_or(UNIX_EPOCH);
+
+    // The age is checked against the state change time below, not the modification time
+    let any_age = CleanerConfig {
+        max_age: Duration::ZERO,
+        ..cleaner_config
+    };
+    in_all_roots(|tenant| {
+        delete_old_files(&get_unapproved_path(tenant), any_age, |file_name| {
+            let Some(uuid) = file_name
+                .strip_suffix(".avif")
+                .and_then(|stem| Uuid::parse_str(stem).ok())
+            else {
+                log::warn!(
+                    "Ignoring unexpected file '{}' in unapproved path",
+                    file_name
+                );
+                return true;
+            };
+
+            server_state
+                .metadata_index
+                .get(uuid)
+                .map_or(true, |times| times.state_changed_at >= threshold)
+        })
+    })
+}
+
+/// Deletes all files in the trash older than the configured max age, i.e. deleted longer ago.
+/// Returns the number of deleted files.
+pub fn delete_old_trashed_files(
+    cleaner_config: CleanerConfig,
+    _: &ServerState,
+) -> Result<usize, String> {
+    let trash_path = get_trash_path();
+    if !trash_path.exists() {
+        return Ok(0);
+    }
+    delete_old_files(&trash_path, cleaner_config, |_| false)
+}
+
+/// Deletes orphaned raw files and, if configured, those of images approved long enough ago, see
+/// `delete_orphaned_raw_files` and `delete_retained_raw_files`.
+/// Returns the number of deleted raw files.
+pub fn delete_raw_files(
+    cleaner_config: CleanerConfig,
+    server_state: &ServerState,
+) -> Result<usize, String> {
+    let orphaned = delete_orphaned_raw_files(cleaner_config)?;
+    let retained = match cleaner_config.approved_max_age {
+        None => 0,
+        Some(approved_max_age) => {
+            delete_retained_raw_files(approved_max_age, cleaner_config, server_state)?
+        }
+    };
+    Ok(orphaned + retained)
+}
+
+/// Deletes all raw files older than the configured grace period, whose image does not exist
+/// in any state (pending, unapproved or original) anymore.
+/// Returns the number of deleted raw files.
+fn delete_orphaned_raw_files(cleaner_config: CleanerConfig) -> Result<usize, String> {
+    in_all_roots(|tenant| {
+        delete_old_files(&get_raw_path(tenant), cleaner_config, |file_name| {
+            // Keep files that are not named after an image, as we don't know what they are
+            let Some(uuid) = file_name
+                .strip_suffix(".raw")
+                .and_then(|stem| Uuid::parse_str(stem).ok())
+            else {
+                log::warn!("Ignoring unexpected file '{}' in raw path", file_name);
+                return true;
+            };
+
+            determine_img_dir(uuid, tenant, ImageSearchBehaviour::All).is_ok()
+        })
+    })
+}
+
+/// Deletes the raw files of all approved images that were approved longer than
+/// `approved_max_age` ago. Raw files of images in any other state are kept, e.g. to rotate them
+/// at approval. Raw files of images without a recorded approval time are kept as well.
+/// Returns the number of deleted raw files.
+fn delete_retained_raw_files(
+    approved_max_age: Duration,
+    cleaner_config: CleanerConfig,
+    server_state: &ServerState,
+) -> Result<usize, String> {
+    let threshold = SystemTime::now()
+        .checked_sub(approved_max_age)
+        .unwrap_or(UNIX_EPOCH);
+
+    // The age is checked against the approval time below, not the modification time
+    let any_age = CleanerConfig {
+        max_age: Duration::ZERO,
+        ..cleaner_config
+    };
+    in_all_roots(|tenant| {
+        let original_path = get_original_path(tenant);
+        delete_old_files(&get_raw_path(tenant), any_age, |file_name| {
+            let Some(uuid) = file_name
+                .strip_suffix(".raw")
+                .and_then(|stem| Uuid::parse_str(stem).ok())
+            else {
+                log::warn!("Ignoring unexpected file '{}' in raw path", file_name);
+                return true;
+            };
+
+            if determine_img_path(original_path.to_str().unwrap(), uuid).is_err() {
+                return true;
+            }
+            server_state
+                .metadata_index
+                .get(uuid)
+                .map_or(true, |times| times.state_changed_at >= threshold)
+        })
+    })
+}
+
+/// Deletes all cache entries older than the configured grace period, whose image does not
+/// exist in the original path anymore. Only approved images are cached, so entries of images in
+/// any other state are orphaned as well.
+/// Returns the number of deleted cache entries.
+pub fn delete_orphaned_cache_entries(
+    cleaner_config: CleanerConfig,
+    _: &ServerState,
+) -> Result<usize, String> {
+    in_all_roots(|tenant| {
+        let original_path = get_original_path(tenant);
+        delete_old_files(&get_cache_path(tenant), cleaner_config, |file_name| {
+            // Cache entries are named '<uuid>-<width>x<height>-<quality>[-<pipeline key>].<format>'
+            let Some(uuid) = file_name
+                .get(..36)
+                .and_then(|prefix| Uuid::parse_str(prefix).ok())
+            else {
+                log::warn!("Ignoring unexpected file '{}' in cache path", file_name);
+                return true;
+            };
+
+            determine_img_path(original_path.to_str().unwrap(), uuid).is_ok()
+        })
+    })
+}
 
-        // Define the threshold for file deletion (1 hour ago)
-        let threshold = current_time - Duration::from_secs(3600);
+/// Deletes all cache entries that were not accessed within the configured max idle time, except
+/// pinned ones. Entries without a recorded access (e.g. from before the index was introduced) are
+/// treated as last accessed when they were written.
+/// Returns the number of deleted cache entries.
+pub fn evict_idle_cache_entries(
+    cleaner_config: CleanerConfig,
+    server_state: &ServerState,
+) -> Result<usize, String> {
+    let cache_index = &server_state.cache_index;
+    let threshold = SystemTime::now()
+        .checked_sub(cleaner_config.max_age)
+        .unwrap_or(UNIX_EPOCH);
 
-        log::info!("Starting deletion of old pending files.");
+    let evicted = in_all_roots(|tenant| {
+        delete_old_files(&get_cache_path(tenant), cleaner_config, |file_name| {
+            cache_index.is_pinned(file_name)
+                || cache_index
+                    .last_access(file_name)
+                    .is_some_and(|last_access| last_access >= threshold)
+        })
+    })?;
 
-        // Get iterator to iterate over all entries in PENDING_PATH directory
-        match read_dir(get_pending_path()) {
-            Err(err) => log::error!("Unable to read pending path: {}", err),
-            Ok(iterator) => iterator.for_each(|dir_entry| dir_entry_handler(dir_entry, threshold)),
+    // Forget entries that were deleted (by this or any other means)
+    let cache_paths = list_roots()
+        .iter()
+        .map(|tenant| get_cache_path(tenant.as_deref()))
+        .collect::<Vec<_>>();
+    cache_index.prune(|file_name| {
+        cache_paths
+            .iter()
+            .any(|cache_path| cache_path.join(file_name).exists())
+    });
+    cache_index.save()?;
+
+    Ok(evicted)
+}
+
+/// Deletes all objects older than the configured grace period, that are not linked to by any
+/// image anymore, i.e. whose only link is the object itself.
+/// Returns the number of deleted objects.
+pub fn delete_unreferenced_objects(
+    cleaner_config: CleanerConfig,
+    _: &ServerState,
+) -> Result<usize, String> {
+    let objects_path = get_objects_path();
+    delete_old_files(&objects_path, cleaner_config, |file_name| {
+        if !file_name.ends_with(".avif") {
+            log::warn!("Ignoring unexpected file '{}' in objects path", file_name);
+            return true;
         }
+        metadata(objects_path.join(file_name)).map_or(true, |metadata| metadata.nlink() > 1)
+    })
+}
 
-        log::info!("Finished deletion of old pending files. Going back to sleep...");
-        // Sleep for 15 minutes
-        thread::sleep(Duration::from_secs(900));
+/// Deletes all images fetched by `/proxy` older than the configured max age.
+/// Returns the number of deleted images.
+pub fn delete_old_proxied_images(
+    cleaner_config: CleanerConfig,
+    _: &ServerState,
+) -> Result<usize, String> {
+    delete_old_files(&get_proxy_cache_path(), cleaner_config, |_| false)
+}
+
+/// Deletes all quarantined files (and their reasons) older than the configured max age, and then
+/// the oldest remaining ones while the quarantine is larger than `QUARANTINE_MAX_BYTES`.
+/// Returns the number of deleted files.
+pub fn delete_old_quarantined_files(
+    cleaner_config: CleanerConfig,
+    server_state: &ServerState,
+) -> Result<usize, String> {
+    let quarantine_path = get_quarantine_path();
+    if !quarantine_path.exists() {
+        return Ok(0);
+    }
+    let expired = delete_old_files(&quarantine_path, cleaner_config, |_| false)?;
+    let evicted = enforce_quarantine_size(
+        server_state.quarantine_max_bytes,
+        cleaner_config.removal_behavior,
+    )?;
+    Ok(expired + evicted)
+}
+
+/// Runs `clean` for the root of every tenant and the one of images without tenant, see
+/// `list_roots`.
+/// Returns the total number of deleted files.
+fn in_all_roots(
+    mut clean: impl FnMut(Option<&str>) -> Result<usize, String>,
+) -> Result<usize, String> {
+    let mut deleted = 0;
+    for tenant in list_roots() {
+        deleted += clean(tenant.as_deref())?;
     }
+    Ok(deleted)
+}
+
+/// Deletes all files in `dir` older than the configured max age, except the ones for which
+/// `keep` returns true when called with their file name.
+/// Returns the number of deleted files.
+fn delete_old_files(
+    dir: &Path,
+    cleaner_config: CleanerConfig,
+    keep: impl Fn(&str) -> bool,
+) -> Result<usize, String> {
+    // Define the threshold for file deletion, max ages beyond the epoch delete everything
+    let threshold = SystemTime::now()
+        .checked_sub(cleaner_config.max_age)
+        .unwrap_or(UNIX_EPOCH);
+
+    // Get iterator to iterate over all entries in the directory
+    let iterator = match read_dir(dir) {
+        Err(err) => return Err(format!("Unable to read {:?}: {}", dir, err)),
+        Ok(iterator) => iterator,
+    };
+
+    Ok(iterator
+        .filter(|dir_entry| {
+            dir_entry_handler(dir_entry, threshold, cleaner_config.removal_behavior, &keep)
+        })
+        .count())
 }
 
 /// Takes a `DirEntry` as a Result and deletes it if it is:
 /// - a regular file and
 /// - not a hidden file and
-/// - older than `threshold`
-fn dir_entry_handler(dir_entry_res: Result<DirEntry, io::Error>, threshold: SystemTime) {
+/// - older than `threshold` and
+/// - not to be kept according to `keep`
+///
+/// In a dry run, the file is only logged instead.
+/// Returns whether the file was (or would be) deleted.
+fn dir_entry_handler(
+    dir_entry_res: &Result<DirEntry, io::Error>,
+    threshold: SystemTime,
+    removal_behavior: RemovalBehavior,
+    keep: impl Fn(&str) -> bool,
+) -> bool {
     let dir_entry = match dir_entry_res {
         Err(err) => {
             log::error!("Error while reading dir entry: {}", err);
-            return;
+            return false;
         }
         Ok(dir_entry) => dir_entry,
     };
@@ -48,13 +636,17 @@ fn dir_entry_handler(dir_entry_res: Result<DirEntry, io::Error>, threshold: Syst
         let file_name_str = match file_name.to_str() {
             None => {
                 log::error!("Unable to get file name as string for: '{:?}'", dir_entry);
-                return;
+                return false;
             }
             Some(name) => name,
         };
 
         if file_name_str.starts_with('.') {
-            return;
+            return false;
+        }
+
+        if keep(file_name_str) {
+            return false;
         }
 
         // Get modified time
@@ -65,7 +657,7 @@ fn dir_entry_handler(dir_entry_res: Result<DirEntry, io::Error>, threshold: Syst
                     dir_entry.path(),
                     err
                 );
-                return;
+                return false;
             }
             Ok(metadata) => match metadata.modified() {
                 Err(err) => {
@@ -74,7 +666,7 @@ fn dir_entry_handler(dir_entry_res: Result<DirEntry, io::Error>, threshold: Syst
                         dir_entry.path(),
                         err
                     );
-                    return;
+                    return false;
                 }
                 Ok(modified_time) => modified_time,
             },
@@ -82,12 +674,23 @@ fn dir_entry_handler(dir_entry_res: Result<DirEntry, io::Error>, threshold: Syst
 
         // Delete the file if it's older than the threshold
         if modified_time < threshold {
-            match remove_file(dir_entry.path()) {
-                Err(err) => log::error!("Unable to delete '{:?}': {}", dir_entry.path(), err),
+            if removal_behavior == RemovalBehavior::DryRun {
+                log::info!("Dry run: Would delete {:?}", dir_entry.path());
+                return true;
+            }
+
+            return match remove_file(dir_entry.path()) {
+                Err(err) => {
+                    log::error!("Unable to delete '{:?}': {}", dir_entry.path(), err);
+                    false
+                }
                 Ok(_) => {
-                    log::info!("Deleted {:?}", dir_entry.path())
+                    log::info!("Deleted {:?}", dir_entry.path());
+                    true
                 }
-            }
+            };
         }
     }
+
+    false
 }
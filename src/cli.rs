@@ -0,0 +1,82 @@
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHasher, SaltString},
+    Argon2,
+};
+use clap::{Parser, Subcommand};
+
+use crate::{
+    content_store::migrate,
+    settings::{format_report, load_config, validate_config},
+    util::durability::parse_durability,
+};
+
+/// Mensatt's image service
+#[derive(Parser)]
+#[command(version, about)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Start the image service (default, if no command is given)
+    Serve,
+    /// Generate an Argon2id hash of an API key, to be used in API_KEY_HASHES
+    HashKey {
+        /// The API key to hash
+        key: String,
+    },
+    /// Validate the config and data directories without starting the service
+    Check,
+    /// Store all approved images under their content hash, to be run before enabling CAS_ENABLED
+    MigrateCas,
+}
+
+/// Prints the Argon2id hash (in encoded form) of `key`
+pub fn hash_key(key: &str) -> Result<(), String> {
+    if key.is_empty() {
+        return Err("Key must not be empty".to_owned());
+    }
+
+    let salt = SaltString::generate(&mut OsRng);
+    match Argon2::default().hash_password(key.as_bytes(), &salt) {
+        Err(err) => Err(format!("Could not hash key: {}", err)),
+        Ok(hash) => {
+            println!("{}", hash);
+            Ok(())
+        }
+    }
+}
+
+/// Validates the config at `config_path` and prints a report of all problems found
+pub fn check(config_path: &str) -> Result<(), String> {
+    let config = match load_config(config_path) {
+        Err(err) => return Err(format!("Could not build config: {}", err)),
+        Ok(config) => config,
+    };
+
+    let problems = validate_config(&config);
+    if !problems.is_empty() {
+        return Err(format_report(&problems));
+    }
+
+    println!("Config '{}' and data directories are valid", config_path);
+    Ok(())
+}
+
+/// Stores the approved images of the data directory under their content hash (see
+/// `content_store`) and prints how many were stored and deduplicated
+pub fn migrate_cas(config_path: &str) -> Result<(), String> {
+    let config = match load_config(config_path) {
+        Err(err) => return Err(format!("Could not build config: {}", err)),
+        Ok(config) => config,
+    };
+
+    let report = migrate(parse_durability(&config))?;
+    println!(
+        "Stored {} images as new objects, deduplicated {} ({} bytes saved), {} were stored already",
+        report.stored, report.deduplicated, report.saved_bytes, report.unchanged
+    );
+    Ok(())
+}
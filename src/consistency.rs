@@ -0,0 +1,266 @@
+use std::{
+    collections::HashMap,
+    fs::remove_file,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use config::Config;
+use serde::Serialize;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::{
+    constants::DEFAULT_CONSISTENCY_CHECK_INTERVAL_SECS,
+    util::{
+        image::{ImageState, RemovalBehavior},
+        path::{get_cache_path, get_original_path, get_raw_path, list_files, list_roots},
+    },
+    ServerState,
+};
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum RepairBehavior {
+    ReportOnly,
+    Repair,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum InconsistencyKind {
+    // The same image exists in more than one of pending, unapproved and originals
+    MultipleStates,
+    // A raw file exists, but its image does not
+    RawWithoutImage,
+    // An image exists, but its raw file does not
+    ImageWithoutRaw,
+    // A cache entry exists, but its original does not
+    CacheWithoutOriginal,
+    // The cache index knows an entry that does not exist
+    IndexWithoutFile,
+    // A file that is not named like any file this service writes
+    UnexpectedFile,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct Inconsistency {
+    pub kind: InconsistencyKind,
+    pub uuid: Option<Uuid>,
+    #[schema(value_type = String)]
+    pub path: PathBuf,
+    pub repaired: bool,
+}
+
+/// Settings of the regular consistency check
+#[derive(Clone, Copy)]
+pub struct ConsistencyCheckConfig {
+    pub enabled: bool,
+    pub interval: Duration,
+    pub repair_behavior: RepairBehavior,
+}
+
+/// Parses the consistency check settings from the config properties
+/// `CONSISTENCY_CHECK_ENABLED`, `CONSISTENCY_CHECK_INTERVAL_SECS` and `CONSISTENCY_CHECK_REPAIR`
+pub fn parse_consistency_check_config(config: &Config) -> ConsistencyCheckConfig {
+    ConsistencyCheckConfig {
+        enabled: config.get_bool("CONSISTENCY_CHECK_ENABLED").unwrap_or(true),
+        interval: Duration::from_secs(
+            config
+                .get::<u64>("CONSISTENCY_CHECK_INTERVAL_SECS")
+                .unwrap_or(DEFAULT_CONSISTENCY_CHECK_INTERVAL_SECS),
+        ),
+        repair_behavior: match config.get_bool("CONSISTENCY_CHECK_REPAIR") {
+            Ok(true) => RepairBehavior::Repair,
+            _ => RepairBehavior::ReportOnly,
+        },
+    }
+}
+
+/// Cross-checks all data directories and the cache index.
+/// With `RepairBehavior::Repair`, inconsistencies that can be fixed safely are fixed:
+/// - images in multiple states are removed from all but the most advanced state
+/// - cache entries without original are deleted
+/// - index entries without file are removed from the index
+///
+/// Nothing is deleted if `MAINTENANCE_DRY_RUN` is enabled.
+pub fn check_consistency(
+    server_state: &ServerState,
+    repair_behavior: RepairBehavior,
+) -> Result<Vec<Inconsistency>, String> {
+    let repair = repair_behavior == RepairBehavior::Repair
+        && server_state.maintenance_behavior == RemovalBehavior::Delete;
+    let mut inconsistencies = Vec::new();
+
+    let roots = list_roots();
+    for tenant in roots.iter() {
+        check_root(tenant.as_deref(), repair, &mut inconsistencies)?;
+    }
+
+    // Cache index, whose entries may be in the cache of any root
+    let cache_paths = roots
+        .iter()
+        .map(|tenant| get_cache_path(tenant.as_deref()))
+        .collect::<Vec<_>>();
+    let mut missing = Vec::new();
+    server_state.cache_index.prune(|name| {
+        let exists = cache_paths
+            .iter()
+            .any(|cache_path| cache_path.join(name).exists());
+        if !exists {
+            missing.push(name.to_owned());
+        }
+        // Only remove entries when repairing
+        exists || !repair
+    });
+    for name in missing {
+        inconsistencies.push(Inconsistency {
+            kind: InconsistencyKind::IndexWithoutFile,
+            uuid: name
+                .get(..36)
+                .and_then(|prefix| Uuid::parse_str(prefix).ok()),
+            path: get_cache_path(None).join(name),
+            repaired: repair,
+        });
+    }
+
+    for inconsistency in inconsistencies.iter() {
+        log::warn!(
+            "CONSISTENCY: {} {:?} (repaired: {})",
+            serde_json::to_string(&inconsistency.kind).unwrap_or_default(),
+            inconsistency.path,
+            inconsistency.repaired
+        );
+    }
+
+    Ok(inconsistencies)
+}
+
+/// Cross-checks the image, raw and cache directories of the root of `tenant`
+fn check_root(
+    tenant: Option<&str>,
+    repair: bool,
+    inconsistencies: &mut Vec<Inconsistency>,
+) -> Result<(), String> {
+    // Ordered from least to most advanced state
+    let state_paths = ImageState::ALL.map(|state| state.path(tenant));
+    let mut states: HashMap<Uuid, Vec<PathBuf>> = HashMap::new();
+    for state_path in state_paths.iter() {
+        for name in read_files(state_path)? {
+            match parse_uuid(&name, ".avif") {
+                None => inconsistencies.push(unexpected_file(state_path.join(name))),
+                Some(uuid) => states.entry(uuid).or_default().push(state_path.join(name)),
+            }
+        }
+    }
+
+    for (uuid, paths) in states.iter() {
+        if paths.len() < 2 {
+            continue;
+        }
+
+        // Keep the most advanced state, which is the last one
+        for path in paths[..paths.len() - 1].iter() {
+            let repaired = repair && try_remove(path);
+            inconsistencies.push(Inconsistency {
+                kind: InconsistencyKind::MultipleStates,
+                uuid: Some(*uuid),
+                path: path.clone(),
+                repaired: repaired,
+            });
+        }
+    }
+
+    // Raw files
+    let raw_path = get_raw_path(tenant);
+    let mut raws: Vec<Uuid> = Vec::new();
+    for name in read_files(&raw_path)? {
+        match parse_uuid(&name, ".raw") {
+            None => inconsistencies.push(unexpected_file(raw_path.join(name))),
+            Some(uuid) => {
+                if !states.contains_key(&uuid) {
+                    // Left to the raw cleaner, which respects a grace period
+                    inconsistencies.push(Inconsistency {
+                        kind: InconsistencyKind::RawWithoutImage,
+                        uuid: Some(uuid),
+                        path: raw_path.join(name),
+                        repaired: false,
+                    });
+                }
+                raws.push(uuid);
+            }
+        }
+    }
+
+    for (uuid, paths) in states.iter() {
+        if !raws.contains(uuid) {
+            inconsistencies.push(Inconsistency {
+                kind: InconsistencyKind::ImageWithoutRaw,
+                uuid: Some(*uuid),
+                path: paths[paths.len() - 1].clone(),
+                repaired: false,
+            });
+        }
+    }
+
+    // Cache entries are named '<uuid>-<width>x<height>-<quality>[-<pipeline key>].<format>'
+    let cache_path = get_cache_path(tenant);
+    let original_path = get_original_path(tenant);
+    for name in read_files(&cache_path)? {
+        let Some(uuid) = name
+            .get(..36)
+            .and_then(|prefix| Uuid::parse_str(prefix).ok())
+        else {
+            inconsistencies.push(unexpected_file(cache_path.join(name)));
+            continue;
+        };
+
+        let has_original = states
+            .get(&uuid)
+            .is_some_and(|paths| paths.iter().any(|path| path.starts_with(&original_path)));
+        if !has_original {
+            let path = cache_path.join(name);
+            let repaired = repair && try_remove(&path);
+            inconsistencies.push(Inconsistency {
+                kind: InconsistencyKind::CacheWithoutOriginal,
+                uuid: Some(uuid),
+                path: path,
+                repaired: repaired,
+            });
+        }
+    }
+
+    Ok(())
+}
+
+fn read_files(dir: &Path) -> Result<Vec<String>, String> {
+    list_files(dir).map_err(|err| format!("Unable to read {:?}: {}", dir, err))
+}
+
+/// Parses the UUID of a file named `<uuid><suffix>`
+fn parse_uuid(name: &str, suffix: &str) -> Option<Uuid> {
+    name.strip_suffix(suffix)
+        .and_then(|stem| Uuid::parse_str(stem).ok())
+}
+
+fn unexpected_file(path: PathBuf) -> Inconsistency {
+    Inconsistency {
+        kind: InconsistencyKind::UnexpectedFile,
+        uuid: None,
+        path: path,
+        repaired: false,
+    }
+}
+
+/// Removes `path` and returns whether that was successful
+fn try_remove(path: &Path) -> bool {
+    match remove_file(path) {
+        Err(err) => {
+            log::error!("Unable to delete '{:?}': {}", path, err);
+            false
+        }
+        Ok(_) => {
+            log::info!("Deleted '{:?}'", path);
+            true
+        }
+    }
+}
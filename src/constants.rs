@@ -1,16 +1,204 @@
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 
 pub const CONTENT_LENGTH_LIMIT: usize = 12 * 1024 * 1024;
-pub const LISTEN_ADDR: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), 3000);
-pub const PENDING_QUALITY: i32 = 80; // Quality setting for encoder for pending (uploaded) images
+// Limit of JSON upload bodies, which contain the image base64 encoded (4 bytes per 3), plus room
+// for the data URI prefix and the JSON syntax. The decoded image is limited to `CONTENT_LENGTH_LIMIT`.
+pub const JSON_UPLOAD_LIMIT: usize = CONTENT_LENGTH_LIMIT.div_ceil(3) * 4 + 4 * 1024;
+pub const DEFAULT_LISTEN_ADDR: SocketAddr =
+    SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), 3000);
+pub const API_PREFIX: &str = "/v1"; // Prefix of the current API version, routes are also served without it
+pub const PENDING_QUALITY: i32 = 80; // Quality setting for encoder for pending (uploaded) images, if `AVIF_PENDING` sets none
 
-// Quality setting for encoder for rotating images
+// Quality setting for encoder for rotating images, if `AVIF_ROTATION` sets none
 // Note that this was set to 100, as not to compromise on quality when (repeatedly)  rotating images
 pub const ROTATION_QUALITY: i32 = 100;
+// CPU effort of the AVIF encoder (0 to 9), if `AVIF_PENDING`/`_ROTATION`/`_REGENERATE` set none
+pub const DEFAULT_AVIF_EFFORT: i32 = 0;
+
+// Page size of listing endpoints
+pub const DEFAULT_LIST_LIMIT: usize = 100;
+pub const MAX_LIST_LIMIT: usize = 1000;
+// Limits of transformation pipelines (`?ops=`)
+pub const MAX_PIPELINE_OPERATIONS: usize = 10;
+pub const MAX_BLUR_SIGMA: f64 = 50.0;
+pub const MAX_SHARPEN_SIGMA: f64 = 10.0;
+// Limits of the dimensions of renditions, if `MAX_OUTPUT_WIDTH`/`_HEIGHT`/`_PIXELS` are not set
+pub const DEFAULT_MAX_OUTPUT_DIMENSION: i32 = 8192;
+pub const DEFAULT_MAX_OUTPUT_PIXELS: i64 = 40_000_000;
+// Limits of the headers of inputs, if `MAX_INPUT_WIDTH`/`_HEIGHT`/`_BIT_DEPTH`/`_FRAMES`/`_PIXELS`
+// are not set
+pub const DEFAULT_MAX_INPUT_DIMENSION: i32 = 20000;
+pub const DEFAULT_MAX_INPUT_BIT_DEPTH: i32 = 16;
+pub const DEFAULT_MAX_INPUT_FRAMES: i32 = 100;
+pub const DEFAULT_MAX_INPUT_PIXELS: i64 = 400_000_000;
+// Time a vips operation (e.g. rendering or encoding an upload) may take, if `VIPS_TIMEOUT_SECS` is
+// not set
+pub const DEFAULT_VIPS_TIMEOUT_SECS: u64 = 60;
+// Device pixel ratios of Client Hints are limited to this, and the quality with `Save-Data: on`,
+// if `SAVE_DATA_QUALITY` is not set
+pub const MAX_CLIENT_HINT_DPR: f64 = 4.0;
+pub const DEFAULT_SAVE_DATA_QUALITY: i32 = 50;
+// Validity of preview tokens, if `PREVIEW_TOKEN_TTL_SECS` is not set
+pub const DEFAULT_PREVIEW_TOKEN_TTL_SECS: u64 = 60 * 60;
+// How long `Idempotency-Key`s of uploads are remembered, if `IDEMPOTENCY_KEY_TTL_SECS` is not set,
+// and their maximum length
+pub const DEFAULT_IDEMPOTENCY_KEY_TTL_SECS: u64 = 24 * 60 * 60;
+pub const MAX_IDEMPOTENCY_KEY_LENGTH: usize = 255;
+// Maximum number of images whose info can be requested at once
+pub const MAX_INFO_IDS: usize = 100;
+// Maximum number of IDs per request to `/images/delete`
+pub const MAX_DELETE_IDS: usize = 100;
+// Widths returned by `/image/:id/srcset`, if none are requested, and the maximum number of widths
+pub const DEFAULT_SRCSET_WIDTHS: [i32; 5] = [320, 640, 960, 1280, 1920];
+pub const MAX_SRCSET_WIDTHS: usize = 20;
+// Width, WebP quality and blur (unless `LQIP_BLUR` is disabled) of low-quality image placeholders
+pub const LQIP_WIDTH: i32 = 24;
+pub const LQIP_QUALITY: i32 = 20;
+pub const LQIP_BLUR_SIGMA: f64 = 1.0;
+// Width of the thumbnail BlurHashes are computed from and their number of components (x, y)
+pub const BLURHASH_WIDTH: i32 = 32;
+pub const BLURHASH_COMPONENTS: (u32, u32) = (4, 3);
+// Height of each side of `/image/:id/compare` if none is requested, its maximum and the gap
+// between the sides
+pub const DEFAULT_COMPARE_HEIGHT: i32 = 600;
+pub const MAX_COMPARE_HEIGHT: i32 = 2000;
+pub const COMPARE_GAP: i32 = 16;
+// Maximum number of IDs per request to `/verify`
+pub const MAX_VERIFY_IDS: usize = 100;
+// Maximum number of images in one archive of `/thumbnails.zip`
+pub const MAX_THUMBNAIL_ARCHIVE_IMAGES: usize = 200;
+// Maximum number of images per request to `/rotate/batch` and how many are rotated concurrently
+pub const MAX_ROTATE_BATCH: usize = 100;
+pub const ROTATE_BATCH_CONCURRENCY: usize = 4;
+// Maximum number of renditions per request to `/cache/warmup` and how many are rendered
+// concurrently
+pub const MAX_WARMUP_REQUESTS: usize = 5000;
+pub const WARMUP_CONCURRENCY: usize = 4;
+
+// Defaults for the cleaner of pending images
+pub const DEFAULT_CLEANER_INTERVAL_SECS: u64 = 15 * 60;
+pub const DEFAULT_PENDING_MAX_AGE_SECS: u64 = 60 * 60;
+
+// Defaults for the cleaner of orphaned raw files
+pub const DEFAULT_RAW_CLEANER_INTERVAL_SECS: u64 = 60 * 60;
+pub const DEFAULT_RAW_CLEANER_GRACE_SECS: u64 = 24 * 60 * 60;
+
+// Defaults for the cleaner of orphaned cache entries
+pub const DEFAULT_CACHE_CLEANER_INTERVAL_SECS: u64 = 60 * 60;
+pub const DEFAULT_CACHE_CLEANER_GRACE_SECS: u64 = 5 * 60;
+
+// Defaults for the eviction of cache entries that were not accessed for a long time
+pub const DEFAULT_CACHE_EVICTION_INTERVAL_SECS: u64 = 24 * 60 * 60;
+pub const DEFAULT_CACHE_MAX_IDLE_SECS: u64 = 30 * 24 * 60 * 60;
+
+// Defaults for the cleaner of objects no image links to anymore
+pub const DEFAULT_OBJECT_CLEANER_INTERVAL_SECS: u64 = 24 * 60 * 60;
+pub const DEFAULT_OBJECT_CLEANER_GRACE_SECS: u64 = 60 * 60;
+
+// Defaults for the cleaner of unapproved images, if enabled
+pub const DEFAULT_UNAPPROVED_CLEANER_INTERVAL_SECS: u64 = 24 * 60 * 60;
+pub const DEFAULT_UNAPPROVED_MAX_AGE_SECS: u64 = 30 * 24 * 60 * 60;
+
+// Defaults for the cleaner of deleted images in `data/trash`
+pub const DEFAULT_TRASH_CLEANER_INTERVAL_SECS: u64 = 60 * 60;
+pub const DEFAULT_TRASH_MAX_AGE_SECS: u64 = 7 * 24 * 60 * 60;
+
+// Defaults for the cleaner of quarantined files
+pub const DEFAULT_QUARANTINE_CLEANER_INTERVAL_SECS: u64 = 60 * 60;
+pub const DEFAULT_QUARANTINE_MAX_AGE_SECS: u64 = 30 * 24 * 60 * 60;
+// Size of `data/quarantine` above which the oldest files are deleted, if `QUARANTINE_MAX_BYTES` is not set
+pub const DEFAULT_QUARANTINE_MAX_BYTES: u64 = 1024 * 1024 * 1024;
+
+// Defaults for the cleaner of images fetched by `/proxy`, longer than `DEFAULT_PROXY_CACHE_TTL_SECS`
+pub const DEFAULT_PROXY_CLEANER_INTERVAL_SECS: u64 = 60 * 60;
+pub const DEFAULT_PROXY_CACHE_MAX_AGE_SECS: u64 = 2 * 24 * 60 * 60;
+// Default interval of the storage consistency check
+pub const DEFAULT_CONSISTENCY_CHECK_INTERVAL_SECS: u64 = 24 * 60 * 60;
+// Files modified within this window before a start after an unclean shutdown are checked, if
+// `RECOVERY_WINDOW_SECS` is not set
+pub const DEFAULT_RECOVERY_WINDOW_SECS: u64 = 24 * 60 * 60;
+// Number of most recent job runs that are kept for polling via `/jobs/:name/runs/:id`
+pub const MAX_JOB_RUNS: usize = 100;
+// Size of the buffer between writing and streaming archives for `/export`
+pub const EXPORT_BUFFER_SIZE: usize = 64 * 1024;
+// Timeout of downloads for `/import`, if `IMPORT_TIMEOUT_SECS` is not set
+pub const DEFAULT_IMPORT_TIMEOUT_SECS: u64 = 30;
+// Defaults of `/proxy`, if `PROXY_TIMEOUT_SECS`, `PROXY_MAX_SIZE` or `PROXY_CACHE_TTL_SECS` are not set
+pub const DEFAULT_PROXY_TIMEOUT_SECS: u64 = 10;
+pub const DEFAULT_PROXY_MAX_SIZE: usize = 8 * 1024 * 1024;
+pub const DEFAULT_PROXY_CACHE_TTL_SECS: u64 = 24 * 60 * 60;
+// Timeout of outgoing requests, e.g. to webhooks
+pub const WEBHOOK_TIMEOUT_SECS: u64 = 10;
+// Subject prefix image events are published on, if `EVENTS_SUBJECT` is not set
+pub const DEFAULT_EVENTS_SUBJECT: &str = "mensatt.images";
+// Number of events that are queued for publishing, before further events are dropped
+pub const EVENTS_QUEUE_CAPACITY: usize = 1000;
+// Delay before reconnecting to the message broker after the connection was lost
+pub const EVENTS_RECONNECT_DELAY_SECS: u64 = 5;
+// Number of images queued for replication, before further ones are left to the reconciliation
+pub const REPLICATION_QUEUE_CAPACITY: usize = 10000;
+// Retries of failed replications, if `REPLICATION_MAX_RETRIES` is not set
+pub const DEFAULT_REPLICATION_MAX_RETRIES: u32 = 5;
+// Delay before the first retry of a failed replication, doubled for each further retry
+pub const REPLICATION_RETRY_DELAY_SECS: u64 = 1;
+// Timeout of requests to the replication peer, which may transfer whole images
+pub const REPLICATION_TIMEOUT_SECS: u64 = 60;
+// Default interval of the reconciliation with the replication peer
+pub const DEFAULT_REPLICATION_RECONCILE_INTERVAL_SECS: u64 = 60 * 60;
+// Default interval of the check whether too many images are waiting for approval
+pub const DEFAULT_BACKLOG_CHECK_INTERVAL_SECS: u64 = 15 * 60;
+// Default timeout of virus scans of uploads by clamd
+pub const DEFAULT_CLAMD_TIMEOUT_SECS: u64 = 30;
+// Size of the chunks uploads are streamed to clamd in
+pub const CLAMD_CHUNK_SIZE: usize = 64 * 1024;
+// Default interval of the check whether the data volume is nearly full
+pub const DEFAULT_DISK_SPACE_CHECK_INTERVAL_SECS: u64 = 5 * 60;
+// Interval in which the cache and metadata indices are written to disk
+pub const CACHE_INDEX_SAVE_INTERVAL_SECS: u64 = 5 * 60;
+// Requests of images are counted for this long, i.e. the maximum window of `/stats/top`
+pub const ACCESS_STATS_RETENTION_SECS: u64 = 30 * 24 * 60 * 60;
+// Score from 0 to 1 from which the moderation hook flags submitted images, if not configured
+pub const DEFAULT_MODERATION_THRESHOLD: f64 = 0.8;
+// Default timeout of a classification by the moderation hook
+pub const DEFAULT_MODERATION_TIMEOUT_SECS: u64 = 10;
+// Number of classifications of uploads awaiting their submission above which the ones of images
+// that are no longer pending are forgotten
+pub const MAX_MODERATION_VERDICTS: usize = 10_000;
+// Window and number of images of `/stats/top`, if they are not requested
+pub const DEFAULT_TOP_WINDOW_SECS: u64 = 24 * 60 * 60;
+pub const DEFAULT_TOP_LIMIT: usize = 10;
+// Number of days of growth returned by `/stats/disk`, if not requested, and their maximum
+pub const DEFAULT_DISK_GROWTH_DAYS: u32 = 7;
+pub const MAX_DISK_GROWTH_DAYS: u32 = 365;
+// Limits of the metadata fields of uploads (`alt_text`, `capture_source` and `tags`), in characters
+pub const MAX_ALT_TEXT_LENGTH: usize = 1000;
+pub const MAX_CAPTURE_SOURCE_LENGTH: usize = 100;
+pub const MAX_TAGS: usize = 20;
+pub const MAX_TAG_LENGTH: usize = 50;
+// Maximum number of files compared with `SHADOW_READ_PATH` at the same time, further reads are not
+// compared
+pub const SHADOW_READ_CONCURRENCY: usize = 4;
+// Maximum length of the names of tenants, which are part of paths
+pub const MAX_TENANT_NAME_LENGTH: usize = 63;
+// Maximum length of the aliases of images, e.g. `mensa-sued-schnitzel-2024`
+pub const MAX_ALIAS_LENGTH: usize = 100;
+// Number of state changes kept per image in the metadata index, older ones are dropped
+pub const MAX_IMAGE_HISTORY: usize = 50;
 
 // Image paths
+pub const DATA_PATH: [&str; 1] = ["data"]; // Root of all paths below, mirrored by `SHADOW_READ_PATH`
 pub const PENDING_PATH: [&str; 2] = ["data", "pending"]; // Uploaded but Review not yet submitted
 pub const UNAPPROVED_PATH: [&str; 2] = ["data", "unapproved"]; // Submitted, but not yet approved
+pub const FLAGGED_PATH: [&str; 2] = ["data", "flagged"]; // Submitted, but flagged by the moderation hook
 pub const ORIGINAL_PATH: [&str; 2] = ["data", "originals"]; // Approved "original" images (rotated and converted to AVIF)
 pub const CACHE_PATH: [&str; 2] = ["data", "cache"]; // Cache for requests
 pub const RAW_PATH: [&str; 2] = ["data", "raw"]; // Raw images as uploaded
+pub const OBJECTS_PATH: [&str; 2] = ["data", "objects"]; // Approved images by content hash, linked from originals with `CAS_ENABLED`
+pub const PROXY_CACHE_PATH: [&str; 2] = ["data", "proxy"]; // External images fetched by `/proxy`
+pub const CACHE_INDEX_PATH: [&str; 2] = ["data", "cache-index.json"]; // Last access of cache entries
+pub const ACCESS_STATS_PATH: [&str; 2] = ["data", "access-stats.json"]; // Requests and bytes served per hour
+pub const METADATA_INDEX_PATH: [&str; 2] = ["data", "metadata-index.json"]; // Upload and state change times, checksums and metadata of images
+pub const QUARANTINE_PATH: [&str; 2] = ["data", "quarantine"]; // Uploads and files that could not be decoded
+pub const TRASH_PATH: [&str; 2] = ["data", "trash"]; // Deleted images and raw files with `TRASH_ENABLED`
+pub const RUNNING_MARKER_PATH: [&str; 2] = ["data", ".running"]; // Exists while the service is running
+pub const TENANTS_PATH: [&str; 2] = ["data", "tenants"]; // Storage roots of tenants, each with its own pending, unapproved, flagged, originals, cache and raw directories
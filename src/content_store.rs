@@ -0,0 +1,179 @@
+use std::{
+    fs, io,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+};
+
+use uuid::Uuid;
+
+use crate::{
+    fsck::file_checksum,
+    util::{
+        durability::Durability,
+        image::ImageState,
+        path::{commit_temp_file, get_objects_path, get_temp_path, list_files, list_roots},
+    },
+    ServerState,
+};
+
+// Approved images are stored in `data/objects` under their content hash (SHA-256, as recorded in
+// the metadata index), if `CAS_ENABLED` is set. `data/originals/<uuid>.avif` is a hard link to
+// the object, so everything reading originals by ID keeps working, and identical images share
+// one object. Objects are removed once no image links to them anymore.
+
+/// How `link_to_object` stored a file
+#[derive(Clone, Copy, PartialEq)]
+pub enum Linked {
+    // The file became a new object
+    Stored,
+    // The file was replaced by a link to an identical object
+    Deduplicated,
+    // The file was already linked to its object
+    Unchanged,
+}
+
+/// Result of `migrate`
+#[derive(Default)]
+pub struct MigrationReport {
+    pub stored: usize,
+    pub deduplicated: usize,
+    pub unchanged: usize,
+    // Bytes that are no longer stored twice
+    pub saved_bytes: u64,
+}
+
+/// Returns the path of the object with `checksum`
+pub fn object_path(checksum: &str) -> PathBuf {
+    get_objects_path().join(format!("{}.avif", checksum))
+}
+
+/// Stores the approved image `uuid` as object, if `CAS_ENABLED` is set, e.g. after it was
+/// approved or rotated. Images that are not approved are left as they are. Failures are only
+/// logged, as the image is still served from `data/originals`.
+pub fn store_original(uuid: Uuid, server_state: &ServerState) {
+    if !server_state.cas_enabled {
+        return;
+    }
+    let tenant = server_state.metadata_index.tenant(uuid);
+    let path = ImageState::Approved
+        .path(tenant.as_deref())
+        .join(format!("{}.avif", uuid));
+    if !path.is_file() {
+        return;
+    }
+    // Computed from the file instead of taking the recorded one, as the file is replaced by the
+    // object if they match, so a stale or wrong record would replace the image with another one
+    let checksum = match file_checksum(&path) {
+        Err(err) => {
+            log::error!("CAS: Could not compute checksum of {:?}: {}", path, err);
+            return;
+        }
+        Ok(checksum) => checksum,
+    };
+    match server_state.metadata_index.checksum(uuid) {
+        Some(recorded) if recorded == checksum => (),
+        recorded => {
+            if recorded.is_some() {
+                log::warn!("CAS: Recorded checksum of {} does not match its file", uuid);
+            }
+            server_state
+                .metadata_index
+                .record_checksum(uuid, checksum.clone());
+        }
+    }
+
+    match link_to_object(&path, &checksum, server_state.durability) {
+        Err(err) => log::error!("CAS: Could not store {:?} as object: {}", path, err),
+        Ok(Linked::Deduplicated) => {
+            log::info!("CAS: {} is identical to object {}", uuid, checksum)
+        }
+        Ok(_) => (),
+    }
+}
+
+/// Removes the object with `checksum`, if no image links to it anymore, e.g. after the image was
+/// deleted or rotated. Without `checksum` (e.g. of images written before checksums were
+/// recorded) or if no such object exists, nothing happens.
+pub fn release_object(checksum: Option<String>) {
+    let Some(checksum) = checksum else {
+        return;
+    };
+    let path = object_path(&checksum);
+    match fs::metadata(&path) {
+        Ok(metadata) if metadata.nlink() == 1 => match fs::remove_file(&path) {
+            Err(err) => log::error!("CAS: Could not remove {:?}: {}", path, err),
+            Ok(_) => log::info!("CAS: Removed unreferenced object {}", checksum),
+        },
+        _ => (),
+    }
+}
+
+/// Makes the file at `path`, whose content has the SHA-256 `checksum`, a hard link to the object
+/// with that checksum. If no such object exists yet, the file becomes the object; otherwise it is
+/// replaced by a link to the existing object.
+pub fn link_to_object(
+    path: &Path,
+    checksum: &str,
+    durability: Durability,
+) -> Result<Linked, io::Error> {
+    let object = object_path(checksum);
+    let file = fs::metadata(path)?;
+    match fs::metadata(&object) {
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            fs::hard_link(path, &object)?;
+            durability.sync_parent_dir(&object)?;
+            Ok(Linked::Stored)
+        }
+        Err(err) => Err(err),
+        Ok(existing) if existing.ino() == file.ino() && existing.dev() == file.dev() => {
+            Ok(Linked::Unchanged)
+        }
+        Ok(_) => {
+            // Linked to the temporary file first, so `path` is replaced atomically
+            let tmp_path = get_temp_path(path);
+            let _ = fs::remove_file(&tmp_path);
+            fs::hard_link(&object, &tmp_path)?;
+            if let Err(err) = commit_temp_file(path, durability) {
+                let _ = fs::remove_file(&tmp_path);
+                return Err(err);
+            }
+            Ok(Linked::Deduplicated)
+        }
+    }
+}
+
+/// Stores all approved images in `data/originals` (and the originals of all tenants) as objects,
+/// e.g. when enabling `CAS_ENABLED` for an existing data directory. Can be run repeatedly; images
+/// that are already stored as objects are skipped. The service should not be running meanwhile.
+pub fn migrate(durability: Durability) -> Result<MigrationReport, String> {
+    let objects = get_objects_path();
+    fs::create_dir_all(&objects)
+        .map_err(|err| format!("Could not create {:?}: {}", objects, err))?;
+
+    let mut report = MigrationReport::default();
+    for tenant in list_roots() {
+        let originals = ImageState::Approved.path(tenant.as_deref());
+        let names = list_files(&originals)
+            .map_err(|err| format!("Could not list {:?}: {}", originals, err))?;
+
+        for name in names {
+            if !name.ends_with(".avif") {
+                continue;
+            }
+            let path = originals.join(&name);
+            let checksum = file_checksum(&path)
+                .map_err(|err| format!("Could not compute checksum of {:?}: {}", path, err))?;
+            match link_to_object(&path, &checksum, durability)
+                .map_err(|err| format!("Could not store {:?} as object: {}", path, err))?
+            {
+                Linked::Stored => report.stored += 1,
+                Linked::Unchanged => report.unchanged += 1,
+                Linked::Deduplicated => {
+                    report.deduplicated += 1;
+                    report.saved_bytes += fs::metadata(&path).map(|m| m.len()).unwrap_or(0);
+                }
+            }
+        }
+    }
+    Ok(report)
+}
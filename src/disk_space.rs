@@ -0,0 +1,113 @@
+use axum::http::StatusCode;
+use config::Config;
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use crate::{
+    constants::DEFAULT_DISK_SPACE_CHECK_INTERVAL_SECS,
+    scheduler::{parse_job_schedule, Job, Scheduler},
+    util::path::{available_space, get_raw_path},
+    ServerState,
+};
+
+/// Parses the free space on the data volume below which uploads are rejected from the config
+/// property `MIN_FREE_DISK_BYTES`. Returns `None` if it is not set, i.e. uploads are not checked.
+pub fn parse_min_free_disk_bytes(config: &Config) -> Option<u64> {
+    config.get::<u64>("MIN_FREE_DISK_BYTES").ok()
+}
+
+/// Returns the bytes available on the data volume. Raw files are the largest files written per
+/// upload, so their directory is checked. The roots of tenants are on the same volume.
+pub fn available_data_space() -> Result<u64, String> {
+    available_space(&get_raw_path(None)).map_err(|err| {
+        format!(
+            "Could not determine the free space on the data volume: {}",
+            err
+        )
+    })
+}
+
+/// Returns 507 (Insufficient Storage) if less than `MIN_FREE_DISK_BYTES` are available on the
+/// data volume, so uploads fail before anything is written instead of leaving partial files.
+/// If `MIN_FREE_DISK_BYTES` is not set or the free space cannot be determined, uploads are
+/// accepted.
+pub fn check_free_space(server_state: &ServerState) -> Result<(), (StatusCode, String)> {
+    let Some(minimum) = server_state.min_free_disk_bytes else {
+        return Ok(());
+    };
+    let available = match available_data_space() {
+        Err(err) => {
+            log::error!("{}", err);
+            return Ok(());
+        }
+        Ok(available) => available,
+    };
+    if available < minimum {
+        log::warn!(
+            "Rejecting upload, as only {}B are available on the data volume (minimum: {}B)",
+            available,
+            minimum
+        );
+        return Err((
+            StatusCode::INSUFFICIENT_STORAGE,
+            "Not enough storage space left!".to_owned(),
+        ));
+    }
+    Ok(())
+}
+
+/// Schedules the regular check of the free space on the data volume, if `MIN_FREE_DISK_BYTES` is
+/// set. Operators are notified once it drops below the minimum, and again only after it has risen
+/// above it in between.
+pub fn schedule_disk_space_check(
+    config: &Config,
+    scheduler: &Scheduler,
+    server_state: &ServerState,
+) {
+    let Some(minimum) = server_state.min_free_disk_bytes else {
+        return;
+    };
+    let schedule = parse_job_schedule(
+        config,
+        "DISK_SPACE_CHECK_SCHEDULE",
+        Duration::from_secs(
+            config
+                .get::<u64>("DISK_SPACE_CHECK_INTERVAL_SECS")
+                .unwrap_or(DEFAULT_DISK_SPACE_CHECK_INTERVAL_SECS),
+        ),
+    );
+    let server_state = server_state.clone();
+    let low = Arc::new(AtomicBool::new(false));
+    scheduler.spawn(Job {
+        name: "disk-space-check",
+        schedule: schedule,
+        run: Arc::new(move || check_disk_space(&server_state, minimum, &low)),
+    });
+}
+
+/// Notifies, if the free space on the data volume is below `minimum` (and wasn't already at the
+/// last check)
+fn check_disk_space(
+    server_state: &ServerState,
+    minimum: u64,
+    low: &AtomicBool,
+) -> Result<usize, String> {
+    let available = available_data_space()?;
+
+    let was_low = low.swap(available < minimum, Ordering::Relaxed);
+    if available < minimum && !was_low {
+        server_state.notifier.notify(
+            "Low disk space",
+            &format!(
+                "Only {}B are available on the data volume, uploads are rejected until at least {}B are free",
+                available, minimum
+            ),
+        );
+    }
+    Ok(1)
+}
@@ -0,0 +1,55 @@
+use std::io;
+
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+
+/// Errors of the service. Handlers (and the GraphQL and gRPC APIs) return them as
+/// `(StatusCode, String)`, see the conversion below: client errors with their message, failures
+/// of the service as 500 with a generic message, while the details are only logged.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("{0}")]
+    BadRequest(String),
+    #[error("{0}")]
+    NotFound(String),
+    // The input is well-formed, but exceeds a limit, e.g. of `InputLimits`
+    #[error("{0}")]
+    Unprocessable(String),
+    // A vips operation did not finish in time, see `Watchdog`
+    #[error("{0}")]
+    Timeout(String),
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
+    #[error("libvips error: {0}")]
+    Vips(#[from] libvips::error::Error),
+    #[error("{0}")]
+    Internal(String),
+}
+
+impl From<Error> for (StatusCode, String) {
+    fn from(err: Error) -> Self {
+        match err {
+            Error::BadRequest(message) => (StatusCode::BAD_REQUEST, format!("{}!", message)),
+            Error::NotFound(message) => (StatusCode::NOT_FOUND, format!("{}!", message)),
+            Error::Unprocessable(message) => {
+                (StatusCode::UNPROCESSABLE_ENTITY, format!("{}!", message))
+            }
+            Error::Timeout(message) => (StatusCode::GATEWAY_TIMEOUT, format!("{}!", message)),
+            Error::Io(_) | Error::Vips(_) | Error::Internal(_) => {
+                log::error!("{}", err);
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "An internal error has occurred!".to_owned(),
+                )
+            }
+        }
+    }
+}
+
+impl IntoResponse for Error {
+    fn into_response(self) -> Response {
+        <(StatusCode, String)>::from(self).into_response()
+    }
+}
@@ -0,0 +1,192 @@
+use std::{io, time::Duration};
+
+use chrono::{DateTime, Utc};
+use config::Config;
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::TcpStream,
+    sync::mpsc,
+    time::sleep,
+};
+use uuid::Uuid;
+
+use crate::constants::{
+    DEFAULT_EVENTS_SUBJECT, EVENTS_QUEUE_CAPACITY, EVENTS_RECONNECT_DELAY_SECS,
+};
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize, async_graphql::Enum)]
+#[serde(rename_all = "snake_case")]
+pub enum ImageEventKind {
+    Uploaded,
+    Submitted,
+    // Submitted, but flagged by the moderation hook
+    Flagged,
+    Approved,
+    Unapproved,
+    Deleted,
+}
+
+impl ImageEventKind {
+    /// Name of the event, also used as last token of the subject it is published on
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Uploaded => "uploaded",
+            Self::Submitted => "submitted",
+            Self::Flagged => "flagged",
+            Self::Approved => "approved",
+            Self::Unapproved => "unapproved",
+            Self::Deleted => "deleted",
+        }
+    }
+}
+
+/// A change in the lifecycle of an image, as published to the message queue
+#[derive(Serialize)]
+pub struct ImageEvent {
+    pub event: ImageEventKind,
+    pub id: Uuid,
+    pub time: DateTime<Utc>,
+}
+
+/// Publishes image events to NATS, if `EVENTS_NATS_ADDR` is configured.
+/// Events are sent by a background task, so publishing never blocks a request. They are
+/// delivered at most once: events that occur while the connection is down are dropped.
+#[derive(Clone)]
+pub struct EventPublisher {
+    sender: Option<mpsc::Sender<(String, Vec<u8>)>>,
+    subject: String,
+}
+
+impl EventPublisher {
+    /// Starts publishing to the broker configured by `EVENTS_NATS_ADDR` (`host:port`) on the
+    /// subject configured by `EVENTS_SUBJECT`. Events are dropped if no broker is configured.
+    pub fn start(config: &Config) -> Self {
+        let subject = config
+            .get_string("EVENTS_SUBJECT")
+            .unwrap_or(DEFAULT_EVENTS_SUBJECT.to_owned());
+
+        let sender = match config.get_string("EVENTS_NATS_ADDR") {
+            Err(_) => {
+                log::info!("EVENTS: Not publishing image events (EVENTS_NATS_ADDR not set)");
+                None
+            }
+            Ok(addr) => {
+                log::info!(
+                    "EVENTS: Publishing image events to NATS at {} on '{}.<event>'",
+                    addr,
+                    subject
+                );
+                let (sender, receiver) = mpsc::channel(EVENTS_QUEUE_CAPACITY);
+                tokio::spawn(run_nats_publisher(addr, receiver));
+                Some(sender)
+            }
+        };
+
+        Self {
+            sender: sender,
+            subject: subject,
+        }
+    }
+
+    /// Queues an event of `kind` for the image `id`, which happened just now
+    pub fn publish(&self, kind: ImageEventKind, id: Uuid) {
+        let Some(sender) = &self.sender else {
+            return;
+        };
+
+        let event = ImageEvent {
+            event: kind,
+            id: id,
+            time: Utc::now(),
+        };
+        let payload = match serde_json::to_vec(&event) {
+            Err(err) => {
+                log::error!("EVENTS: Could not serialize event: {}", err);
+                return;
+            }
+            Ok(payload) => payload,
+        };
+
+        let subject = format!("{}.{}", self.subject, kind.name());
+        if sender.try_send((subject, payload)).is_err() {
+            log::warn!(
+                "EVENTS: Dropping '{}' event of {}, as the queue is full",
+                kind.name(),
+                id
+            );
+        }
+    }
+}
+
+/// Sends all queued events to the NATS server at `addr`, reconnecting if the connection is lost
+async fn run_nats_publisher(addr: String, mut events: mpsc::Receiver<(String, Vec<u8>)>) {
+    loop {
+        match publish_until_disconnected(&addr, &mut events).await {
+            // All publishers are gone, i.e. the service is shutting down
+            Ok(()) => return,
+            Err(err) => {
+                log::error!(
+                    "EVENTS: Connection to NATS at {} failed, reconnecting in {}s: {}",
+                    addr,
+                    EVENTS_RECONNECT_DELAY_SECS,
+                    err
+                );
+                sleep(Duration::from_secs(EVENTS_RECONNECT_DELAY_SECS)).await;
+                // Drop the events of the meantime, instead of flooding the broker with stale ones
+                while events.try_recv().is_ok() {}
+            }
+        }
+    }
+}
+
+/// Connects to the NATS server at `addr` and publishes events, until the connection fails.
+/// Only the parts of the (text based) NATS protocol needed for publishing are implemented.
+async fn publish_until_disconnected(
+    addr: &str,
+    events: &mut mpsc::Receiver<(String, Vec<u8>)>,
+) -> Result<(), io::Error> {
+    let (read, mut write) = TcpStream::connect(addr).await?.into_split();
+    let mut lines = BufReader::new(read).lines();
+
+    // The server greets with its INFO first
+    match lines.next_line().await? {
+        Some(line) if line.starts_with("INFO") => (),
+        line => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Expected INFO from server, got {:?}", line),
+            ))
+        }
+    }
+    write
+        .write_all(b"CONNECT {\"verbose\":false,\"pedantic\":false,\"name\":\"mensatt-img\"}\r\n")
+        .await?;
+    log::info!("EVENTS: Connected to NATS at {}", addr);
+
+    loop {
+        tokio::select! {
+            event = events.recv() => {
+                let Some((subject, payload)) = event else {
+                    return Ok(());
+                };
+                let mut message = format!("PUB {} {}\r\n", subject, payload.len()).into_bytes();
+                message.extend(payload);
+                message.extend(b"\r\n");
+                write.write_all(&message).await?;
+            }
+            line = lines.next_line() => match line? {
+                None => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "Connection closed by server",
+                    ))
+                }
+                // The server disconnects clients that don't answer its pings
+                Some(line) if line.starts_with("PING") => write.write_all(b"PONG\r\n").await?,
+                Some(line) if line.starts_with("-ERR") => log::error!("EVENTS: NATS: {}", line),
+                Some(_) => (),
+            },
+        }
+    }
+}
@@ -0,0 +1,106 @@
+use std::{fs, io, path::Path};
+
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::{
+    util::{
+        image::ImageState,
+        path::{list_files, list_roots},
+    },
+    ServerState,
+};
+
+#[derive(Serialize, ToSchema)]
+pub struct ChecksumMismatch {
+    pub uuid: Uuid,
+    pub state: ImageState,
+    // SHA-256 (hex) recorded when the image was written and of the file as it is now
+    pub expected: String,
+    pub actual: String,
+}
+
+#[derive(Default, Serialize, ToSchema)]
+pub struct FsckReport {
+    // Number of images whose checksum was compared
+    pub checked: usize,
+    // Images without a recorded checksum (stored before checksums were introduced), whose
+    // current checksum was recorded instead
+    pub recorded: usize,
+    pub mismatches: Vec<ChecksumMismatch>,
+}
+
+/// Returns the SHA-256 (hex) of the file at `path`
+pub fn file_checksum(path: &Path) -> Result<String, io::Error> {
+    Ok(format!("{:x}", Sha256::digest(fs::read(path)?)))
+}
+
+/// Records the checksum of the image with `uuid` stored at `path` after it was written, so
+/// `check_checksums` can detect later corruption. Failures are only logged.
+pub fn record_checksum(uuid: Uuid, path: &Path, server_state: &ServerState) {
+    match file_checksum(path) {
+        Err(err) => log::error!("Could not compute checksum of {:?}: {}", path, err),
+        Ok(checksum) => server_state.metadata_index.record_checksum(uuid, checksum),
+    }
+}
+
+/// Hashes all images again and compares them to the checksums recorded when they were written,
+/// to detect silent corruption of the storage. Images are locked while they are hashed, so
+/// concurrent rotations aren't reported as mismatches.
+pub fn check_checksums(server_state: &ServerState) -> Result<FsckReport, String> {
+    let mut report = FsckReport::default();
+
+    let dirs = list_roots()
+        .into_iter()
+        .flat_map(|tenant| ImageState::ALL.map(|state| (state, state.path(tenant.as_deref()))));
+    for (state, dir) in dirs {
+        let names = list_files(&dir)
+            .map_err(|err| format!("Unable to read directory {:?}: {}", dir, err))?;
+
+        for name in names {
+            // Unexpected files are reported by the consistency check
+            let Some(uuid) = name
+                .strip_suffix(".avif")
+                .and_then(|uuid| Uuid::parse_str(uuid).ok())
+            else {
+                continue;
+            };
+
+            let _lock = server_state.image_locks.blocking_lock(uuid);
+            let path = dir.join(&name);
+            let actual = match file_checksum(&path) {
+                // Deleted or moved to another state since the directory was listed
+                Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+                Err(err) => return Err(format!("Unable to read {:?}: {}", path, err)),
+                Ok(actual) => actual,
+            };
+
+            report.checked += 1;
+            match server_state.metadata_index.checksum(uuid) {
+                None => {
+                    server_state.metadata_index.record_checksum(uuid, actual);
+                    report.recorded += 1;
+                }
+                Some(expected) if expected != actual => {
+                    log::warn!(
+                        "FSCK: Checksum of {:?} does not match (expected {}, actual {})",
+                        path,
+                        expected,
+                        actual
+                    );
+                    report.mismatches.push(ChecksumMismatch {
+                        uuid: uuid,
+                        state: state,
+                        expected: expected,
+                        actual: actual,
+                    });
+                }
+                Some(_) => (),
+            }
+        }
+    }
+
+    Ok(report)
+}
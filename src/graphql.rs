@@ -0,0 +1,243 @@
+use std::{collections::HashMap, sync::Mutex};
+
+use async_graphql::{Context, EmptySubscription, Error, Object, Result, Schema, SimpleObject};
+use axum::http::StatusCode;
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+use crate::{
+    events::ImageEventKind,
+    operations::{approve_image, delete_image_everywhere, submit_image, unapprove_image},
+    util::{
+        image::{
+            determine_img_dim, find_stored_image, list_cache_variants, list_images, CacheVariant,
+            ImageState, RemovalBehavior, StoredImage,
+        },
+        listing::{ListQuery, SortKey, SortOrder},
+        path::{dir_usage, get_cache_path, list_roots},
+        short_id::to_short_id,
+    },
+    ServerState,
+};
+
+/// GraphQL schema for moderation tooling, served at `/graphql`
+pub type ImageSchema = Schema<QueryRoot, MutationRoot, EmptySubscription>;
+
+pub fn build_schema() -> ImageSchema {
+    Schema::build(QueryRoot, MutationRoot, EmptySubscription).finish()
+}
+
+/// Cached renditions of all images, listed at most once per request instead of once per image.
+/// Has to be added to the data of each request.
+#[derive(Default)]
+pub struct CacheListing(Mutex<Option<HashMap<Uuid, Vec<CacheVariant>>>>);
+
+pub struct QueryRoot;
+
+#[Object]
+impl QueryRoot {
+    /// Images, filtered, sorted and paginated like the listing endpoints of the HTTP API
+    #[allow(clippy::too_many_arguments)]
+    async fn images(
+        &self,
+        ctx: &Context<'_>,
+        state: Option<ImageState>,
+        after: Option<DateTime<Utc>>,
+        before: Option<DateTime<Utc>>,
+        changed_after: Option<DateTime<Utc>>,
+        changed_before: Option<DateTime<Utc>>,
+        sort: Option<SortKey>,
+        order: Option<SortOrder>,
+        limit: Option<usize>,
+        offset: Option<usize>,
+    ) -> Result<ImagePage> {
+        let query = ListQuery {
+            limit: limit,
+            offset: offset,
+            sort: sort,
+            order: order,
+            state: state,
+            after: after,
+            before: before,
+            changed_after: changed_after,
+            changed_before: changed_before,
+        };
+
+        let metadata_index = &ctx.data_unchecked::<ServerState>().metadata_index;
+        let images = list_images(&query.states(), metadata_index).map_err(|err| {
+            log::error!("Error while listing images: {}", err);
+            Error::new("Error while listing images!")
+        })?;
+        let page = query.apply(images).map_err(to_error)?;
+
+        Ok(ImagePage {
+            items: page.items.into_iter().map(Image).collect(),
+            total: page.total,
+            limit: page.limit,
+            offset: page.offset,
+        })
+    }
+
+    /// The image with `id`, if it exists in any state
+    async fn image(&self, ctx: &Context<'_>, id: Uuid) -> Option<Image> {
+        let metadata_index = &ctx.data_unchecked::<ServerState>().metadata_index;
+        find_stored_image(id, metadata_index).map(Image)
+    }
+
+    /// Number and total size of all cache entries
+    async fn cache_stats(&self, ctx: &Context<'_>) -> Result<CacheStats> {
+        let (mut entries, mut bytes) = (0, 0);
+        for tenant in list_roots() {
+            let (root_entries, root_bytes) = dir_usage(&get_cache_path(tenant.as_deref()))
+                .map_err(|err| {
+                    log::error!("Error while listing cache entries: {}", err);
+                    Error::new("Error while getting cache stats!")
+                })?;
+            entries += root_entries;
+            bytes += root_bytes;
+        }
+
+        Ok(CacheStats {
+            entries: entries,
+            bytes: bytes,
+            tracked_entries: ctx.data_unchecked::<ServerState>().cache_index.len(),
+        })
+    }
+}
+
+pub struct MutationRoot;
+
+#[Object]
+impl MutationRoot {
+    async fn submit(&self, ctx: &Context<'_>, id: Uuid) -> Result<Uuid> {
+        let server_state = ctx.data_unchecked::<ServerState>();
+        submit_image(id, server_state).await.map_err(to_error)?;
+        Ok(id)
+    }
+
+    async fn approve(&self, ctx: &Context<'_>, id: Uuid) -> Result<Uuid> {
+        let server_state = ctx.data_unchecked::<ServerState>();
+        approve_image(id, server_state).await.map_err(to_error)?;
+        Ok(id)
+    }
+
+    async fn unapprove(&self, ctx: &Context<'_>, id: Uuid) -> Result<Uuid> {
+        let server_state = ctx.data_unchecked::<ServerState>();
+        unapprove_image(id, server_state).await.map_err(to_error)?;
+        Ok(id)
+    }
+
+    /// Deletes the image from all states and the cache
+    async fn delete(&self, ctx: &Context<'_>, id: Uuid) -> Result<Uuid> {
+        let server_state = ctx.data_unchecked::<ServerState>();
+        delete_image_everywhere(id, RemovalBehavior::Delete, server_state)
+            .await
+            .map_err(to_error)?;
+        Ok(id)
+    }
+}
+
+#[derive(SimpleObject)]
+pub struct ImagePage {
+    items: Vec<Image>,
+    total: usize,
+    limit: usize,
+    offset: usize,
+}
+
+pub struct Image(StoredImage);
+
+#[Object]
+impl Image {
+    async fn id(&self) -> Uuid {
+        self.0.uuid
+    }
+
+    /// Shorter alternative to the ID, accepted by `/image/:id`
+    async fn short_id(&self) -> String {
+        to_short_id(self.0.uuid)
+    }
+
+    async fn state(&self) -> ImageState {
+        self.0.state
+    }
+
+    /// Time of the upload
+    async fn created_at(&self) -> DateTime<Utc> {
+        self.0.created_at.into()
+    }
+
+    /// Time the image entered its current state, e.g. was approved
+    async fn state_changed_at(&self) -> DateTime<Utc> {
+        self.0.state_changed_at.into()
+    }
+
+    /// Dimensions of the image (as stored, not of a rendition)
+    async fn dimensions(&self) -> Result<Dimensions> {
+        let path = self
+            .0
+            .state
+            .path(self.0.tenant.as_deref())
+            .join(format!("{}.avif", self.0.uuid));
+        let (width, height) = determine_img_dim(path.to_str().unwrap())
+            .map_err(|_| Error::new("Error while getting image dimensions!"))?;
+        Ok(Dimensions {
+            width: width,
+            height: height,
+        })
+    }
+
+    /// Cached renditions of the image
+    async fn cache_variants(&self, ctx: &Context<'_>) -> Result<Vec<CacheVariant>> {
+        let mut listing = ctx.data_unchecked::<CacheListing>().0.lock().unwrap();
+        if listing.is_none() {
+            *listing = Some(list_cache_variants().map_err(|err| {
+                log::error!("Error while listing cache entries: {}", err);
+                Error::new("Error while listing cache entries!")
+            })?);
+        }
+        Ok(listing
+            .as_ref()
+            .and_then(|variants| variants.get(&self.0.uuid))
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    /// Changes of the state of the image, oldest first. Only the last 50 are kept, and none
+    /// from before the history was introduced.
+    async fn history(&self, ctx: &Context<'_>) -> Vec<HistoryEntry> {
+        let metadata_index = &ctx.data_unchecked::<ServerState>().metadata_index;
+        metadata_index
+            .history(self.0.uuid)
+            .into_iter()
+            .map(|(event, time)| HistoryEntry {
+                event: event,
+                time: time.into(),
+            })
+            .collect()
+    }
+}
+
+#[derive(SimpleObject)]
+pub struct HistoryEntry {
+    event: ImageEventKind,
+    time: DateTime<Utc>,
+}
+
+#[derive(SimpleObject)]
+pub struct Dimensions {
+    width: i32,
+    height: i32,
+}
+
+#[derive(SimpleObject)]
+pub struct CacheStats {
+    entries: usize,
+    bytes: u64,
+    // Entries whose last access is known
+    tracked_entries: usize,
+}
+
+fn to_error((_, message): (StatusCode, String)) -> Error {
+    Error::new(message)
+}
@@ -0,0 +1,230 @@
+// Service methods have to return the (large) tonic::Status, so helpers do the same
+#![allow(clippy::result_large_err)]
+
+use std::{io, net::SocketAddr, str::FromStr};
+
+use axum::{body::Bytes, http::StatusCode};
+use config::Config;
+use tokio_util::sync::CancellationToken;
+use tonic::{transport::Server, Request, Response, Status};
+use uuid::Uuid;
+
+use crate::{
+    constants::CONTENT_LENGTH_LIMIT,
+    operations::{
+        approve_image, delete_image_everywhere, image_info, submit_image, unapprove_image,
+        upload_image,
+    },
+    util::{
+        auth::check_auth_key,
+        image::{find_image, list_cache_variants, ImageState, RemovalBehavior},
+        image_metadata::ImageMetadata,
+    },
+    ServerState,
+};
+
+use proto::{
+    image_service_server::{ImageService, ImageServiceServer},
+    CacheVariant, ExistsResponse, ImageId, ImageInfo, UploadRequest,
+};
+
+pub mod proto {
+    tonic::include_proto!("mensatt.image.v1");
+}
+
+/// Parses the address of the gRPC server from the optional config property `GRPC_LISTEN_ADDR`
+pub fn parse_grpc_listen_addr(config: &Config) -> Option<SocketAddr> {
+    let value = config.get_string("GRPC_LISTEN_ADDR").ok()?;
+    match SocketAddr::from_str(&value) {
+        Err(err) => {
+            log::error!("Invalid GRPC_LISTEN_ADDR '{}': {}", value, err);
+            None
+        }
+        Ok(addr) => Some(addr),
+    }
+}
+
+/// Serves the gRPC API on `addr` until `shutdown` is cancelled
+pub async fn serve_grpc(
+    addr: SocketAddr,
+    server_state: ServerState,
+    shutdown: CancellationToken,
+) -> Result<(), io::Error> {
+    log::info!("gRPC: Listening on {}", addr);
+    let service = ImageServiceServer::new(GrpcService { server_state })
+        // Leave some room for the other fields of an upload
+        .max_decoding_message_size(CONTENT_LENGTH_LIMIT + 1024);
+
+    Server::builder()
+        .add_service(service)
+        .serve_with_shutdown(addr, shutdown.cancelled_owned())
+        .await
+        .map_err(io::Error::other)
+}
+
+struct GrpcService {
+    server_state: ServerState,
+}
+
+impl GrpcService {
+    /// Checks the API key in the `authorization: Bearer <key>` metadata of `request`
+    fn authorize<T>(&self, request: &Request<T>) -> Result<(), Status> {
+        let key = request
+            .metadata()
+            .get("authorization")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .ok_or_else(|| Status::unauthenticated("Authorization failed!"))?;
+
+        check_auth_key(
+            key.as_bytes(),
+            &self.server_state.reloadable().api_key_hashes,
+        )
+        .map(|_| ())
+        .map_err(to_status)
+    }
+}
+
+#[tonic::async_trait]
+impl ImageService for GrpcService {
+    async fn upload(&self, request: Request<UploadRequest>) -> Result<Response<ImageId>, Status> {
+        self.authorize(&request)?;
+        let request = request.into_inner();
+        log::info!("gRPC: Received upload with size {}B", request.data.len());
+
+        let uuid = upload_image(
+            &Bytes::from(request.data),
+            request.angle,
+            ImageMetadata::default(),
+            None,
+            &self.server_state,
+        )
+        .await
+        .map_err(to_status)?;
+        Ok(Response::new(ImageId {
+            id: uuid.to_string(),
+        }))
+    }
+
+    async fn submit(&self, request: Request<ImageId>) -> Result<Response<ImageId>, Status> {
+        self.authorize(&request)?;
+        let uuid = parse_id(request.get_ref())?;
+        submit_image(uuid, &self.server_state)
+            .await
+            .map_err(to_status)?;
+        Ok(Response::new(request.into_inner()))
+    }
+
+    async fn approve(&self, request: Request<ImageId>) -> Result<Response<ImageId>, Status> {
+        self.authorize(&request)?;
+        let uuid = parse_id(request.get_ref())?;
+        approve_image(uuid, &self.server_state)
+            .await
+            .map_err(to_status)?;
+        Ok(Response::new(request.into_inner()))
+    }
+
+    async fn unapprove(&self, request: Request<ImageId>) -> Result<Response<ImageId>, Status> {
+        self.authorize(&request)?;
+        let uuid = parse_id(request.get_ref())?;
+        unapprove_image(uuid, &self.server_state)
+            .await
+            .map_err(to_status)?;
+        Ok(Response::new(request.into_inner()))
+    }
+
+    async fn delete(&self, request: Request<ImageId>) -> Result<Response<ImageId>, Status> {
+        self.authorize(&request)?;
+        let uuid = parse_id(request.get_ref())?;
+        delete_image_everywhere(uuid, RemovalBehavior::Delete, &self.server_state)
+            .await
+            .map_err(to_status)?;
+        Ok(Response::new(request.into_inner()))
+    }
+
+    async fn get_info(&self, request: Request<ImageId>) -> Result<Response<ImageInfo>, Status> {
+        self.authorize(&request)?;
+        let uuid = parse_id(request.get_ref())?;
+
+        let cache_variants = list_cache_variants().map_err(|err| {
+            log::error!("Error while listing cache entries: {}", err);
+            Status::internal("Error while getting image info!")
+        })?;
+        let info = image_info(
+            uuid,
+            true,
+            &cache_variants,
+            &self.server_state.metadata_index,
+        )
+        .ok_or_else(|| Status::not_found("Image not found!"))?;
+
+        Ok(Response::new(ImageInfo {
+            id: uuid.to_string(),
+            state: proto::ImageState::from(info.state).into(),
+            width: info.width,
+            height: info.height,
+            cache_variants: info
+                .cache_variants
+                .into_iter()
+                .map(|variant| CacheVariant {
+                    width: variant.width,
+                    height: variant.height,
+                    quality: variant.quality,
+                    pipeline: variant.pipeline.unwrap_or_default(),
+                    format: variant.format.extension().to_owned(),
+                    progressive: variant.progressive,
+                    color_profile: variant.color_profile.name().to_owned(),
+                })
+                .collect(),
+        }))
+    }
+
+    async fn exists(&self, request: Request<ImageId>) -> Result<Response<ExistsResponse>, Status> {
+        self.authorize(&request)?;
+        let uuid = parse_id(request.get_ref())?;
+
+        let tenant = self.server_state.metadata_index.tenant(uuid);
+        Ok(Response::new(match find_image(uuid, tenant.as_deref()) {
+            None => ExistsResponse {
+                exists: false,
+                state: proto::ImageState::Unspecified.into(),
+            },
+            Some((state, _)) => ExistsResponse {
+                exists: true,
+                state: proto::ImageState::from(state).into(),
+            },
+        }))
+    }
+}
+
+impl From<ImageState> for proto::ImageState {
+    fn from(state: ImageState) -> Self {
+        match state {
+            ImageState::Pending => proto::ImageState::Pending,
+            ImageState::Unapproved => proto::ImageState::Unapproved,
+            ImageState::Flagged => proto::ImageState::Flagged,
+            ImageState::Approved => proto::ImageState::Approved,
+        }
+    }
+}
+
+fn parse_id(image_id: &ImageId) -> Result<Uuid, Status> {
+    Uuid::parse_str(&image_id.id).map_err(|_| Status::invalid_argument("Invalid ID!"))
+}
+
+/// Converts an error of the shared operations to the corresponding gRPC status
+fn to_status((status_code, message): (StatusCode, String)) -> Status {
+    match status_code {
+        StatusCode::BAD_REQUEST | StatusCode::UNPROCESSABLE_ENTITY => {
+            Status::invalid_argument(message)
+        }
+        StatusCode::SERVICE_UNAVAILABLE => Status::unavailable(message),
+        StatusCode::UNAUTHORIZED => Status::unauthenticated(message),
+        StatusCode::NOT_FOUND => Status::not_found(message),
+        StatusCode::CONFLICT => Status::already_exists(message),
+        StatusCode::PAYLOAD_TOO_LARGE | StatusCode::INSUFFICIENT_STORAGE => {
+            Status::resource_exhausted(message)
+        }
+        _ => Status::internal(message),
+    }
+}
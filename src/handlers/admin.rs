@@ -0,0 +1,34 @@
+use crate::{util::auth::check_auth, ServerState};
+
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::Html,
+};
+use axum_extra::{
+    headers::{authorization::Bearer, Authorization},
+    TypedHeader,
+};
+use serde::Deserialize;
+
+#[derive(Deserialize)]
+pub struct AdminQuery {
+    auth: Option<String>,
+}
+
+/// Serves the admin page for reviewing pending and unapproved images.
+/// Like for `/docs`, the API key has to be passed as `?auth=` query parameter.
+/// The page uses it to call the existing endpoints.
+pub async fn admin_handler(
+    State(server_state): State<ServerState>,
+    authorization_header_opt: Option<TypedHeader<Authorization<Bearer>>>,
+    query: Query<AdminQuery>,
+) -> Result<Html<&'static str>, (StatusCode, String)> {
+    check_auth(
+        query.auth.as_ref(),
+        authorization_header_opt,
+        &server_state.reloadable().api_key_hashes,
+    )?;
+
+    Ok(Html(include_str!("../admin.html")))
+}
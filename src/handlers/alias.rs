@@ -0,0 +1,154 @@
+use crate::{
+    constants::MAX_ALIAS_LENGTH,
+    util::{
+        auth::check_auth_header, image::find_stored_image, metadata_index::MetadataIndex,
+        short_id::parse_image_id,
+    },
+    ServerState,
+};
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use axum_extra::{
+    headers::{authorization::Bearer, Authorization},
+    TypedHeader,
+};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+/// Human-readable name of an image, which can be used instead of its ID at `/image/:id`
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct ImageAlias {
+    /// Lowercase letters, digits and dashes, e.g. `mensa-sued-schnitzel-2024`
+    alias: String,
+}
+
+/// Returns the image `id` refers to: its UUID, its short ID or its alias.
+/// Returns 400 if it is neither and 404 if no image has the alias.
+pub fn resolve_image_id(
+    id: &str,
+    metadata_index: &MetadataIndex,
+) -> Result<Uuid, (StatusCode, String)> {
+    if let Some(uuid) = parse_image_id(id) {
+        return Ok(uuid);
+    }
+    if validate_alias(id).is_err() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("Invalid image ID '{}'!", id),
+        ));
+    }
+    metadata_index
+        .resolve_alias(id)
+        .ok_or_else(|| (StatusCode::NOT_FOUND, "Image not found!".to_owned()))
+}
+
+/// Checks that `alias` consists of lowercase letters, digits and dashes and can't be mistaken for
+/// an ID
+fn validate_alias(alias: &str) -> Result<(), String> {
+    if alias.is_empty() || alias.len() > MAX_ALIAS_LENGTH {
+        return Err(format!(
+            "Aliases must have between 1 and {} characters",
+            MAX_ALIAS_LENGTH
+        ));
+    }
+    if !alias
+        .bytes()
+        .all(|byte| byte.is_ascii_lowercase() || byte.is_ascii_digit() || byte == b'-')
+    {
+        return Err("Aliases may only contain lowercase letters, digits and dashes".to_owned());
+    }
+    if parse_image_id(alias).is_some() {
+        return Err("Aliases must not be UUIDs or short IDs".to_owned());
+    }
+    Ok(())
+}
+
+/// Assigns an alias to the image, e.g. for stable pretty URLs like
+/// `/image/mensa-sued-schnitzel-2024`. An image has at most one alias, a previous one is replaced
+/// (and no longer resolved). Aliases are removed with the image.
+#[utoipa::path(
+    put,
+    path = "/image/{id}/alias",
+    tag = "images",
+    params(("id" = Uuid, Path, description = "ID of the image")),
+    request_body = ImageAlias,
+    responses(
+        (status = 200, description = "The assigned alias", body = ImageAlias),
+        (status = 400, description = "Invalid ID or alias"),
+        (status = 401, description = "Missing or invalid API key"),
+        (status = 404, description = "Image not found"),
+        (status = 409, description = "Alias is assigned to another image"),
+    ),
+    security(("api_key" = []))
+)]
+pub async fn alias_update_handler(
+    State(server_state): State<ServerState>,
+    TypedHeader(authorization): TypedHeader<Authorization<Bearer>>,
+    Path(id): Path<Uuid>,
+    Json(request): Json<ImageAlias>,
+) -> Result<Json<ImageAlias>, (StatusCode, String)> {
+    check_auth_header(authorization, &server_state.reloadable().api_key_hashes)?;
+
+    if id.is_nil() {
+        return Err((StatusCode::BAD_REQUEST, "Invalid ID!".to_owned()));
+    }
+    validate_alias(&request.alias).map_err(|err| (StatusCode::BAD_REQUEST, format!("{}!", err)))?;
+
+    // Serialized with state changes, so no alias is assigned to a deleted image
+    let _lock = server_state.image_locks.lock(id).await;
+    if find_stored_image(id, &server_state.metadata_index).is_none() {
+        return Err((StatusCode::NOT_FOUND, "Image not found!".to_owned()));
+    }
+    match server_state
+        .metadata_index
+        .set_alias(id, request.alias.clone())
+    {
+        Err(owner) => {
+            return Err((
+                StatusCode::CONFLICT,
+                format!("'{}' is already the alias of {}!", request.alias, owner),
+            ))
+        }
+        Ok(Some(previous)) => log::info!(
+            "Replaced alias '{}' of {} with '{}'",
+            previous,
+            id,
+            request.alias
+        ),
+        Ok(None) => log::info!("Assigned alias '{}' to {}", request.alias, id),
+    }
+
+    Ok(Json(request))
+}
+
+/// Removes the alias of the image, so it is no longer resolved
+#[utoipa::path(
+    delete,
+    path = "/image/{id}/alias",
+    tag = "images",
+    params(("id" = Uuid, Path, description = "ID of the image")),
+    responses(
+        (status = 200, description = "The removed alias", body = ImageAlias),
+        (status = 401, description = "Missing or invalid API key"),
+        (status = 404, description = "Image has no alias"),
+    ),
+    security(("api_key" = []))
+)]
+pub async fn alias_delete_handler(
+    State(server_state): State<ServerState>,
+    TypedHeader(authorization): TypedHeader<Authorization<Bearer>>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<ImageAlias>, (StatusCode, String)> {
+    check_auth_header(authorization, &server_state.reloadable().api_key_hashes)?;
+
+    let Some(alias) = server_state.metadata_index.remove_alias(id) else {
+        return Err((StatusCode::NOT_FOUND, "Image has no alias!".to_owned()));
+    };
+    log::info!("Removed alias '{}' of {}", alias, id);
+    Ok(Json(ImageAlias { alias: alias }))
+}
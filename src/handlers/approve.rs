@@ -1,11 +1,4 @@
-use crate::{
-    util::{
-        auth::check_auth_header,
-        image::move_image,
-        path::{get_original_path, get_unapproved_path},
-    },
-    ServerState,
-};
+use crate::{operations::approve_image, util::auth::check_auth_header, ServerState};
 
 use axum::{
     extract::{Path, State},
@@ -18,32 +11,27 @@ use axum_extra::{
 use uuid::Uuid;
 
 // TODO: Add cron pruning
+/// Approves the submitted image, so it is served publicly
+#[utoipa::path(
+    post,
+    path = "/approve/{id}",
+    tag = "images",
+    params(("id" = Uuid, Path, description = "ID of the image")),
+    responses(
+        (status = 200, description = "ID of the approved image", body = String),
+        (status = 400, description = "Invalid ID"),
+        (status = 401, description = "Missing or invalid API key"),
+        (status = 404, description = "Image not found"),
+    ),
+    security(("api_key" = []))
+)]
 pub async fn approve_handler(
     State(server_state): State<ServerState>,
     TypedHeader(authorization): TypedHeader<Authorization<Bearer>>,
     Path(uuid): Path<Uuid>,
 ) -> Result<String, (StatusCode, String)> {
-    check_auth_header(authorization, &server_state.api_key_hashes)?;
-
-    // Check ID
-    if uuid.is_nil() {
-        return Err((StatusCode::BAD_REQUEST, "Invalid ID!".to_owned()));
-    }
+    check_auth_header(authorization, &server_state.reloadable().api_key_hashes)?;
 
-    return match move_image(
-        get_unapproved_path().as_path(),
-        get_original_path().as_path(),
-        uuid,
-    ) {
-        Err(err) => match err.kind() {
-            std::io::ErrorKind::NotFound => {
-                Err((StatusCode::NOT_FOUND, "Image not found!".to_owned()))
-            }
-            _ => Err((
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "Error while approving image!".to_owned(),
-            )),
-        },
-        Ok(_) => Ok(uuid.to_string()),
-    };
+    approve_image(uuid, &server_state).await?;
+    Ok(uuid.to_string())
 }
@@ -0,0 +1,195 @@
+use crate::{
+    error::Error,
+    handlers::image::{image_handler_helper, ImageQuery},
+    util::{
+        auth::check_auth_header,
+        cache_index::CachePin,
+        image::{determine_img_path, validate_rendition, CacheBehavior},
+        path::get_original_path,
+        short_id::ImageIdParam,
+    },
+    ServerState,
+};
+
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use axum_extra::{
+    headers::{authorization::Bearer, Authorization},
+    TypedHeader,
+};
+use serde::Deserialize;
+use utoipa::IntoParams;
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct CacheRegenerateQuery {
+    /// ID (or short ID) of the approved image
+    #[param(value_type = String)]
+    id: ImageIdParam,
+    /// Width of the cached rendition, like at `/image/:id`
+    width: Option<i32>,
+    /// Height of the cached rendition, like at `/image/:id`
+    height: Option<i32>,
+    /// Quality of the cached rendition, like at `/image/:id`
+    quality: Option<i32>,
+}
+
+/// Renders the rendition of an approved image again and overwrites its cache entry, e.g. after an
+/// encoder bug produced artifacts, without purging the other renditions of the image. The
+/// rendition is cached even if it was not before. Returns the new rendition like `/image/:id`;
+/// the image is purged from the configured CDNs.
+#[utoipa::path(
+    post,
+    path = "/cache/regenerate",
+    tag = "admin",
+    params(CacheRegenerateQuery),
+    responses(
+        (status = 200, description = "The regenerated rendition", content_type = "image/webp"),
+        (status = 400, description = "Invalid ID, dimensions or quality"),
+        (status = 401, description = "Missing or invalid API key"),
+        (status = 404, description = "Approved image not found"),
+        (status = 504, description = "Rendering took longer than `VIPS_TIMEOUT_SECS`"),
+    ),
+    security(("api_key" = []))
+)]
+pub async fn cache_regenerate_handler(
+    State(server_state): State<ServerState>,
+    TypedHeader(authorization): TypedHeader<Authorization<Bearer>>,
+    query: Query<CacheRegenerateQuery>,
+) -> Result<Response, (StatusCode, String)> {
+    check_auth_header(authorization, &server_state.reloadable().api_key_hashes)?;
+
+    let ImageIdParam(id) = query.id;
+    validate_rendition(query.width, query.height, query.quality)
+        .map_err(|err| (StatusCode::BAD_REQUEST, format!("{}!", err)))?;
+    // Only approved images are cached
+    let tenant = server_state.metadata_index.tenant(id);
+    let original_path = get_original_path(tenant.as_deref());
+    let Ok(path) = determine_img_path(original_path.to_str().unwrap(), id) else {
+        return Err((
+            StatusCode::NOT_FOUND,
+            format!("No approved image with UUID '{}'!", id),
+        ));
+    };
+
+    let image_query = ImageQuery::with_size(query.width, query.height, query.quality);
+    let (headers, body) = image_handler_helper(
+        id,
+        path.to_str().unwrap(),
+        image_query,
+        CacheBehavior::Refresh,
+        None,
+        &server_state,
+    )
+    .await?;
+
+    log::info!("Regenerated cache entry of {}", id);
+    // CDNs would keep serving the broken rendition otherwise
+    server_state.cdn.purge(&server_state.http_client, id);
+    Ok((headers, body).into_response())
+}
+
+/// Lists the pinned renditions, which are never evicted from the cache
+#[utoipa::path(
+    get,
+    path = "/cache/pins",
+    tag = "admin",
+    responses(
+        (status = 200, description = "Pinned renditions", body = Vec<CachePin>),
+        (status = 401, description = "Missing or invalid API key"),
+    ),
+    security(("api_key" = []))
+)]
+pub async fn cache_pins_handler(
+    State(server_state): State<ServerState>,
+    TypedHeader(authorization): TypedHeader<Authorization<Bearer>>,
+) -> Result<Json<Vec<CachePin>>, (StatusCode, String)> {
+    check_auth_header(authorization, &server_state.reloadable().api_key_hashes)?;
+
+    Ok(Json(server_state.cache_index.pins()))
+}
+
+/// Pins the renditions of an approved image with the given dimensions (as in their cache entry),
+/// e.g. the hero images of the homepage, so cache eviction never removes them. Without `width`
+/// or `height`, renditions of any width or height are pinned. Pins are removed with the image.
+#[utoipa::path(
+    post,
+    path = "/cache/pins",
+    tag = "admin",
+    params(CachePin),
+    responses(
+        (status = 200, description = "ID of the image, whose renditions were already pinned", body = String),
+        (status = 201, description = "ID of the image, whose renditions were pinned", body = String),
+        (status = 400, description = "Invalid ID or dimensions"),
+        (status = 401, description = "Missing or invalid API key"),
+        (status = 404, description = "Approved image not found"),
+    ),
+    security(("api_key" = []))
+)]
+pub async fn cache_pin_handler(
+    State(server_state): State<ServerState>,
+    TypedHeader(authorization): TypedHeader<Authorization<Bearer>>,
+    Query(pin): Query<CachePin>,
+) -> Result<(StatusCode, String), (StatusCode, String)> {
+    check_auth_header(authorization, &server_state.reloadable().api_key_hashes)?;
+
+    validate_rendition(pin.width, pin.height, None)
+        .map_err(|err| (StatusCode::BAD_REQUEST, format!("{}!", err)))?;
+    // Only approved images are cached
+    let tenant = server_state.metadata_index.tenant(pin.id);
+    let original_path = get_original_path(tenant.as_deref());
+    if determine_img_path(original_path.to_str().unwrap(), pin.id).is_err() {
+        return Err((
+            StatusCode::NOT_FOUND,
+            format!("No approved image with UUID '{}'!", pin.id),
+        ));
+    }
+
+    let id = pin.id;
+    let status = match server_state.cache_index.pin(pin) {
+        false => StatusCode::OK,
+        true => {
+            log::info!("Pinned cache entries of {}", id);
+            StatusCode::CREATED
+        }
+    };
+    save_pins(&server_state)?;
+    Ok((status, id.to_string()))
+}
+
+/// Removes a pin, so the renditions are evicted like any others again
+#[utoipa::path(
+    delete,
+    path = "/cache/pins",
+    tag = "admin",
+    params(CachePin),
+    responses(
+        (status = 200, description = "ID of the image, whose renditions were unpinned", body = String),
+        (status = 401, description = "Missing or invalid API key"),
+        (status = 404, description = "No such pin"),
+    ),
+    security(("api_key" = []))
+)]
+pub async fn cache_unpin_handler(
+    State(server_state): State<ServerState>,
+    TypedHeader(authorization): TypedHeader<Authorization<Bearer>>,
+    Query(pin): Query<CachePin>,
+) -> Result<String, (StatusCode, String)> {
+    check_auth_header(authorization, &server_state.reloadable().api_key_hashes)?;
+
+    if !server_state.cache_index.unpin(&pin) {
+        return Err((StatusCode::NOT_FOUND, "Pin not found!".to_owned()));
+    }
+    log::info!("Unpinned cache entries of {}", pin.id);
+    save_pins(&server_state)?;
+    Ok(pin.id.to_string())
+}
+
+/// Writes the cache index right away, so pins don't get lost on a crash
+fn save_pins(server_state: &ServerState) -> Result<(), Error> {
+    server_state.cache_index.save().map_err(Error::Internal)
+}
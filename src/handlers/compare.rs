@@ -0,0 +1,158 @@
+use crate::{
+    constants::{DEFAULT_COMPARE_HEIGHT, MAX_COMPARE_HEIGHT},
+    error::Error,
+    util::short_id::ImageIdParam,
+    util::{
+        auth::check_auth_header,
+        image::{
+            compose_side_by_side, find_image, get_raw_file, validate_rendition, ColorProfile,
+            Encoding, OutputFormat,
+        },
+        pipeline::Pipeline,
+        webp::WebpTuning,
+    },
+    ServerState,
+};
+
+use axum::{
+    extract::{Path, Query, State},
+    http::{header, StatusCode},
+    response::IntoResponse,
+};
+use axum_extra::{
+    headers::{authorization::Bearer, Authorization},
+    TypedHeader,
+};
+use libvips::{ops, VipsImage};
+use serde::Deserialize;
+use utoipa::{IntoParams, ToSchema};
+use uuid::Uuid;
+
+#[derive(Clone, Copy, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum CompareSource {
+    // The stored image in its current state
+    Image,
+    // The raw file as uploaded, rotated according to its EXIF orientation
+    Raw,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct CompareQuery {
+    /// Source of the left side, defaults to `image`
+    left: Option<CompareSource>,
+    /// Source of the right side, defaults to `raw`
+    right: Option<CompareSource>,
+    /// Operations applied to the left side, like `ops` of `/image/:id`
+    left_ops: Option<String>,
+    /// Operations applied to the right side, like `ops` of `/image/:id`
+    right_ops: Option<String>,
+    /// Height of both sides in pixels, defaults to 600
+    height: Option<i32>,
+    /// WebP quality from 1 to 100, defaults to 80
+    quality: Option<i32>,
+}
+
+/// Returns two renditions of an image (in any state) side by side as WebP, e.g. the stored
+/// image next to its raw upload or before and after a crop, so moderators can review edits at
+/// a glance
+#[utoipa::path(
+    get,
+    path = "/image/{id}/compare",
+    tag = "images",
+    params(("id" = String, Path, description = "UUID or short ID of the image"), CompareQuery),
+    responses(
+        (status = 200, description = "Both renditions side by side", content_type = "image/webp", body = Vec<u8>),
+        (status = 400, description = "Invalid ID, height, quality or operations"),
+        (status = 401, description = "Missing or invalid API key"),
+        (status = 404, description = "Image or raw file not found"),
+        (status = 504, description = "Comparing took longer than `VIPS_TIMEOUT_SECS`"),
+    ),
+    security(("api_key" = []))
+)]
+pub async fn compare_handler(
+    State(server_state): State<ServerState>,
+    TypedHeader(authorization): TypedHeader<Authorization<Bearer>>,
+    Path(ImageIdParam(id)): Path<ImageIdParam>,
+    query: Query<CompareQuery>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    check_auth_header(authorization, &server_state.reloadable().api_key_hashes)?;
+
+    if id.is_nil() {
+        return Err((StatusCode::BAD_REQUEST, "Invalid ID!".to_owned()));
+    }
+    validate_rendition(None, query.height, query.quality)
+        .map_err(|err| (StatusCode::BAD_REQUEST, format!("{}!", err)))?;
+    let height = query.height.unwrap_or(DEFAULT_COMPARE_HEIGHT);
+    if height > MAX_COMPARE_HEIGHT {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("height must be at most {}!", MAX_COMPARE_HEIGHT),
+        ));
+    }
+
+    let left = (
+        query.left.unwrap_or(CompareSource::Image),
+        parse_ops(&query.left_ops)?,
+    );
+    let right = (
+        query.right.unwrap_or(CompareSource::Raw),
+        parse_ops(&query.right_ops)?,
+    );
+    let encoding = Encoding {
+        format: OutputFormat::Webp,
+        quality: query.quality.unwrap_or(80),
+        progressive: false,
+        color_profile: ColorProfile::Srgb,
+        webp: WebpTuning::default(),
+    };
+
+    // Timeouts quarantine the stored image, or the raw file of images that were deleted
+    let tenant = server_state.metadata_index.tenant(id);
+    let source = find_image(id, tenant.as_deref())
+        .map(|(_, path)| path)
+        .unwrap_or_else(|| get_raw_file(id, tenant.as_deref()));
+    let body = server_state
+        .watchdog
+        .with_timeout(id, &source, "Comparing", move || {
+            let left = load_side(id, tenant.as_deref(), left)?;
+            let right = load_side(id, tenant.as_deref(), right)?;
+            let composed = compose_side_by_side(left, right, height)?;
+            Ok(encoding.encode(&composed)?)
+        })
+        .await?;
+    Ok(([(header::CONTENT_TYPE, "image/webp")], body))
+}
+
+fn parse_ops(ops: &Option<String>) -> Result<Pipeline, (StatusCode, String)> {
+    match ops {
+        None => Ok(Pipeline::default()),
+        Some(ops) => ops
+            .parse::<Pipeline>()
+            .map_err(|err| (StatusCode::BAD_REQUEST, format!("{}!", err))),
+    }
+}
+
+/// Loads the `source` of the image with `uuid` of `tenant` and applies `pipeline` to it
+fn load_side(
+    uuid: Uuid,
+    tenant: Option<&str>,
+    (source, pipeline): (CompareSource, Pipeline),
+) -> Result<VipsImage, Error> {
+    let image = match source {
+        CompareSource::Image => {
+            let (_, path) = find_image(uuid, tenant)
+                .ok_or_else(|| Error::NotFound("Image not found".to_owned()))?;
+            VipsImage::new_from_file(path.to_str().unwrap())?
+        }
+        CompareSource::Raw => {
+            let path = get_raw_file(uuid, tenant);
+            if !path.exists() {
+                return Err(Error::NotFound("Raw file not found".to_owned()));
+            }
+            ops::autorot(&VipsImage::new_from_file(path.to_str().unwrap())?)?
+        }
+    };
+    Ok(pipeline.apply(image)?)
+}
@@ -0,0 +1,65 @@
+use crate::{
+    consistency::{check_consistency, Inconsistency, RepairBehavior},
+    error::Error,
+    util::auth::check_auth_header,
+    ServerState,
+};
+
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    Json,
+};
+use axum_extra::{
+    headers::{authorization::Bearer, Authorization},
+    TypedHeader,
+};
+use serde::Deserialize;
+use utoipa::IntoParams;
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ConsistencyQuery {
+    /// Also repair inconsistencies that can be fixed safely
+    repair: Option<bool>,
+}
+
+/// Runs the storage consistency check and returns all inconsistencies found.
+/// With `?repair=true`, inconsistencies that can be fixed safely are fixed.
+#[utoipa::path(
+    post,
+    path = "/consistency",
+    tag = "admin",
+    params(ConsistencyQuery),
+    responses(
+        (status = 200, description = "All inconsistencies found", body = Vec<Inconsistency>),
+        (status = 401, description = "Missing or invalid API key"),
+        (status = 500, description = "A data directory could not be read"),
+    ),
+    security(("api_key" = []))
+)]
+pub async fn consistency_handler(
+    State(server_state): State<ServerState>,
+    TypedHeader(authorization): TypedHeader<Authorization<Bearer>>,
+    query: Query<ConsistencyQuery>,
+) -> Result<Json<Vec<Inconsistency>>, (StatusCode, String)> {
+    check_auth_header(authorization, &server_state.reloadable().api_key_hashes)?;
+
+    let repair_behavior = match query.repair {
+        Some(true) => RepairBehavior::Repair,
+        _ => RepairBehavior::ReportOnly,
+    };
+
+    // Scanning all directories may take a while, so don't block the runtime
+    let res =
+        tokio::task::spawn_blocking(move || check_consistency(&server_state, repair_behavior))
+            .await;
+
+    match res {
+        Ok(Ok(inconsistencies)) => Ok(Json(inconsistencies)),
+        Ok(Err(err)) => {
+            Err(Error::Internal(format!("Error during consistency check: {}", err)).into())
+        }
+        Err(err) => Err(Error::Internal(format!("Consistency check panicked: {}", err)).into()),
+    }
+}
@@ -0,0 +1,51 @@
+use crate::{openapi::ApiDoc, util::auth::check_auth, ServerState};
+
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::Html,
+    Json,
+};
+use axum_extra::{
+    headers::{authorization::Bearer, Authorization},
+    TypedHeader,
+};
+use serde::Deserialize;
+use utoipa::OpenApi;
+
+#[derive(Deserialize)]
+pub struct DocsQuery {
+    auth: Option<String>,
+}
+
+/// Serves the OpenAPI specification of this service
+pub async fn openapi_handler(
+    State(server_state): State<ServerState>,
+    authorization_header_opt: Option<TypedHeader<Authorization<Bearer>>>,
+    query: Query<DocsQuery>,
+) -> Result<Json<utoipa::openapi::OpenApi>, (StatusCode, String)> {
+    check_auth(
+        query.auth.as_ref(),
+        authorization_header_opt,
+        &server_state.reloadable().api_key_hashes,
+    )?;
+
+    Ok(Json(ApiDoc::openapi()))
+}
+
+/// Serves Swagger UI for the OpenAPI specification.
+/// As browsers can't send the Authorization header when opening a page, the API key has to be
+/// passed as `?auth=` query parameter. It is also used to authorize the requests sent from the UI.
+pub async fn docs_handler(
+    State(server_state): State<ServerState>,
+    authorization_header_opt: Option<TypedHeader<Authorization<Bearer>>>,
+    query: Query<DocsQuery>,
+) -> Result<Html<&'static str>, (StatusCode, String)> {
+    check_auth(
+        query.auth.as_ref(),
+        authorization_header_opt,
+        &server_state.reloadable().api_key_hashes,
+    )?;
+
+    Ok(Html(include_str!("../docs.html")))
+}
@@ -0,0 +1,205 @@
+use crate::{
+    constants::EXPORT_BUFFER_SIZE,
+    error::Error,
+    util::{
+        auth::check_auth_header,
+        image::{get_raw_file, list_images, ImageState, StoredImage},
+        image_metadata::ImageMetadata,
+    },
+    ServerState,
+};
+
+use axum::{
+    body::Body,
+    extract::{Query, State},
+    http::{header, StatusCode},
+    response::IntoResponse,
+};
+use axum_extra::{
+    headers::{authorization::Bearer, Authorization},
+    TypedHeader,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    io::{self, Write},
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tokio_util::io::{ReaderStream, SyncIoBridge};
+use utoipa::IntoParams;
+use uuid::Uuid;
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ExportQuery {
+    /// Only export images in this state (`originals` can be used for `approved`)
+    state: Option<ImageState>,
+    /// Whether to include the raw files, defaults to false
+    raw: Option<bool>,
+    /// Whether to include `manifest.json` with the metadata of all images, defaults to true
+    manifest: Option<bool>,
+}
+
+/// Entry of `manifest.json` in the archive
+#[derive(Serialize, Deserialize)]
+pub struct ManifestEntry {
+    pub id: Uuid,
+    pub state: ImageState,
+    pub created_at: DateTime<Utc>,
+    pub state_changed_at: DateTime<Utc>,
+    // Path of the image in the archive
+    pub path: String,
+    // Path of the raw file in the archive, if included
+    pub raw_path: Option<String>,
+    // Missing in exports of older versions
+    #[serde(default, skip_serializing_if = "ImageMetadata::is_empty")]
+    pub metadata: ImageMetadata,
+    // Name of the tenant the image belongs to, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+    // Alias of the image, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub alias: Option<String>,
+}
+
+/// Streams a tar archive of the stored images, e.g. for off-site backups.
+/// Images are stored in the archive like in the data directory, i.e. as
+/// `<pending|unapproved|originals>/<id>.avif` and `raw/<id>.raw`.
+#[utoipa::path(
+    get,
+    path = "/export",
+    tag = "images",
+    params(ExportQuery),
+    responses(
+        (status = 200, description = "The archive", content_type = "application/x-tar", body = Vec<u8>),
+        (status = 401, description = "Missing or invalid API key"),
+    ),
+    security(("api_key" = []))
+)]
+pub async fn export_handler(
+    State(server_state): State<ServerState>,
+    TypedHeader(authorization): TypedHeader<Authorization<Bearer>>,
+    query: Query<ExportQuery>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    check_auth_header(authorization, &server_state.reloadable().api_key_hashes)?;
+
+    let states = match query.state {
+        None => ImageState::ALL.to_vec(),
+        Some(state) => vec![state],
+    };
+    let images = list_images(&states, &server_state.metadata_index)
+        .map_err(|err| Error::Internal(format!("Error while listing images: {}", err)))?;
+    log::info!("Exporting {} image(s)", images.len());
+    let aliases = server_state.metadata_index.aliases();
+
+    // The archive is written by a blocking task and streamed to the client while it is written
+    let (writer, reader) = tokio::io::duplex(EXPORT_BUFFER_SIZE);
+    let writer = SyncIoBridge::new(writer);
+    let include_raw = query.raw.unwrap_or(false);
+    let include_manifest = query.manifest.unwrap_or(true);
+    tokio::task::spawn_blocking(move || {
+        match write_archive(writer, &images, &aliases, include_raw, include_manifest) {
+            // The client only notices an incomplete archive, as the status was already sent
+            Err(err) => log::error!("Export aborted: {}", err),
+            Ok(()) => log::info!("Exported {} image(s)", images.len()),
+        }
+    });
+
+    let headers = [
+        (header::CONTENT_TYPE, "application/x-tar".to_owned()),
+        (
+            header::CONTENT_DISPOSITION,
+            format!(
+                "attachment; filename=\"mensatt-images-{}.tar\"",
+                Utc::now().format("%Y%m%dT%H%M%SZ")
+            ),
+        ),
+    ];
+    Ok((headers, Body::from_stream(ReaderStream::new(reader))))
+}
+
+/// Writes the tar archive of `images` with their `aliases` to `writer`.
+/// Images that were deleted since they were listed are skipped.
+pub fn write_archive(
+    writer: impl Write,
+    images: &[StoredImage],
+    aliases: &HashMap<Uuid, String>,
+    include_raw: bool,
+    include_manifest: bool,
+) -> Result<(), io::Error> {
+    let mut archive = tar::Builder::new(writer);
+
+    let entries: Vec<ManifestEntry> = images
+        .iter()
+        .map(|image| {
+            let raw_path = get_raw_file(image.uuid, image.tenant.as_deref());
+            ManifestEntry {
+                id: image.uuid,
+                state: image.state,
+                created_at: image.created_at.into(),
+                state_changed_at: image.state_changed_at.into(),
+                path: archive_path(image.state, image.uuid),
+                raw_path: (include_raw && raw_path.is_file())
+                    .then(|| format!("raw/{}.raw", image.uuid)),
+                metadata: image.metadata.clone(),
+                tenant: image.tenant.clone(),
+                alias: aliases.get(&image.uuid).cloned(),
+            }
+        })
+        .collect();
+
+    if include_manifest {
+        let json = serde_json::to_vec_pretty(&entries)?;
+        let mut header = tar::Header::new_gnu();
+        header.set_size(json.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+        );
+        header.set_cksum();
+        archive.append_data(&mut header, "manifest.json", json.as_slice())?;
+    }
+
+    for entry in entries {
+        let tenant = entry.tenant.as_deref();
+        let path = entry.state.path(tenant).join(format!("{}.avif", entry.id));
+        append_file(&mut archive, &path, &entry.path)?;
+
+        if let Some(raw_path) = entry.raw_path {
+            let path = get_raw_file(entry.id, tenant);
+            append_file(&mut archive, &path, &raw_path)?;
+        }
+    }
+
+    archive.finish()
+}
+
+fn append_file(
+    archive: &mut tar::Builder<impl Write>,
+    path: &Path,
+    name: &str,
+) -> Result<(), io::Error> {
+    match archive.append_path_with_name(path, name) {
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            log::warn!("Skipping {:?} in export, as it was deleted meanwhile", path);
+            Ok(())
+        }
+        res => res,
+    }
+}
+
+/// Returns the path of an image in the archive, which is the same as in the data directory of
+/// images without tenant. The tenant of an image is recorded in the manifest.
+fn archive_path(state: ImageState, uuid: Uuid) -> String {
+    let dir = state
+        .path(None)
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    format!("{}/{}.avif", dir, uuid)
+}
@@ -0,0 +1,44 @@
+use crate::{
+    error::Error,
+    fsck::{check_checksums, FsckReport},
+    util::auth::check_auth_header,
+    ServerState,
+};
+
+use axum::{extract::State, http::StatusCode, Json};
+use axum_extra::{
+    headers::{authorization::Bearer, Authorization},
+    TypedHeader,
+};
+
+/// Hashes all stored images and compares them to the checksums recorded when they were written.
+/// Mismatches indicate silent corruption of the storage. Images without a recorded checksum get
+/// their current one recorded.
+#[utoipa::path(
+    post,
+    path = "/fsck",
+    tag = "admin",
+    responses(
+        (status = 200, description = "Number of checked images and all mismatches", body = FsckReport),
+        (status = 401, description = "Missing or invalid API key"),
+        (status = 500, description = "A data directory or image could not be read"),
+    ),
+    security(("api_key" = []))
+)]
+pub async fn fsck_handler(
+    State(server_state): State<ServerState>,
+    TypedHeader(authorization): TypedHeader<Authorization<Bearer>>,
+) -> Result<Json<FsckReport>, (StatusCode, String)> {
+    check_auth_header(authorization, &server_state.reloadable().api_key_hashes)?;
+
+    // Reading all images may take a while, so don't block the runtime
+    let res = tokio::task::spawn_blocking(move || check_checksums(&server_state)).await;
+
+    match res {
+        Ok(Ok(report)) => Ok(Json(report)),
+        Ok(Err(err)) => {
+            Err(Error::Internal(format!("Error during integrity check: {}", err)).into())
+        }
+        Err(err) => Err(Error::Internal(format!("Integrity check panicked: {}", err)).into()),
+    }
+}
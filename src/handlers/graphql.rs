@@ -0,0 +1,21 @@
+use crate::{graphql::CacheListing, util::auth::check_auth_header, ServerState};
+
+use axum::{extract::State, http::StatusCode, Json};
+use axum_extra::{
+    headers::{authorization::Bearer, Authorization},
+    TypedHeader,
+};
+
+/// Executes a GraphQL query or mutation against the moderation schema
+pub async fn graphql_handler(
+    State(server_state): State<ServerState>,
+    TypedHeader(authorization): TypedHeader<Authorization<Bearer>>,
+    Json(request): Json<async_graphql::Request>,
+) -> Result<Json<async_graphql::Response>, (StatusCode, String)> {
+    check_auth_header(authorization, &server_state.reloadable().api_key_hashes)?;
+
+    let request = request
+        .data(server_state.clone())
+        .data(CacheListing::default());
+    Ok(Json(server_state.graphql_schema.execute(request).await))
+}
@@ -1,165 +1,545 @@
 use crate::{
+    cdn::cache_tag,
+    error::Error,
+    handlers::alias::resolve_image_id,
+    operations::delete_image_everywhere,
     util::{
         auth::{check_auth, check_auth_header},
+        client_hints::{parse_client_hints, ClientHints, CLIENT_HINT_HEADERS},
         image::{
-            check_cache, delete_image, determine_img_dim, determine_img_path, get_cache_entry,
-            manipulate_image, remove_cache_entries, CacheBehavior,
+            cache_rendition, check_cache, determine_buffer_dim, determine_img_dim,
+            determine_img_path, get_cache_entry, manipulate_image, resize_dimensions,
+            validate_rendition, CacheBehavior, ColorProfile, Encoding, RemovalBehavior,
         },
-        path::{get_original_path, get_pending_path, get_unapproved_path},
+        path::{get_flagged_path, get_original_path, get_pending_path, get_unapproved_path},
+        pipeline::Pipeline,
+        webp::WebpTuning,
     },
     ServerState,
 };
 
+use argon2::password_hash::PasswordHashString;
 use axum::{
+    body::Bytes,
     extract::{Path, Query, State},
-    http::{header, StatusCode},
-    response::IntoResponse,
+    http::{header, HeaderMap, HeaderName, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
 };
 use axum_extra::{
     headers::{authorization::Bearer, Authorization},
     TypedHeader,
 };
 use serde::Deserialize;
-use std::fs::read;
+use std::{fs::read, time::Duration};
+use utoipa::IntoParams;
 use uuid::Uuid;
 
-#[derive(Deserialize)]
+#[derive(Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct ImageQuery {
+    /// Width in pixels, defaults to the one of the recipe, the `Sec-CH-Width` hint or the width
+    /// of the original. Multiplied by the `Sec-CH-DPR` hint.
     width: Option<i32>,
+    /// Height in pixels, defaults to the one of the recipe or the height of the original.
+    /// Multiplied by the `Sec-CH-DPR` hint.
     height: Option<i32>,
+    /// Encoder quality from 1 to 100, defaults to the one of the recipe or 80. Reduced to
+    /// `SAVE_DATA_QUALITY` with `Save-Data: on`.
     quality: Option<i32>,
+    /// Comma separated operations applied (in order) before resizing, e.g.
+    /// `rotate:90,crop:800x600,blur:2`. Supported are `rotate:<90|180|270>`, `flip:<h|v>`,
+    /// `crop:<width>x<height>` (centered), `blur:<sigma>` and `sharpen:<sigma>`.
+    ops: Option<String>,
+    /// Name of a recipe from the config, which sets operations, dimensions and encoding.
+    /// Cannot be combined with `ops`, but `width`, `height`, `quality`, `progressive` and
+    /// `color_profile` override the recipe.
+    recipe: Option<String>,
+    /// Whether JPEG and PNG renditions are interlaced to display progressively while loading,
+    /// defaults to the one of the recipe or `PROGRESSIVE_ENCODING`
+    progressive: Option<bool>,
+    /// Color profile of the rendition, defaults to the one of the recipe or `COLOR_PROFILE`.
+    /// `preserve` keeps the embedded ICC profile (and wide-gamut colors) instead of converting to
+    /// `srgb`, `display_p3` converts to Display P3.
+    color_profile: Option<ColorProfile>,
+    /// CPU effort of the WebP encoder from 0 (fastest) to 6 (smallest files), defaults to the one
+    /// of the recipe or `WEBP_EFFORT`. Like the other `webp_*` options, it requires an API key or
+    /// preview token.
+    webp_effort: Option<i32>,
+    /// Whether WebP renditions use high quality chroma subsampling, defaults to the one of the
+    /// recipe or `WEBP_SMART_SUBSAMPLE`
+    webp_smart_subsample: Option<bool>,
+    /// Whether WebP renditions are encoded near-lossless (using `quality` for the preprocessing),
+    /// defaults to the one of the recipe or `WEBP_NEAR_LOSSLESS`
+    webp_near_lossless: Option<bool>,
+    /// Quality of the alpha channel of WebP renditions from 0 to 100, defaults to the one of the
+    /// recipe or `WEBP_ALPHA_QUALITY`
+    webp_alpha_quality: Option<i32>,
+    /// API key, alternative to the Authorization header
     auth: Option<String>,
+    /// Preview token of this image, see `/image/:id/preview-token`. Grants access to the image
+    /// while it is unapproved or pending, like an API key.
+    token: Option<String>,
+    /// Maximum age in seconds of a cached rendition to be served, older ones are rendered again.
+    /// Overrides `CACHE_TTL_SECS` and requires an API key or preview token.
+    cache_ttl_secs: Option<u64>,
 }
 
-// This handler serves images with the given id from the filesystem
-// It accepts optional query parameters for width, height and quality
-// It also accepts an optional Authorization header and - if it's valid - serves unapproved images
-// Images are resized, and compressed using vips
+impl ImageQuery {
+    /// Returns the query of a rendition with the given dimensions and quality, e.g. a thumbnail
+    pub fn with_size(width: Option<i32>, height: Option<i32>, quality: Option<i32>) -> Self {
+        ImageQuery {
+            width: width,
+            height: height,
+            quality: quality,
+            ..ImageQuery::default()
+        }
+    }
+
+    /// Returns whether any option of the WebP encoder is requested
+    fn has_webp_tuning(&self) -> bool {
+        self.webp_effort.is_some()
+            || self.webp_smart_subsample.is_some()
+            || self.webp_near_lossless.is_some()
+            || self.webp_alpha_quality.is_some()
+    }
+}
+
+/// Returns the image, resized and compressed with libvips as requested by the query, in
+/// `DEFAULT_OUTPUT_FORMAT` (WebP by default) or in the format of the requested recipe. Unapproved
+/// and pending images are only returned with a valid API key or preview token. If a placeholder
+/// is configured, it is returned (with the configured status) instead of a plain-text 404.
+/// If `CLIENT_HINTS_ENABLED` is set, the Client Hints `Sec-CH-Width`, `Sec-CH-DPR` and
+/// `Save-Data` are honored. The headers `X-Image-Width`/`-Height` contain the dimensions of the
+/// returned rendition, `X-Original-Width`/`-Height` those of the stored image.
+#[utoipa::path(
+    get,
+    path = "/image/{id}",
+    tag = "images",
+    params(("id" = String, Path, description = "UUID, short ID or alias of the image"), ImageQuery),
+    responses(
+        (status = 200, description = "The image", content_type = "image/webp", body = Vec<u8>,
+         headers(
+             ("X-Image-Width" = i32, description = "Width of the returned rendition"),
+             ("X-Image-Height" = i32, description = "Height of the returned rendition"),
+             ("X-Original-Width" = i32, description = "Width of the stored image"),
+             ("X-Original-Height" = i32, description = "Height of the stored image"),
+         )),
+        (status = 400, description = "Invalid ID, dimensions, quality, operations or recipe"),
+        (status = 401, description = "`cache_ttl_secs` or `webp_*` without API key or preview token"),
+        (status = 404, description = "Image not found, or the placeholder"),
+        (status = 504, description = "Rendering took longer than `VIPS_TIMEOUT_SECS`"),
+    )
+)]
 pub async fn image_handler(
     State(server_state): State<ServerState>,
     authorization_header_opt: Option<TypedHeader<Authorization<Bearer>>>,
-    Path(id): Path<Uuid>,
+    Path(id): Path<String>,
+    request_headers: HeaderMap,
     query: Query<ImageQuery>,
-) -> impl IntoResponse {
+) -> Result<Response, (StatusCode, String)> {
+    let id = resolve_image_id(&id, &server_state.metadata_index)?;
+    let hashes = server_state.reloadable().api_key_hashes.clone();
+    serve_image(
+        id,
+        authorization_header_opt,
+        &request_headers,
+        query,
+        &hashes,
+        &server_state,
+    )
+    .await
+}
+
+/// Serves the image `id` like `/image/:id`. Unapproved and pending images are only returned with
+/// a preview token or an API key matching one of `hashes`.
+pub async fn serve_image(
+    id: Uuid,
+    authorization_header_opt: Option<TypedHeader<Authorization<Bearer>>>,
+    request_headers: &HeaderMap,
+    query: Query<ImageQuery>,
+    hashes: &Vec<PasswordHashString>,
+    server_state: &ServerState,
+) -> Result<Response, (StatusCode, String)> {
     // Check ID
     if id.is_nil() {
         return Err((StatusCode::BAD_REQUEST, "Invalid ID!".to_owned()));
     }
+    validate_rendition(query.width, query.height, query.quality)
+        .map_err(|err| (StatusCode::BAD_REQUEST, format!("{}!", err)))?;
+
+    let hints = match server_state.client_hints_enabled {
+        false => None,
+        true => Some(parse_client_hints(request_headers)),
+    };
 
     // Return image if it exists in original path
-    match determine_img_path(get_original_path().to_str().unwrap(), id) {
+    let tenant = server_state.metadata_index.tenant(id);
+    let tenant = tenant.as_deref();
+    match determine_img_path(get_original_path(tenant).to_str().unwrap(), id) {
         Err(_) => (),
         Ok(path) => {
-            return image_handler_helper(
+            // Rendering again and slow encoder settings are expensive, so anonymous clients can't
+            // force them. They can still request recipes configured by the operators.
+            let needs_auth = query.cache_ttl_secs.is_some() || query.has_webp_tuning();
+            // Approved images are served without an API key, but a sent one is checked to
+            // attribute the bytes to it
+            let key_sent = authorization_header_opt.is_some() || query.auth.is_some();
+            let (authorized, key) = match needs_auth || key_sent {
+                false => (false, None),
+                true => authorize(id, &query, authorization_header_opt, hashes, server_state),
+            };
+            if needs_auth && !authorized {
+                return Err((
+                    StatusCode::UNAUTHORIZED,
+                    "cache_ttl_secs and webp_* require an API key or preview token!".to_owned(),
+                ));
+            }
+            let (headers, body) = image_handler_helper(
                 id,
                 path.to_str().unwrap(),
                 query.0,
                 CacheBehavior::Normal,
-            );
+                hints,
+                server_state,
+            )
+            .await?;
+            server_state.access_stats.record(id);
+            server_state
+                .access_stats
+                .record_bytes(id, key.as_deref(), body.len() as u64);
+            return Ok((headers, body).into_response());
         }
     };
 
-    let not_found_resp = Err((StatusCode::NOT_FOUND, "Image not found!".to_owned()));
-    return match check_auth(
-        query.auth.as_ref(),
-        authorization_header_opt,
-        &server_state.api_key_hashes,
-    ) {
-        Err(_) => not_found_resp,
-        Ok(()) => match determine_img_path(get_unapproved_path().to_str().unwrap(), id) {
-            Err(_) => not_found_resp, // Return 404 if image was also not found in unapproved path
+    let (authorized, key) = authorize(id, &query, authorization_header_opt, hashes, server_state);
+    match authorized {
+        false => not_found_response(server_state, id, query.0, hints).await,
+        true => match determine_img_path(get_unapproved_path(tenant).to_str().unwrap(), id)
+            .or_else(|_| determine_img_path(get_flagged_path(tenant).to_str().unwrap(), id))
+            .or_else(|_| determine_img_path(get_pending_path(tenant).to_str().unwrap(), id))
+        {
+            Err(_) => not_found_response(server_state, id, query.0, hints).await, // Return 404 if image was also not found in unapproved or pending path
             Ok(path) => {
-                // Skip cache for unapproved images to avoid leaking them via cache
-                image_handler_helper(id, path.to_str().unwrap(), query.0, CacheBehavior::Skip)
+                // Skip cache for unapproved and pending images to avoid leaking them via cache
+                let (headers, body) = image_handler_helper(
+                    id,
+                    path.to_str().unwrap(),
+                    query.0,
+                    CacheBehavior::Skip,
+                    hints,
+                    server_state,
+                )
+                .await?;
+                server_state
+                    .access_stats
+                    .record_bytes(id, key.as_deref(), body.len() as u64);
+                Ok((headers, body).into_response())
             }
         },
+    }
+}
+
+/// Returns whether the request may access the image `id` although it is not approved, i.e. has a
+/// valid preview token of it or an API key matching one of `hashes`, and the key, if any
+fn authorize(
+    id: Uuid,
+    query: &ImageQuery,
+    authorization_header_opt: Option<TypedHeader<Authorization<Bearer>>>,
+    hashes: &Vec<PasswordHashString>,
+    server_state: &ServerState,
+) -> (bool, Option<String>) {
+    // A valid preview token grants access like an API key, but only to this image
+    let token_valid = match (&query.token, &server_state.preview_tokens) {
+        (Some(token), Some(preview_tokens)) => preview_tokens.verify(id, token),
+        _ => false,
     };
+    let key = match token_valid {
+        true => None,
+        false => check_auth(query.auth.as_ref(), authorization_header_opt, hashes).ok(),
+    };
+    (token_valid || key.is_some(), key)
 }
 
-type Headers = [(header::HeaderName, String); 2];
-type Body = Vec<u8>;
+/// Returns the placeholder manipulated by `image_query` if one is configured, a plain-text 404
+/// otherwise
+async fn not_found_response(
+    server_state: &ServerState,
+    uuid: Uuid,
+    image_query: ImageQuery,
+    hints: Option<ClientHints>,
+) -> Result<Response, (StatusCode, String)> {
+    let Some(placeholder) = &server_state.placeholder else {
+        return Err((StatusCode::NOT_FOUND, "Image not found!".to_owned()));
+    };
+
+    // Neither cached here nor by clients, as the image may be approved later
+    let (headers, body) = image_handler_helper(
+        uuid,
+        placeholder.path.to_str().unwrap(),
+        image_query,
+        CacheBehavior::Skip,
+        hints,
+        server_state,
+    )
+    .await?;
+    Ok((
+        placeholder.status,
+        [(header::CACHE_CONTROL, "no-store")],
+        headers,
+        body,
+    )
+        .into_response())
+}
 
-/// Takes a uuid, path,an image query and a skip_cache flag and returns the image manipulated by the arguments of image query
-/// If a error occurs, an appropriate HTTP status code and message is returned.
-fn image_handler_helper(
+// Dimensions of the served rendition and of the stored image it was rendered from, so frontends
+// can reserve layout space without requesting `/images/info`
+pub const IMAGE_WIDTH: HeaderName = HeaderName::from_static("x-image-width");
+pub const IMAGE_HEIGHT: HeaderName = HeaderName::from_static("x-image-height");
+pub const ORIGINAL_WIDTH: HeaderName = HeaderName::from_static("x-original-width");
+pub const ORIGINAL_HEIGHT: HeaderName = HeaderName::from_static("x-original-height");
+
+pub type Headers = HeaderMap;
+pub type Body = Vec<u8>;
+
+/// Takes a uuid, path, an image query and a cache behavior and returns the image manipulated by
+/// the arguments of the image query. Accesses of cache entries are recorded in the cache index.
+/// A requested recipe is looked up in the reloadable config. If `hints` are given (i.e.
+/// enabled), they are applied to the dimensions and quality. If an error occurs, it is returned,
+/// see `Error` for the HTTP responses.
+pub async fn image_handler_helper(
     uuid: Uuid,
     path: &str,
     image_query: ImageQuery,
     cache_behavior: CacheBehavior,
-) -> Result<(Headers, Body), (StatusCode, String)> {
+    hints: Option<ClientHints>,
+    server_state: &ServerState,
+) -> Result<(Headers, Body), Error> {
     // Get image dimensions; used as fallback in case height and/or width missing in image_query
-    let img_dim = match determine_img_dim(path) {
-        Err(err) => {
-            log::error!("{}", err);
-            return Err((
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "Error while getting image dimensions".to_owned(),
+    let img_dim = determine_img_dim(path)?;
+
+    let reloadable = server_state.reloadable();
+    let recipe = match &image_query.recipe {
+        None => None,
+        Some(name) => Some(
+            reloadable
+                .recipes
+                .get(name)
+                .ok_or_else(|| Error::BadRequest(format!("Unknown recipe '{}'", name)))?,
+        ),
+    };
+    let pipeline = match (&image_query.ops, recipe) {
+        (Some(_), Some(_)) => {
+            return Err(Error::BadRequest(
+                "ops and recipe cannot be combined".to_owned(),
             ));
         }
-        Ok(img_dim) => img_dim,
+        (None, Some(recipe)) => recipe.pipeline.clone(),
+        (Some(ops), None) => ops.parse::<Pipeline>().map_err(Error::BadRequest)?,
+        (None, None) => Pipeline::default(),
     };
+    let original_dim = img_dim;
+    // Dimensions after the pipeline, e.g. swapped by a rotation
+    let img_dim = pipeline.output_dimensions(img_dim);
 
     // Get arguments for manipulate image
-    let width = image_query.width.unwrap_or(img_dim.0);
-    let height = image_query.height.unwrap_or(img_dim.1);
-    let quality = image_query.quality.unwrap_or(80);
+    // Dimensions of the request and the recipe are in CSS pixels, those of the hint in physical pixels
+    let hints = hints.unwrap_or_default();
+    let width = image_query
+        .width
+        .or(recipe.and_then(|recipe| recipe.width))
+        .map(|width| hints.scale(width))
+        .or(hints.width);
+    let height = image_query
+        .height
+        .or(recipe.and_then(|recipe| recipe.height))
+        .map(|height| hints.scale(height));
+    let (width, height, mode) = resize_dimensions(width, height, img_dim);
+    let (width, height) = server_state
+        .output_limits
+        .apply((width, height))
+        .map_err(Error::BadRequest)?;
+    let quality = image_query
+        .quality
+        .or(recipe.and_then(|recipe| recipe.quality))
+        .unwrap_or(80);
+    let encoding = Encoding {
+        format: recipe
+            .and_then(|recipe| recipe.format)
+            .unwrap_or(server_state.default_format),
+        quality: match hints.save_data {
+            false => quality,
+            true => quality.min(server_state.save_data_quality),
+        },
+        progressive: image_query
+            .progressive
+            .or(recipe.and_then(|recipe| recipe.progressive))
+            .unwrap_or(server_state.progressive_encoding),
+        color_profile: image_query
+            .color_profile
+            .or(recipe.and_then(|recipe| recipe.color_profile))
+            .unwrap_or(server_state.color_profile),
+        webp: WebpTuning {
+            effort: image_query
+                .webp_effort
+                .or(recipe.and_then(|recipe| recipe.webp_effort))
+                .unwrap_or(server_state.webp_tuning.effort),
+            smart_subsample: image_query
+                .webp_smart_subsample
+                .or(recipe.and_then(|recipe| recipe.webp_smart_subsample))
+                .unwrap_or(server_state.webp_tuning.smart_subsample),
+            near_lossless: image_query
+                .webp_near_lossless
+                .or(recipe.and_then(|recipe| recipe.webp_near_lossless))
+                .unwrap_or(server_state.webp_tuning.near_lossless),
+            alpha_quality: image_query
+                .webp_alpha_quality
+                .or(recipe.and_then(|recipe| recipe.webp_alpha_quality))
+                .unwrap_or(server_state.webp_tuning.alpha_quality),
+        },
+    };
+    encoding.webp.validate().map_err(Error::BadRequest)?;
+    let format = encoding.format;
 
     // Construct HTTP Header
-    let headers = [
-        (header::CONTENT_TYPE, "image/webp".to_owned()),
-        (
-            header::CONTENT_DISPOSITION,
-            format!("inline; filename={:?}.webp", uuid),
-        ),
-    ];
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static(format.content_type()),
+    );
+    headers.insert(
+        header::CONTENT_DISPOSITION,
+        HeaderValue::from_str(&format!(
+            "inline; filename={:?}.{}",
+            uuid,
+            format.extension()
+        ))
+        .unwrap(),
+    );
+    // Allow CDNs to purge all renditions of the image at once, see `CdnPurger`
+    let tag = HeaderValue::from_str(&cache_tag(uuid)).unwrap();
+    headers.insert(header::HeaderName::from_static("cache-tag"), tag.clone());
+    headers.insert(header::HeaderName::from_static("surrogate-key"), tag);
+    if server_state.client_hints_enabled {
+        headers.insert(header::VARY, HeaderValue::from_static(CLIENT_HINT_HEADERS));
+    }
+    headers.insert(ORIGINAL_WIDTH, HeaderValue::from(original_dim.0));
+    headers.insert(ORIGINAL_HEIGHT, HeaderValue::from(original_dim.1));
 
     // Construct HTTP Body
     // If cache is desired and requested image is already cached, the cached version is returned
+    let tenant = server_state.metadata_index.tenant(uuid);
+    let cache_entry = get_cache_entry(
+        &uuid.to_string(),
+        tenant.as_deref(),
+        height,
+        width,
+        &pipeline,
+        encoding,
+    );
+    // Stale cache entries are rendered again and overwritten
+    let cache_ttl = image_query
+        .cache_ttl_secs
+        .map(Duration::from_secs)
+        .or(server_state.cache_ttl);
     let body = match cache_behavior {
-        CacheBehavior::Normal if check_cache(uuid, height, width, quality) => {
-            read(get_cache_entry(&uuid.to_string(), height, width, quality)).unwrap()
+        CacheBehavior::Normal
+            if check_cache(
+                uuid,
+                tenant.as_deref(),
+                height,
+                width,
+                &pipeline,
+                encoding,
+                cache_ttl,
+            ) =>
+        {
+            read(&cache_entry)?
+        }
+        _ => {
+            let (source, pipeline) = (path.to_owned(), pipeline.clone());
+            let shadow_reader = server_state.shadow_reader.clone();
+            let body = server_state
+                .watchdog
+                .with_timeout(uuid, std::path::Path::new(path), "Rendering", move || {
+                    let content = Bytes::from(read(&source)?);
+                    if let Some(shadow_reader) = shadow_reader {
+                        shadow_reader.compare(std::path::Path::new(&source), content.clone());
+                    }
+                    manipulate_image(&content, height, width, mode, &pipeline, encoding)
+                })
+                .await?;
+            // Only cached once rendering finished in time, abandoned renditions are discarded
+            cache_rendition(cache_entry.clone(), &body, cache_behavior)?;
+            body
         }
-        _ => match manipulate_image(path, height, width, quality, cache_behavior) {
-            Err(err) => {
-                log::error!("{}", err);
-                return Err((
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    "Error while processing image!".to_owned(),
-                ));
-            }
-            Ok(buf) => buf,
-        },
     };
 
+    // The requested dimensions may differ from the actual ones, e.g. the height of fitted images
+    match determine_buffer_dim(&body) {
+        Err(err) => log::error!(
+            "Could not determine dimensions of rendition of {}: {}",
+            uuid,
+            err
+        ),
+        Ok((width, height)) => {
+            headers.insert(IMAGE_WIDTH, HeaderValue::from(width));
+            headers.insert(IMAGE_HEIGHT, HeaderValue::from(height));
+        }
+    }
+
+    if cache_behavior != CacheBehavior::Skip {
+        if let Some(file_name) = cache_entry.file_name().and_then(|name| name.to_str()) {
+            server_state.cache_index.record_access(file_name);
+        }
+    }
+
     Ok((headers, body))
 }
 
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct DeleteQuery {
+    /// Only return the files that would be deleted
+    dry_run: Option<bool>,
+}
+
+/// Deletes the image with the given id from all states and the cache.
+/// With `?dry_run=true` nothing is deleted; instead, the paths that would be deleted are
+/// returned (one per line).
+#[utoipa::path(
+    delete,
+    path = "/image/{id}",
+    tag = "images",
+    params(("id" = Uuid, Path, description = "ID of the image"), DeleteQuery),
+    responses(
+        (status = 200, description = "ID of the deleted image, or the files that would be deleted (one per line) in a dry run", body = String),
+        (status = 400, description = "Invalid ID"),
+        (status = 401, description = "Missing or invalid API key"),
+    ),
+    security(("api_key" = []))
+)]
 pub async fn image_delete_handler(
     State(server_state): State<ServerState>,
     TypedHeader(authorization): TypedHeader<Authorization<Bearer>>,
     Path(uuid): Path<Uuid>,
+    query: Query<DeleteQuery>,
 ) -> Result<String, (StatusCode, String)> {
-    check_auth_header(authorization, &server_state.api_key_hashes)?;
+    check_auth_header(authorization, &server_state.reloadable().api_key_hashes)?;
 
-    // Check ID
-    if uuid.is_nil() {
-        return Err((StatusCode::BAD_REQUEST, "Invalid ID!".to_owned()));
-    }
+    let removal_behavior = match query.dry_run {
+        Some(true) => RemovalBehavior::DryRun,
+        _ => RemovalBehavior::Delete,
+    };
 
-    // To avoid code duplication below
-    let internal_server_error = (
-        StatusCode::INTERNAL_SERVER_ERROR,
-        "Error while deleting image!".to_owned(),
-    );
+    let removed = delete_image_everywhere(uuid, removal_behavior, &server_state).await?;
 
-    // Make sure image is deleted from pending, unapproved and original paths
-    delete_image(&get_pending_path(), uuid)
-        .map_err(|_| -> (StatusCode, String) { internal_server_error.clone() })?;
-    delete_image(&get_unapproved_path(), uuid)
-        .map_err(|_| -> (StatusCode, String) { internal_server_error.clone() })?;
-    delete_image(&get_original_path(), uuid)
-        .map_err(|_| -> (StatusCode, String) { internal_server_error.clone() })?;
-    remove_cache_entries(uuid);
+    if removal_behavior == RemovalBehavior::DryRun {
+        return Ok(removed
+            .iter()
+            .map(|path| path.to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("\n"));
+    }
 
     Ok(uuid.to_string())
 }
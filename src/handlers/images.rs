@@ -0,0 +1,353 @@
+use crate::{
+    constants::{API_PREFIX, MAX_DELETE_IDS, MAX_INFO_IDS},
+    error::Error,
+    operations::{
+        create_lqip_in_background, delete_image_everywhere, image_info, ImageInfo, StorageLocation,
+    },
+    util::{
+        auth::{check_auth, check_auth_header},
+        image::{
+            delete_raw, determine_img_dim, list_cache_variants, list_images, validate_rendition,
+            ImageState, RemovalBehavior, StoredImage,
+        },
+        image_metadata::ImageMetadata,
+        listing::{ListItem, ListQuery, Page},
+    },
+    ServerState,
+};
+
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    Json,
+};
+use axum_extra::{
+    headers::{authorization::Bearer, Authorization},
+    TypedHeader,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, time::SystemTime};
+use utoipa::{IntoParams, ToSchema};
+use uuid::Uuid;
+
+#[derive(Serialize, ToSchema)]
+pub struct ImageListEntry {
+    id: Uuid,
+    state: ImageState,
+    created_at: DateTime<Utc>,
+    // Time the image entered its current state, e.g. was approved
+    state_changed_at: DateTime<Utc>,
+    #[serde(skip_serializing_if = "ImageMetadata::is_empty")]
+    metadata: ImageMetadata,
+    // Name of the tenant the image belongs to, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tenant: Option<String>,
+    /// URL of a thumbnail, only with `thumbnail_width`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    thumbnail_url: Option<String>,
+    /// Dimensions of the image, only with `thumbnail_width`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    width: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    height: Option<i32>,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ThumbnailQuery {
+    /// Adds the URL of a thumbnail of this width and the dimensions of the image to each item,
+    /// e.g. to render the review grid of a moderation UI from a single request
+    thumbnail_width: Option<i32>,
+}
+
+impl ListItem for StoredImage {
+    fn id(&self) -> Uuid {
+        self.uuid
+    }
+
+    fn state(&self) -> ImageState {
+        self.state
+    }
+
+    fn created_at(&self) -> SystemTime {
+        self.created_at
+    }
+
+    fn state_changed_at(&self) -> SystemTime {
+        self.state_changed_at
+    }
+}
+
+/// Lists images of all (or the requested) states with the times of their upload and their last
+/// state change, e.g. to find out which images were approved in a time range.
+/// With `thumbnail_width`, thumbnail URLs are included. Those of images that are not approved
+/// contain a preview token, if preview tokens are enabled, so they can be used in `<img>` tags.
+#[utoipa::path(
+    get,
+    path = "/images",
+    tag = "images",
+    params(ListQuery, ThumbnailQuery),
+    responses(
+        (status = 200, description = "One page of images", body = crate::util::listing::ImagePage),
+        (status = 400, description = "Invalid query or thumbnail width"),
+        (status = 401, description = "Missing or invalid API key"),
+    ),
+    security(("api_key" = []))
+)]
+pub async fn images_handler(
+    State(server_state): State<ServerState>,
+    TypedHeader(authorization): TypedHeader<Authorization<Bearer>>,
+    query: Query<ListQuery>,
+    Query(thumbnail_query): Query<ThumbnailQuery>,
+) -> Result<Json<Page<ImageListEntry>>, (StatusCode, String)> {
+    check_auth_header(authorization, &server_state.reloadable().api_key_hashes)?;
+
+    let images = list_images(&query.states(), &server_state.metadata_index)
+        .map_err(|err| Error::Internal(format!("Error while listing images: {}", err)))?;
+
+    image_page(images, &query, thumbnail_query, &server_state)
+}
+
+/// Returns the page of `images` selected by `query`, with thumbnail URLs and dimensions if
+/// requested by `thumbnail_query`
+pub fn image_page(
+    images: Vec<StoredImage>,
+    query: &ListQuery,
+    thumbnail_query: ThumbnailQuery,
+    server_state: &ServerState,
+) -> Result<Json<Page<ImageListEntry>>, (StatusCode, String)> {
+    if let Some(width) = thumbnail_query.thumbnail_width {
+        validate_rendition(Some(width), None, None)
+            .map_err(|err| (StatusCode::BAD_REQUEST, format!("{}!", err)))?;
+    }
+    let page = query.apply(images)?;
+    Ok(Json(Page {
+        items: page
+            .items
+            .into_iter()
+            .map(|image| {
+                let (thumbnail_url, dimensions) = match thumbnail_query.thumbnail_width {
+                    None => (None, None),
+                    Some(width) => {
+                        let path = image
+                            .state
+                            .path(image.tenant.as_deref())
+                            .join(format!("{}.avif", image.uuid));
+                        (
+                            Some(thumbnail_url(&image, width, server_state)),
+                            determine_img_dim(path.to_str().unwrap()).ok(),
+                        )
+                    }
+                };
+                ImageListEntry {
+                    id: image.uuid,
+                    state: image.state,
+                    created_at: image.created_at.into(),
+                    state_changed_at: image.state_changed_at.into(),
+                    metadata: image.metadata,
+                    tenant: image.tenant,
+                    thumbnail_url,
+                    width: dimensions.map(|(width, _)| width),
+                    height: dimensions.map(|(_, height)| height),
+                }
+            })
+            .collect(),
+        total: page.total,
+        limit: page.limit,
+        offset: page.offset,
+    }))
+}
+
+/// Returns the URL of a thumbnail of `image` with `width`, absolute if `PUBLIC_URL` is set.
+/// Images that are not approved get a preview token, if enabled.
+fn thumbnail_url(image: &StoredImage, width: i32, server_state: &ServerState) -> String {
+    let url = format!(
+        "{}{}/image/{}?width={}",
+        server_state.public_url.as_deref().unwrap_or_default(),
+        API_PREFIX,
+        image.uuid,
+        width
+    );
+    match (image.state, &server_state.preview_tokens) {
+        (ImageState::Approved, _) | (_, None) => url,
+        (_, Some(preview_tokens)) => {
+            format!("{}&token={}", url, preview_tokens.issue(image.uuid).0)
+        }
+    }
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct InfoRequest {
+    ids: Vec<Uuid>,
+}
+
+/// Returns metadata of multiple images at once.
+/// Images that don't exist are returned as `null`. Without authorization, this is also the case
+/// for images that are not approved.
+#[utoipa::path(
+    post,
+    path = "/images/info",
+    tag = "images",
+    request_body = InfoRequest,
+    responses(
+        (status = 200, description = "Metadata by image ID", body = HashMap<String, ImageInfo>),
+        (status = 400, description = "Too many IDs"),
+    )
+)]
+pub async fn images_info_handler(
+    State(server_state): State<ServerState>,
+    authorization_header_opt: Option<TypedHeader<Authorization<Bearer>>>,
+    Json(request): Json<InfoRequest>,
+) -> Result<Json<HashMap<Uuid, Option<ImageInfo>>>, (StatusCode, String)> {
+    if request.ids.len() > MAX_INFO_IDS {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("At most {} IDs can be requested at once!", MAX_INFO_IDS),
+        ));
+    }
+
+    let authorized = check_auth(
+        None,
+        authorization_header_opt,
+        &server_state.reloadable().api_key_hashes,
+    )
+    .is_ok();
+
+    let cache_variants = list_cache_variants()
+        .map_err(|err| Error::Internal(format!("Error while listing cache entries: {}", err)))?;
+
+    let infos = request
+        .ids
+        .into_iter()
+        .map(|uuid| {
+            (
+                uuid,
+                image_info(
+                    uuid,
+                    authorized,
+                    &cache_variants,
+                    &server_state.metadata_index,
+                ),
+            )
+        })
+        .collect::<HashMap<_, _>>();
+
+    // Images approved before BlurHashes were introduced get theirs on the first request
+    for (uuid, info) in infos.iter() {
+        if let Some(ImageInfo {
+            state: ImageState::Approved,
+            blurhash: None,
+            ..
+        }) = info
+        {
+            create_lqip_in_background(*uuid, &server_state);
+        }
+    }
+
+    Ok(Json(infos))
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct DeleteRequest {
+    ids: Vec<Uuid>,
+    // Also delete the raw files right away instead of leaving them to the raw cleaner,
+    // defaults to false
+    raw: Option<bool>,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct DeleteBatchQuery {
+    /// Only report what would be deleted
+    dry_run: Option<bool>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct DeleteResult {
+    // Whether any file of the image was found
+    found: bool,
+    // Locations files were removed from (or would be in a dry run), each listed once
+    removed_from: Vec<StorageLocation>,
+    #[schema(value_type = Vec<String>)]
+    files: Vec<String>,
+    // Why the image could not be deleted (completely)
+    error: Option<String>,
+}
+
+/// Deletes multiple images from all states and the cache, like `DELETE /image/:id`, e.g. for
+/// reconciliation scripts of the backend. Returns by ID which locations files were removed from,
+/// so the deletions can be audited. With `?dry_run=true`, nothing is deleted.
+#[utoipa::path(
+    post,
+    path = "/images/delete",
+    tag = "images",
+    params(DeleteBatchQuery),
+    request_body = DeleteRequest,
+    responses(
+        (status = 200, description = "Result by image ID", body = HashMap<String, DeleteResult>),
+        (status = 400, description = "Too many IDs"),
+        (status = 401, description = "Missing or invalid API key"),
+    ),
+    security(("api_key" = []))
+)]
+pub async fn images_delete_handler(
+    State(server_state): State<ServerState>,
+    TypedHeader(authorization): TypedHeader<Authorization<Bearer>>,
+    query: Query<DeleteBatchQuery>,
+    Json(request): Json<DeleteRequest>,
+) -> Result<Json<HashMap<Uuid, DeleteResult>>, (StatusCode, String)> {
+    check_auth_header(authorization, &server_state.reloadable().api_key_hashes)?;
+
+    if request.ids.len() > MAX_DELETE_IDS {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("At most {} IDs can be deleted at once!", MAX_DELETE_IDS),
+        ));
+    }
+
+    let removal_behavior = match query.dry_run {
+        Some(true) => RemovalBehavior::DryRun,
+        _ => RemovalBehavior::Delete,
+    };
+    let with_raw = request.raw.unwrap_or(false);
+
+    let mut results = HashMap::new();
+    for uuid in request.ids {
+        let mut removed = Vec::new();
+        let mut error = None;
+        let tenant = server_state.metadata_index.tenant(uuid);
+        match delete_image_everywhere(uuid, removal_behavior, &server_state).await {
+            Err((_, message)) => error = Some(message),
+            Ok(paths) => removed.extend(paths),
+        }
+        if with_raw && error.is_none() {
+            match delete_raw(uuid, tenant.as_deref(), removal_behavior) {
+                Err(_) => error = Some("Error while deleting raw file!".to_owned()),
+                Ok(path) => removed.extend(path),
+            }
+        }
+
+        let mut removed_from = Vec::new();
+        for location in removed
+            .iter()
+            .filter_map(|path| StorageLocation::of(path, tenant.as_deref()))
+        {
+            if !removed_from.contains(&location) {
+                removed_from.push(location);
+            }
+        }
+        let result = DeleteResult {
+            found: !removed.is_empty(),
+            removed_from: removed_from,
+            files: removed
+                .iter()
+                .map(|path| path.to_string_lossy().to_string())
+                .collect(),
+            error: error,
+        };
+        results.insert(uuid, result);
+    }
+
+    Ok(Json(results))
+}
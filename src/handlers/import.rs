@@ -0,0 +1,59 @@
+use crate::{
+    operations::upload_image,
+    util::{auth::check_auth_header, image_metadata::ImageMetadata},
+    ServerState,
+};
+
+use axum::{extract::State, http::StatusCode, Json};
+use axum_extra::{
+    headers::{authorization::Bearer, Authorization},
+    TypedHeader,
+};
+use serde::Deserialize;
+use utoipa::ToSchema;
+
+#[derive(Deserialize, ToSchema)]
+pub struct ImportRequest {
+    // URL of the image, its host has to be one of `IMPORT_ALLOWED_HOSTS`
+    url: String,
+    // Angle in degrees to rotate the image by before saving
+    angle: Option<f64>,
+}
+
+/// Downloads an image from a remote URL and saves it like an upload, e.g. to migrate legacy images
+#[utoipa::path(
+    post,
+    path = "/import",
+    tag = "images",
+    request_body = ImportRequest,
+    responses(
+        (status = 200, description = "ID of the imported (pending) image", body = String),
+        (status = 400, description = "Invalid or not allowed URL, or unsupported file type"),
+        (status = 401, description = "Missing or invalid API key"),
+        (status = 413, description = "File too large"),
+        (status = 422, description = "File is infected or exceeds the input limits"),
+        (status = 502, description = "Download failed"),
+        (status = 504, description = "Download or encoding timed out"),
+        (status = 507, description = "Data volume nearly full"),
+    ),
+    security(("api_key" = []))
+)]
+pub async fn import_handler(
+    State(server_state): State<ServerState>,
+    TypedHeader(authorization): TypedHeader<Authorization<Bearer>>,
+    Json(request): Json<ImportRequest>,
+) -> Result<String, (StatusCode, String)> {
+    check_auth_header(authorization, &server_state.reloadable().api_key_hashes)?;
+
+    let data = server_state.importer.download(&request.url).await?;
+    let uuid = upload_image(
+        &data,
+        request.angle.unwrap_or(0.0),
+        ImageMetadata::default(),
+        None,
+        &server_state,
+    )
+    .await?;
+
+    Ok(uuid.to_string())
+}
@@ -0,0 +1,160 @@
+use crate::{constants::CONTENT_LENGTH_LIMIT, util::auth::check_auth_header, ServerState};
+
+use axum::{extract::State, http::StatusCode, Json};
+use axum_extra::{
+    headers::{authorization::Bearer, Authorization},
+    TypedHeader,
+};
+use libvips::bindings::{vips_type_find, vips_version_string};
+use serde::Serialize;
+use std::ffi::{CStr, CString};
+use utoipa::ToSchema;
+
+// Formats whose codecs are optional in libvips builds, besides those uploads are accepted in
+const CODECS: [&str; 6] = ["heif", "jxl", "gif", "webp", "jpeg", "png"];
+
+#[derive(Serialize, ToSchema)]
+pub struct ServiceInfo {
+    // Version of this service, from its Cargo.toml
+    version: String,
+    // Commit the binary was built from, `unknown` if built without git
+    git_commit: String,
+    libvips_version: String,
+    codecs: Vec<CodecInfo>,
+    limits: LimitsInfo,
+    features: FeaturesInfo,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct CodecInfo {
+    // Name of the format, e.g. `heif`
+    name: String,
+    // Whether libvips can decode and encode it
+    load: bool,
+    save: bool,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct LimitsInfo {
+    max_upload_bytes: usize,
+    // Inputs exceeding these are rejected
+    max_input_width: i32,
+    max_input_height: i32,
+    max_input_bit_depth: i32,
+    max_input_frames: i32,
+    max_input_pixels: i64,
+    // Requested renditions exceeding these are rejected or clamped
+    max_output_width: i32,
+    max_output_height: i32,
+    max_output_pixels: i64,
+    vips_timeout_secs: u64,
+    vips_concurrency: usize,
+    // Age after which cache entries are rendered again, if set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cache_ttl_secs: Option<u64>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct FeaturesInfo {
+    grpc: bool,
+    client_hints: bool,
+    preview_tokens: bool,
+    placeholder: bool,
+    proxy: bool,
+    replication: bool,
+    moderation: bool,
+    virus_scan: bool,
+    shadow_reads: bool,
+    uuid_v7: bool,
+    content_addressed_storage: bool,
+    // Names of the CDNs purged on changes, e.g. `cloudflare`
+    cdn_purge: Vec<String>,
+    // Names of the channels failures are notified on, e.g. `email`
+    notifications: Vec<String>,
+}
+
+/// Returns the version, the commit and the libvips version this service was built with, the
+/// codecs libvips supports, the configured limits and the enabled features, e.g. for deployment
+/// tooling to verify what is running. Requires an API key, as versions and enabled security
+/// features help attackers.
+#[utoipa::path(
+    get,
+    path = "/info",
+    tag = "admin",
+    responses(
+        (status = 200, description = "Versions, codecs, limits and features", body = ServiceInfo),
+        (status = 401, description = "Missing or invalid API key"),
+    ),
+    security(("api_key" = []))
+)]
+pub async fn info_handler(
+    State(server_state): State<ServerState>,
+    TypedHeader(authorization): TypedHeader<Authorization<Bearer>>,
+) -> Result<Json<ServiceInfo>, (StatusCode, String)> {
+    check_auth_header(authorization, &server_state.reloadable().api_key_hashes)?;
+
+    let input_limits = server_state.input_limits;
+    let output_limits = server_state.output_limits;
+    Ok(Json(ServiceInfo {
+        version: env!("CARGO_PKG_VERSION").to_owned(),
+        git_commit: env!("GIT_COMMIT").to_owned(),
+        libvips_version: libvips_version(),
+        codecs: CODECS
+            .iter()
+            .map(|codec| CodecInfo {
+                name: codec.to_string(),
+                load: has_operation(&format!("{}load", codec)),
+                save: has_operation(&format!("{}save", codec)),
+            })
+            .collect(),
+        limits: LimitsInfo {
+            max_upload_bytes: CONTENT_LENGTH_LIMIT,
+            max_input_width: input_limits.max_width,
+            max_input_height: input_limits.max_height,
+            max_input_bit_depth: input_limits.max_bit_depth,
+            max_input_frames: input_limits.max_frames,
+            max_input_pixels: input_limits.max_pixels,
+            max_output_width: output_limits.max_width,
+            max_output_height: output_limits.max_height,
+            max_output_pixels: output_limits.max_pixels,
+            vips_timeout_secs: server_state.watchdog.timeout.as_secs(),
+            vips_concurrency: server_state.watchdog.concurrency,
+            cache_ttl_secs: server_state.cache_ttl.map(|ttl| ttl.as_secs()),
+        },
+        features: FeaturesInfo {
+            grpc: cfg!(feature = "grpc"),
+            client_hints: server_state.client_hints_enabled,
+            preview_tokens: server_state.preview_tokens.is_some(),
+            placeholder: server_state.placeholder.is_some(),
+            proxy: server_state.proxy.is_some(),
+            replication: server_state.replicator.is_enabled(),
+            moderation: server_state.moderator.is_some(),
+            virus_scan: server_state.virus_scanner.is_some(),
+            shadow_reads: server_state.shadow_reader.is_some(),
+            uuid_v7: server_state.uuid_v7_enabled,
+            content_addressed_storage: server_state.cas_enabled,
+            cdn_purge: to_strings(server_state.cdn.provider_names()),
+            notifications: to_strings(server_state.notifier.channel_names()),
+        },
+    }))
+}
+
+/// Returns the version of the libvips library loaded at runtime, e.g. `8.15.2`
+fn libvips_version() -> String {
+    // SAFETY: libvips returns a pointer to a static, null-terminated string
+    unsafe { CStr::from_ptr(vips_version_string()) }
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// Returns whether libvips provides the operation `nickname`, e.g. `heifsave`, which depends on
+/// the libraries it was built with
+fn has_operation(nickname: &str) -> bool {
+    let (base, nickname) = (c"VipsOperation", CString::new(nickname).unwrap());
+    // SAFETY: Both are valid C strings, `vips_type_find` only looks the type up
+    unsafe { vips_type_find(base.as_ptr(), nickname.as_ptr()) != 0 }
+}
+
+fn to_strings(names: Vec<&'static str>) -> Vec<String> {
+    names.into_iter().map(str::to_owned).collect()
+}
@@ -0,0 +1,172 @@
+use crate::{
+    error::Error,
+    scheduler::{JobRun, JobRunState, TriggerError},
+    util::auth::check_auth_header,
+    ServerState,
+};
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use axum_extra::{
+    headers::{authorization::Bearer, Authorization},
+    TypedHeader,
+};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::time::SystemTime;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+#[derive(Serialize, ToSchema)]
+pub struct JobStatusResponse {
+    #[schema(value_type = String)]
+    name: &'static str,
+    schedule: String,
+    running: bool,
+    runs: u64,
+    // RFC 3339 timestamp of the start of the last run
+    last_run: Option<String>,
+    last_duration_ms: Option<u128>,
+    last_items_processed: usize,
+    last_error: Option<String>,
+}
+
+/// Lists all background jobs with the status of their last run
+#[utoipa::path(
+    get,
+    path = "/jobs",
+    tag = "jobs",
+    responses(
+        (status = 200, description = "Status of all background jobs", body = Vec<JobStatusResponse>),
+        (status = 401, description = "Missing or invalid API key"),
+    ),
+    security(("api_key" = []))
+)]
+pub async fn jobs_handler(
+    State(server_state): State<ServerState>,
+    TypedHeader(authorization): TypedHeader<Authorization<Bearer>>,
+) -> Result<Json<Vec<JobStatusResponse>>, (StatusCode, String)> {
+    check_auth_header(authorization, &server_state.reloadable().api_key_hashes)?;
+
+    Ok(Json(
+        server_state
+            .scheduler
+            .statuses()
+            .into_iter()
+            .map(|(name, status)| JobStatusResponse {
+                name: name,
+                schedule: status.schedule,
+                running: status.running,
+                runs: status.runs,
+                last_run: status.last_run.map(to_rfc3339),
+                last_duration_ms: status.last_duration.map(|duration| duration.as_millis()),
+                last_items_processed: status.last_items_processed,
+                last_error: status.last_error,
+            })
+            .collect(),
+    ))
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct JobRunResponse {
+    id: Uuid,
+    #[schema(value_type = String)]
+    job: &'static str,
+    state: JobRunState,
+    started_at: String,
+    duration_ms: Option<u128>,
+    items_processed: usize,
+    error: Option<String>,
+}
+
+impl From<JobRun> for JobRunResponse {
+    fn from(run: JobRun) -> Self {
+        Self {
+            id: run.id,
+            job: run.job,
+            state: run.state,
+            started_at: to_rfc3339(run.started_at),
+            duration_ms: run.duration.map(|duration| duration.as_millis()),
+            items_processed: run.items_processed,
+            error: run.error,
+        }
+    }
+}
+
+/// Runs the background job called `name` right away.
+/// Returns the new run, which can be polled via `/jobs/:name/runs/:id`.
+#[utoipa::path(
+    post,
+    path = "/jobs/{name}/run",
+    tag = "jobs",
+    params(("name" = String, Path, description = "Name of the job")),
+    responses(
+        (status = 202, description = "The new run", body = JobRunResponse),
+        (status = 401, description = "Missing or invalid API key"),
+        (status = 404, description = "Job not found"),
+        (status = 409, description = "Job is already running"),
+    ),
+    security(("api_key" = []))
+)]
+pub async fn job_run_handler(
+    State(server_state): State<ServerState>,
+    TypedHeader(authorization): TypedHeader<Authorization<Bearer>>,
+    Path(name): Path<String>,
+) -> Result<(StatusCode, Json<JobRunResponse>), (StatusCode, String)> {
+    check_auth_header(authorization, &server_state.reloadable().api_key_hashes)?;
+
+    let scheduler = &server_state.scheduler;
+    let id = match scheduler.trigger(&name) {
+        Err(TriggerError::UnknownJob) => {
+            return Err(Error::NotFound("Job not found".to_owned()).into())
+        }
+        Err(TriggerError::AlreadyRunning) => {
+            return Err((StatusCode::CONFLICT, "Job is already running!".to_owned()))
+        }
+        Ok(id) => id,
+    };
+
+    match scheduler.run(id) {
+        None => {
+            Err(Error::Internal(format!("Run {} of job '{}' was not recorded", id, name)).into())
+        }
+        Some(run) => Ok((StatusCode::ACCEPTED, Json(run.into()))),
+    }
+}
+
+/// Returns the run with `id` of the background job called `name`.
+/// Only the most recent runs are kept.
+#[utoipa::path(
+    get,
+    path = "/jobs/{name}/runs/{id}",
+    tag = "jobs",
+    params(
+        ("name" = String, Path, description = "Name of the job"),
+        ("id" = Uuid, Path, description = "ID of the run"),
+    ),
+    responses(
+        (status = 200, description = "The run", body = JobRunResponse),
+        (status = 401, description = "Missing or invalid API key"),
+        (status = 404, description = "Job run not found"),
+    ),
+    security(("api_key" = []))
+)]
+pub async fn job_run_status_handler(
+    State(server_state): State<ServerState>,
+    TypedHeader(authorization): TypedHeader<Authorization<Bearer>>,
+    Path((name, id)): Path<(String, Uuid)>,
+) -> Result<Json<JobRunResponse>, (StatusCode, String)> {
+    check_auth_header(authorization, &server_state.reloadable().api_key_hashes)?;
+
+    match server_state.scheduler.run(id) {
+        Some(run) if run.job == name => Ok(Json(run.into())),
+        _ => Err((StatusCode::NOT_FOUND, "Job run not found!".to_owned())),
+    }
+}
+
+fn to_rfc3339(time: SystemTime) -> String {
+    DateTime::<Utc>::from(time).to_rfc3339()
+}
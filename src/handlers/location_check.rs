@@ -0,0 +1,136 @@
+use crate::{
+    error::Error,
+    util::{
+        auth::check_auth_header,
+        location::scrub_location,
+        path::{get_raw_path, list_files, list_roots, write_atomically},
+    },
+    ServerState,
+};
+
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    Json,
+};
+use axum_extra::{
+    headers::{authorization::Bearer, Authorization},
+    TypedHeader,
+};
+use serde::{Deserialize, Serialize};
+use std::{fs, io};
+use utoipa::{IntoParams, ToSchema};
+use uuid::Uuid;
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct LocationCheckQuery {
+    /// Whether to remove the location metadata found, e.g. from raw files stored before it was
+    /// removed at upload. Defaults to false, i.e. only reporting them.
+    #[serde(default)]
+    scrub: bool,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct LocationOffender {
+    id: Uuid,
+    // What location metadata was found, e.g. `GPS IFD with 7 entries`
+    findings: Vec<String>,
+}
+
+#[derive(Default, Serialize, ToSchema)]
+pub struct LocationCheckReport {
+    // Number of raw files that were checked
+    checked: usize,
+    offenders: Vec<LocationOffender>,
+    // Number of offenders whose location metadata was removed
+    scrubbed: usize,
+}
+
+/// Checks all raw files for location metadata (GPS, maker notes and XMP geotags), which is
+/// removed at upload, e.g. to verify the stored files after an update or after importing files
+#[utoipa::path(
+    post,
+    path = "/raw/location-check",
+    tag = "admin",
+    params(LocationCheckQuery),
+    responses(
+        (status = 200, description = "Number of checked raw files and those with location metadata", body = LocationCheckReport),
+        (status = 401, description = "Missing or invalid API key"),
+        (status = 500, description = "The raw directory or a raw file could not be read or written"),
+    ),
+    security(("api_key" = []))
+)]
+pub async fn location_check_handler(
+    State(server_state): State<ServerState>,
+    TypedHeader(authorization): TypedHeader<Authorization<Bearer>>,
+    Query(query): Query<LocationCheckQuery>,
+) -> Result<Json<LocationCheckReport>, (StatusCode, String)> {
+    check_auth_header(authorization, &server_state.reloadable().api_key_hashes)?;
+
+    // Reading all raw files may take a while, so don't block the runtime
+    let res =
+        tokio::task::spawn_blocking(move || check_raw_files(query.scrub, &server_state)).await;
+
+    match res {
+        Ok(Ok(report)) => Ok(Json(report)),
+        Ok(Err(err)) => {
+            Err(Error::Internal(format!("Error during location check: {}", err)).into())
+        }
+        Err(err) => Err(Error::Internal(format!("Location check panicked: {}", err)).into()),
+    }
+}
+
+/// Runs `scrub_location` on each raw file and writes the result back if `scrub` is set.
+/// Images are locked while their raw file is checked, like by the integrity check.
+fn check_raw_files(scrub: bool, server_state: &ServerState) -> Result<LocationCheckReport, String> {
+    let mut report = LocationCheckReport::default();
+
+    let raw_paths = list_roots()
+        .into_iter()
+        .map(|tenant| get_raw_path(tenant.as_deref()));
+    for raw_path in raw_paths {
+        let names = list_files(&raw_path)
+            .map_err(|err| format!("Unable to read directory {:?}: {}", raw_path, err))?;
+
+        for name in names {
+            let Some(uuid) = name
+                .strip_suffix(".raw")
+                .and_then(|uuid| Uuid::parse_str(uuid).ok())
+            else {
+                continue;
+            };
+
+            let _lock = server_state.image_locks.blocking_lock(uuid);
+            let path = raw_path.join(&name);
+            let mut data = match fs::read(&path) {
+                // Deleted since the directory was listed
+                Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+                Err(err) => return Err(format!("Unable to read {:?}: {}", path, err)),
+                Ok(data) => data,
+            };
+
+            report.checked += 1;
+            let findings = scrub_location(&mut data);
+            if findings.is_empty() {
+                continue;
+            }
+
+            log::warn!(
+                "Raw file {:?} contains location metadata: {}",
+                path,
+                findings.join(", ")
+            );
+            if scrub {
+                write_atomically(&path, &data, server_state.durability)?;
+                report.scrubbed += 1;
+            }
+            report.offenders.push(LocationOffender {
+                id: uuid,
+                findings: findings,
+            });
+        }
+    }
+
+    Ok(report)
+}
@@ -0,0 +1,62 @@
+use crate::{
+    cdn::cache_tag,
+    error::Error,
+    operations::create_lqip_entry,
+    util::short_id::ImageIdParam,
+    util::{
+        image::{determine_img_path, get_lqip_entry},
+        path::get_original_path,
+    },
+    ServerState,
+};
+
+use axum::{
+    extract::{Path, State},
+    http::header,
+};
+use std::fs::read;
+
+/// Returns a low-quality image placeholder of an approved image, a tiny (24 pixels wide),
+/// heavily compressed and blurred WebP, that can be inlined to show a preview while the image
+/// is loaded. It is created when the image is approved (or rotated) and then served from cache.
+#[utoipa::path(
+    get,
+    path = "/image/{id}/lqip",
+    tag = "images",
+    params(("id" = String, Path, description = "UUID or short ID of the image")),
+    responses(
+        (status = 200, description = "The placeholder", content_type = "image/webp", body = Vec<u8>),
+        (status = 400, description = "Invalid ID"),
+        (status = 404, description = "Image not found"),
+        (status = 504, description = "Creating the placeholder took longer than `VIPS_TIMEOUT_SECS`"),
+    )
+)]
+pub async fn lqip_handler(
+    State(server_state): State<ServerState>,
+    Path(ImageIdParam(id)): Path<ImageIdParam>,
+) -> Result<([(header::HeaderName, String); 3], Vec<u8>), Error> {
+    if id.is_nil() {
+        return Err(Error::BadRequest("Invalid ID".to_owned()));
+    }
+    let tenant = server_state.metadata_index.tenant(id);
+    let path = determine_img_path(get_original_path(tenant.as_deref()).to_str().unwrap(), id)
+        .map_err(|_| Error::NotFound("Image not found".to_owned()))?;
+
+    // Created on request, if it was evicted from the cache or the image approved before
+    let body = match read(get_lqip_entry(id, tenant.as_deref())) {
+        Ok(body) => body,
+        Err(_) => create_lqip_entry(id, &path, &server_state).await?,
+    };
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, "image/webp".to_owned()),
+            (header::HeaderName::from_static("cache-tag"), cache_tag(id)),
+            (
+                header::HeaderName::from_static("surrogate-key"),
+                cache_tag(id),
+            ),
+        ],
+        body,
+    ))
+}
@@ -0,0 +1,147 @@
+use crate::{
+    util::{
+        auth::{check_auth, check_auth_header},
+        image::{find_stored_image, ImageState},
+        image_metadata::ImageMetadata,
+    },
+    ServerState,
+};
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use axum_extra::{
+    headers::{authorization::Bearer, Authorization},
+    TypedHeader,
+};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+/// Everything recorded about an image in the metadata index
+#[derive(Serialize, ToSchema)]
+pub struct ImageRecord {
+    id: Uuid,
+    state: ImageState,
+    created_at: DateTime<Utc>,
+    // Time the image entered its current state, e.g. was approved
+    state_changed_at: DateTime<Utc>,
+    /// SHA-256 of the stored image (hex), unknown for images stored before it was introduced
+    checksum: Option<String>,
+    metadata: ImageMetadata,
+    /// Human-readable name of the image, see `PUT /image/:id/alias`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    alias: Option<String>,
+}
+
+/// Returns the times, checksum and metadata (alt text, tags, focal point, ...) of the image.
+/// Without authorization, only approved images are returned.
+#[utoipa::path(
+    get,
+    path = "/image/{id}/metadata",
+    tag = "images",
+    params(("id" = Uuid, Path, description = "ID of the image")),
+    responses(
+        (status = 200, description = "The metadata", body = ImageRecord),
+        (status = 400, description = "Invalid ID"),
+        (status = 404, description = "Image not found"),
+    )
+)]
+pub async fn metadata_handler(
+    State(server_state): State<ServerState>,
+    authorization_header_opt: Option<TypedHeader<Authorization<Bearer>>>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<ImageRecord>, (StatusCode, String)> {
+    if id.is_nil() {
+        return Err((StatusCode::BAD_REQUEST, "Invalid ID!".to_owned()));
+    }
+    let authorized = check_auth(
+        None,
+        authorization_header_opt,
+        &server_state.reloadable().api_key_hashes,
+    )
+    .is_ok();
+
+    image_record(id, authorized, &server_state).map(Json)
+}
+
+/// Returns everything recorded about the image `id`. Unless `authorized`, only approved images
+/// are returned.
+pub fn image_record(
+    id: Uuid,
+    authorized: bool,
+    server_state: &ServerState,
+) -> Result<ImageRecord, (StatusCode, String)> {
+    let image = match find_stored_image(id, &server_state.metadata_index) {
+        Some(image) if authorized || image.state == ImageState::Approved => image,
+        _ => return Err((StatusCode::NOT_FOUND, "Image not found!".to_owned())),
+    };
+
+    Ok(ImageRecord {
+        id: id,
+        state: image.state,
+        created_at: image.created_at.into(),
+        state_changed_at: image.state_changed_at.into(),
+        checksum: server_state.metadata_index.checksum(id),
+        metadata: image.metadata,
+        alias: server_state.metadata_index.alias(id),
+    })
+}
+
+/// Replaces the metadata of the image, e.g. to correct the alt text or set a focal point.
+/// Tags are lowercased and deduplicated like those of uploads.
+#[utoipa::path(
+    put,
+    path = "/image/{id}/metadata",
+    tag = "images",
+    params(("id" = Uuid, Path, description = "ID of the image")),
+    request_body = ImageMetadata,
+    responses(
+        (status = 200, description = "The stored metadata", body = ImageMetadata),
+        (status = 400, description = "Invalid ID or metadata"),
+        (status = 401, description = "Missing or invalid API key"),
+        (status = 404, description = "Image not found"),
+    ),
+    security(("api_key" = []))
+)]
+pub async fn metadata_update_handler(
+    State(server_state): State<ServerState>,
+    TypedHeader(authorization): TypedHeader<Authorization<Bearer>>,
+    Path(id): Path<Uuid>,
+    Json(metadata): Json<ImageMetadata>,
+) -> Result<Json<ImageMetadata>, (StatusCode, String)> {
+    check_auth_header(authorization, &server_state.reloadable().api_key_hashes)?;
+
+    update_metadata(id, metadata, &server_state).await.map(Json)
+}
+
+/// Validates `metadata` and records it for the image `id`.
+/// Returns the stored metadata.
+pub async fn update_metadata(
+    id: Uuid,
+    mut metadata: ImageMetadata,
+    server_state: &ServerState,
+) -> Result<ImageMetadata, (StatusCode, String)> {
+    if id.is_nil() {
+        return Err((StatusCode::BAD_REQUEST, "Invalid ID!".to_owned()));
+    }
+    metadata.normalize_tags();
+    metadata
+        .validate()
+        .map_err(|err| (StatusCode::BAD_REQUEST, err))?;
+
+    // Serialized with state changes, so the metadata isn't recorded for a deleted image
+    let _lock = server_state.image_locks.lock(id).await;
+    if find_stored_image(id, &server_state.metadata_index).is_none() {
+        return Err((StatusCode::NOT_FOUND, "Image not found!".to_owned()));
+    }
+    server_state
+        .metadata_index
+        .record_metadata(id, metadata.clone());
+    server_state.replicator.replicate(id);
+
+    Ok(metadata)
+}
@@ -1,6 +1,35 @@
+pub mod admin;
+pub mod alias;
 pub mod approve;
+pub mod cache;
+pub mod compare;
+pub mod consistency;
+pub mod docs;
+pub mod export;
+pub mod fsck;
+pub mod graphql;
 pub mod image;
+pub mod images;
+pub mod import;
+pub mod info;
+pub mod jobs;
+pub mod location_check;
+pub mod lqip;
+pub mod metadata;
+pub mod preview_token;
+pub mod proxy;
+pub mod quarantine;
+pub mod raw;
+pub mod regenerate;
+pub mod reload;
+pub mod restore;
 pub mod rotate;
+pub mod srcset;
+pub mod stats;
 pub mod submit;
+pub mod tenant;
+pub mod thumbnails;
 pub mod unapprove;
 pub mod upload;
+pub mod verify;
+pub mod warmup;
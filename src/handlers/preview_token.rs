@@ -0,0 +1,78 @@
+use crate::{
+    constants::API_PREFIX,
+    util::{auth::check_auth_header, image::find_image},
+    ServerState,
+};
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use axum_extra::{
+    headers::{authorization::Bearer, Authorization},
+    TypedHeader,
+};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+#[derive(Serialize, ToSchema)]
+pub struct PreviewToken {
+    /// Passed as `?token=` to `/image/:id`
+    token: String,
+    expires_at: DateTime<Utc>,
+    /// URL of the image with the token, absolute if `PUBLIC_URL` is set
+    url: String,
+}
+
+/// Issues a token that grants access to the image via `/image/:id?token=<token>` until it
+/// expires, even while it is pending or unapproved. The backend can pass it to the uploading
+/// client, which can then preview its image without an API key.
+#[utoipa::path(
+    post,
+    path = "/image/{id}/preview-token",
+    tag = "images",
+    params(("id" = Uuid, Path, description = "ID of the image")),
+    responses(
+        (status = 200, description = "The token", body = PreviewToken),
+        (status = 400, description = "Invalid ID or preview tokens not enabled"),
+        (status = 401, description = "Missing or invalid API key"),
+        (status = 404, description = "Image not found"),
+    ),
+    security(("api_key" = []))
+)]
+pub async fn preview_token_handler(
+    State(server_state): State<ServerState>,
+    TypedHeader(authorization): TypedHeader<Authorization<Bearer>>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<PreviewToken>, (StatusCode, String)> {
+    check_auth_header(authorization, &server_state.reloadable().api_key_hashes)?;
+
+    let Some(preview_tokens) = &server_state.preview_tokens else {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "Preview tokens are not enabled!".to_owned(),
+        ));
+    };
+    if id.is_nil() {
+        return Err((StatusCode::BAD_REQUEST, "Invalid ID!".to_owned()));
+    }
+    if find_image(id, server_state.metadata_index.tenant(id).as_deref()).is_none() {
+        return Err((StatusCode::NOT_FOUND, "Image not found!".to_owned()));
+    }
+
+    let (token, expires_at) = preview_tokens.issue(id);
+    Ok(Json(PreviewToken {
+        url: format!(
+            "{}{}/image/{}?token={}",
+            server_state.public_url.as_deref().unwrap_or_default(),
+            API_PREFIX,
+            id,
+            token
+        ),
+        token: token,
+        expires_at: expires_at.into(),
+    }))
+}
@@ -0,0 +1,89 @@
+use crate::{
+    handlers::image::{image_handler_helper, ImageQuery},
+    util::{
+        auth::check_auth,
+        image::{validate_rendition, CacheBehavior},
+    },
+    ServerState,
+};
+
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use axum_extra::{
+    headers::{authorization::Bearer, Authorization},
+    TypedHeader,
+};
+use serde::Deserialize;
+use utoipa::IntoParams;
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ProxyQuery {
+    /// URL of the external image, its host has to be one of `PROXY_ALLOWED_HOSTS`
+    url: String,
+    /// Width in pixels, like at `/image/:id`
+    width: Option<i32>,
+    /// Height in pixels, like at `/image/:id`
+    height: Option<i32>,
+    /// Encoder quality from 1 to 100, defaults to 80
+    quality: Option<i32>,
+    /// API key, alternative to the Authorization header
+    auth: Option<String>,
+}
+
+/// Fetches an external image and returns it resized and encoded like a stored image, without
+/// storing it as original, e.g. to display images of partners with consistent sizing.
+/// Fetched images are cached for `PROXY_CACHE_TTL_SECS`, renditions are not cached.
+#[utoipa::path(
+    get,
+    path = "/proxy",
+    tag = "images",
+    params(ProxyQuery),
+    responses(
+        (status = 200, description = "The image", content_type = "image/webp", body = Vec<u8>),
+        (status = 400, description = "Invalid or not allowed URL, dimensions or quality"),
+        (status = 401, description = "Missing or invalid API key"),
+        (status = 404, description = "The proxy is disabled"),
+        (status = 413, description = "External image too large"),
+        (status = 422, description = "External image exceeds the input limits"),
+        (status = 502, description = "Fetching failed or the file is no supported image"),
+        (status = 504, description = "Fetching timed out"),
+    ),
+    security(("api_key" = []))
+)]
+pub async fn proxy_handler(
+    State(server_state): State<ServerState>,
+    authorization_header_opt: Option<TypedHeader<Authorization<Bearer>>>,
+    query: Query<ProxyQuery>,
+) -> Result<Response, (StatusCode, String)> {
+    check_auth(
+        query.auth.as_ref(),
+        authorization_header_opt,
+        &server_state.reloadable().api_key_hashes,
+    )?;
+
+    let Some(proxy) = &server_state.proxy else {
+        return Err((StatusCode::NOT_FOUND, "Proxy disabled!".to_owned()));
+    };
+    validate_rendition(query.width, query.height, query.quality)
+        .map_err(|err| (StatusCode::BAD_REQUEST, format!("{}!", err)))?;
+
+    let (uuid, path) = proxy.fetch(&query.url, &server_state.input_limits).await?;
+
+    // Not cached, the cache only holds renditions of stored images
+    let image_query = ImageQuery::with_size(query.width, query.height, query.quality);
+    image_handler_helper(
+        uuid,
+        path.to_str().unwrap(),
+        image_query,
+        CacheBehavior::Skip,
+        None,
+        &server_state,
+    )
+    .await
+    .map(IntoResponse::into_response)
+    .map_err(Into::into)
+}
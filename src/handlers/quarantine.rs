@@ -0,0 +1,39 @@
+use crate::{
+    error::Error,
+    quarantine::{list_quarantine, QuarantinedFile},
+    util::auth::check_auth_header,
+    ServerState,
+};
+
+use axum::{extract::State, http::StatusCode, Json};
+use axum_extra::{
+    headers::{authorization::Bearer, Authorization},
+    TypedHeader,
+};
+
+/// Lists the quarantined files, i.e. uploads that passed the header check but could not be
+/// decoded (with the reason) and files that were broken after an unclean shutdown, e.g. to
+/// investigate recurring encoding bugs of clients
+#[utoipa::path(
+    get,
+    path = "/quarantine",
+    tag = "admin",
+    responses(
+        (status = 200, description = "Quarantined files, most recent first", body = Vec<QuarantinedFile>),
+        (status = 401, description = "Missing or invalid API key"),
+    ),
+    security(("api_key" = []))
+)]
+pub async fn quarantine_handler(
+    State(server_state): State<ServerState>,
+    TypedHeader(authorization): TypedHeader<Authorization<Bearer>>,
+) -> Result<Json<Vec<QuarantinedFile>>, (StatusCode, String)> {
+    check_auth_header(authorization, &server_state.reloadable().api_key_hashes)?;
+
+    match list_quarantine() {
+        Err(err) => {
+            Err(Error::Internal(format!("Error while listing quarantined files: {}", err)).into())
+        }
+        Ok(files) => Ok(Json(files)),
+    }
+}
@@ -0,0 +1,97 @@
+use crate::{
+    error::Error,
+    util::short_id::ImageIdParam,
+    util::{
+        auth::check_auth_header,
+        image::{determine_file_type, get_raw_file},
+    },
+    ServerState,
+};
+
+use axum::{
+    body::{Body, Bytes},
+    extract::{Path, State},
+    http::{header, StatusCode},
+    response::IntoResponse,
+};
+use axum_extra::{
+    headers::{authorization::Bearer, Authorization},
+    TypedHeader,
+};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio_util::io::ReaderStream;
+
+// Enough for the headers of all supported file types, see `determine_file_type`
+const FILE_HEADER_LENGTH: usize = 16;
+
+/// Streams the raw file of an image, i.e. the exact bytes that were uploaded, e.g. for audits
+/// or to process it with external tools. Its content type is detected like for uploads.
+#[utoipa::path(
+    get,
+    path = "/raw/{id}",
+    tag = "images",
+    params(("id" = String, Path, description = "UUID or short ID of the image")),
+    responses(
+        (status = 200, description = "The raw file", content_type = "application/octet-stream", body = Vec<u8>),
+        (status = 400, description = "Invalid ID"),
+        (status = 401, description = "Missing or invalid API key"),
+        (status = 404, description = "Raw file not found"),
+    ),
+    security(("api_key" = []))
+)]
+pub async fn raw_handler(
+    State(server_state): State<ServerState>,
+    TypedHeader(authorization): TypedHeader<Authorization<Bearer>>,
+    Path(ImageIdParam(id)): Path<ImageIdParam>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let key = check_auth_header(authorization, &server_state.reloadable().api_key_hashes)?;
+
+    if id.is_nil() {
+        return Err(Error::BadRequest("Invalid ID".to_owned()).into());
+    }
+
+    let path = get_raw_file(id, server_state.metadata_index.tenant(id).as_deref());
+    let mut file = tokio::fs::File::open(&path)
+        .await
+        .map_err(|_| Error::NotFound("Raw file not found".to_owned()))?;
+
+    let internal_error =
+        |err: std::io::Error| Error::Internal(format!("Error while reading {:?}: {}", path, err));
+    // Only the header is needed to detect the file type, the rest is streamed
+    let mut file_header = Vec::with_capacity(FILE_HEADER_LENGTH);
+    (&mut file)
+        .take(FILE_HEADER_LENGTH as u64)
+        .read_to_end(&mut file_header)
+        .await
+        .map_err(internal_error)?;
+    file.rewind().await.map_err(internal_error)?;
+    let size = file.metadata().await.map_err(internal_error)?.len();
+
+    let (content_type, extension) = match determine_file_type(&Bytes::from(file_header)) {
+        None => ("application/octet-stream", "raw"),
+        Some(file_identification) => (
+            file_identification.content_type(),
+            file_identification.extension(),
+        ),
+    };
+    let headers = [
+        (header::CONTENT_TYPE, content_type.to_owned()),
+        (
+            header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"{}.{}\"", id, extension),
+        ),
+    ];
+    server_state.access_stats.record_bytes(id, Some(&key), size);
+    // Read at once to compare the bytes that are served, raw files are limited to
+    // `CONTENT_LENGTH_LIMIT` anyway
+    if let Some(shadow_reader) = &server_state.shadow_reader {
+        let mut content = Vec::with_capacity(size as usize);
+        file.read_to_end(&mut content)
+            .await
+            .map_err(internal_error)?;
+        let content = Bytes::from(content);
+        shadow_reader.compare(&path, content.clone());
+        return Ok((headers, Body::from(content)));
+    }
+    Ok((headers, Body::from_stream(ReaderStream::new(file))))
+}
@@ -0,0 +1,51 @@
+use crate::{operations::regenerate_image, util::auth::check_auth_header, ServerState};
+
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+};
+use axum_extra::{
+    headers::{authorization::Bearer, Authorization},
+    TypedHeader,
+};
+use serde::Deserialize;
+use utoipa::IntoParams;
+use uuid::Uuid;
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct RegenerateQuery {
+    /// Angle in degrees to rotate the raw file by, like at upload. Defaults to 0, as the angle of
+    /// the upload is not stored.
+    angle: Option<f64>,
+}
+
+/// Rebuilds the stored image from its raw file with the current encoder settings, e.g. after
+/// codec fixes. The image keeps its state; its cache entries are removed.
+#[utoipa::path(
+    post,
+    path = "/regenerate/{id}",
+    tag = "images",
+    params(("id" = Uuid, Path, description = "ID of the image"), RegenerateQuery),
+    responses(
+        (status = 200, description = "ID of the regenerated image", body = String),
+        (status = 400, description = "Invalid ID"),
+        (status = 401, description = "Missing or invalid API key"),
+        (status = 404, description = "Image or raw file not found"),
+        (status = 422, description = "Raw file exceeds the input limits"),
+        (status = 504, description = "Regenerating took longer than `VIPS_TIMEOUT_SECS`"),
+    ),
+    security(("api_key" = []))
+)]
+pub async fn regenerate_handler(
+    State(server_state): State<ServerState>,
+    TypedHeader(authorization): TypedHeader<Authorization<Bearer>>,
+    Path(id): Path<Uuid>,
+    query: Query<RegenerateQuery>,
+) -> Result<String, (StatusCode, String)> {
+    check_auth_header(authorization, &server_state.reloadable().api_key_hashes)?;
+
+    let angle = query.angle.unwrap_or(0.0);
+    regenerate_image(id, angle, &server_state).await?;
+    Ok(id.to_string())
+}
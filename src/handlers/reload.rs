@@ -0,0 +1,67 @@
+use crate::{
+    error::Error,
+    settings::{load_config, ReloadableConfig},
+    util::auth::check_auth_header,
+    ServerState,
+};
+
+use axum::{extract::State, http::StatusCode};
+use axum_extra::{
+    headers::{authorization::Bearer, Authorization},
+    TypedHeader,
+};
+
+/// Re-reads the config and swaps API key hashes and CORS origins without a restart.
+/// If the new config is invalid, the currently loaded config is kept.
+#[utoipa::path(
+    post,
+    path = "/reload",
+    tag = "admin",
+    responses(
+        (status = 200, description = "Config reloaded", body = String),
+        (status = 400, description = "Invalid or unreadable config, the previous one is kept"),
+        (status = 401, description = "Missing or invalid API key"),
+    ),
+    security(("api_key" = []))
+)]
+pub async fn reload_handler(
+    State(server_state): State<ServerState>,
+    TypedHeader(authorization): TypedHeader<Authorization<Bearer>>,
+) -> Result<String, (StatusCode, String)> {
+    check_auth_header(authorization, &server_state.reloadable().api_key_hashes)?;
+
+    let config = match load_config(&server_state.config_path) {
+        Err(err) => {
+            log::error!("Could not reload config: {}", err);
+            return Err(Error::BadRequest(format!(
+                "Could not read config, keeping the previous one: {}",
+                err
+            ))
+            .into());
+        }
+        Ok(config) => config,
+    };
+
+    let reloadable = match ReloadableConfig::from_config(&config) {
+        Err(err) => {
+            log::error!("Could not reload config: {}", err);
+            return Err(Error::BadRequest(format!(
+                "Invalid config, keeping the previous one: {}",
+                err
+            ))
+            .into());
+        }
+        Ok(reloadable) => reloadable,
+    };
+
+    log::info!(
+        "Reloaded config: {:?} password hashes, CORS origins {:?} (by route group {:?}), {} recipes",
+        reloadable.api_key_hashes.len(),
+        reloadable.cors_origins,
+        reloadable.cors_policy_origins,
+        reloadable.recipes.len()
+    );
+    server_state.swap_reloadable(reloadable);
+
+    Ok("Config reloaded".to_owned())
+}
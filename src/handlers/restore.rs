@@ -0,0 +1,347 @@
+use crate::{
+    content_store::store_original,
+    error::Error,
+    fsck::record_checksum,
+    handlers::export::ManifestEntry,
+    util::{
+        auth::check_auth_header,
+        durability::Durability,
+        image::{
+            check_upload_header, delete_image, find_image, get_raw_file, remove_cache_entries,
+            ImageState, RemovalBehavior,
+        },
+        image_metadata::ImageMetadata,
+        input_limits::InputLimits,
+        metadata_index::ImageTimes,
+        path::{create_root, write_atomically},
+        tenant::is_valid_tenant_name,
+    },
+    ServerState,
+};
+
+use axum::{
+    body::{Body, Bytes},
+    extract::{Query, State},
+    http::StatusCode,
+    Json,
+};
+use axum_extra::{
+    headers::{authorization::Bearer, Authorization},
+    TypedHeader,
+};
+use futures_util::TryStreamExt;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    io::{self, Read},
+    path::Path,
+};
+use tokio_util::io::{StreamReader, SyncIoBridge};
+use utoipa::{IntoParams, ToSchema};
+use uuid::Uuid;
+
+/// What to do with images of the archive that already exist (in any state)
+#[derive(Clone, Copy, Default, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ConflictBehavior {
+    /// Keep the existing image and its raw file
+    #[default]
+    Skip,
+    /// Replace the existing image (and its raw file, if included in the archive)
+    Overwrite,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct RestoreQuery {
+    /// What to do with images that already exist, defaults to `skip`
+    conflict: Option<ConflictBehavior>,
+}
+
+#[derive(Default, Serialize, ToSchema)]
+pub struct RestoreReport {
+    /// Number of restored images
+    restored: usize,
+    /// Number of restored raw files
+    raw_restored: usize,
+    /// Number of images and raw files that were skipped, as they already exist
+    skipped: usize,
+    /// Entries of the archive that are no images or raw files and were ignored
+    ignored: Vec<String>,
+    /// Images and raw files exceeding the input limits, which were not restored
+    rejected: Vec<String>,
+}
+
+/// Restores images from an archive created by `/export`, e.g. for disaster recovery or to
+/// clone an environment. The archive is streamed, so it may be larger than uploads.
+/// Images are placed into the state given by `manifest.json` or, if it is missing,
+/// by the directory they are stored in. Their times are restored from the manifest, too.
+/// Images and raw files are checked against the input limits like uploads, as they are served
+/// without being checked again.
+#[utoipa::path(
+    post,
+    path = "/restore",
+    tag = "images",
+    params(RestoreQuery),
+    request_body(content = Vec<u8>, description = "Archive created by `/export`", content_type = "application/x-tar"),
+    responses(
+        (status = 200, description = "Archive restored", body = RestoreReport),
+        (status = 400, description = "Invalid archive, images before the error have been restored"),
+        (status = 401, description = "Missing or invalid API key"),
+    ),
+    security(("api_key" = []))
+)]
+pub async fn restore_handler(
+    State(server_state): State<ServerState>,
+    TypedHeader(authorization): TypedHeader<Authorization<Bearer>>,
+    query: Query<RestoreQuery>,
+    body: Body,
+) -> Result<Json<RestoreReport>, (StatusCode, String)> {
+    check_auth_header(authorization, &server_state.reloadable().api_key_hashes)?;
+
+    // The archive is read by a blocking task while it is received
+    let stream = body.into_data_stream().map_err(io::Error::other);
+    let reader = SyncIoBridge::new(StreamReader::new(stream));
+    let conflict = query.conflict.unwrap_or_default();
+    let state = server_state.clone();
+    let result = tokio::task::spawn_blocking(move || read_archive(reader, conflict, &state))
+        .await
+        .map_err(|err| Error::Internal(format!("Restore task failed: {}", err)))?;
+
+    match result {
+        Err(err) => {
+            log::warn!("Restore aborted: {}", err);
+            Err((
+                StatusCode::BAD_REQUEST,
+                format!("Error while restoring archive: {}", err),
+            ))
+        }
+        Ok(report) => {
+            log::info!(
+                "Restored {} image(s) and {} raw file(s), skipped {}",
+                report.restored,
+                report.raw_restored,
+                report.skipped
+            );
+            Ok(Json(report))
+        }
+    }
+}
+
+/// Entry of the archive that is restored
+enum Target {
+    Image(Uuid, ImageState),
+    Raw(Uuid),
+}
+
+/// Reads the tar archive from `reader` and restores its images and raw files
+fn read_archive(
+    reader: impl Read,
+    conflict: ConflictBehavior,
+    server_state: &ServerState,
+) -> Result<RestoreReport, io::Error> {
+    let mut archive = tar::Archive::new(reader);
+    let mut report = RestoreReport::default();
+    let mut manifest: HashMap<String, ManifestEntry> = HashMap::new();
+    let mut restored = Vec::new();
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let name = entry.path()?.to_string_lossy().to_string();
+        let name = name.strip_prefix("./").unwrap_or(&name).to_owned();
+
+        if name == "manifest.json" {
+            let entries: Vec<ManifestEntry> = serde_json::from_reader(&mut entry)?;
+            manifest = entries
+                .into_iter()
+                .map(|entry| (entry.path.clone(), entry))
+                .collect();
+            continue;
+        }
+
+        // Only the layout of exports is accepted, so entries can't be written anywhere else
+        let Some(target) = parse_entry_name(&name, &manifest) else {
+            report.ignored.push(name);
+            continue;
+        };
+
+        match target {
+            Target::Image(uuid, state) => {
+                let _lock = server_state.image_locks.blocking_lock(uuid);
+                let existing_tenant = server_state.metadata_index.tenant(uuid);
+                let existing_tenant = existing_tenant.as_deref();
+                if let Some((existing_state, _)) = find_image(uuid, existing_tenant) {
+                    if conflict == ConflictBehavior::Skip {
+                        report.skipped += 1;
+                        continue;
+                    }
+                    let existing_path = existing_state.path(existing_tenant);
+                    delete_image(&existing_path, uuid, RemovalBehavior::Delete)?;
+                    remove_cache_entries(uuid, existing_tenant, RemovalBehavior::Delete);
+                    server_state.cdn.purge(&server_state.http_client, uuid);
+                }
+                let tenant = manifest_tenant(uuid, &manifest);
+                create_root(tenant.as_deref())?;
+                let path = state.path(tenant.as_deref()).join(format!("{}.avif", uuid));
+                let limits = &server_state.input_limits;
+                if let Err(err) = write_entry(&mut entry, &path, server_state.durability, limits)? {
+                    log::warn!("Not restoring {}: {}", name, err);
+                    report.rejected.push(name);
+                    continue;
+                }
+                restored.push((uuid, path));
+                report.restored += 1;
+            }
+            Target::Raw(uuid) => {
+                let tenant = manifest_tenant(uuid, &manifest);
+                create_root(tenant.as_deref())?;
+                let path = get_raw_file(uuid, tenant.as_deref());
+                if path.exists() && conflict == ConflictBehavior::Skip {
+                    report.skipped += 1;
+                    continue;
+                }
+                let limits = &server_state.input_limits;
+                if let Err(err) = write_entry(&mut entry, &path, server_state.durability, limits)? {
+                    log::warn!("Not restoring {}: {}", name, err);
+                    report.rejected.push(name);
+                    continue;
+                }
+                report.raw_restored += 1;
+            }
+        }
+    }
+
+    // The manifest is the first entry of exports, but may also come after the images
+    let by_id: HashMap<Uuid, &ManifestEntry> =
+        manifest.values().map(|entry| (entry.id, entry)).collect();
+    for (uuid, path) in restored {
+        match by_id.get(&uuid) {
+            None => server_state
+                .metadata_index
+                .record_upload(uuid, ImageMetadata::default(), None),
+            Some(entry) => {
+                server_state.metadata_index.record_times(
+                    uuid,
+                    ImageTimes {
+                        created_at: entry.created_at.into(),
+                        state_changed_at: entry.state_changed_at.into(),
+                    },
+                    entry.metadata.clone(),
+                );
+                if let Some(tenant) = manifest_tenant(uuid, &manifest) {
+                    server_state.metadata_index.record_tenant(uuid, tenant);
+                }
+                if let Some(alias) = &entry.alias {
+                    // Aliases of other images are kept, as they may have been assigned since
+                    if let Err(owner) = server_state.metadata_index.set_alias(uuid, alias.clone()) {
+                        log::warn!(
+                            "Not restoring alias '{}' of {}, as it belongs to {}",
+                            alias,
+                            uuid,
+                            owner
+                        );
+                    }
+                }
+            }
+        }
+        record_checksum(uuid, &path, server_state);
+        store_original(uuid, server_state);
+    }
+
+    Ok(report)
+}
+
+/// Returns the tenant of the image `uuid` according to the manifest, if it has a valid name.
+/// Images and raw files of tenants are restored to their root, see `get_root_path`.
+fn manifest_tenant(uuid: Uuid, manifest: &HashMap<String, ManifestEntry>) -> Option<String> {
+    manifest
+        .values()
+        .find(|entry| entry.id == uuid)
+        .and_then(|entry| entry.tenant.clone())
+        .filter(|tenant| is_valid_tenant_name(tenant))
+}
+
+/// Parses an entry name like `originals/<id>.avif` or `raw/<id>.raw`.
+/// The state of images is taken from the manifest, if they are listed there.
+fn parse_entry_name(name: &str, manifest: &HashMap<String, ManifestEntry>) -> Option<Target> {
+    let (dir, file) = name.split_once('/')?;
+    let parse_id = |suffix| {
+        Uuid::parse_str(file.strip_suffix(suffix)?)
+            .ok()
+            .filter(|uuid| !uuid.is_nil())
+    };
+
+    if dir == "raw" {
+        let uuid = parse_id(".raw")?;
+        return Some(Target::Raw(uuid));
+    }
+
+    let uuid = parse_id(".avif")?;
+    let state = match manifest.get(name) {
+        Some(entry) if entry.id == uuid => entry.state,
+        _ => *ImageState::ALL.iter().find(|state| {
+            state
+                .path(None)
+                .file_name()
+                .is_some_and(|state_dir| state_dir.to_string_lossy() == dir)
+        })?,
+    };
+    Some(Target::Image(uuid, state))
+}
+
+/// Writes the image or raw file `entry` to `path`, unless its header exceeds `input_limits`.
+/// Returns the problem of rejected entries.
+fn write_entry(
+    entry: &mut impl Read,
+    path: &Path,
+    durability: Durability,
+    input_limits: &InputLimits,
+) -> Result<Result<(), String>, io::Error> {
+    let mut data = Vec::new();
+    entry.read_to_end(&mut data)?;
+    let data = Bytes::from(data);
+    if let Err(err) = check_upload_header(&data, input_limits) {
+        return Ok(Err(err.to_string()));
+    }
+    // Written atomically, so an aborted restore can't leave a truncated image
+    write_atomically(path, &data, durability).map_err(io::Error::other)?;
+    Ok(Ok(()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::input_limits::tests::{limits, png_header, start_vips};
+
+    fn restore(data: &Bytes, name: &str) -> (Result<(), String>, bool) {
+        start_vips();
+        let path = std::env::temp_dir().join(format!("{}-{}", Uuid::new_v4(), name));
+        let result = write_entry(&mut data.as_ref(), &path, Durability::None, &limits()).unwrap();
+        let written = path.exists();
+        let _ = std::fs::remove_file(&path);
+        (result, written)
+    }
+
+    #[test]
+    fn write_entry_writes_images_within_limits() {
+        let data = png_header(640, 480);
+        assert_eq!(restore(&data, "image.avif"), (Ok(()), true));
+    }
+
+    #[test]
+    fn write_entry_rejects_oversized_images() {
+        let (result, written) = restore(&png_header(100_000, 100_000), "image.avif");
+        assert!(result.is_err());
+        assert!(!written);
+    }
+
+    #[test]
+    fn write_entry_rejects_oversized_raw_files() {
+        let (result, written) = restore(&png_header(1, 100_000), "image.raw");
+        assert!(result.is_err());
+        assert!(!written);
+    }
+}
@@ -1,135 +1,141 @@
-use crate::constants::ROTATION_QUALITY;
-use crate::util::image::remove_cache_entries;
 use crate::{
-    util::{
-        auth::check_auth_header,
-        image::{determine_img_dir, determine_img_path, save_image, ImageSearchBehaviour},
-    },
+    constants::{MAX_ROTATE_BATCH, ROTATE_BATCH_CONCURRENCY},
+    operations::rotate_image,
+    util::auth::check_auth_header,
     ServerState,
 };
 use axum::{
     extract::{Query, State},
     http::StatusCode,
+    Json,
 };
 use axum_extra::{
     headers::{authorization::Bearer, Authorization},
     TypedHeader,
 };
-use libvips::{ops, VipsImage};
-use serde::Deserialize;
-use std::fs::rename;
+use futures_util::{stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
 use uuid::Uuid;
 
-#[derive(Deserialize)]
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct RotateQuery {
-    id: Uuid,
-    angle: i64,
+    /// ID of the image
+    pub id: Uuid,
+    /// One of 90, 180 or 270
+    pub angle: i64,
+    /// Also rotate the image, if it is still pending (not submitted yet), defaults to false
+    pub include_pending: Option<bool>,
 }
 
+/// Rotates an existing (unapproved or approved) image clockwise.
+/// With `include_pending=true`, pending images are rotated as well, e.g. to fix them before
+/// submission.
+#[utoipa::path(
+    post,
+    path = "/rotate",
+    tag = "images",
+    params(RotateQuery),
+    responses(
+        (status = 200, description = "ID of the rotated image", body = String),
+        (status = 400, description = "Invalid angle"),
+        (status = 401, description = "Missing or invalid API key"),
+        (status = 404, description = "Image not found"),
+        (status = 504, description = "Rotating took longer than `VIPS_TIMEOUT_SECS`"),
+    ),
+    security(("api_key" = []))
+)]
 pub async fn rotate_handler(
     State(server_state): State<ServerState>,
     TypedHeader(authorization): TypedHeader<Authorization<Bearer>>,
     query: Query<RotateQuery>,
 ) -> Result<String, (StatusCode, String)> {
-    check_auth_header(authorization, &server_state.api_key_hashes)?;
-
-    if query.angle <= 0 || query.angle >= 360 || query.angle % 90 != 0 {
-        return Err((
-            StatusCode::BAD_REQUEST,
-            "Angle must be one of {90, 180, 270}!".to_owned(),
-        ));
-    }
-
-    let image_directory = match determine_img_dir(query.id, ImageSearchBehaviour::Valid) {
-        Ok(image_directory) => image_directory,
-        Err(_) => return Err((StatusCode::NOT_FOUND, "Image not found!".to_owned())),
-    };
-
-    let image_directory_string = image_directory.to_string_lossy().to_string();
+    check_auth_header(authorization, &server_state.reloadable().api_key_hashes)?;
 
-    let image_path = match determine_img_path(image_directory_string.as_str(), query.id) {
-        Err(err) => {
-            log::warn!(
-            "Image not found where the path was previously determined. Id: {:?}, Directory: {:?}, Error: {:?}",
-            query.id,
-            image_directory_string.as_str(),
-            err
-        );
-            return Err((StatusCode::NOT_FOUND, "Image not found!".to_owned()));
-        }
-        Ok(image_path) => image_path,
-    };
+    rotate_image(
+        query.id,
+        query.angle,
+        query.include_pending.unwrap_or(false),
+        &server_state,
+    )
+    .await?;
 
-    let image_path_string = image_path.to_string_lossy().to_string();
-
-    let image = match VipsImage::new_from_file(image_path_string.as_str()) {
-        Ok(image) => image,
-        Err(err) => {
-            log::error!(
-                "Error while opening image. Id: {:?}, Error: {:?}, Path: {:?}",
-                query.id,
-                err,
-                image_path
-            );
-            return Err((
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "Error while opening image!".to_owned(),
-            ));
-        }
-    };
+    Ok(query.id.to_string())
+}
 
-    let rotated = match ops::rotate(&image, query.angle as f64) {
-        Ok(rotated) => rotated,
-        Err(err) => {
-            log::error!(
-                "Error while rotating image. Id: {:?}, Error: {:?}",
-                query.id,
-                err
-            );
-            return Err((
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "Error while rotating image!".to_owned(),
-            ));
-        }
-    };
+#[derive(Deserialize, ToSchema)]
+pub struct RotateBatchEntry {
+    id: Uuid,
+    // One of 90, 180 or 270
+    angle: i64,
+    // Also rotate the image, if it is still pending, defaults to false
+    include_pending: Option<bool>,
+}
 
-    let rotated_image_path = format!(
-        "{}-rotation{}.avif",
-        image_path_string.strip_suffix(".avif").unwrap(),
-        query.angle
-    );
+#[derive(Serialize, ToSchema)]
+pub struct RotateBatchResult {
+    id: Uuid,
+    rotated: bool,
+    // Why the image was not rotated, e.g. that it was not found
+    error: Option<String>,
+}
 
-    match save_image(&rotated, rotated_image_path.as_str(), ROTATION_QUALITY) {
-        Ok(_) => (),
-        Err(err) => {
-            log::error!(
-                "Error while saving image. Id: {:?}, Error: {:?}",
-                query.id,
-                err
-            );
-            return Err((
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "Error while saving image!".to_owned(),
-            ));
-        }
-    }
+/// Rotates multiple images clockwise, e.g. a whole batch uploaded sideways. Expects a list of
+/// `{"id": ..., "angle": ...}` and returns the result of each image in the same order; a failed
+/// rotation does not abort the others.
+#[utoipa::path(
+    post,
+    path = "/rotate/batch",
+    tag = "images",
+    request_body = Vec<RotateBatchEntry>,
+    responses(
+        (status = 200, description = "Result of each image", body = Vec<RotateBatchResult>),
+        (status = 400, description = "Too many images"),
+        (status = 401, description = "Missing or invalid API key"),
+    ),
+    security(("api_key" = []))
+)]
+pub async fn rotate_batch_handler(
+    State(server_state): State<ServerState>,
+    TypedHeader(authorization): TypedHeader<Authorization<Bearer>>,
+    Json(entries): Json<Vec<RotateBatchEntry>>,
+) -> Result<Json<Vec<RotateBatchResult>>, (StatusCode, String)> {
+    check_auth_header(authorization, &server_state.reloadable().api_key_hashes)?;
 
-    match rename(rotated_image_path, image_path) {
-        Ok(_) => (),
-        Err(err) => {
-            log::error!(
-                "Error while renaming image. Id: {:?}, Error: {:?}",
-                query.id,
-                err
-            );
-            return Err((
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "Error while renaming image!".to_owned(),
-            ));
-        }
+    if entries.len() > MAX_ROTATE_BATCH {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!(
+                "At most {} images can be rotated at once!",
+                MAX_ROTATE_BATCH
+            ),
+        ));
     }
 
-    remove_cache_entries(query.id);
+    // Rotations decode and encode whole images, so only a few run at the same time
+    let results = stream::iter(entries)
+        .map(|entry| {
+            let server_state = server_state.clone();
+            async move {
+                let res = rotate_image(
+                    entry.id,
+                    entry.angle,
+                    entry.include_pending.unwrap_or(false),
+                    &server_state,
+                )
+                .await;
+                let error = res.err().map(|(_, message)| message);
+                RotateBatchResult {
+                    id: entry.id,
+                    rotated: error.is_none(),
+                    error: error,
+                }
+            }
+        })
+        .buffered(ROTATE_BATCH_CONCURRENCY)
+        .collect()
+        .await;
 
-    Ok(query.id.to_string())
+    Ok(Json(results))
 }
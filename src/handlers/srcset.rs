@@ -0,0 +1,142 @@
+use crate::{
+    constants::{API_PREFIX, DEFAULT_SRCSET_WIDTHS, MAX_SRCSET_WIDTHS},
+    error::Error,
+    util::short_id::ImageIdParam,
+    util::{
+        image::{determine_img_dim, determine_img_path, validate_rendition},
+        path::get_original_path,
+    },
+    ServerState,
+};
+
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct SrcsetQuery {
+    /// Comma separated widths in pixels, e.g. `320,640,1280`, defaults to `320,640,960,1280,1920`
+    widths: Option<String>,
+    /// WebP quality passed on to the image URLs, defaults to the one of `/image/:id`
+    quality: Option<i32>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct SrcsetEntry {
+    width: i32,
+    height: i32,
+    url: String,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct Srcset {
+    /// Intrinsic width of the image
+    width: i32,
+    /// Intrinsic height of the image
+    height: i32,
+    /// One entry per requested width that is not larger than the image
+    images: Vec<SrcsetEntry>,
+    /// Value for the `srcset` attribute of an `<img>`, e.g. `<url> 320w, <url> 640w`
+    srcset: String,
+}
+
+/// Returns the URLs of an approved image in multiple widths, so frontends can build
+/// `<img srcset>` without knowing the URL scheme.
+/// Widths larger than the image are left out, as images are not upscaled. If all are larger,
+/// the image is returned in its intrinsic width.
+#[utoipa::path(
+    get,
+    path = "/image/{id}/srcset",
+    tag = "images",
+    params(("id" = String, Path, description = "UUID or short ID of the image"), SrcsetQuery),
+    responses(
+        (status = 200, description = "URLs by width", body = Srcset),
+        (status = 400, description = "Invalid ID, widths or quality"),
+        (status = 404, description = "Image not found"),
+    )
+)]
+pub async fn srcset_handler(
+    State(server_state): State<ServerState>,
+    Path(ImageIdParam(id)): Path<ImageIdParam>,
+    query: Query<SrcsetQuery>,
+) -> Result<Json<Srcset>, (StatusCode, String)> {
+    if id.is_nil() {
+        return Err((StatusCode::BAD_REQUEST, "Invalid ID!".to_owned()));
+    }
+    validate_rendition(None, None, query.quality)
+        .map_err(|err| (StatusCode::BAD_REQUEST, format!("{}!", err)))?;
+    let widths = match &query.widths {
+        None => DEFAULT_SRCSET_WIDTHS.to_vec(),
+        Some(widths) => parse_widths(widths)?,
+    };
+
+    let tenant = server_state.metadata_index.tenant(id);
+    let path = determine_img_path(get_original_path(tenant.as_deref()).to_str().unwrap(), id)
+        .map_err(|_| Error::NotFound("Image not found".to_owned()))?;
+    let (width, height) = determine_img_dim(path.to_str().unwrap()).map_err(Error::from)?;
+
+    let mut widths: Vec<i32> = widths.into_iter().filter(|w| *w <= width).collect();
+    if widths.is_empty() {
+        widths.push(width);
+    }
+
+    let base_url = format!(
+        "{}{}/image/{}",
+        server_state.public_url.as_deref().unwrap_or_default(),
+        API_PREFIX,
+        id
+    );
+    let images: Vec<SrcsetEntry> = widths
+        .into_iter()
+        .map(|w| SrcsetEntry {
+            width: w,
+            // Images keep their aspect ratio, if only the width is set
+            height: (height as f64 * w as f64 / width as f64).round() as i32,
+            url: match query.quality {
+                None => format!("{}?width={}", base_url, w),
+                Some(quality) => format!("{}?width={}&quality={}", base_url, w, quality),
+            },
+        })
+        .collect();
+    let srcset = images
+        .iter()
+        .map(|image| format!("{} {}w", image.url, image.width))
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    Ok(Json(Srcset {
+        width: width,
+        height: height,
+        images: images,
+        srcset: srcset,
+    }))
+}
+
+/// Parses comma separated widths, which are returned sorted and without duplicates
+fn parse_widths(value: &str) -> Result<Vec<i32>, (StatusCode, String)> {
+    let mut widths = value
+        .split(',')
+        .map(|width| match width.trim().parse::<i32>() {
+            Ok(width) if width > 0 => Ok(width),
+            _ => Err((
+                StatusCode::BAD_REQUEST,
+                format!("Invalid width '{}'!", width),
+            )),
+        })
+        .collect::<Result<Vec<i32>, _>>()?;
+    widths.sort_unstable();
+    widths.dedup();
+
+    if widths.len() > MAX_SRCSET_WIDTHS {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("At most {} widths can be requested!", MAX_SRCSET_WIDTHS),
+        ));
+    }
+    Ok(widths)
+}
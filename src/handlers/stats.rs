@@ -0,0 +1,522 @@
+use crate::{
+    constants::{
+        ACCESS_STATS_RETENTION_SECS, DEFAULT_DISK_GROWTH_DAYS, DEFAULT_TOP_LIMIT,
+        DEFAULT_TOP_WINDOW_SECS, MAX_DISK_GROWTH_DAYS, MAX_LIST_LIMIT,
+    },
+    disk_space::available_data_space,
+    error::Error,
+    shadow_read::ShadowReadStats,
+    util::{
+        auth::check_auth_header,
+        image::{get_raw_file, list_images, ImageState},
+        path::{
+            dir_usage, get_cache_path, get_proxy_cache_path, get_quarantine_path, get_raw_path,
+            get_trash_path, list_roots,
+        },
+    },
+    ServerState,
+};
+
+use axum::{
+    extract::{Query, State},
+    http::{header, StatusCode},
+    response::IntoResponse,
+    Json,
+};
+use axum_extra::{
+    headers::{authorization::Bearer, Authorization},
+    TypedHeader,
+};
+use chrono::{DateTime, Days, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use utoipa::{IntoParams, ToSchema};
+use uuid::Uuid;
+
+#[derive(Serialize, ToSchema)]
+pub struct DirStats {
+    // Number of files
+    count: usize,
+    bytes: u64,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ImageStats {
+    pending: DirStats,
+    unapproved: DirStats,
+    flagged: DirStats,
+    approved: DirStats,
+    // Raw files as uploaded, of images in any state
+    raw: DirStats,
+    // Cached renditions of approved images
+    cache: DirStats,
+    // Deleted images and raw files, see `TRASH_ENABLED`
+    trash: DirStats,
+    // Free bytes on the data volume, uploads are rejected below `MIN_FREE_DISK_BYTES`. Not set, if
+    // it could not be determined.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    available_bytes: Option<u64>,
+    // How long files of each state are kept
+    retention: Vec<RetentionStats>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct RetentionStats {
+    state: String,
+    // What is deleted, e.g. "orphaned raw files"
+    description: String,
+    enabled: bool,
+    // Files are deleted once they are older than this
+    max_age_secs: u64,
+    // Files of approved images are deleted once they were approved longer ago than this
+    #[serde(skip_serializing_if = "Option::is_none")]
+    approved_max_age_secs: Option<u64>,
+}
+
+/// Returns the number and total size of the files in each state, the free space on the data
+/// volume and the retention policies, e.g. to monitor the moderation backlog
+#[utoipa::path(
+    get,
+    path = "/stats/images",
+    tag = "images",
+    responses(
+        (status = 200, description = "Counts and sizes by state", body = ImageStats),
+        (status = 401, description = "Missing or invalid API key"),
+    ),
+    security(("api_key" = []))
+)]
+pub async fn image_stats_handler(
+    State(server_state): State<ServerState>,
+    TypedHeader(authorization): TypedHeader<Authorization<Bearer>>,
+) -> Result<Json<ImageStats>, (StatusCode, String)> {
+    check_auth_header(authorization, &server_state.reloadable().api_key_hashes)?;
+
+    Ok(Json(ImageStats {
+        pending: stats_of_roots(|tenant| ImageState::Pending.path(tenant))?,
+        unapproved: stats_of_roots(|tenant| ImageState::Unapproved.path(tenant))?,
+        flagged: stats_of_roots(|tenant| ImageState::Flagged.path(tenant))?,
+        approved: stats_of_roots(|tenant| ImageState::Approved.path(tenant))?,
+        raw: stats_of_roots(get_raw_path)?,
+        cache: stats_of_roots(get_cache_path)?,
+        trash: stats_of_existing(&get_trash_path())?,
+        available_bytes: available_bytes(),
+        retention: server_state
+            .retention_policies
+            .iter()
+            .map(|policy| RetentionStats {
+                state: policy.cleaner.state.to_owned(),
+                description: policy.cleaner.description.to_owned(),
+                enabled: policy.config.enabled,
+                max_age_secs: policy.config.max_age.as_secs(),
+                approved_max_age_secs: policy.config.approved_max_age.map(|age| age.as_secs()),
+            })
+            .collect(),
+    }))
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct DiskQuery {
+    /// Number of days of growth, including today, defaults to 7 and is at most 365
+    days: Option<u32>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct DirUsage {
+    // `pending`, `unapproved`, `flagged`, `approved`, `raw`, `cache`, `proxy_cache`,
+    // `quarantine` or `trash`
+    directory: String,
+    // Number of files
+    count: usize,
+    bytes: u64,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct DayGrowth {
+    // Day (UTC) in the format `YYYY-MM-DD`
+    #[schema(value_type = String)]
+    date: NaiveDate,
+    // Number of images uploaded on this day that still exist
+    images: usize,
+    // Bytes of their stored and raw files
+    bytes: u64,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct DiskStats {
+    // Usage of each data directory
+    directories: Vec<DirUsage>,
+    // Of all data directories
+    total_bytes: u64,
+    // Free bytes on the data volume, not set if it could not be determined
+    #[serde(skip_serializing_if = "Option::is_none")]
+    available_bytes: Option<u64>,
+    // Images uploaded per day, ordered from the oldest day to today
+    growth: Vec<DayGrowth>,
+}
+
+/// Returns the number and total size of the files in each data directory and how much the
+/// images grew per day over the last days (by their upload times in the metadata index), e.g.
+/// for capacity planning without `du` on the host
+#[utoipa::path(
+    get,
+    path = "/stats/disk",
+    tag = "images",
+    params(DiskQuery),
+    responses(
+        (status = 200, description = "Usage by directory and growth by day", body = DiskStats),
+        (status = 400, description = "Invalid number of days"),
+        (status = 401, description = "Missing or invalid API key"),
+    ),
+    security(("api_key" = []))
+)]
+pub async fn disk_stats_handler(
+    State(server_state): State<ServerState>,
+    TypedHeader(authorization): TypedHeader<Authorization<Bearer>>,
+    query: Query<DiskQuery>,
+) -> Result<Json<DiskStats>, (StatusCode, String)> {
+    check_auth_header(authorization, &server_state.reloadable().api_key_hashes)?;
+
+    let days = query.days.unwrap_or(DEFAULT_DISK_GROWTH_DAYS);
+    if days == 0 || days > MAX_DISK_GROWTH_DAYS {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("days must be between 1 and {}!", MAX_DISK_GROWTH_DAYS),
+        ));
+    }
+
+    let mut directories = Vec::new();
+    for (directory, stats) in [
+        (
+            "pending",
+            stats_of_roots(|tenant| ImageState::Pending.path(tenant))?,
+        ),
+        (
+            "unapproved",
+            stats_of_roots(|tenant| ImageState::Unapproved.path(tenant))?,
+        ),
+        (
+            "flagged",
+            stats_of_roots(|tenant| ImageState::Flagged.path(tenant))?,
+        ),
+        (
+            "approved",
+            stats_of_roots(|tenant| ImageState::Approved.path(tenant))?,
+        ),
+        ("raw", stats_of_roots(get_raw_path)?),
+        ("cache", stats_of_roots(get_cache_path)?),
+        ("proxy_cache", stats_of_existing(&get_proxy_cache_path())?),
+        ("quarantine", stats_of_existing(&get_quarantine_path())?),
+        ("trash", stats_of_existing(&get_trash_path())?),
+    ] {
+        directories.push(DirUsage {
+            directory: directory.to_owned(),
+            count: stats.count,
+            bytes: stats.bytes,
+        });
+    }
+
+    Ok(Json(DiskStats {
+        total_bytes: directories.iter().map(|usage| usage.bytes).sum(),
+        directories: directories,
+        available_bytes: available_bytes(),
+        growth: growth_of(&server_state, days)?,
+    }))
+}
+
+/// Sums up the images uploaded on each of the last `days` days (including today), which still
+/// exist, and the sizes of their stored and raw files
+fn growth_of(server_state: &ServerState, days: u32) -> Result<Vec<DayGrowth>, Error> {
+    let first_day = Utc::now().date_naive() - Days::new(days as u64 - 1);
+    let mut growth: Vec<DayGrowth> = first_day
+        .iter_days()
+        .take(days as usize)
+        .map(|date| DayGrowth {
+            date: date,
+            images: 0,
+            bytes: 0,
+        })
+        .collect();
+
+    let images = list_images(&ImageState::ALL, &server_state.metadata_index)
+        .map_err(|err| Error::Internal(format!("Error while listing images: {}", err)))?;
+    for image in images {
+        let date = DateTime::<Utc>::from(image.created_at).date_naive();
+        let Ok(offset) = usize::try_from((date - first_day).num_days()) else {
+            continue;
+        };
+        let Some(day) = growth.get_mut(offset) else {
+            continue;
+        };
+        day.images += 1;
+        let tenant = image.tenant.as_deref();
+        day.bytes += [
+            image
+                .state
+                .path(tenant)
+                .join(format!("{}.avif", image.uuid)),
+            get_raw_file(image.uuid, tenant),
+        ]
+        .iter()
+        .filter_map(|path| fs::metadata(path).ok())
+        .map(|metadata| metadata.len())
+        .sum::<u64>();
+    }
+    Ok(growth)
+}
+
+/// Returns the free bytes on the data volume, or `None` if they could not be determined, so the
+/// other stats are still returned
+fn available_bytes() -> Option<u64> {
+    available_data_space()
+        .map_err(|err| log::error!("{}", err))
+        .ok()
+}
+
+/// Sums up the stats of the directory `dir` returns for the root of each tenant and the one of
+/// images without tenant, see `list_roots`
+fn stats_of_roots(dir: impl Fn(Option<&str>) -> PathBuf) -> Result<DirStats, Error> {
+    let mut total = DirStats { count: 0, bytes: 0 };
+    for tenant in list_roots() {
+        let stats = stats_of_existing(&dir(tenant.as_deref()))?;
+        total.count += stats.count;
+        total.bytes += stats.bytes;
+    }
+    Ok(total)
+}
+
+/// Returns the stats of `dir`, or empty ones if it doesn't exist, e.g. the quarantine and trash
+/// directories, which are only created once something is quarantined or deleted
+fn stats_of_existing(dir: &Path) -> Result<DirStats, Error> {
+    match dir.exists() {
+        true => stats_of(dir),
+        false => Ok(DirStats { count: 0, bytes: 0 }),
+    }
+}
+
+fn stats_of(dir: &Path) -> Result<DirStats, Error> {
+    match dir_usage(dir) {
+        Err(err) => Err(Error::Internal(format!(
+            "Error while listing {:?}: {}",
+            dir, err
+        ))),
+        Ok((count, bytes)) => Ok(DirStats {
+            count: count,
+            bytes: bytes,
+        }),
+    }
+}
+
+/// Returns how many files read since the start were identical, missing or different in the
+/// secondary copy of the data directory (`SHADOW_READ_PATH`), e.g. to gain confidence before
+/// migrating to another storage. Differences are logged as well.
+#[utoipa::path(
+    get,
+    path = "/stats/shadow-reads",
+    tag = "admin",
+    responses(
+        (status = 200, description = "Outcomes of the shadow reads", body = ShadowReadStats),
+        (status = 401, description = "Missing or invalid API key"),
+        (status = 404, description = "Shadow reads are disabled"),
+    ),
+    security(("api_key" = []))
+)]
+pub async fn shadow_read_stats_handler(
+    State(server_state): State<ServerState>,
+    TypedHeader(authorization): TypedHeader<Authorization<Bearer>>,
+) -> Result<Json<ShadowReadStats>, (StatusCode, String)> {
+    check_auth_header(authorization, &server_state.reloadable().api_key_hashes)?;
+
+    match &server_state.shadow_reader {
+        None => Err((
+            StatusCode::NOT_FOUND,
+            "Shadow reads are disabled!".to_owned(),
+        )),
+        Some(shadow_reader) => Ok(Json(shadow_reader.stats())),
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct TopQuery {
+    /// Window in seconds (rounded up to full hours), defaults to 86400 (one day) and is at most
+    /// 30 days
+    window_secs: Option<u64>,
+    /// Number of images, defaults to 10
+    limit: Option<usize>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct TopImage {
+    id: Uuid,
+    requests: u64,
+}
+
+/// Returns the most requested approved images within a time window, ordered by the number of
+/// requests, e.g. to decide which images to precache
+#[utoipa::path(
+    get,
+    path = "/stats/top",
+    tag = "images",
+    params(TopQuery),
+    responses(
+        (status = 200, description = "Most requested images", body = Vec<TopImage>),
+        (status = 400, description = "Invalid window or limit"),
+        (status = 401, description = "Missing or invalid API key"),
+    ),
+    security(("api_key" = []))
+)]
+pub async fn top_images_handler(
+    State(server_state): State<ServerState>,
+    TypedHeader(authorization): TypedHeader<Authorization<Bearer>>,
+    query: Query<TopQuery>,
+) -> Result<Json<Vec<TopImage>>, (StatusCode, String)> {
+    check_auth_header(authorization, &server_state.reloadable().api_key_hashes)?;
+
+    let (window, limit) = parse_window_and_limit(&query)?;
+    let top = server_state
+        .access_stats
+        .top(window, limit)
+        .into_iter()
+        .map(|(uuid, requests)| TopImage {
+            id: uuid,
+            requests: requests,
+        })
+        .collect();
+    Ok(Json(top))
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ImageBandwidth {
+    id: Uuid,
+    bytes: u64,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct KeyBandwidth {
+    // ID of the API key, the first 12 hex digits of the SHA-256 of its configured hash
+    key: String,
+    bytes: u64,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct BandwidthStats {
+    // Bytes served of all images
+    total_bytes: u64,
+    // Images with the most bytes served, ordered by them
+    images: Vec<ImageBandwidth>,
+    // Bytes served to each API key, ordered by them. Approved images are served without a key,
+    // so their requests only count if one is sent.
+    keys: Vec<KeyBandwidth>,
+}
+
+/// Returns the bytes served of images (at `/image/:id` and `/raw/:id`) within a time window, in
+/// total, per image and per API key, e.g. to attribute egress costs or to spot hotlinking
+#[utoipa::path(
+    get,
+    path = "/stats/bandwidth",
+    tag = "images",
+    params(TopQuery),
+    responses(
+        (status = 200, description = "Bytes served in total, per image and per API key", body = BandwidthStats),
+        (status = 400, description = "Invalid window or limit"),
+        (status = 401, description = "Missing or invalid API key"),
+    ),
+    security(("api_key" = []))
+)]
+pub async fn bandwidth_stats_handler(
+    State(server_state): State<ServerState>,
+    TypedHeader(authorization): TypedHeader<Authorization<Bearer>>,
+    query: Query<TopQuery>,
+) -> Result<Json<BandwidthStats>, (StatusCode, String)> {
+    check_auth_header(authorization, &server_state.reloadable().api_key_hashes)?;
+
+    let (window, limit) = parse_window_and_limit(&query)?;
+    let images = server_state.access_stats.image_bytes(window);
+    let keys = server_state.access_stats.key_bytes(window);
+    Ok(Json(BandwidthStats {
+        total_bytes: images.iter().map(|(_, bytes)| bytes).sum(),
+        images: images
+            .into_iter()
+            .take(limit)
+            .map(|(uuid, bytes)| ImageBandwidth {
+                id: uuid,
+                bytes: bytes,
+            })
+            .collect(),
+        keys: keys
+            .into_iter()
+            .map(|(key, bytes)| KeyBandwidth {
+                key: key,
+                bytes: bytes,
+            })
+            .collect(),
+    }))
+}
+
+/// Returns the bytes served since the start in the Prometheus text format, in total and per
+/// API key, so egress can be graphed and alerted on. Per image, they are only returned by
+/// `/stats/bandwidth`, as a time series per image would be too many.
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    tag = "admin",
+    responses(
+        (status = 200, description = "Metrics in the Prometheus text format", content_type = "text/plain", body = String),
+        (status = 401, description = "Missing or invalid API key"),
+    ),
+    security(("api_key" = []))
+)]
+pub async fn metrics_handler(
+    State(server_state): State<ServerState>,
+    TypedHeader(authorization): TypedHeader<Authorization<Bearer>>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    check_auth_header(authorization, &server_state.reloadable().api_key_hashes)?;
+
+    let served = server_state.access_stats.served_since_start();
+    let total: u64 = served.iter().map(|(_, bytes)| bytes).sum();
+    let mut lines = vec![
+        "# HELP mensatt_img_served_bytes_total Bytes of images served at /image/:id and /raw/:id"
+            .to_owned(),
+        "# TYPE mensatt_img_served_bytes_total counter".to_owned(),
+        format!("mensatt_img_served_bytes_total {}", total),
+        "# HELP mensatt_img_key_served_bytes_total Bytes of images served to each API key (by ID)"
+            .to_owned(),
+        "# TYPE mensatt_img_key_served_bytes_total counter".to_owned(),
+    ];
+    lines.extend(served.into_iter().filter_map(|(key, bytes)| {
+        Some(format!(
+            "mensatt_img_key_served_bytes_total{{key=\"{}\"}} {}",
+            key?, bytes
+        ))
+    }));
+    let metrics = lines.join("\n") + "\n";
+
+    let headers = [(header::CONTENT_TYPE, "text/plain; version=0.0.4")];
+    Ok((headers, metrics))
+}
+
+fn parse_window_and_limit(query: &TopQuery) -> Result<(Duration, usize), (StatusCode, String)> {
+    let window_secs = query.window_secs.unwrap_or(DEFAULT_TOP_WINDOW_SECS);
+    if window_secs == 0 || window_secs > ACCESS_STATS_RETENTION_SECS {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!(
+                "window_secs must be between 1 and {}!",
+                ACCESS_STATS_RETENTION_SECS
+            ),
+        ));
+    }
+    let limit = query.limit.unwrap_or(DEFAULT_TOP_LIMIT);
+    if limit == 0 || limit > MAX_LIST_LIMIT {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("limit must be between 1 and {}!", MAX_LIST_LIMIT),
+        ));
+    }
+    Ok((Duration::from_secs(window_secs), limit))
+}
@@ -1,48 +1,81 @@
 use crate::{
-    util::{
-        auth::check_auth_header,
-        image::move_image,
-        path::{get_pending_path, get_unapproved_path},
-    },
+    operations::submit_image,
+    util::{auth::check_auth_header, image::determine_img_dim},
+    webhook::{send_webhook, SubmitCallback},
     ServerState,
 };
 
 use axum::{
-    extract::{Path, State},
+    extract::{Path, Query, State},
     http::StatusCode,
-    response::IntoResponse,
 };
 use axum_extra::{
     headers::{authorization::Bearer, Authorization},
     TypedHeader,
 };
+use serde::Deserialize;
+use utoipa::IntoParams;
 use uuid::Uuid;
 
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct SubmitQuery {
+    /// URL that is called (signed) once the submitted image was validated.
+    /// Has to start with one of `CALLBACK_ALLOWED_URLS`.
+    callback: Option<String>,
+}
+
+/// Submits the pending image, so it can be approved. If the moderation hook flags it, it is held
+/// as flagged instead of unapproved.
+#[utoipa::path(
+    post,
+    path = "/submit/{id}",
+    tag = "images",
+    params(("id" = Uuid, Path, description = "ID of the image"), SubmitQuery),
+    responses(
+        (status = 200, description = "ID of the submitted image", body = String),
+        (status = 400, description = "Invalid ID or callback URL"),
+        (status = 401, description = "Missing or invalid API key"),
+        (status = 404, description = "Image not found"),
+    ),
+    security(("api_key" = []))
+)]
 pub async fn submit_handler(
     State(server_state): State<ServerState>,
     TypedHeader(authorization): TypedHeader<Authorization<Bearer>>,
     Path(uuid): Path<Uuid>,
-) -> impl IntoResponse {
-    check_auth_header(authorization, &server_state.api_key_hashes)?;
-    // Check ID
-    if uuid.is_nil() {
-        return Err((StatusCode::BAD_REQUEST, "Invalid ID!".to_owned()));
-    }
+    query: Query<SubmitQuery>,
+) -> Result<String, (StatusCode, String)> {
+    check_auth_header(authorization, &server_state.reloadable().api_key_hashes)?;
 
-    return match move_image(
-        get_pending_path().as_path(),
-        get_unapproved_path().as_path(),
-        uuid,
-    ) {
-        Err(err) => match err.kind() {
-            std::io::ErrorKind::NotFound => {
-                Err((StatusCode::NOT_FOUND, "Image not found!".to_owned()))
-            }
-            _ => Err((
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "Error while submitting image!".to_owned(),
-            )),
-        },
-        Ok(_) => Ok(uuid.to_string()),
+    // Check the callback first, so an invalid one doesn't leave the image submitted
+    let callback = match &query.callback {
+        None => None,
+        Some(value) => Some(server_state.webhooks.parse_callback_url(value)?),
     };
+
+    let state = submit_image(uuid, &server_state).await?;
+
+    if let Some(url) = callback {
+        let tenant = server_state.metadata_index.tenant(uuid);
+        let path = state.path(tenant.as_deref()).join(format!("{}.avif", uuid));
+        let dimensions = determine_img_dim(path.to_str().unwrap());
+        if let Err(err) = &dimensions {
+            log::error!("Submitted image {} could not be read: {}", uuid, err);
+        }
+        send_webhook(
+            &server_state.http_client,
+            url,
+            server_state.webhooks.secret.as_deref(),
+            SubmitCallback {
+                event: "submitted",
+                id: uuid,
+                valid: dimensions.is_ok(),
+                width: dimensions.as_ref().ok().map(|(width, _)| *width),
+                height: dimensions.as_ref().ok().map(|(_, height)| *height),
+            },
+        );
+    }
+
+    Ok(uuid.to_string())
 }
@@ -0,0 +1,426 @@
+use crate::{
+    error::Error,
+    handlers::{
+        image::{serve_image, ImageQuery},
+        images::{image_page, ImageListEntry, ThumbnailQuery},
+        metadata::{image_record, update_metadata, ImageRecord},
+        rotate::RotateQuery,
+    },
+    operations::{
+        approve_image, delete_image_everywhere, rotate_image, submit_image, unapprove_image,
+    },
+    util::{
+        auth::{check_auth, check_auth_header},
+        image::{find_image, list_tenant_images, RemovalBehavior},
+        image_metadata::ImageMetadata,
+        listing::{ListQuery, Page},
+        path::get_root_path,
+        short_id::ImageIdParam,
+        tenant::{stored_bytes, Tenant},
+    },
+    ServerState,
+};
+
+use argon2::password_hash::PasswordHashString;
+use axum::{
+    extract::{Path, Query, State},
+    http::{HeaderMap, StatusCode},
+    response::Response,
+    Json,
+};
+use axum_extra::{
+    headers::{authorization::Bearer, Authorization},
+    TypedHeader,
+};
+use serde::Serialize;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+/// Images and bytes stored for a tenant
+#[derive(Serialize, ToSchema)]
+pub struct TenantUsage {
+    pub images: usize,
+    // Of the stored images and their raw files
+    pub bytes: u64,
+    // Unlimited if not set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quota_bytes: Option<u64>,
+}
+
+/// Returns the tenant `name` from the reloadable config or 404, if it is not configured
+pub fn find_tenant(name: &str, server_state: &ServerState) -> Result<Tenant, (StatusCode, String)> {
+    server_state
+        .reloadable()
+        .tenants
+        .get(name)
+        .cloned()
+        .ok_or_else(|| (StatusCode::NOT_FOUND, format!("Unknown tenant '{}'!", name)))
+}
+
+/// Returns the number and size of the images of the tenant `name`. Sizes are taken from the
+/// metadata index, only images whose size was not recorded are looked up.
+pub fn tenant_usage(name: &str, server_state: &ServerState) -> TenantUsage {
+    let images = server_state.metadata_index.tenant_images(name);
+    let bytes = images
+        .iter()
+        .map(|(uuid, bytes)| {
+            bytes.unwrap_or_else(|| {
+                find_image(*uuid, Some(name))
+                    .map(|(_, path)| stored_bytes(*uuid, Some(name), &path))
+                    .unwrap_or(0)
+            })
+        })
+        .sum();
+
+    TenantUsage {
+        images: images.len(),
+        bytes: bytes,
+        quota_bytes: None,
+    }
+}
+
+/// Returns the API key hashes accepted for the tenant `name`, i.e. its own and `API_KEY_HASHES`,
+/// or 404, if it is not configured
+fn tenant_hashes(
+    name: &str,
+    server_state: &ServerState,
+) -> Result<Vec<PasswordHashString>, (StatusCode, String)> {
+    let mut hashes = find_tenant(name, server_state)?.api_key_hashes;
+    hashes.extend(server_state.reloadable().api_key_hashes.iter().cloned());
+    Ok(hashes)
+}
+
+/// Checks the API key against the hashes of the tenant `name` and that the image `uuid` belongs
+/// to it. Images of other tenants are reported as missing, so their IDs can't be probed.
+fn check_tenant_access(
+    name: &str,
+    uuid: Uuid,
+    authorization: Authorization<Bearer>,
+    server_state: &ServerState,
+) -> Result<(), (StatusCode, String)> {
+    check_auth_header(authorization, &tenant_hashes(name, server_state)?)?;
+    check_owner(name, uuid, server_state)
+}
+
+fn check_owner(
+    name: &str,
+    uuid: Uuid,
+    server_state: &ServerState,
+) -> Result<(), (StatusCode, String)> {
+    match server_state.metadata_index.tenant(uuid) {
+        Some(owner) if owner == name => Ok(()),
+        _ => Err((StatusCode::NOT_FOUND, "Image not found!".to_owned())),
+    }
+}
+
+/// Returns an image of the tenant like `/image/:id`. Unapproved and pending images are only
+/// returned with an API key of the tenant (or a global one) or a preview token.
+#[utoipa::path(
+    get,
+    path = "/t/{tenant}/image/{id}",
+    tag = "tenants",
+    params(
+        ("tenant" = String, Path, description = "Name of the tenant"),
+        ("id" = String, Path, description = "UUID or short ID of the image"),
+        ImageQuery
+    ),
+    responses(
+        (status = 200, description = "The image", content_type = "image/webp", body = Vec<u8>),
+        (status = 400, description = "Invalid ID, dimensions, quality, operations or recipe"),
+        (status = 404, description = "Tenant or image of the tenant not found"),
+        (status = 504, description = "Rendering took longer than `VIPS_TIMEOUT_SECS`"),
+    )
+)]
+pub async fn tenant_image_handler(
+    State(server_state): State<ServerState>,
+    authorization_header_opt: Option<TypedHeader<Authorization<Bearer>>>,
+    Path((tenant, ImageIdParam(id))): Path<(String, ImageIdParam)>,
+    request_headers: HeaderMap,
+    query: Query<ImageQuery>,
+) -> Result<Response, (StatusCode, String)> {
+    let hashes = tenant_hashes(&tenant, &server_state)?;
+    check_owner(&tenant, id, &server_state)?;
+
+    serve_image(
+        id,
+        authorization_header_opt,
+        &request_headers,
+        query,
+        &hashes,
+        &server_state,
+    )
+    .await
+}
+
+/// Deletes an image of the tenant like `DELETE /image/:id`
+#[utoipa::path(
+    delete,
+    path = "/t/{tenant}/image/{id}",
+    tag = "tenants",
+    params(
+        ("tenant" = String, Path, description = "Name of the tenant"),
+        ("id" = Uuid, Path, description = "ID of the image")
+    ),
+    responses(
+        (status = 200, description = "ID of the deleted image", body = String),
+        (status = 400, description = "Invalid ID"),
+        (status = 401, description = "Missing or invalid API key"),
+        (status = 404, description = "Tenant or image of the tenant not found"),
+    ),
+    security(("api_key" = []))
+)]
+pub async fn tenant_image_delete_handler(
+    State(server_state): State<ServerState>,
+    TypedHeader(authorization): TypedHeader<Authorization<Bearer>>,
+    Path((tenant, uuid)): Path<(String, Uuid)>,
+) -> Result<String, (StatusCode, String)> {
+    check_tenant_access(&tenant, uuid, authorization, &server_state)?;
+
+    delete_image_everywhere(uuid, RemovalBehavior::Delete, &server_state).await?;
+    Ok(uuid.to_string())
+}
+
+/// Submits a pending image of the tenant like `/submit/:id`
+#[utoipa::path(
+    post,
+    path = "/t/{tenant}/submit/{id}",
+    tag = "tenants",
+    params(
+        ("tenant" = String, Path, description = "Name of the tenant"),
+        ("id" = Uuid, Path, description = "ID of the image")
+    ),
+    responses(
+        (status = 200, description = "ID of the submitted image", body = String),
+        (status = 400, description = "Invalid ID"),
+        (status = 401, description = "Missing or invalid API key"),
+        (status = 404, description = "Tenant or image of the tenant not found"),
+    ),
+    security(("api_key" = []))
+)]
+pub async fn tenant_submit_handler(
+    State(server_state): State<ServerState>,
+    TypedHeader(authorization): TypedHeader<Authorization<Bearer>>,
+    Path((tenant, uuid)): Path<(String, Uuid)>,
+) -> Result<String, (StatusCode, String)> {
+    check_tenant_access(&tenant, uuid, authorization, &server_state)?;
+
+    submit_image(uuid, &server_state).await?;
+    Ok(uuid.to_string())
+}
+
+/// Approves a submitted image of the tenant like `/approve/:id`
+#[utoipa::path(
+    post,
+    path = "/t/{tenant}/approve/{id}",
+    tag = "tenants",
+    params(
+        ("tenant" = String, Path, description = "Name of the tenant"),
+        ("id" = Uuid, Path, description = "ID of the image")
+    ),
+    responses(
+        (status = 200, description = "ID of the approved image", body = String),
+        (status = 400, description = "Invalid ID"),
+        (status = 401, description = "Missing or invalid API key"),
+        (status = 404, description = "Tenant or image of the tenant not found"),
+    ),
+    security(("api_key" = []))
+)]
+pub async fn tenant_approve_handler(
+    State(server_state): State<ServerState>,
+    TypedHeader(authorization): TypedHeader<Authorization<Bearer>>,
+    Path((tenant, uuid)): Path<(String, Uuid)>,
+) -> Result<String, (StatusCode, String)> {
+    check_tenant_access(&tenant, uuid, authorization, &server_state)?;
+
+    approve_image(uuid, &server_state).await?;
+    Ok(uuid.to_string())
+}
+
+/// Unapproves an approved image of the tenant like `/unapprove/:id`
+#[utoipa::path(
+    post,
+    path = "/t/{tenant}/unapprove/{id}",
+    tag = "tenants",
+    params(
+        ("tenant" = String, Path, description = "Name of the tenant"),
+        ("id" = Uuid, Path, description = "ID of the image")
+    ),
+    responses(
+        (status = 200, description = "ID of the unapproved image", body = String),
+        (status = 400, description = "Invalid ID"),
+        (status = 401, description = "Missing or invalid API key"),
+        (status = 404, description = "Tenant or image of the tenant not found"),
+    ),
+    security(("api_key" = []))
+)]
+pub async fn tenant_unapprove_handler(
+    State(server_state): State<ServerState>,
+    TypedHeader(authorization): TypedHeader<Authorization<Bearer>>,
+    Path((tenant, uuid)): Path<(String, Uuid)>,
+) -> Result<String, (StatusCode, String)> {
+    check_tenant_access(&tenant, uuid, authorization, &server_state)?;
+
+    unapprove_image(uuid, &server_state).await?;
+    Ok(uuid.to_string())
+}
+
+/// Returns the number and size of the images of the tenant and its quota
+#[utoipa::path(
+    get,
+    path = "/t/{tenant}/usage",
+    tag = "tenants",
+    params(("tenant" = String, Path, description = "Name of the tenant")),
+    responses(
+        (status = 200, description = "Usage of the tenant", body = TenantUsage),
+        (status = 401, description = "Missing or invalid API key"),
+        (status = 404, description = "Tenant not found"),
+    ),
+    security(("api_key" = []))
+)]
+pub async fn tenant_usage_handler(
+    State(server_state): State<ServerState>,
+    TypedHeader(authorization): TypedHeader<Authorization<Bearer>>,
+    Path(tenant): Path<String>,
+) -> Result<Json<TenantUsage>, (StatusCode, String)> {
+    check_auth_header(authorization, &tenant_hashes(&tenant, &server_state)?)?;
+
+    Ok(Json(TenantUsage {
+        quota_bytes: find_tenant(&tenant, &server_state)?.quota_bytes,
+        ..tenant_usage(&tenant, &server_state)
+    }))
+}
+
+/// Lists the images of the tenant like `/images`
+#[utoipa::path(
+    get,
+    path = "/t/{tenant}/images",
+    tag = "tenants",
+    params(
+        ("tenant" = String, Path, description = "Name of the tenant"),
+        ListQuery,
+        ThumbnailQuery
+    ),
+    responses(
+        (status = 200, description = "One page of images of the tenant", body = crate::util::listing::ImagePage),
+        (status = 400, description = "Invalid query or thumbnail width"),
+        (status = 401, description = "Missing or invalid API key"),
+        (status = 404, description = "Tenant not found"),
+    ),
+    security(("api_key" = []))
+)]
+pub async fn tenant_images_handler(
+    State(server_state): State<ServerState>,
+    TypedHeader(authorization): TypedHeader<Authorization<Bearer>>,
+    Path(tenant): Path<String>,
+    query: Query<ListQuery>,
+    Query(thumbnail_query): Query<ThumbnailQuery>,
+) -> Result<Json<Page<ImageListEntry>>, (StatusCode, String)> {
+    check_auth_header(authorization, &tenant_hashes(&tenant, &server_state)?)?;
+
+    // The root is only created by the first upload of the tenant
+    let images = match get_root_path(Some(&tenant)).exists() {
+        false => Vec::new(),
+        true => list_tenant_images(&query.states(), Some(&tenant), &server_state.metadata_index)
+            .map_err(|err| {
+                Error::Internal(format!(
+                    "Error while listing images of tenant '{}': {}",
+                    tenant, err
+                ))
+            })?,
+    };
+
+    image_page(images, &query, thumbnail_query, &server_state)
+}
+
+/// Rotates an image of the tenant clockwise like `/rotate`
+#[utoipa::path(
+    post,
+    path = "/t/{tenant}/rotate",
+    tag = "tenants",
+    params(("tenant" = String, Path, description = "Name of the tenant"), RotateQuery),
+    responses(
+        (status = 200, description = "ID of the rotated image", body = String),
+        (status = 400, description = "Invalid angle"),
+        (status = 401, description = "Missing or invalid API key"),
+        (status = 404, description = "Tenant or image of the tenant not found"),
+        (status = 504, description = "Rotating took longer than `VIPS_TIMEOUT_SECS`"),
+    ),
+    security(("api_key" = []))
+)]
+pub async fn tenant_rotate_handler(
+    State(server_state): State<ServerState>,
+    TypedHeader(authorization): TypedHeader<Authorization<Bearer>>,
+    Path(tenant): Path<String>,
+    query: Query<RotateQuery>,
+) -> Result<String, (StatusCode, String)> {
+    check_tenant_access(&tenant, query.id, authorization, &server_state)?;
+
+    rotate_image(
+        query.id,
+        query.angle,
+        query.include_pending.unwrap_or(false),
+        &server_state,
+    )
+    .await?;
+    Ok(query.id.to_string())
+}
+
+/// Returns the metadata of an image of the tenant like `/image/:id/metadata`. Without an API key
+/// of the tenant (or a global one), only approved images are returned.
+#[utoipa::path(
+    get,
+    path = "/t/{tenant}/image/{id}/metadata",
+    tag = "tenants",
+    params(
+        ("tenant" = String, Path, description = "Name of the tenant"),
+        ("id" = Uuid, Path, description = "ID of the image")
+    ),
+    responses(
+        (status = 200, description = "The metadata", body = ImageRecord),
+        (status = 400, description = "Invalid ID"),
+        (status = 404, description = "Tenant or image of the tenant not found"),
+    )
+)]
+pub async fn tenant_metadata_handler(
+    State(server_state): State<ServerState>,
+    authorization_header_opt: Option<TypedHeader<Authorization<Bearer>>>,
+    Path((tenant, id)): Path<(String, Uuid)>,
+) -> Result<Json<ImageRecord>, (StatusCode, String)> {
+    let hashes = tenant_hashes(&tenant, &server_state)?;
+    if id.is_nil() {
+        return Err((StatusCode::BAD_REQUEST, "Invalid ID!".to_owned()));
+    }
+    check_owner(&tenant, id, &server_state)?;
+    let authorized = check_auth(None, authorization_header_opt, &hashes).is_ok();
+
+    image_record(id, authorized, &server_state).map(Json)
+}
+
+/// Replaces the metadata of an image of the tenant like `PUT /image/:id/metadata`
+#[utoipa::path(
+    put,
+    path = "/t/{tenant}/image/{id}/metadata",
+    tag = "tenants",
+    params(
+        ("tenant" = String, Path, description = "Name of the tenant"),
+        ("id" = Uuid, Path, description = "ID of the image")
+    ),
+    request_body = ImageMetadata,
+    responses(
+        (status = 200, description = "The stored metadata", body = ImageMetadata),
+        (status = 400, description = "Invalid ID or metadata"),
+        (status = 401, description = "Missing or invalid API key"),
+        (status = 404, description = "Tenant or image of the tenant not found"),
+    ),
+    security(("api_key" = []))
+)]
+pub async fn tenant_metadata_update_handler(
+    State(server_state): State<ServerState>,
+    TypedHeader(authorization): TypedHeader<Authorization<Bearer>>,
+    Path((tenant, id)): Path<(String, Uuid)>,
+    Json(metadata): Json<ImageMetadata>,
+) -> Result<Json<ImageMetadata>, (StatusCode, String)> {
+    check_tenant_access(&tenant, id, authorization, &server_state)?;
+
+    update_metadata(id, metadata, &server_state).await.map(Json)
+}
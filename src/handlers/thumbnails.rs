@@ -0,0 +1,153 @@
+use crate::{
+    constants::{EXPORT_BUFFER_SIZE, MAX_THUMBNAIL_ARCHIVE_IMAGES},
+    error::Error,
+    handlers::image::{image_handler_helper, ImageQuery},
+    util::{
+        auth::check_auth_header,
+        image::{find_image, validate_rendition, CacheBehavior, ImageState},
+        zip::ZipWriter,
+    },
+    ServerState,
+};
+
+use axum::{
+    body::Body,
+    extract::State,
+    http::{header, StatusCode},
+    response::IntoResponse,
+    Json,
+};
+use axum_extra::{
+    headers::{authorization::Bearer, Authorization},
+    TypedHeader,
+};
+use chrono::Utc;
+use serde::Deserialize;
+use std::path::PathBuf;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tokio_util::io::ReaderStream;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+#[derive(Deserialize, ToSchema)]
+pub struct ThumbnailsRequest {
+    // IDs of the images (in any state), duplicates are only included once
+    ids: Vec<Uuid>,
+    // Dimensions like at `/image/:id`, at least one of them is required
+    width: Option<i32>,
+    height: Option<i32>,
+    // Encoder quality from 1 to 100, defaults to 80
+    quality: Option<i32>,
+}
+
+/// Returns a zip archive of the given images rendered as thumbnails, e.g. for printing menus,
+/// instead of requesting them one by one. The thumbnails are named `<id>.webp` (or the extension of
+/// `DEFAULT_OUTPUT_FORMAT`). The archive is streamed while the thumbnails are rendered, so if
+/// rendering one fails, it ends incomplete.
+#[utoipa::path(
+    post,
+    path = "/thumbnails.zip",
+    tag = "images",
+    request_body = ThumbnailsRequest,
+    responses(
+        (status = 200, description = "The archive", content_type = "application/zip", body = Vec<u8>),
+        (status = 400, description = "Too many IDs, or invalid dimensions or quality"),
+        (status = 401, description = "Missing or invalid API key"),
+        (status = 404, description = "An image was not found"),
+    ),
+    security(("api_key" = []))
+)]
+pub async fn thumbnails_handler(
+    State(server_state): State<ServerState>,
+    TypedHeader(authorization): TypedHeader<Authorization<Bearer>>,
+    Json(request): Json<ThumbnailsRequest>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    check_auth_header(authorization, &server_state.reloadable().api_key_hashes)?;
+
+    if request.ids.len() > MAX_THUMBNAIL_ARCHIVE_IMAGES {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!(
+                "At most {} thumbnails can be requested at once!",
+                MAX_THUMBNAIL_ARCHIVE_IMAGES
+            ),
+        ));
+    }
+    if request.width.is_none() && request.height.is_none() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "Width or height required!".to_owned(),
+        ));
+    }
+    validate_rendition(request.width, request.height, request.quality)
+        .map_err(|err| (StatusCode::BAD_REQUEST, format!("{}!", err)))?;
+
+    // Missing images are reported before streaming starts, afterwards only the log tells
+    let mut images = Vec::new();
+    for uuid in request.ids.iter().copied() {
+        if images.iter().any(|(added, _, _)| *added == uuid) {
+            continue;
+        }
+        let tenant = server_state.metadata_index.tenant(uuid);
+        let Some((state, path)) = find_image(uuid, tenant.as_deref()) else {
+            return Err((StatusCode::NOT_FOUND, format!("Image {} not found!", uuid)));
+        };
+        images.push((uuid, state, path));
+    }
+
+    // The thumbnails are rendered one by one and streamed to the client as they are added
+    let (writer, reader) = tokio::io::duplex(EXPORT_BUFFER_SIZE);
+    tokio::spawn(async move {
+        match write_archive(writer, &request, &images, &server_state).await {
+            // The client only notices an incomplete archive, as the status was already sent
+            Err(err) => log::error!("Thumbnail archive aborted: {}", err),
+            Ok(()) => log::info!("Sent {} thumbnail(s)", images.len()),
+        }
+    });
+
+    let headers = [
+        (header::CONTENT_TYPE, "application/zip".to_owned()),
+        (
+            header::CONTENT_DISPOSITION,
+            format!(
+                "attachment; filename=\"mensatt-thumbnails-{}.zip\"",
+                Utc::now().format("%Y%m%dT%H%M%SZ")
+            ),
+        ),
+    ];
+    Ok((headers, Body::from_stream(ReaderStream::new(reader))))
+}
+
+/// Renders the thumbnails of `images` as requested by `request` and writes the zip archive of
+/// them to `writer`
+async fn write_archive(
+    mut writer: impl AsyncWrite + Unpin,
+    request: &ThumbnailsRequest,
+    images: &[(Uuid, ImageState, PathBuf)],
+    server_state: &ServerState,
+) -> Result<(), Error> {
+    let mut zip = ZipWriter::new(Vec::new());
+    for (uuid, state, path) in images {
+        // Like at `/image/:id`, only approved images are cached to not leak the others
+        let cache_behavior = match state {
+            ImageState::Approved => CacheBehavior::Normal,
+            _ => CacheBehavior::Skip,
+        };
+        let image_query = ImageQuery::with_size(request.width, request.height, request.quality);
+        let (_, body) = image_handler_helper(
+            *uuid,
+            path.to_str().unwrap(),
+            image_query,
+            cache_behavior,
+            None,
+            server_state,
+        )
+        .await?;
+
+        let name = format!("{}.{}", uuid, server_state.default_format.extension());
+        zip.add_file(&name, &body)?;
+        writer.write_all(&std::mem::take(zip.get_mut())).await?;
+    }
+    writer.write_all(&zip.finish()?).await?;
+    Ok(writer.shutdown().await?)
+}
@@ -1,94 +1,446 @@
 use axum::{
-    extract::{Multipart, Query},
-    http::StatusCode,
+    body::{to_bytes, Bytes},
+    extract::{multipart::Field, FromRequest, Multipart, Path, Query, Request, State},
+    http::{header, HeaderMap, HeaderName, HeaderValue, StatusCode},
+    Extension,
 };
+use base64::{engine::general_purpose::STANDARD, Engine};
 use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use utoipa::{IntoParams, ToSchema};
 use uuid::Uuid;
 
-use crate::util::image::save_raw;
 use crate::{
-    constants::CONTENT_LENGTH_LIMIT,
-    util::image::{determine_file_type, save_pending},
+    constants::{CONTENT_LENGTH_LIMIT, JSON_UPLOAD_LIMIT},
+    disk_space::check_free_space,
+    handlers::tenant::{find_tenant, tenant_usage},
+    operations::upload_image,
+    util::{client_ip::ClientIp, idempotency::Claim, image_metadata::ImageMetadata},
+    ServerState,
 };
 
-#[derive(Deserialize)]
+const IDEMPOTENCY_KEY: HeaderName = HeaderName::from_static("idempotency-key");
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct UploadQuery {
+    /// Angle in degrees to rotate the image by before saving
     angle: Option<f64>,
 }
 
+/// Upload of an image as JSON, for integrations that can't easily produce multipart bodies
+#[derive(Deserialize, ToSchema)]
+pub struct UploadRequest {
+    /// The image as data URI (`data:image/jpeg;base64,...`) or plain base64
+    data: String,
+}
+
 /// This function handles image uploads. An image is expected to be part of a multipart stream.\
-/// Only one image (the first field in the stream) is processed.
+/// Only one image (the first field in the stream that isn't a metadata field) is processed.\
+/// The optional fields `alt_text`, `capture_source` and `tags` (comma-separated, may be repeated)
+/// are validated and stored along with the image.\
+/// Retries with the same `Idempotency-Key` header return the ID of the first upload.\
+/// With `Content-Type: application/json`, the image is expected base64 encoded instead, see
+/// `UploadRequest`.
 ///
 /// Arguments:
 ///  - query: HTTP Query parameters
 ///     - angle: To rotate image before saving. Default 0.
-///  - multipart: Multipart stream
+///  - request: Multipart stream or JSON body
+#[utoipa::path(
+    post,
+    path = "/upload",
+    tag = "images",
+    params(
+        UploadQuery,
+        ("Idempotency-Key" = Option<String>, Header, description = "Retries with the same key \
+         return the ID of the first upload instead of storing the image again")
+    ),
+    request_body(
+        content = Vec<u8>,
+        content_type = "multipart/form-data",
+        description = "The image as first field, optionally with the fields `alt_text`, \
+                       `capture_source` and `tags`, or with `Content-Type: application/json` as \
+                       `UploadRequest`"
+    ),
+    responses(
+        (status = 200, description = "ID of the uploaded (pending) image", body = String),
+        (status = 400, description = "No or empty file, invalid base64, unsupported file type, \
+                                      invalid metadata or invalid `Idempotency-Key`"),
+        (status = 409, description = "Upload with the same `Idempotency-Key` in progress"),
+        (status = 413, description = "File too large"),
+        (status = 415, description = "Media type of the data URI is not an image type"),
+        (status = 422, description = "File is infected, exceeds the input limits or \
+                                      `Idempotency-Key` was used for another file"),
+        (status = 503, description = "Virus scan failed"),
+        (status = 504, description = "Encoding took longer than `VIPS_TIMEOUT_SECS`"),
+        (status = 507, description = "Data volume nearly full"),
+    )
+)]
 pub async fn upload_handler(
+    State(server_state): State<ServerState>,
     query: Query<UploadQuery>,
-    mut multipart: Multipart,
+    Extension(client_ip): Extension<ClientIp>,
+    request: Request,
 ) -> Result<String, (StatusCode, String)> {
-    // Get first Multipart field
-    let field = match multipart.next_field().await {
-        Err(err) => {
-            log::error!("{}", err.body_text());
-            return Err((StatusCode::BAD_REQUEST, "No fields provided!".to_owned()));
-        }
-        Ok(next_field) => match next_field {
-            None => return Err((StatusCode::BAD_REQUEST, "No fields provided!".to_owned())),
-            Some(field) => field,
-        },
+    // Fail before receiving the file, it is checked again before saving it
+    check_free_space(&server_state)?;
+
+    let idempotency_key = request.headers().get(IDEMPOTENCY_KEY).cloned();
+    let (data, metadata) = receive_upload(request, &server_state).await?;
+
+    let uuid = upload_idempotently(
+        client_ip,
+        idempotency_key.as_ref(),
+        &data,
+        query.angle.unwrap_or(0.0),
+        metadata,
+        None,
+        &server_state,
+    )
+    .await?;
+
+    Ok(uuid.to_string())
+}
+
+/// Uploads an image like `/upload`, which belongs to the tenant afterwards, i.e. can be managed
+/// with its API keys under `/t/:tenant`. Uploads exceeding the quota of the tenant are rejected.
+#[utoipa::path(
+    post,
+    path = "/t/{tenant}/upload",
+    tag = "tenants",
+    params(
+        ("tenant" = String, Path, description = "Name of the tenant"),
+        UploadQuery,
+        ("Idempotency-Key" = Option<String>, Header, description = "Retries with the same key \
+         return the ID of the first upload instead of storing the image again")
+    ),
+    request_body(
+        content = Vec<u8>,
+        content_type = "multipart/form-data",
+        description = "Like at `/upload`"
+    ),
+    responses(
+        (status = 200, description = "ID of the uploaded (pending) image", body = String),
+        (status = 400, description = "Like at `/upload`"),
+        (status = 404, description = "Tenant not found"),
+        (status = 413, description = "File too large"),
+        (status = 422, description = "Like at `/upload`"),
+        (status = 507, description = "Quota of the tenant exceeded or data volume nearly full"),
+    )
+)]
+pub async fn tenant_upload_handler(
+    State(server_state): State<ServerState>,
+    Path(tenant): Path<String>,
+    query: Query<UploadQuery>,
+    Extension(client_ip): Extension<ClientIp>,
+    request: Request,
+) -> Result<String, (StatusCode, String)> {
+    let quota = find_tenant(&tenant, &server_state)?.quota_bytes;
+    check_free_space(&server_state)?;
+
+    let idempotency_key = request.headers().get(IDEMPOTENCY_KEY).cloned();
+    let (data, metadata) = receive_upload(request, &server_state).await?;
+
+    // The stored image is usually smaller than the upload, which is also stored as raw file.
+    // Reserved until the image is recorded as the tenant's, so concurrent uploads can't exceed
+    // the quota together.
+    let _reservation = match quota {
+        None => None,
+        Some(quota) => Some(
+            server_state
+                .quota_reservations
+                .reserve(&tenant, data.len() as u64, quota, || {
+                    tenant_usage(&tenant, &server_state).bytes
+                })
+                .ok_or_else(|| {
+                    (
+                        StatusCode::INSUFFICIENT_STORAGE,
+                        format!("The quota of {} bytes of '{}' is exceeded!", quota, tenant),
+                    )
+                })?,
+        ),
     };
 
-    // Get name and data from field
-    let name = field.name().unwrap().to_string();
-    let data = match field.bytes().await {
-        Err(err) => {
-            log::error!("{}", err.body_text());
-            return match err.status() {
-                StatusCode::PAYLOAD_TOO_LARGE => Err((
+    let uuid = upload_idempotently(
+        client_ip,
+        idempotency_key.as_ref(),
+        &data,
+        query.angle.unwrap_or(0.0),
+        metadata,
+        Some(tenant),
+        &server_state,
+    )
+    .await?;
+
+    Ok(uuid.to_string())
+}
+
+/// Receives the image and its metadata from `request`, a multipart stream or, with
+/// `Content-Type: application/json`, an `UploadRequest`
+async fn receive_upload(
+    request: Request,
+    server_state: &ServerState,
+) -> Result<(Bytes, ImageMetadata), (StatusCode, String)> {
+    let is_json = request
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .is_some_and(|value| value.as_bytes().starts_with(b"application/json"));
+    match is_json {
+        false => {
+            let multipart = Multipart::from_request(request, server_state)
+                .await
+                .map_err(|rejection| (rejection.status(), rejection.body_text()))?;
+            receive_multipart(multipart).await
+        }
+        true => {
+            // Read with a larger limit than the one of the route, as the image is base64 encoded
+            let body = to_bytes(request.into_body(), JSON_UPLOAD_LIMIT)
+                .await
+                .map_err(|_| {
+                    (
+                        StatusCode::PAYLOAD_TOO_LARGE,
+                        format!(
+                            "Content length limit exceeded! Max allowed body size is {}B",
+                            JSON_UPLOAD_LIMIT
+                        ),
+                    )
+                })?;
+            let request = serde_json::from_slice::<UploadRequest>(&body).map_err(|err| {
+                (
+                    StatusCode::UNPROCESSABLE_ENTITY,
+                    format!("Invalid upload request: {}!", err),
+                )
+            })?;
+            let data = decode_data_uri(&request.data)?;
+            if data.len() > CONTENT_LENGTH_LIMIT {
+                return Err((
                     StatusCode::PAYLOAD_TOO_LARGE,
                     format!(
-                        "Content length limit exceeded!. Max allowed file size is {}B",
+                        "Content length limit exceeded! Max allowed file size is {}B",
                         CONTENT_LENGTH_LIMIT
                     ),
-                )),
-                _ => Err((err.status(), "An error occurred your request".to_owned())),
-            };
+                ));
+            }
+            Ok((data, ImageMetadata::default()))
         }
-        Ok(data) => data,
+    }
+}
+
+/// Saves the upload like `upload_image`. With an `Idempotency-Key` header, retries of the upload
+/// return the ID of the image stored by the first attempt (until the key expires), see
+/// `IdempotencyKeys`. Stored uploads are logged with the `client_ip`.
+async fn upload_idempotently(
+    client_ip: ClientIp,
+    idempotency_key: Option<&HeaderValue>,
+    data: &Bytes,
+    angle: f64,
+    metadata: ImageMetadata,
+    tenant: Option<String>,
+    server_state: &ServerState,
+) -> Result<Uuid, (StatusCode, String)> {
+    let Some(key) = idempotency_key else {
+        let uuid = upload_image(data, angle, metadata, tenant, server_state).await?;
+        log::info!("Stored upload from {} as {}", client_ip.0, uuid);
+        return Ok(uuid);
     };
-    log::info!("Received '{}' with size {}B", name, data.len());
+    let key = key.to_str().unwrap_or_default();
 
-    if data.is_empty() {
-        return Err((StatusCode::BAD_REQUEST, "Empty file provided!".to_owned()));
+    let checksum = format!("{:x}", Sha256::digest(data));
+    let idempotency_keys = &server_state.idempotency_keys;
+    if let Claim::Done(uuid) = idempotency_keys.claim(key, &checksum)? {
+        log::info!(
+            "Upload with Idempotency-Key '{}' was already stored as {}",
+            key,
+            uuid
+        );
+        return Ok(uuid);
     }
 
-    let uuid = Uuid::new_v4();
-    let angle = query.angle.unwrap_or(0.0);
+    match upload_image(data, angle, metadata, tenant, server_state).await {
+        Err(err) => {
+            idempotency_keys.release(key);
+            Err(err)
+        }
+        Ok(uuid) => {
+            idempotency_keys.complete(key, uuid);
+            log::info!("Stored upload from {} as {}", client_ip.0, uuid);
+            Ok(uuid)
+        }
+    }
+}
 
-    if determine_file_type(&data).is_none() {
-        return Err((
-            StatusCode::BAD_REQUEST,
-            "File type could not be determined or your file type is not supported!".to_owned(),
-        ));
+/// Receives the image, the first field of `multipart` that isn't a metadata field, and the
+/// metadata fields `alt_text`, `capture_source` and `tags` (comma-separated, may be repeated),
+/// which may come before or after it. Other fields are ignored.
+async fn receive_multipart(
+    mut multipart: Multipart,
+) -> Result<(Bytes, ImageMetadata), (StatusCode, String)> {
+    let mut image = None;
+    let mut metadata = ImageMetadata::default();
+    loop {
+        let field = match multipart.next_field().await {
+            Err(err) => {
+                log::error!("{}", err.body_text());
+                return Err((err.status(), "An error occurred your request".to_owned()));
+            }
+            Ok(None) => break,
+            Ok(Some(field)) => field,
+        };
+
+        // Get name and data from field
+        let name = field.name().unwrap_or_default().to_string();
+        match name.as_str() {
+            "alt_text" => metadata.alt_text = Some(receive_text(field).await?),
+            "capture_source" => metadata.capture_source = Some(receive_text(field).await?),
+            "tags" => metadata.add_tags(&receive_text(field).await?),
+            "focal_point" => {
+                let focal_point = receive_text(field).await?;
+                metadata.focal_point = Some(
+                    focal_point
+                        .parse()
+                        .map_err(|err| (StatusCode::BAD_REQUEST, err))?,
+                );
+            }
+            _ if image.is_some() => log::info!("Ignoring additional field '{}'", name),
+            _ => {
+                let data = match field.bytes().await {
+                    Err(err) => {
+                        log::error!("{}", err.body_text());
+                        return match err.status() {
+                            StatusCode::PAYLOAD_TOO_LARGE => Err((
+                                StatusCode::PAYLOAD_TOO_LARGE,
+                                format!(
+                                    "Content length limit exceeded!. Max allowed file size is {}B",
+                                    CONTENT_LENGTH_LIMIT
+                                ),
+                            )),
+                            _ => Err((err.status(), "An error occurred your request".to_owned())),
+                        };
+                    }
+                    Ok(data) => data,
+                };
+                log::info!("Received '{}' with size {}B", name, data.len());
+                image = Some(data);
+            }
+        }
     }
 
-    // Save raw image without any modifications
-    if let Err(err) = save_raw(&data, uuid) {
-        log::error!("{}", err);
-        return Err((
-            StatusCode::INTERNAL_SERVER_ERROR,
-            "An internal error has occurred!".to_owned(),
-        ));
+    match image {
+        None => Err((StatusCode::BAD_REQUEST, "No fields provided!".to_owned())),
+        Some(data) => Ok((data, metadata)),
+    }
+}
+
+/// Receives the text of the metadata field `field`
+async fn receive_text(field: Field<'_>) -> Result<String, (StatusCode, String)> {
+    let name = field.name().unwrap_or_default().to_string();
+    field.text().await.map_err(|err| {
+        log::error!("{}", err.body_text());
+        (
+            err.status(),
+            format!("Field '{}' could not be read as text!", name),
+        )
+    })
+}
+
+/// Decodes `data`, a data URI with an image media type (`data:image/jpeg;base64,...`) or plain
+/// base64. The actual type is determined from the content like for multipart uploads.
+fn decode_data_uri(data: &str) -> Result<Bytes, (StatusCode, String)> {
+    let encoded = match data.strip_prefix("data:") {
+        None => data,
+        Some(uri) => {
+            let Some((media_type, encoded)) = uri.split_once(',') else {
+                return Err((StatusCode::BAD_REQUEST, "Invalid data URI!".to_owned()));
+            };
+            let Some(media_type) = media_type.strip_suffix(";base64") else {
+                return Err((
+                    StatusCode::BAD_REQUEST,
+                    "Data URI must be base64 encoded!".to_owned(),
+                ));
+            };
+            check_content_type(media_type)?;
+            encoded
+        }
+    };
+
+    match STANDARD.decode(encoded.trim()) {
+        Err(err) => Err((StatusCode::BAD_REQUEST, format!("Invalid base64: {}!", err))),
+        Ok(data) => {
+            log::info!("Received base64 encoded image with size {}B", data.len());
+            Ok(Bytes::from(data))
+        }
     }
+}
 
-    if let Err(err) = save_pending(&data, uuid, angle) {
-        log::error!("{}", err);
+/// Checks that `content_type` of an upload is an image type (or `application/octet-stream`)
+fn check_content_type(content_type: &str) -> Result<(), (StatusCode, String)> {
+    if !content_type.starts_with("image/") && content_type != "application/octet-stream" {
         return Err((
-            StatusCode::INTERNAL_SERVER_ERROR,
-            "An internal error has occurred!".to_owned(),
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            "Content-Type must be an image type!".to_owned(),
         ));
-    };
+    }
+    Ok(())
+}
+
+/// Handles uploads of an image as raw request body, e.g. by scripts and mobile SDKs that can't
+/// easily produce multipart bodies. The `Content-Type` has to be an image type (or
+/// `application/octet-stream`), the actual type is determined from the content like for
+/// multipart uploads. Retries with the same `Idempotency-Key` header return the ID of the first
+/// upload.
+#[utoipa::path(
+    put,
+    path = "/upload",
+    tag = "images",
+    params(
+        UploadQuery,
+        ("Idempotency-Key" = Option<String>, Header, description = "Retries with the same key \
+         return the ID of the first upload instead of storing the image again")
+    ),
+    request_body(
+        content = Vec<u8>,
+        content_type = "image/*",
+        description = "The image"
+    ),
+    responses(
+        (status = 200, description = "ID of the uploaded (pending) image", body = String),
+        (status = 400, description = "Empty file, unsupported file type or invalid \
+                                      `Idempotency-Key`"),
+        (status = 409, description = "Upload with the same `Idempotency-Key` in progress"),
+        (status = 413, description = "File too large"),
+        (status = 415, description = "Content-Type is not an image type"),
+        (status = 422, description = "File is infected, exceeds the input limits or \
+                                      `Idempotency-Key` was used for another file"),
+        (status = 503, description = "Virus scan failed"),
+        (status = 504, description = "Encoding took longer than `VIPS_TIMEOUT_SECS`"),
+        (status = 507, description = "Data volume nearly full"),
+    )
+)]
+pub async fn upload_raw_handler(
+    State(server_state): State<ServerState>,
+    query: Query<UploadQuery>,
+    Extension(client_ip): Extension<ClientIp>,
+    headers: HeaderMap,
+    data: Bytes,
+) -> Result<String, (StatusCode, String)> {
+    let content_type = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    check_content_type(content_type)?;
+    log::info!("Received {} with size {}B", content_type, data.len());
+
+    let uuid = upload_idempotently(
+        client_ip,
+        headers.get(IDEMPOTENCY_KEY),
+        &data,
+        query.angle.unwrap_or(0.0),
+        ImageMetadata::default(),
+        None,
+        &server_state,
+    )
+    .await?;
 
     Ok(uuid.to_string())
 }
@@ -0,0 +1,103 @@
+use crate::{
+    constants::MAX_VERIFY_IDS,
+    util::{
+        auth::check_auth_header,
+        image::{find_image, get_raw_file, ImageState},
+    },
+    ServerState,
+};
+
+use axum::{extract::State, http::StatusCode, Json};
+use axum_extra::{
+    headers::{authorization::Bearer, Authorization},
+    TypedHeader,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{collections::HashMap, fs};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+#[derive(Deserialize, ToSchema)]
+pub struct VerifyRequest {
+    ids: Vec<Uuid>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct VerifyResult {
+    // Whether the image exists in any state
+    exists: bool,
+    state: Option<ImageState>,
+    // Hex encoded SHA-256 hash of the stored image
+    sha256: Option<String>,
+    // Whether the raw file (as uploaded) exists, e.g. to restore a lost image from it
+    raw: bool,
+}
+
+/// Verifies that images the backend knows about exist, e.g. to detect lost images.
+/// Returns the state and the content hash of the stored image (or that it doesn't exist) by ID.
+#[utoipa::path(
+    post,
+    path = "/verify",
+    tag = "images",
+    request_body = VerifyRequest,
+    responses(
+        (status = 200, description = "Result by image ID", body = HashMap<String, VerifyResult>),
+        (status = 400, description = "Too many IDs"),
+        (status = 401, description = "Missing or invalid API key"),
+    ),
+    security(("api_key" = []))
+)]
+pub async fn verify_handler(
+    State(server_state): State<ServerState>,
+    TypedHeader(authorization): TypedHeader<Authorization<Bearer>>,
+    Json(request): Json<VerifyRequest>,
+) -> Result<Json<HashMap<Uuid, VerifyResult>>, (StatusCode, String)> {
+    check_auth_header(authorization, &server_state.reloadable().api_key_hashes)?;
+
+    if request.ids.len() > MAX_VERIFY_IDS {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("At most {} IDs can be verified at once!", MAX_VERIFY_IDS),
+        ));
+    }
+
+    let results = request
+        .ids
+        .into_iter()
+        .map(|uuid| {
+            let tenant = server_state.metadata_index.tenant(uuid);
+            (uuid, verify_image(uuid, tenant.as_deref()))
+        })
+        .collect();
+
+    Ok(Json(results))
+}
+
+fn verify_image(uuid: Uuid, tenant: Option<&str>) -> VerifyResult {
+    let raw = get_raw_file(uuid, tenant).is_file();
+
+    let Some((state, path)) = find_image(uuid, tenant) else {
+        return VerifyResult {
+            exists: false,
+            state: None,
+            sha256: None,
+            raw: raw,
+        };
+    };
+
+    let sha256 = match fs::read(&path) {
+        Err(err) => {
+            log::error!("Error while reading {:?}: {}", path, err);
+            None
+        }
+        Ok(data) => Some(format!("{:x}", Sha256::digest(data))),
+    };
+
+    VerifyResult {
+        exists: true,
+        state: Some(state),
+        sha256: sha256,
+        raw: raw,
+    }
+}
@@ -0,0 +1,148 @@
+use crate::{
+    constants::{MAX_WARMUP_REQUESTS, WARMUP_CONCURRENCY},
+    handlers::image::{image_handler_helper, ImageQuery},
+    util::{
+        auth::check_auth_header,
+        image::{determine_img_path, CacheBehavior},
+        path::get_original_path,
+        short_id::parse_image_id,
+    },
+    ServerState,
+};
+
+use axum::{
+    extract::{Query, State},
+    http::{StatusCode, Uri},
+    Json,
+};
+use axum_extra::{
+    headers::{authorization::Bearer, Authorization},
+    TypedHeader,
+};
+use futures_util::{stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+#[derive(Deserialize, ToSchema)]
+pub struct WarmupRequest {
+    // Previously requested URLs of images, e.g. `/v1/image/<id>?width=400` (with or without host),
+    // as exported from access logs or a CDN
+    requests: Vec<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct WarmupFailure {
+    request: String,
+    error: String,
+}
+
+#[derive(Default, Serialize, ToSchema)]
+pub struct WarmupReport {
+    // Renditions that are cached now (or are being written to the cache), including those that
+    // were cached already
+    warmed: usize,
+    // Requests of images that are not approved (or don't exist), which are never cached
+    skipped: usize,
+    failed: Vec<WarmupFailure>,
+}
+
+/// Renders previously requested renditions into the cache, e.g. to warm a fresh instance or a
+/// wiped cache before it serves traffic. Renditions are requested like at `/image/:id`; those
+/// that are cached already are only counted.
+#[utoipa::path(
+    post,
+    path = "/cache/warmup",
+    tag = "admin",
+    request_body = WarmupRequest,
+    responses(
+        (status = 200, description = "Number of warmed and skipped renditions and all failures", body = WarmupReport),
+        (status = 400, description = "Too many requests"),
+        (status = 401, description = "Missing or invalid API key"),
+    ),
+    security(("api_key" = []))
+)]
+pub async fn warmup_handler(
+    State(server_state): State<ServerState>,
+    TypedHeader(authorization): TypedHeader<Authorization<Bearer>>,
+    Json(request): Json<WarmupRequest>,
+) -> Result<Json<WarmupReport>, (StatusCode, String)> {
+    check_auth_header(authorization, &server_state.reloadable().api_key_hashes)?;
+
+    if request.requests.len() > MAX_WARMUP_REQUESTS {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!(
+                "At most {} requests can be warmed at once!",
+                MAX_WARMUP_REQUESTS
+            ),
+        ));
+    }
+
+    // Rendering is expensive, so only a few renditions are rendered at the same time
+    let outcomes: Vec<(String, Result<bool, String>)> = stream::iter(request.requests)
+        .map(|request| {
+            let server_state = server_state.clone();
+            async move {
+                let outcome = warm(&request, &server_state).await;
+                (request, outcome)
+            }
+        })
+        .buffer_unordered(WARMUP_CONCURRENCY)
+        .collect()
+        .await;
+
+    let mut report = WarmupReport::default();
+    for (request, outcome) in outcomes {
+        match outcome {
+            Ok(true) => report.warmed += 1,
+            Ok(false) => report.skipped += 1,
+            Err(error) => report.failed.push(WarmupFailure {
+                request: request,
+                error: error,
+            }),
+        }
+    }
+    log::info!(
+        "Warmed {} rendition(s), skipped {} and failed to warm {}",
+        report.warmed,
+        report.skipped,
+        report.failed.len()
+    );
+
+    Ok(Json(report))
+}
+
+/// Renders the rendition requested by `request` into the cache. Returns whether it is cached,
+/// i.e. `false` if the image is not approved.
+async fn warm(request: &str, server_state: &ServerState) -> Result<bool, String> {
+    let uri = request
+        .parse::<Uri>()
+        .map_err(|_| "Invalid URL".to_owned())?;
+    // Any prefix, e.g. the API version, is ignored
+    let id = uri
+        .path()
+        .rsplit_once("/image/")
+        .map(|(_, id)| id)
+        .filter(|id| !id.contains('/'))
+        .ok_or_else(|| "Not a request of /image/:id".to_owned())?;
+    let uuid = parse_image_id(id).ok_or_else(|| format!("Invalid image ID '{}'", id))?;
+    let Query(image_query) =
+        Query::<ImageQuery>::try_from_uri(&uri).map_err(|err| err.body_text())?;
+
+    let tenant = server_state.metadata_index.tenant(uuid);
+    let original_path = get_original_path(tenant.as_deref());
+    let Ok(path) = determine_img_path(original_path.to_str().unwrap(), uuid) else {
+        return Ok(false);
+    };
+    image_handler_helper(
+        uuid,
+        path.to_str().unwrap(),
+        image_query,
+        CacheBehavior::Normal,
+        None,
+        server_state,
+    )
+    .await
+    .map_err(|err| <(StatusCode, String)>::from(err).1)?;
+    Ok(true)
+}
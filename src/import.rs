@@ -0,0 +1,138 @@
+use std::time::Duration;
+
+use axum::{body::Bytes, http::StatusCode};
+use config::Config;
+use reqwest::{redirect, Client, Url};
+
+use crate::constants::{CONTENT_LENGTH_LIMIT, DEFAULT_IMPORT_TIMEOUT_SECS};
+
+/// Downloads images from remote URLs for `/import` (and `/proxy`)
+pub struct Importer {
+    allowed_hosts: Vec<String>,
+    // Maximum size of downloads in bytes
+    max_size: usize,
+    client: Client,
+}
+
+/// Parses the hosts images may be imported from (`IMPORT_ALLOWED_HOSTS`) and the timeout of
+/// downloads (`IMPORT_TIMEOUT_SECS`) from the config
+pub fn parse_importer(config: &Config) -> Importer {
+    let allowed_hosts = config
+        .get::<Vec<String>>("IMPORT_ALLOWED_HOSTS")
+        .unwrap_or_default();
+    let timeout = Duration::from_secs(
+        config
+            .get::<u64>("IMPORT_TIMEOUT_SECS")
+            .unwrap_or(DEFAULT_IMPORT_TIMEOUT_SECS),
+    );
+    // Imported images are saved like uploads, so they are limited the same way
+    Importer::new(allowed_hosts, timeout, CONTENT_LENGTH_LIMIT)
+}
+
+fn is_allowed(allowed_hosts: &[String], url: &Url) -> bool {
+    (url.scheme() == "http" || url.scheme() == "https")
+        && url
+            .host_str()
+            .is_some_and(|host| allowed_hosts.iter().any(|allowed| allowed == host))
+}
+
+impl Importer {
+    /// Builds an importer downloading files of at most `max_size` bytes from `allowed_hosts`
+    pub fn new(allowed_hosts: Vec<String>, timeout: Duration, max_size: usize) -> Self {
+        // Redirects are followed only within the allowed hosts
+        let redirect_hosts = allowed_hosts.clone();
+        let redirect_policy = redirect::Policy::custom(move |attempt| {
+            if attempt.previous().len() >= 5 {
+                attempt.error("Too many redirects")
+            } else if is_allowed(&redirect_hosts, attempt.url()) {
+                attempt.follow()
+            } else {
+                attempt.stop()
+            }
+        });
+
+        Importer {
+            allowed_hosts: allowed_hosts,
+            max_size: max_size,
+            client: Client::builder()
+                .timeout(timeout)
+                .redirect(redirect_policy)
+                .build()
+                .expect("Could not build HTTP client"),
+        }
+    }
+
+    /// Downloads the file at `url`, if its host is allowed and it is not larger than the
+    /// maximum size. Returns the HTTP status code and message to respond with otherwise.
+    pub async fn download(&self, url: &str) -> Result<Bytes, (StatusCode, String)> {
+        let url =
+            Url::parse(url).map_err(|_| (StatusCode::BAD_REQUEST, "Invalid URL!".to_owned()))?;
+        if !is_allowed(&self.allowed_hosts, &url) {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                "Downloading from this host is not allowed!".to_owned(),
+            ));
+        }
+
+        let mut response = self
+            .client
+            .get(url.clone())
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|err| download_error(&url, err))?;
+        // E.g. a redirect to a host that is not allowed
+        if !response.status().is_success() {
+            log::warn!("Downloading {} returned {}", url, response.status());
+            return Err((
+                StatusCode::BAD_GATEWAY,
+                format!("Downloading the image returned {}!", response.status()),
+            ));
+        }
+
+        let too_large = (
+            StatusCode::PAYLOAD_TOO_LARGE,
+            format!(
+                "Content length limit exceeded! Max allowed file size is {}B",
+                self.max_size
+            ),
+        );
+        if response
+            .content_length()
+            .is_some_and(|length| length > self.max_size as u64)
+        {
+            return Err(too_large);
+        }
+
+        // The content length may be missing or wrong, so it is also checked while downloading
+        let mut data = Vec::new();
+        while let Some(chunk) = response
+            .chunk()
+            .await
+            .map_err(|err| download_error(&url, err))?
+        {
+            data.extend_from_slice(&chunk);
+            if data.len() > self.max_size {
+                return Err(too_large);
+            }
+        }
+
+        log::info!("Downloaded {} with size {}B", url, data.len());
+        Ok(Bytes::from(data))
+    }
+}
+
+fn download_error(url: &Url, err: reqwest::Error) -> (StatusCode, String) {
+    log::warn!("Error while downloading {}: {}", url, err);
+    if err.is_timeout() {
+        (
+            StatusCode::GATEWAY_TIMEOUT,
+            "Timeout while downloading the image!".to_owned(),
+        )
+    } else {
+        (
+            StatusCode::BAD_GATEWAY,
+            "Error while downloading the image!".to_owned(),
+        )
+    }
+}
@@ -1,123 +1,691 @@
 #![allow(clippy::redundant_field_names)]
 
+mod cdn;
 mod cleaner;
+mod cli;
+mod consistency;
 mod constants;
+mod content_store;
+mod disk_space;
+mod error;
+mod events;
+mod fsck;
+mod graphql;
+#[cfg(feature = "grpc")]
+mod grpc;
 mod handlers;
+mod import;
+mod moderation;
+mod notifier;
+mod openapi;
+mod operations;
+mod proxy;
+mod quarantine;
+mod recovery;
+mod replication;
+mod scheduler;
+mod settings;
+mod shadow_read;
+mod trash;
 mod util;
+mod virus_scan;
+mod webhook;
 
 use crate::{
-    cleaner::delete_old_pending_images,
-    constants::{CONTENT_LENGTH_LIMIT, LISTEN_ADDR},
+    cdn::{parse_cdn_purger, CdnPurger},
+    cleaner::{
+        parse_maintenance_behavior, parse_retention_policies, schedule_cleaners, RetentionPolicy,
+    },
+    cli::{check, hash_key, migrate_cas, Cli, Command},
+    consistency::{check_consistency, parse_consistency_check_config, RepairBehavior},
+    constants::{
+        API_PREFIX, CACHE_INDEX_SAVE_INTERVAL_SECS, CONTENT_LENGTH_LIMIT,
+        DEFAULT_QUARANTINE_MAX_BYTES, DEFAULT_REPLICATION_RECONCILE_INTERVAL_SECS,
+        DEFAULT_SAVE_DATA_QUALITY,
+    },
+    disk_space::{parse_min_free_disk_bytes, schedule_disk_space_check},
+    events::EventPublisher,
+    graphql::{build_schema, ImageSchema},
     handlers::{
+        admin::admin_handler,
+        alias::{alias_delete_handler, alias_update_handler},
         approve::approve_handler,
+        cache::{
+            cache_pin_handler, cache_pins_handler, cache_regenerate_handler, cache_unpin_handler,
+        },
+        compare::compare_handler,
+        consistency::consistency_handler,
+        docs::{docs_handler, openapi_handler},
+        export::export_handler,
+        fsck::fsck_handler,
+        graphql::graphql_handler,
         image::{image_delete_handler, image_handler},
-        rotate::rotate_handler,
+        images::{images_delete_handler, images_handler, images_info_handler},
+        import::import_handler,
+        info::info_handler,
+        jobs::{job_run_handler, job_run_status_handler, jobs_handler},
+        location_check::location_check_handler,
+        lqip::lqip_handler,
+        metadata::{metadata_handler, metadata_update_handler},
+        preview_token::preview_token_handler,
+        proxy::proxy_handler,
+        quarantine::quarantine_handler,
+        raw::raw_handler,
+        regenerate::regenerate_handler,
+        reload::reload_handler,
+        restore::restore_handler,
+        rotate::{rotate_batch_handler, rotate_handler},
+        srcset::srcset_handler,
+        stats::{
+            bandwidth_stats_handler, disk_stats_handler, image_stats_handler, metrics_handler,
+            shadow_read_stats_handler, top_images_handler,
+        },
         submit::submit_handler,
+        tenant::{
+            tenant_approve_handler, tenant_image_delete_handler, tenant_image_handler,
+            tenant_images_handler, tenant_metadata_handler, tenant_metadata_update_handler,
+            tenant_rotate_handler, tenant_submit_handler, tenant_unapprove_handler,
+            tenant_usage_handler,
+        },
+        thumbnails::thumbnails_handler,
         unapprove::unapprove_handler,
-        upload::upload_handler,
+        upload::{tenant_upload_handler, upload_handler, upload_raw_handler},
+        verify::verify_handler,
+        warmup::warmup_handler,
     },
-    util::cors::{parse_methods, parse_origins},
+    import::{parse_importer, Importer},
+    moderation::{parse_moderator, Moderator},
+    notifier::{parse_notifier, schedule_notifications, Notifier},
+    proxy::{parse_proxy, Proxy},
+    recovery::{mark_running, mark_shut_down, parse_recovery_config, recover},
+    replication::Replicator,
+    scheduler::{parse_job_schedule, Job, JobSchedule, Scheduler},
+    settings::{format_report, load_config, validate_config, ReloadableConfig},
+    shadow_read::{parse_shadow_reader, ShadowReader},
+    util::{
+        access_stats::AccessStats,
+        avif::{parse_avif_profiles, AvifProfiles},
+        cache_index::{parse_cache_ttl, CacheIndex},
+        client_ip::{parse_trusted_proxies, resolve_client_ip, TrustedProxies},
+        cors::{parse_cors_config, parse_cors_policies, RouteGroup, SplitCorsLayer},
+        durability::{parse_durability, Durability},
+        idempotency::{parse_idempotency_keys, IdempotencyKeys},
+        image::{list_images, ColorProfile, ImageState, OutputFormat, RemovalBehavior},
+        image_lock::ImageLocks,
+        input_limits::{parse_input_limits, InputLimits},
+        listen::{bind_all, parse_listen_addrs},
+        metadata_index::MetadataIndex,
+        output_limits::{parse_output_limits, OutputLimits},
+        path::{get_access_stats_path, get_cache_index_path, get_metadata_index_path},
+        placeholder::{parse_placeholder, Placeholder},
+        preview_token::{parse_preview_tokens, PreviewTokens},
+        tenant::QuotaReservations,
+        watchdog::{parse_watchdog, Watchdog},
+        webp::{parse_webp_tuning, WebpTuning},
+    },
+    virus_scan::{parse_virus_scanner, VirusScanner},
+    webhook::{build_http_client, parse_webhook_config, WebhookConfig},
 };
 
-use argon2::password_hash::PasswordHashString;
-
 use axum::{
     extract::DefaultBodyLimit,
+    http::Method,
+    middleware,
     response::Html,
-    routing::{delete, get, post},
+    routing::{delete, get, post, put},
     Router,
 };
-use config::Config;
+use clap::Parser;
 use libvips::VipsApp;
-use std::{env, thread};
-use tower::ServiceBuilder;
-use tower_http::cors::CorsLayer;
+use std::{
+    env,
+    future::IntoFuture,
+    net::SocketAddr,
+    process,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+use tokio::{signal, task::JoinSet};
+use tokio_util::sync::CancellationToken;
 
 #[derive(Clone)]
 pub struct ServerState {
-    pub api_key_hashes: Vec<PasswordHashString>,
+    pub config_path: String,
+    // Base URL the service is reachable at from clients, used for absolute URLs in responses
+    pub public_url: Option<String>,
+    // Served instead of a plain-text 404 by `/image/:id`, if configured
+    pub placeholder: Option<Placeholder>,
+    // Tokens granting access to single unapproved or pending images, if enabled
+    pub preview_tokens: Option<Arc<PreviewTokens>>,
+    // `Idempotency-Key`s of recent uploads
+    pub idempotency_keys: Arc<IdempotencyKeys>,
+    // Whether new uploads get time-ordered UUIDv7s instead of random UUIDv4s
+    pub uuid_v7_enabled: bool,
+    // Whether approved images are stored under their content hash, see `content_store`
+    pub cas_enabled: bool,
+    // Whether deleted images are moved to `data/trash` instead of being removed, see `trash`
+    pub trash_enabled: bool,
+    // Maximum dimensions of renditions requested via `/image/:id`
+    pub output_limits: OutputLimits,
+    // Limits of uploads, checked before they are decoded
+    pub input_limits: InputLimits,
+    // Runs vips operations with a timeout and limits how many run at once
+    pub watchdog: Watchdog,
+    // Age after which cache entries are rendered again, if set
+    pub cache_ttl: Option<Duration>,
+    // Whether `/image/:id` honors Client Hints, and the quality with `Save-Data: on`
+    pub client_hints_enabled: bool,
+    pub save_data_quality: i32,
+    // Color profile of renditions, if not requested otherwise
+    pub color_profile: ColorProfile,
+    // Format of renditions, if no recipe requests another one
+    pub default_format: OutputFormat,
+    // Encoder settings of stored images
+    pub avif: AvifProfiles,
+    // Tuning of WebP renditions, if not requested otherwise
+    pub webp_tuning: WebpTuning,
+    // Whether JPEG and PNG renditions are interlaced, if not requested otherwise
+    pub progressive_encoding: bool,
+    // Whether low-quality image placeholders are blurred
+    pub lqip_blur: bool,
+    // How thoroughly saved and moved images are flushed to disk
+    pub durability: Durability,
+    // Free space on the data volume below which uploads are rejected, if set
+    pub min_free_disk_bytes: Option<u64>,
+    // Size of the quarantine directory above which the oldest files are deleted
+    pub quarantine_max_bytes: u64,
+    // Reverse proxies whose forwarding headers determine the `ClientIp`
+    pub trusted_proxies: Arc<TrustedProxies>,
+    reloadable: Arc<RwLock<Arc<ReloadableConfig>>>,
+    pub cache_index: CacheIndex,
+    pub metadata_index: MetadataIndex,
+    pub access_stats: AccessStats,
+    // Serialize mutations of the same image
+    pub image_locks: ImageLocks,
+    // Bytes reserved for uploads of tenants in progress, see `QuotaReservations`
+    pub quota_reservations: QuotaReservations,
+    pub maintenance_behavior: RemovalBehavior,
+    // How long files of each state are kept, reported by `/stats/images`
+    pub retention_policies: Arc<Vec<RetentionPolicy>>,
+    pub scheduler: Scheduler,
+    pub graphql_schema: ImageSchema,
+    pub http_client: reqwest::Client,
+    pub webhooks: Arc<WebhookConfig>,
+    pub events: EventPublisher,
+    pub importer: Arc<Importer>,
+    // Fetches external images for `/proxy`, if any hosts are allowed
+    pub proxy: Option<Arc<Proxy>>,
+    pub replicator: Replicator,
+    pub cdn: Arc<CdnPurger>,
+    pub notifier: Arc<Notifier>,
+    // Classifies submitted images, if configured
+    pub moderator: Option<Arc<Moderator>>,
+    // Scans uploads for viruses, if configured
+    pub virus_scanner: Option<Arc<VirusScanner>>,
+    // Compares reads with a secondary copy of the data directory, if configured
+    pub shadow_reader: Option<Arc<ShadowReader>>,
+}
+
+impl ServerState {
+    /// Returns a snapshot of the currently loaded reloadable config.
+    /// Requests keep using their snapshot, even if the config is reloaded in the meantime.
+    pub fn reloadable(&self) -> Arc<ReloadableConfig> {
+        self.reloadable.read().unwrap().clone()
+    }
+
+    /// Atomically replaces the reloadable config
+    pub fn swap_reloadable(&self, reloadable: ReloadableConfig) {
+        *self.reloadable.write().unwrap() = Arc::new(reloadable);
+    }
 }
 
 #[tokio::main]
 async fn main() {
     env_logger::init();
 
+    // If set, read config from CONFIG_PATH env variable, if not try to read from default path
+    let config_path = env::var("CONFIG_PATH").unwrap_or("config.yml".to_string());
+
+    let res = match Cli::parse().command.unwrap_or(Command::Serve) {
+        Command::Serve => {
+            serve(config_path).await;
+            Ok(())
+        }
+        Command::HashKey { key } => hash_key(&key),
+        Command::Check => check(&config_path),
+        Command::MigrateCas => migrate_cas(&config_path),
+    };
+
+    if let Err(err) = res {
+        eprintln!("{}", err);
+        process::exit(1);
+    }
+}
+
+/// Starts the image service with the config at `config_path`
+async fn serve(config_path: String) {
+    // Get config from config path
+    let config = load_config(&config_path).expect("Could not build config");
+
+    // Report all problems with the config at once, instead of failing at the first one
+    let problems = validate_config(&config);
+    if !problems.is_empty() {
+        log::error!("{}", format_report(&problems));
+        process::exit(1);
+    }
+
     // Initialize libvips app
     let libvips = VipsApp::new("mensatt", true).expect("Could not start libvips");
     libvips.concurrency_set(4);
     libvips.cache_set_max(0);
 
-    // Create thread that cleans up old pending files
-    thread::spawn(|| {
-        delete_old_pending_images();
-    });
+    let maintenance_behavior = parse_maintenance_behavior(&config);
+    if maintenance_behavior == RemovalBehavior::DryRun {
+        log::warn!("MAINTENANCE_DRY_RUN is enabled. Maintenance jobs will not delete anything.");
+    }
 
-    // If set, read config from CONFIG_PATH env variable, if not try to read from default path
-    let config_path = env::var("CONFIG_PATH").unwrap_or("config.yml".to_string());
+    let reloadable = ReloadableConfig::from_config(&config).unwrap_or_else(|err| panic!("{}", err));
+    log::info!(
+        "AUTH: Loaded {:?} password hashes",
+        reloadable.api_key_hashes.len()
+    );
 
-    // Get config from config path
-    // Options set in environment variables override the properties from config file
-
-    // Use ';' as separator, as argon hashes contain commas
-    let env_source = config::Environment::default()
-        .list_separator(";")
-        .with_list_parse_key("API_KEY_HASHES")
-        .with_list_parse_key("CORS_ALLOWED_ORIGINS")
-        .with_list_parse_key("CORS_ALLOWED_METHODS")
-        .try_parsing(true);
-
-    let config = Config::builder()
-        .add_source(config::File::with_name(&config_path).required(false))
-        .add_source(env_source)
-        .build()
-        .expect("Could not build config");
-
-    // Get allowed api key hash from config
-    let hash_values: Vec<String> = match config.get("API_KEY_HASHES") {
-        Err(err) => panic!("$API_KEY_HASHES is not set ({})", err),
-        Ok(val) => val,
+    // Set up CORS
+    let cors_config = parse_cors_config(&config);
+    log::info!(
+        "CORS: Allowing {:?} requests from {:?}.",
+        cors_config.methods,
+        reloadable.cors_origins
+    );
+
+    let metadata_index = MetadataIndex::load(get_metadata_index_path());
+    let http_client = build_http_client();
+    let server_state = ServerState {
+        config_path: config_path,
+        public_url: config
+            .get_string("PUBLIC_URL")
+            .ok()
+            .map(|url| url.trim_end_matches('/').to_owned()),
+        placeholder: parse_placeholder(&config),
+        preview_tokens: parse_preview_tokens(&config).map(Arc::new),
+        idempotency_keys: Arc::new(parse_idempotency_keys(&config)),
+        uuid_v7_enabled: config.get_bool("UUID_V7_ENABLED").unwrap_or(false),
+        cas_enabled: config.get_bool("CAS_ENABLED").unwrap_or(false),
+        trash_enabled: config.get_bool("TRASH_ENABLED").unwrap_or(false),
+        output_limits: parse_output_limits(&config),
+        input_limits: parse_input_limits(&config),
+        watchdog: parse_watchdog(&config),
+        cache_ttl: parse_cache_ttl(&config),
+        client_hints_enabled: config.get_bool("CLIENT_HINTS_ENABLED").unwrap_or(false),
+        save_data_quality: config
+            .get::<i32>("SAVE_DATA_QUALITY")
+            .unwrap_or(DEFAULT_SAVE_DATA_QUALITY),
+        color_profile: config.get("COLOR_PROFILE").unwrap_or_default(),
+        default_format: config.get("DEFAULT_OUTPUT_FORMAT").unwrap_or_default(),
+        avif: parse_avif_profiles(&config).unwrap_or_else(|err| panic!("{}", err)),
+        webp_tuning: parse_webp_tuning(&config).unwrap_or_else(|err| panic!("{}", err)),
+        progressive_encoding: config.get_bool("PROGRESSIVE_ENCODING").unwrap_or(false),
+        lqip_blur: config.get_bool("LQIP_BLUR").unwrap_or(true),
+        durability: parse_durability(&config),
+        min_free_disk_bytes: parse_min_free_disk_bytes(&config),
+        quarantine_max_bytes: config
+            .get::<u64>("QUARANTINE_MAX_BYTES")
+            .unwrap_or(DEFAULT_QUARANTINE_MAX_BYTES),
+        trusted_proxies: Arc::new(parse_trusted_proxies(&config)),
+        reloadable: Arc::new(RwLock::new(Arc::new(reloadable))),
+        cache_index: CacheIndex::load(get_cache_index_path()),
+        metadata_index: metadata_index.clone(),
+        access_stats: AccessStats::load(get_access_stats_path()),
+        image_locks: ImageLocks::default(),
+        quota_reservations: QuotaReservations::default(),
+        maintenance_behavior: maintenance_behavior,
+        retention_policies: Arc::new(parse_retention_policies(&config)),
+        scheduler: Scheduler::default(),
+        graphql_schema: build_schema(),
+        http_client: http_client.clone(),
+        webhooks: Arc::new(parse_webhook_config(&config)),
+        events: EventPublisher::start(&config),
+        importer: Arc::new(parse_importer(&config)),
+        proxy: parse_proxy(&config).map(Arc::new),
+        shadow_reader: parse_shadow_reader(&config).map(Arc::new),
+        replicator: Replicator::start(&config, metadata_index),
+        cdn: Arc::new(parse_cdn_purger(&config)),
+        notifier: Arc::new(parse_notifier(&config, http_client.clone())),
+        moderator: parse_moderator(&config, http_client).map(Arc::new),
+        virus_scanner: parse_virus_scanner(&config).map(Arc::new),
     };
 
-    let hashes: Vec<PasswordHashString> = hash_values
-        .iter()
-        .map(|hv| PasswordHashString::new(hv).expect("Failed to parse hash"))
-        .collect();
+    if let Some(url) = &server_state.webhooks.upload_url {
+        log::info!("WEBHOOK: Calling {} after each upload", url);
+    }
+    if let Some(virus_scanner) = &server_state.virus_scanner {
+        log::info!(
+            "CLAMAV: Scanning uploads with clamd at {}",
+            virus_scanner.describe()
+        );
+    }
+    if let Some(moderator) = &server_state.moderator {
+        log::info!(
+            "MODERATION: Classifying submitted images with {}",
+            moderator.describe()
+        );
+    }
+    let cdns = server_state.cdn.provider_names();
+    if !cdns.is_empty() {
+        log::info!("CDN: Purging changed images from {}", cdns.join(", "));
+    }
+    let channels = server_state.notifier.channel_names();
+    if !channels.is_empty() {
+        log::info!("NOTIFY: Sending notifications via {}", channels.join(", "));
+    }
 
-    log::info!("AUTH: Loaded {:?} password hashes", hashes.len());
+    // Clean up after an unclean shutdown, before the images are indexed and served
+    let recovery_config = parse_recovery_config(&config);
+    if recovery_config.enabled {
+        recover(recovery_config, &server_state);
+    }
+    mark_running();
 
-    let server_state = ServerState {
-        api_key_hashes: hashes,
-    };
+    // Index images stored before the metadata index existed (or while it wasn't written)
+    match list_images(&ImageState::ALL, &server_state.metadata_index) {
+        Err(err) => log::error!("Could not list images to sync the metadata index: {}", err),
+        Ok(images) => {
+            let (added, removed) = server_state.metadata_index.sync(&images);
+            log::info!(
+                "METADATA: Indexed {} image(s), added {} and removed {} entries",
+                images.len(),
+                added,
+                removed
+            );
+        }
+    }
 
-    // Set up CORS
-    let methods = parse_methods(&config);
-    let origins = parse_origins(&config);
-    log::info!("CORS: Allowing {:?} requests from {:?}.", methods, origins);
-    let cors = CorsLayer::new()
-        .allow_methods(methods)
-        .allow_origin(origins);
+    // Schedule background jobs
+    let scheduler = &server_state.scheduler;
+    schedule_cleaners(&config, scheduler, &server_state);
+    schedule_notifications(&config, scheduler, &server_state);
+    schedule_disk_space_check(&config, scheduler, &server_state);
 
-    let services = ServiceBuilder::new().layer(cors);
+    let consistency_check = parse_consistency_check_config(&config);
+    if consistency_check.enabled {
+        let schedule = parse_job_schedule(
+            &config,
+            "CONSISTENCY_CHECK_SCHEDULE",
+            consistency_check.interval,
+        );
+        log::info!(
+            "CONSISTENCY: Checking storage consistency {} (repair: {})",
+            schedule,
+            consistency_check.repair_behavior == RepairBehavior::Repair
+        );
+        let state = server_state.clone();
+        scheduler.spawn(Job {
+            name: "consistency-check",
+            schedule: schedule,
+            run: Arc::new(move || {
+                let res = check_consistency(&state, consistency_check.repair_behavior)
+                    .map(|inconsistencies| inconsistencies.len());
+                match &res {
+                    Err(err) => state.notifier.notify_job_failure("consistency-check", err),
+                    Ok(0) => (),
+                    Ok(count) => state.notifier.notify(
+                        "Storage inconsistencies found",
+                        &format!(
+                            "The consistency check found {} inconsistencies (repaired: {}). \
+                             POST /consistency lists them.",
+                            count,
+                            consistency_check.repair_behavior == RepairBehavior::Repair
+                        ),
+                    ),
+                }
+                res
+            }),
+        });
+    } else {
+        log::info!("CONSISTENCY: Regular storage consistency check disabled");
+    }
 
-    // Create router with index and upload endpoints
-    let app = Router::new()
-        .route("/", get(root_handler))
-        .route("/upload", post(upload_handler))
+    if server_state.replicator.is_enabled() {
+        let schedule = parse_job_schedule(
+            &config,
+            "REPLICATION_RECONCILE_SCHEDULE",
+            Duration::from_secs(
+                config
+                    .get::<u64>("REPLICATION_RECONCILE_INTERVAL_SECS")
+                    .unwrap_or(DEFAULT_REPLICATION_RECONCILE_INTERVAL_SECS),
+            ),
+        );
+        log::info!("REPLICATION: Reconciling with the peer {}", schedule);
+        let state = server_state.clone();
+        scheduler.spawn(Job {
+            name: "replication-reconcile",
+            schedule: schedule,
+            run: Arc::new(move || state.replicator.reconcile(&state.metadata_index)),
+        });
+    }
+
+    // Regularly persist cache accesses, so a restart doesn't lose too many of them
+    let cache_index = server_state.cache_index.clone();
+    scheduler.spawn(Job {
+        name: "cache-index-writer",
+        schedule: JobSchedule::Interval {
+            interval: Duration::from_secs(CACHE_INDEX_SAVE_INTERVAL_SECS),
+            jitter: Duration::ZERO,
+        },
+        run: Arc::new(move || cache_index.save().map(|_| 1)),
+    });
+
+    // Same for the upload and state change times of images
+    let metadata_index = server_state.metadata_index.clone();
+    scheduler.spawn(Job {
+        name: "metadata-index-writer",
+        schedule: JobSchedule::Interval {
+            interval: Duration::from_secs(CACHE_INDEX_SAVE_INTERVAL_SECS),
+            jitter: Duration::ZERO,
+        },
+        run: Arc::new(move || metadata_index.save().map(|_| 1)),
+    });
+
+    // And the requests of images
+    let access_stats = server_state.access_stats.clone();
+    scheduler.spawn(Job {
+        name: "access-stats-writer",
+        schedule: JobSchedule::Interval {
+            interval: Duration::from_secs(CACHE_INDEX_SAVE_INTERVAL_SECS),
+            jitter: Duration::ZERO,
+        },
+        run: Arc::new(move || access_stats.save().map(|_| 1)),
+    });
+
+    // Each route group has its own CORS policy, falling back to the global one, see `RouteGroup`
+    let cors_policies = parse_cors_policies(&config).unwrap_or_else(|err| panic!("{}", err));
+    let cors = |group: RouteGroup| {
+        let policy = match cors_policies.get(&group) {
+            None => cors_config.clone(),
+            Some(policy) => cors_config.with_policy(policy),
+        };
+        policy.layer(server_state.clone(), group)
+    };
+    // Images can be changed with the admin policy only
+    let images_cors = SplitCorsLayer {
+        other: cors(RouteGroup::Images),
+        mutating: cors(RouteGroup::Admin),
+        methods: vec![Method::PUT, Method::DELETE],
+    };
+
+    let images = Router::new()
+        .route("/image/:id", get(image_handler))
+        .route("/image/:id", delete(image_delete_handler))
+        .route("/image/:id/srcset", get(srcset_handler))
+        .route("/image/:id/lqip", get(lqip_handler))
+        .route("/image/:id/compare", get(compare_handler))
+        .route(
+            "/image/:id/metadata",
+            get(metadata_handler).put(metadata_update_handler),
+        )
+        .route(
+            "/image/:id/alias",
+            put(alias_update_handler).delete(alias_delete_handler),
+        )
+        .route("/images/info", post(images_info_handler))
+        .route("/thumbnails.zip", post(thumbnails_handler))
+        .route(
+            "/t/:tenant/image/:id",
+            get(tenant_image_handler).delete(tenant_image_delete_handler),
+        )
+        .route(
+            "/t/:tenant/image/:id/metadata",
+            get(tenant_metadata_handler).put(tenant_metadata_update_handler),
+        )
+        .route("/proxy", get(proxy_handler))
+        .route("/openapi.json", get(openapi_handler))
+        .route("/docs", get(docs_handler))
+        .layer(images_cors);
+
+    let upload = Router::new()
+        .route("/upload", post(upload_handler).put(upload_raw_handler))
+        .route("/t/:tenant/upload", post(tenant_upload_handler))
         .layer(DefaultBodyLimit::max(CONTENT_LENGTH_LIMIT))
+        .route("/import", post(import_handler))
         .route("/submit/:id", post(submit_handler))
+        .route("/t/:tenant/submit/:id", post(tenant_submit_handler))
+        .route("/image/:id/preview-token", post(preview_token_handler))
+        .layer(cors(RouteGroup::Upload));
+
+    let admin = Router::new()
         .route("/approve/:id", post(approve_handler))
-        .route("/image/:id", get(image_handler))
-        .route("/image/:id", delete(image_delete_handler))
         .route("/unapprove/:id", post(unapprove_handler))
+        .route("/t/:tenant/approve/:id", post(tenant_approve_handler))
+        .route("/t/:tenant/unapprove/:id", post(tenant_unapprove_handler))
+        .route("/t/:tenant/usage", get(tenant_usage_handler))
+        .route("/t/:tenant/images", get(tenant_images_handler))
+        .route("/t/:tenant/rotate", post(tenant_rotate_handler))
+        .route("/images", get(images_handler))
+        .route("/images/delete", post(images_delete_handler))
+        .route("/raw/location-check", post(location_check_handler))
+        .route("/raw/:id", get(raw_handler))
+        .route("/stats/images", get(image_stats_handler))
+        .route("/stats/top", get(top_images_handler))
+        .route("/stats/bandwidth", get(bandwidth_stats_handler))
+        .route("/stats/disk", get(disk_stats_handler))
+        .route("/stats/shadow-reads", get(shadow_read_stats_handler))
+        .route("/metrics", get(metrics_handler))
+        .route("/verify", post(verify_handler))
+        .route("/export", get(export_handler))
+        // Not limited like uploads, as the archive is streamed to disk
+        .route("/restore", post(restore_handler))
         .route("/rotate", post(rotate_handler))
-        .layer(services)
-        .with_state(server_state);
-
-    log::info!("Listening on {}", LISTEN_ADDR);
-    let listener = tokio::net::TcpListener::bind(&LISTEN_ADDR).await.unwrap();
-    axum::serve(listener, app.into_make_service())
-        .await
-        .unwrap();
+        .route("/rotate/batch", post(rotate_batch_handler))
+        .route("/regenerate/:id", post(regenerate_handler))
+        .route("/reload", post(reload_handler))
+        .route("/consistency", post(consistency_handler))
+        .route("/fsck", post(fsck_handler))
+        .route("/quarantine", get(quarantine_handler))
+        .route("/cache/warmup", post(warmup_handler))
+        .route("/cache/regenerate", post(cache_regenerate_handler))
+        .route(
+            "/cache/pins",
+            get(cache_pins_handler)
+                .post(cache_pin_handler)
+                .delete(cache_unpin_handler),
+        )
+        .route("/jobs", get(jobs_handler))
+        .route("/jobs/:name/run", post(job_run_handler))
+        .route("/jobs/:name/runs/:id", get(job_run_status_handler))
+        .route("/graphql", post(graphql_handler))
+        .route("/admin", get(admin_handler))
+        .route("/info", get(info_handler))
+        .layer(cors(RouteGroup::Admin));
+
+    // All endpoints of the current API version
+    let api = images.merge(upload).merge(admin);
+
+    // Serve the API under its version prefix and, for existing clients, without prefix.
+    // A future breaking version can then be nested under its own prefix, e.g. `/v2`.
+    let app = Router::new()
+        .route("/", get(root_handler))
+        .nest(API_PREFIX, api.clone())
+        .merge(api)
+        .layer(middleware::from_fn_with_state(
+            server_state.clone(),
+            resolve_client_ip,
+        ))
+        .with_state(server_state.clone());
+
+    // Bind to all configured addresses before serving, so a taken port is reported right away
+    let listen_addrs = parse_listen_addrs(&config);
+    let listeners = bind_all(&listen_addrs);
+
+    // Cancelled on SIGINT/SIGTERM or if one of the servers stops
+    let shutdown = CancellationToken::new();
+    tokio::spawn(shutdown_signal(shutdown.clone()));
+
+    let mut servers = JoinSet::new();
+    for (addr, listener) in listen_addrs.into_iter().zip(listeners) {
+        log::info!("Listening on {}", addr);
+        servers.spawn(
+            axum::serve(
+                listener,
+                app.clone()
+                    .into_make_service_with_connect_info::<SocketAddr>(),
+            )
+            .with_graceful_shutdown(shutdown.clone().cancelled_owned())
+            .into_future(),
+        );
+    }
+
+    #[cfg(feature = "grpc")]
+    if let Some(addr) = grpc::parse_grpc_listen_addr(&config) {
+        servers.spawn(grpc::serve_grpc(
+            addr,
+            server_state.clone(),
+            shutdown.clone(),
+        ));
+    }
+
+    // Serve until shutdown, finishing requests that are in progress
+    while let Some(res) = servers.join_next().await {
+        if let Err(err) = res.expect("Server task panicked") {
+            log::error!("Server stopped unexpectedly: {}", err);
+        }
+        shutdown.cancel();
+    }
+
+    log::info!("Waiting for background jobs to finish...");
+    server_state.scheduler.shutdown().await;
+    if let Err(err) = server_state.cache_index.save() {
+        log::error!("{}", err);
+    }
+    if let Err(err) = server_state.metadata_index.save() {
+        log::error!("{}", err);
+    }
+    if let Err(err) = server_state.access_stats.save() {
+        log::error!("{}", err);
+    }
+    mark_shut_down();
+    log::info!("Shut down");
+}
+
+/// Cancels `shutdown` once SIGINT (Ctrl+C) or SIGTERM is received
+async fn shutdown_signal(shutdown: CancellationToken) {
+    let ctrl_c = async {
+        signal::ctrl_c()
+            .await
+            .expect("Could not install Ctrl+C handler");
+    };
+    let terminate = async {
+        signal::unix::signal(signal::unix::SignalKind::terminate())
+            .expect("Could not install SIGTERM handler")
+            .recv()
+            .await;
+    };
+
+    tokio::select! {
+        _ = ctrl_c => (),
+        _ = terminate => (),
+    }
+    log::info!("Shutting down...");
+    shutdown.cancel();
 }
 
 /// A simple handler that prints information about this image service
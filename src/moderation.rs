@@ -0,0 +1,159 @@
+use std::{collections::HashMap, path::Path, process::Stdio, sync::Mutex, time::Duration};
+
+use config::Config;
+use reqwest::{Client, Url};
+use serde::Deserialize;
+use tokio::process::Command;
+use uuid::Uuid;
+
+use crate::{
+    constants::{
+        DEFAULT_MODERATION_THRESHOLD, DEFAULT_MODERATION_TIMEOUT_SECS, MAX_MODERATION_VERDICTS,
+    },
+    util::{image::ImageState, path::list_roots},
+};
+
+/// A classifier rating how likely an image violates the content policy
+enum Classifier {
+    // Service the image is POSTed to, responding with `{"score": <0 to 1>}`
+    Http { url: Url },
+    // Local program (e.g. running a model) called with the path of the image as last argument,
+    // printing the score to stdout
+    Command { program: String, args: Vec<String> },
+}
+
+#[derive(Deserialize)]
+struct ClassifierResponse {
+    score: f64,
+}
+
+/// Classifies uploaded images, so those scoring at least the threshold are held as flagged when
+/// they are submitted instead of waiting for approval with the others
+pub struct Moderator {
+    classifier: Classifier,
+    threshold: f64,
+    timeout: Duration,
+    client: Client,
+    // Whether pending images classified after their upload should be flagged at submission
+    verdicts: Mutex<HashMap<Uuid, bool>>,
+}
+
+/// Parses the moderation hook from the config properties `MODERATION_URL` or
+/// `MODERATION_COMMAND` (program and arguments), `MODERATION_THRESHOLD` and
+/// `MODERATION_TIMEOUT_SECS`. Returns `None` if neither classifier is set.
+pub fn parse_moderator(config: &Config, client: Client) -> Option<Moderator> {
+    let classifier = match (
+        config.get_string("MODERATION_URL"),
+        config.get::<Vec<String>>("MODERATION_COMMAND"),
+    ) {
+        (Ok(url), _) => Classifier::Http {
+            url: Url::parse(&url).ok()?,
+        },
+        (_, Ok(command)) => {
+            let (program, args) = command.split_first()?;
+            Classifier::Command {
+                program: program.clone(),
+                args: args.to_vec(),
+            }
+        }
+        _ => return None,
+    };
+
+    Some(Moderator {
+        classifier: classifier,
+        threshold: config
+            .get::<f64>("MODERATION_THRESHOLD")
+            .unwrap_or(DEFAULT_MODERATION_THRESHOLD),
+        timeout: Duration::from_secs(
+            config
+                .get::<u64>("MODERATION_TIMEOUT_SECS")
+                .unwrap_or(DEFAULT_MODERATION_TIMEOUT_SECS),
+        ),
+        client: client,
+        verdicts: Mutex::new(HashMap::new()),
+    })
+}
+
+impl Moderator {
+    /// Describes the classifier, e.g. for logging
+    pub fn describe(&self) -> String {
+        match &self.classifier {
+            Classifier::Http { url } => url.to_string(),
+            Classifier::Command { program, .. } => program.clone(),
+        }
+    }
+
+    /// Returns whether the image at `path` should be flagged, i.e. its score is at least the
+    /// threshold, and the score
+    pub async fn classify(&self, path: &Path) -> Result<(bool, f64), String> {
+        let score = match tokio::time::timeout(self.timeout, self.score(path)).await {
+            Err(_) => return Err("Classifier timed out".to_owned()),
+            Ok(score) => score?,
+        };
+        Ok((score >= self.threshold, score))
+    }
+
+    /// Records whether the pending image `uuid` should be flagged at its submission
+    pub fn record_verdict(&self, uuid: Uuid, flagged: bool) {
+        let mut verdicts = self.verdicts.lock().unwrap();
+        // Images that are never submitted are deleted by the pending cleaner eventually
+        if verdicts.len() >= MAX_MODERATION_VERDICTS {
+            let pending_paths = list_roots()
+                .iter()
+                .map(|tenant| ImageState::Pending.path(tenant.as_deref()))
+                .collect::<Vec<_>>();
+            verdicts.retain(|uuid, _| {
+                pending_paths
+                    .iter()
+                    .any(|path| path.join(format!("{}.avif", uuid)).exists())
+            });
+        }
+        verdicts.insert(uuid, flagged);
+    }
+
+    /// Returns (and forgets) whether the pending image `uuid` should be flagged, if it was
+    /// classified after its upload
+    pub fn take_verdict(&self, uuid: Uuid) -> Option<bool> {
+        self.verdicts.lock().unwrap().remove(&uuid)
+    }
+
+    async fn score(&self, path: &Path) -> Result<f64, String> {
+        match &self.classifier {
+            Classifier::Http { url } => {
+                let data = tokio::fs::read(path)
+                    .await
+                    .map_err(|err| format!("Could not read {:?}: {}", path, err))?;
+                let response = self
+                    .client
+                    .post(url.clone())
+                    .header(reqwest::header::CONTENT_TYPE, "image/avif")
+                    .body(data)
+                    .send()
+                    .await
+                    .and_then(|res| res.error_for_status())
+                    .map_err(|err| err.to_string())?;
+                let response: ClassifierResponse =
+                    response.json().await.map_err(|err| err.to_string())?;
+                Ok(response.score)
+            }
+            Classifier::Command { program, args } => {
+                let output = Command::new(program)
+                    .args(args)
+                    .arg(path)
+                    .stdin(Stdio::null())
+                    // Killed if it times out
+                    .kill_on_drop(true)
+                    .output()
+                    .await
+                    .map_err(|err| format!("Could not run {}: {}", program, err))?;
+                if !output.status.success() {
+                    return Err(format!("{} exited with {}", program, output.status));
+                }
+                String::from_utf8_lossy(&output.stdout)
+                    .trim()
+                    .parse::<f64>()
+                    .map_err(|err| format!("{} printed no score ({})", program, err))
+            }
+        }
+    }
+}
@@ -0,0 +1,173 @@
+use utoipa::{
+    openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme},
+    Modify, OpenApi,
+};
+
+use crate::{
+    consistency::{Inconsistency, InconsistencyKind},
+    fsck::{ChecksumMismatch, FsckReport},
+    handlers::{
+        alias, approve, cache, compare, consistency, export, fsck, image, images, import, info,
+        jobs, location_check, lqip, metadata, preview_token, proxy, quarantine, raw, regenerate,
+        reload, restore, rotate, srcset, stats, submit, tenant, thumbnails, unapprove, upload,
+        verify, warmup,
+    },
+    operations::{ImageInfo, StorageLocation},
+    quarantine::{QuarantineReason, QuarantinedFile},
+    scheduler::JobRunState,
+    shadow_read::ShadowReadStats,
+    util::{
+        cache_index::CachePin,
+        image::{CacheVariant, ColorProfile, ImageState, OutputFormat},
+        image_metadata::{FocalPoint, ImageMetadata},
+        listing::{ImagePage, SortKey, SortOrder},
+    },
+};
+
+/// OpenAPI specification of all endpoints, served at `/openapi.json`.
+/// New handlers have to be annotated with `#[utoipa::path]` and listed here.
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "Mensatt Image Service"),
+    servers((url = "/v1", description = "Current API version")),
+    paths(
+        upload::upload_handler,
+        upload::upload_raw_handler,
+        import::import_handler,
+        submit::submit_handler,
+        approve::approve_handler,
+        image::image_handler,
+        image::image_delete_handler,
+        srcset::srcset_handler,
+        lqip::lqip_handler,
+        compare::compare_handler,
+        thumbnails::thumbnails_handler,
+        proxy::proxy_handler,
+        raw::raw_handler,
+        location_check::location_check_handler,
+        preview_token::preview_token_handler,
+        metadata::metadata_handler,
+        metadata::metadata_update_handler,
+        alias::alias_update_handler,
+        alias::alias_delete_handler,
+        images::images_handler,
+        images::images_info_handler,
+        images::images_delete_handler,
+        stats::image_stats_handler,
+        stats::top_images_handler,
+        stats::bandwidth_stats_handler,
+        stats::metrics_handler,
+        stats::disk_stats_handler,
+        stats::shadow_read_stats_handler,
+        verify::verify_handler,
+        export::export_handler,
+        restore::restore_handler,
+        unapprove::unapprove_handler,
+        rotate::rotate_handler,
+        rotate::rotate_batch_handler,
+        regenerate::regenerate_handler,
+        reload::reload_handler,
+        consistency::consistency_handler,
+        fsck::fsck_handler,
+        quarantine::quarantine_handler,
+        warmup::warmup_handler,
+        cache::cache_regenerate_handler,
+        cache::cache_pins_handler,
+        cache::cache_pin_handler,
+        cache::cache_unpin_handler,
+        info::info_handler,
+        upload::tenant_upload_handler,
+        tenant::tenant_image_handler,
+        tenant::tenant_image_delete_handler,
+        tenant::tenant_submit_handler,
+        tenant::tenant_approve_handler,
+        tenant::tenant_unapprove_handler,
+        tenant::tenant_usage_handler,
+        tenant::tenant_images_handler,
+        tenant::tenant_rotate_handler,
+        tenant::tenant_metadata_handler,
+        tenant::tenant_metadata_update_handler,
+        jobs::jobs_handler,
+        jobs::job_run_handler,
+        jobs::job_run_status_handler,
+    ),
+    components(schemas(
+        ImageState,
+        upload::UploadRequest,
+        images::ImageListEntry,
+        images::InfoRequest,
+        images::DeleteRequest,
+        images::DeleteResult,
+        StorageLocation,
+        import::ImportRequest,
+        restore::ConflictBehavior,
+        restore::RestoreReport,
+        ImageInfo,
+        ImageMetadata,
+        FocalPoint,
+        metadata::ImageRecord,
+        alias::ImageAlias,
+        srcset::Srcset,
+        srcset::SrcsetEntry,
+        compare::CompareSource,
+        thumbnails::ThumbnailsRequest,
+        rotate::RotateBatchEntry,
+        rotate::RotateBatchResult,
+        preview_token::PreviewToken,
+        CacheVariant,
+        OutputFormat,
+        ColorProfile,
+        ImagePage,
+        stats::ImageStats,
+        stats::DirStats,
+        stats::RetentionStats,
+        stats::TopImage,
+        stats::BandwidthStats,
+        stats::ImageBandwidth,
+        stats::KeyBandwidth,
+        stats::DiskStats,
+        stats::DirUsage,
+        stats::DayGrowth,
+        ShadowReadStats,
+        verify::VerifyRequest,
+        verify::VerifyResult,
+        SortKey,
+        SortOrder,
+        Inconsistency,
+        InconsistencyKind,
+        FsckReport,
+        ChecksumMismatch,
+        location_check::LocationCheckReport,
+        location_check::LocationOffender,
+        QuarantinedFile,
+        QuarantineReason,
+        warmup::WarmupRequest,
+        warmup::WarmupReport,
+        warmup::WarmupFailure,
+        CachePin,
+        info::ServiceInfo,
+        tenant::TenantUsage,
+        info::CodecInfo,
+        info::LimitsInfo,
+        info::FeaturesInfo,
+        jobs::JobStatusResponse,
+        jobs::JobRunResponse,
+        JobRunState,
+    )),
+    modifiers(&BearerAuth)
+)]
+pub struct ApiDoc;
+
+/// Adds the API key as bearer token security scheme
+struct BearerAuth;
+
+impl Modify for BearerAuth {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        if let Some(components) = openapi.components.as_mut() {
+            components.add_security_scheme(
+                "api_key",
+                SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build()),
+            );
+        }
+    }
+}
@@ -0,0 +1,797 @@
+use std::{
+    collections::HashMap,
+    io,
+    path::{Path, PathBuf},
+};
+
+use axum::{body::Bytes, http::StatusCode};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::{
+    content_store::{release_object, store_original},
+    disk_space::check_free_space,
+    error::Error,
+    events::ImageEventKind,
+    fsck::record_checksum,
+    quarantine::{quarantine_infected, quarantine_upload},
+    trash::move_to_trash,
+    util::{
+        durability::Durability,
+        image::{
+            check_upload_header, create_blurhash, create_lqip, delete_image, delete_raw,
+            determine_file_type, determine_img_dim, determine_img_dir, determine_img_path,
+            encode_pending, encode_rotated, encode_upload, find_image, get_lqip_entry,
+            get_raw_file, move_image, remove_cache_entries, save_raw, CacheVariant,
+            ImageSearchBehaviour, ImageState, RemovalBehavior,
+        },
+        image_metadata::ImageMetadata,
+        metadata_index::MetadataIndex,
+        path::{create_root, get_cache_path, get_pending_path, get_raw_path, write_atomically},
+        short_id::to_short_id,
+        tenant::stored_bytes,
+    },
+    virus_scan::ScanResult,
+    webhook::{send_webhook, UploadEvent},
+    ServerState,
+};
+
+// Operations on images shared by the HTTP, gRPC and GraphQL APIs.
+// Errors are returned with the HTTP status code and message to respond with.
+// Uploads and state changes are recorded in the metadata index, published as events and
+// replicated to the peer, if configured.
+
+/// Returns the ID of a new image. UUIDv7s start with the time of the upload, so they sort
+/// chronologically; images with UUIDv4s (uploaded before they were enabled) are still accepted.
+fn new_image_id(server_state: &ServerState) -> Uuid {
+    match server_state.uuid_v7_enabled {
+        false => Uuid::new_v4(),
+        true => Uuid::now_v7(),
+    }
+}
+
+/// Saves an uploaded image as raw file and as pending image, rotated by `angle` degrees, and
+/// records its `metadata`, which is validated before anything is stored, and the `tenant` it
+/// belongs to, if any. Calls the upload webhook, if configured. Returns the ID of the new image,
+/// 507 if the data volume is nearly full (see `check_free_space`) or 422 if the file is infected
+/// (see `scan_upload`). If encoding fails, the raw file is removed, see `discard_failed_upload`.
+pub async fn upload_image(
+    data: &Bytes,
+    angle: f64,
+    metadata: ImageMetadata,
+    tenant: Option<String>,
+    server_state: &ServerState,
+) -> Result<Uuid, (StatusCode, String)> {
+    if data.is_empty() {
+        return Err(Error::BadRequest("Empty file provided".to_owned()).into());
+    }
+    metadata
+        .validate()
+        .map_err(|err| (StatusCode::BAD_REQUEST, err))?;
+
+    let Some(file_identification) = determine_file_type(data) else {
+        return Err(Error::BadRequest(
+            "File type could not be determined or your file type is not supported".to_owned(),
+        )
+        .into());
+    };
+    check_free_space(server_state)?;
+    // Reject decompression bombs before anything is stored
+    check_upload_header(data, &server_state.input_limits)?;
+
+    let uuid = new_image_id(server_state);
+    scan_upload(uuid, data, &file_identification.name(), server_state).await?;
+
+    // Images of tenants are stored in their own root, which is created by their first upload
+    create_root(tenant.as_deref()).map_err(Error::from)?;
+    // Save raw image without any modifications
+    save_raw(data, uuid, tenant.as_deref(), server_state.durability)?;
+    // Rotated and encoded as AVIF
+    let (data_owned, tenant_owned, avif, input_limits) = (
+        data.clone(),
+        tenant.clone(),
+        server_state.avif.pending,
+        server_state.input_limits,
+    );
+    let raw_path = get_raw_file(uuid, tenant.as_deref());
+    let encoded = server_state
+        .watchdog
+        .with_timeout(uuid, &raw_path, "Encoding upload", move || {
+            encode_pending(
+                &data_owned,
+                uuid,
+                tenant_owned.as_deref(),
+                angle,
+                &avif,
+                &input_limits,
+            )
+        })
+        .await;
+    // An abandoned encoding removes its temporary file, so the pending image is never written
+    if let Err(err) = encoded.and_then(|tmp_file| Ok(tmp_file.commit(server_state.durability)?)) {
+        discard_failed_upload(uuid, tenant.as_deref(), &file_identification.name(), &err);
+        return Err(err.into());
+    }
+
+    let path = ImageState::Pending
+        .path(tenant.as_deref())
+        .join(format!("{}.avif", uuid));
+    let has_metadata = !metadata.is_empty();
+    server_state
+        .metadata_index
+        .record_upload(uuid, metadata, tenant.clone());
+    // The index is otherwise only written regularly, so the metadata would be lost on a crash
+    // while the image is kept. Times are not worth a write, as they can be recovered from files.
+    if has_metadata {
+        if let Err(err) = server_state.metadata_index.save() {
+            server_state.metadata_index.remove(uuid);
+            let pending_path = ImageState::Pending.path(tenant.as_deref());
+            let _ = delete_image(&pending_path, uuid, RemovalBehavior::Delete);
+            let _ = delete_raw(uuid, tenant.as_deref(), RemovalBehavior::Delete);
+            return Err(
+                Error::Internal(format!("Could not save metadata of {}: {}", uuid, err)).into(),
+            );
+        }
+    }
+    record_checksum(uuid, &path, server_state);
+    server_state
+        .metadata_index
+        .record_bytes(uuid, stored_bytes(uuid, tenant.as_deref(), &path));
+    server_state.events.publish(ImageEventKind::Uploaded, uuid);
+    server_state.replicator.replicate(uuid);
+    classify_upload(uuid, &path, server_state);
+
+    if let Some(url) = &server_state.webhooks.upload_url {
+        match determine_img_dim(path.to_str().unwrap()) {
+            Err(err) => log::error!("Not calling upload webhook for {}: {}", uuid, err),
+            Ok((width, height)) => send_webhook(
+                &server_state.http_client,
+                url.clone(),
+                server_state.webhooks.secret.as_deref(),
+                UploadEvent {
+                    event: "upload",
+                    id: uuid,
+                    file_type: file_identification.name(),
+                    width: width,
+                    height: height,
+                    sha256: format!("{:x}", Sha256::digest(data)),
+                },
+            ),
+        }
+    }
+
+    Ok(uuid)
+}
+
+/// Removes the raw file of the upload `uuid` of `tenant`, which was saved before encoding it as
+/// `file_type` failed with `err`, so no raw file is left without an image. If the header looked
+/// fine but the file could not be decoded, it is quarantined instead to investigate why. A timed
+/// out upload has already been copied to the quarantine directory by the watchdog.
+fn discard_failed_upload(uuid: Uuid, tenant: Option<&str>, file_type: &str, err: &Error) {
+    match err {
+        Error::Vips(vips_err) => quarantine_upload(uuid, tenant, file_type, &vips_err.to_string()),
+        // Failures are already logged
+        _ => {
+            let _ = delete_raw(uuid, tenant, RemovalBehavior::Delete);
+        }
+    }
+}
+
+/// Scans the upload `uuid` with clamd, if configured. Infected files are quarantined and rejected
+/// with 422. If the scan fails, uploads are rejected with 503, as they must not be stored
+/// unscanned.
+async fn scan_upload(
+    uuid: Uuid,
+    data: &Bytes,
+    file_type: &str,
+    server_state: &ServerState,
+) -> Result<(), (StatusCode, String)> {
+    let Some(virus_scanner) = &server_state.virus_scanner else {
+        return Ok(());
+    };
+
+    match virus_scanner.scan(data).await {
+        Err(err) => {
+            log::error!("CLAMAV: {}", err);
+            Err((
+                StatusCode::SERVICE_UNAVAILABLE,
+                "Virus scan failed, please try again later!".to_owned(),
+            ))
+        }
+        Ok(ScanResult::Infected(signature)) => {
+            log::warn!("CLAMAV: Upload {} is infected with {}", uuid, signature);
+            quarantine_infected(uuid, data, file_type, &signature);
+            Err((
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "File is infected and was rejected!".to_owned(),
+            ))
+        }
+        Ok(ScanResult::Clean) => Ok(()),
+    }
+}
+
+/// Moves the pending image with `uuid` to unapproved (step 2 of the image flow), or to flagged if
+/// the moderation hook flags it. Returns the new state of the image.
+pub async fn submit_image(
+    uuid: Uuid,
+    server_state: &ServerState,
+) -> Result<ImageState, (StatusCode, String)> {
+    check_id(uuid)?;
+    let (to, event) = match is_flagged(uuid, server_state).await? {
+        false => (ImageState::Unapproved, ImageEventKind::Submitted),
+        true => (ImageState::Flagged, ImageEventKind::Flagged),
+    };
+
+    let _lock = server_state.image_locks.lock(uuid).await;
+    transition_image(
+        uuid,
+        ImageState::Pending,
+        to,
+        "submitting",
+        event,
+        server_state,
+    )?;
+    Ok(to)
+}
+
+/// Classifies the new upload `uuid` with the moderation hook in the background, if configured,
+/// so its submission doesn't have to wait for the classifier (see `is_flagged`)
+fn classify_upload(uuid: Uuid, path: &Path, server_state: &ServerState) {
+    let Some(moderator) = server_state.moderator.clone() else {
+        return;
+    };
+    let path = path.to_owned();
+    tokio::spawn(async move {
+        match moderator.classify(&path).await {
+            Err(err) => log::warn!("MODERATION: Could not classify upload {}: {}", uuid, err),
+            Ok((flagged, score)) => {
+                log::info!("MODERATION: Upload {} scored {}", uuid, score);
+                moderator.record_verdict(uuid, flagged);
+            }
+        }
+    });
+}
+
+/// Returns whether the pending image with `uuid` should be flagged, as classified after its
+/// upload or, if that didn't finish (successfully), now with the moderation hook, if configured.
+/// Images that cannot be classified are not flagged, as they are still reviewed before their
+/// approval.
+async fn is_flagged(uuid: Uuid, server_state: &ServerState) -> Result<bool, (StatusCode, String)> {
+    let Some(moderator) = &server_state.moderator else {
+        return Ok(false);
+    };
+    let tenant = server_state.metadata_index.tenant(uuid);
+    let path = ImageState::Pending
+        .path(tenant.as_deref())
+        .join(format!("{}.avif", uuid));
+    if !path.exists() {
+        return Err(Error::NotFound("Image not found".to_owned()).into());
+    }
+    if let Some(flagged) = moderator.take_verdict(uuid) {
+        if flagged {
+            log::info!(
+                "MODERATION: Flagging {} as classified after its upload",
+                uuid
+            );
+        }
+        return Ok(flagged);
+    }
+
+    match moderator.classify(&path).await {
+        Err(err) => {
+            log::error!("MODERATION: Could not classify {}: {}", uuid, err);
+            Ok(false)
+        }
+        Ok((flagged, score)) => {
+            log::info!(
+                "MODERATION: {} scored {}{}",
+                uuid,
+                score,
+                if flagged { ", flagging it" } else { "" }
+            );
+            Ok(flagged)
+        }
+    }
+}
+
+/// Moves the unapproved (or flagged) image with `uuid` to approved (step 3 of the image flow)
+pub async fn approve_image(
+    uuid: Uuid,
+    server_state: &ServerState,
+) -> Result<(), (StatusCode, String)> {
+    let _lock = server_state.image_locks.lock(uuid).await;
+    let tenant = server_state.metadata_index.tenant(uuid);
+    let flagged_path = ImageState::Flagged.path(tenant.as_deref());
+    let from = match determine_img_path(flagged_path.to_str().unwrap(), uuid) {
+        Ok(_) => ImageState::Flagged,
+        Err(_) => ImageState::Unapproved,
+    };
+    transition_image(
+        uuid,
+        from,
+        ImageState::Approved,
+        "approving",
+        ImageEventKind::Approved,
+        server_state,
+    )?;
+    store_original(uuid, server_state);
+    create_lqip_in_background(uuid, server_state);
+    Ok(())
+}
+
+/// Rotates the (unapproved or approved) image with `uuid` clockwise by `angle` degrees (90, 180
+/// or 270), pending images only with `include_pending`. Its cache entries and placeholder are
+/// renewed.
+pub async fn rotate_image(
+    uuid: Uuid,
+    angle: i64,
+    include_pending: bool,
+    server_state: &ServerState,
+) -> Result<(), (StatusCode, String)> {
+    if angle <= 0 || angle >= 360 || angle % 90 != 0 {
+        return Err(Error::BadRequest("Angle must be one of {90, 180, 270}".to_owned()).into());
+    }
+
+    // Held until the rotated image is saved, so it can't be moved or deleted in the meantime
+    let _lock = server_state.image_locks.lock(uuid).await;
+    let previous_checksum = server_state.metadata_index.checksum(uuid);
+    let tenant = server_state.metadata_index.tenant(uuid);
+
+    let search_behaviour = match include_pending {
+        true => ImageSearchBehaviour::All,
+        false => ImageSearchBehaviour::Valid,
+    };
+    let not_found = || Error::NotFound("Image not found".to_owned());
+    let image_directory =
+        determine_img_dir(uuid, tenant.as_deref(), search_behaviour).map_err(|_| not_found())?;
+
+    let image_directory_string = image_directory.to_string_lossy().to_string();
+
+    let image_path = match determine_img_path(image_directory_string.as_str(), uuid) {
+        Err(err) => {
+            log::warn!(
+            "Image not found where the path was previously determined. Id: {:?}, Directory: {:?}, Error: {:?}",
+            uuid,
+            image_directory_string.as_str(),
+            err
+        );
+            return Err(not_found().into());
+        }
+        Ok(image_path) => image_path,
+    };
+
+    // Pending images are saved like uploads, as they are not kept if not submitted. The others
+    // with the highest quality, to keep the loss of repeated rotations low.
+    let avif = match image_directory == get_pending_path(tenant.as_deref()) {
+        true => server_state.avif.pending,
+        false => server_state.avif.rotation,
+    };
+
+    // Saved atomically, so the image is never partially rotated
+    let source = image_path.clone();
+    server_state
+        .watchdog
+        .with_timeout(uuid, &image_path, "Rotating", move || {
+            encode_rotated(&source, angle as f64, &avif)
+        })
+        .await?
+        .commit(server_state.durability)
+        .map_err(Error::from)?;
+    log::info!("Rotated {:?} by {} degrees", image_path, angle);
+
+    record_checksum(uuid, &image_path, server_state);
+    server_state
+        .metadata_index
+        .record_bytes(uuid, stored_bytes(uuid, tenant.as_deref(), &image_path));
+    release_object(previous_checksum);
+    store_original(uuid, server_state);
+    remove_cache_entries(uuid, tenant.as_deref(), RemovalBehavior::Delete);
+    server_state.cdn.purge(&server_state.http_client, uuid);
+    server_state.replicator.replicate(uuid);
+    create_lqip_in_background(uuid, server_state);
+
+    Ok(())
+}
+
+/// Rebuilds the image with `uuid` from its raw file like an upload (rotated by `angle` degrees),
+/// e.g. after the encoder settings changed. The image keeps its state, its cache entries and
+/// placeholder are renewed. Returns the state of the image.
+pub async fn regenerate_image(
+    uuid: Uuid,
+    angle: f64,
+    server_state: &ServerState,
+) -> Result<ImageState, (StatusCode, String)> {
+    check_id(uuid)?;
+    let _lock = server_state.image_locks.lock(uuid).await;
+
+    let tenant = server_state.metadata_index.tenant(uuid);
+    let Some((state, path)) = find_image(uuid, tenant.as_deref()) else {
+        return Err(Error::NotFound("Image not found".to_owned()).into());
+    };
+    let previous_checksum = server_state.metadata_index.checksum(uuid);
+    let raw_path = get_raw_file(uuid, tenant.as_deref());
+    let data = match std::fs::read(&raw_path) {
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            return Err(Error::NotFound("Raw file not found".to_owned()).into());
+        }
+        Err(err) => return Err(Error::from(err).into()),
+        Ok(data) => Bytes::from(data),
+    };
+
+    log::info!("Regenerating {:?} from {:?}", path, raw_path);
+    let (target, avif, input_limits) = (
+        path.clone(),
+        server_state.avif.regenerate,
+        server_state.input_limits,
+    );
+    server_state
+        .watchdog
+        .with_timeout(uuid, &raw_path, "Regenerating", move || {
+            encode_upload(&data, &target, angle, &avif, &input_limits)
+        })
+        .await?
+        .commit(server_state.durability)
+        .map_err(Error::from)?;
+
+    record_checksum(uuid, &path, server_state);
+    server_state
+        .metadata_index
+        .record_bytes(uuid, stored_bytes(uuid, tenant.as_deref(), &path));
+    release_object(previous_checksum);
+    store_original(uuid, server_state);
+    remove_cache_entries(uuid, tenant.as_deref(), RemovalBehavior::Delete);
+    server_state.cdn.purge(&server_state.http_client, uuid);
+    server_state.replicator.replicate(uuid);
+    create_lqip_in_background(uuid, server_state);
+    Ok(state)
+}
+
+/// Moves the approved image with `uuid` back to unapproved and deletes it from the cache
+/// and the CDNs
+pub async fn unapprove_image(
+    uuid: Uuid,
+    server_state: &ServerState,
+) -> Result<(), (StatusCode, String)> {
+    let _lock = server_state.image_locks.lock(uuid).await;
+    transition_image(
+        uuid,
+        ImageState::Approved,
+        ImageState::Unapproved,
+        "unapproving",
+        ImageEventKind::Unapproved,
+        server_state,
+    )?;
+    let tenant = server_state.metadata_index.tenant(uuid);
+    remove_cache_entries(uuid, tenant.as_deref(), RemovalBehavior::Delete);
+    server_state.cdn.purge(&server_state.http_client, uuid);
+    Ok(())
+}
+
+/// Creates the low-quality image placeholder of the image with `uuid` at `path` (see
+/// `create_lqip`) and writes it to the cache
+pub async fn create_lqip_entry(
+    uuid: Uuid,
+    path: &Path,
+    server_state: &ServerState,
+) -> Result<Vec<u8>, Error> {
+    let (source, blur) = (path.to_owned(), server_state.lqip_blur);
+    let buffer = server_state
+        .watchdog
+        .with_timeout(uuid, path, "Creating placeholder of", move || {
+            create_lqip(source.to_str().unwrap(), blur)
+        })
+        .await?;
+    let tenant = server_state.metadata_index.tenant(uuid);
+    write_atomically(
+        &get_lqip_entry(uuid, tenant.as_deref()),
+        &buffer,
+        Durability::None,
+    )
+    .map_err(Error::Internal)?;
+    Ok(buffer)
+}
+
+/// Creates the low-quality image placeholder of the image with `uuid` in the background, if it
+/// is approved, so it is already cached when it is first requested, and records its BlurHash
+pub fn create_lqip_in_background(uuid: Uuid, server_state: &ServerState) {
+    let server_state = server_state.clone();
+    tokio::spawn(async move {
+        let tenant = server_state.metadata_index.tenant(uuid);
+        let approved_path = ImageState::Approved.path(tenant.as_deref());
+        let Ok(path) = determine_img_path(approved_path.to_str().unwrap(), uuid) else {
+            return;
+        };
+        if let Err(err) = create_lqip_entry(uuid, &path, &server_state).await {
+            log::error!(
+                "Could not create low-quality placeholder of {}: {}",
+                uuid,
+                err
+            );
+        }
+        let source = path.clone();
+        let blurhash = server_state
+            .watchdog
+            .with_timeout(uuid, &path, "Creating BlurHash of", move || {
+                create_blurhash(source.to_str().unwrap())
+            })
+            .await;
+        match blurhash {
+            Err(err) => log::error!("Could not create BlurHash of {}: {}", uuid, err),
+            Ok(blurhash) => server_state.metadata_index.record_blurhash(uuid, blurhash),
+        }
+    });
+}
+
+fn transition_image(
+    uuid: Uuid,
+    from: ImageState,
+    to: ImageState,
+    action: &str,
+    event: ImageEventKind,
+    server_state: &ServerState,
+) -> Result<(), (StatusCode, String)> {
+    check_id(uuid)?;
+
+    let tenant = server_state.metadata_index.tenant(uuid);
+    match move_image(
+        &from.path(tenant.as_deref()),
+        &to.path(tenant.as_deref()),
+        uuid,
+        server_state.durability,
+    ) {
+        Err(err) => match err.kind() {
+            io::ErrorKind::NotFound => Err(Error::NotFound("Image not found".to_owned()).into()),
+            _ => Err(
+                Error::Internal(format!("Error while {} image {}: {}", action, uuid, err)).into(),
+            ),
+        },
+        Ok(_) => {
+            server_state.metadata_index.record_state_change(uuid, event);
+            server_state.events.publish(event, uuid);
+            server_state.replicator.replicate(uuid);
+            Ok(())
+        }
+    }
+}
+
+/// Deletes the image with `uuid` from all states and the cache. With `TRASH_ENABLED`, the image
+/// and its raw file are moved to the trash instead of being deleted.
+/// Returns the removed (or, in a dry run, the to be removed) files.
+pub async fn delete_image_everywhere(
+    uuid: Uuid,
+    removal_behavior: RemovalBehavior,
+    server_state: &ServerState,
+) -> Result<Vec<PathBuf>, (StatusCode, String)> {
+    check_id(uuid)?;
+    let _lock = server_state.image_locks.lock(uuid).await;
+    let checksum = server_state.metadata_index.checksum(uuid);
+    let tenant = server_state.metadata_index.tenant(uuid);
+
+    let error = |err| Error::Internal(format!("Error while deleting image {}: {}", uuid, err));
+    let to_trash = server_state.trash_enabled && removal_behavior == RemovalBehavior::Delete;
+
+    let mut removed = Vec::new();
+    let mut removed_any_image = false;
+    for state in ImageState::ALL {
+        let removed_image = if to_trash {
+            trash_file(
+                determine_img_path(state.path(tenant.as_deref()).to_str().unwrap(), uuid),
+                server_state,
+            )
+        } else {
+            delete_image(&state.path(tenant.as_deref()), uuid, removal_behavior)
+        }
+        .map_err(error)?;
+        removed_any_image |= removed_image.is_some();
+        removed.extend(removed_image);
+    }
+    // The raw file is kept with the image, so it can be restored completely
+    if to_trash {
+        let raw_path = get_raw_file(uuid, tenant.as_deref());
+        removed.extend(trash_file(Ok(raw_path), server_state).map_err(error)?);
+    }
+    removed.extend(remove_cache_entries(
+        uuid,
+        tenant.as_deref(),
+        removal_behavior,
+    ));
+    if removal_behavior == RemovalBehavior::Delete {
+        server_state.metadata_index.remove(uuid);
+        server_state.cache_index.unpin_image(uuid);
+        release_object(checksum);
+        if removed_any_image {
+            server_state.events.publish(ImageEventKind::Deleted, uuid);
+            server_state.replicator.replicate(uuid);
+            server_state.cdn.purge(&server_state.http_client, uuid);
+        }
+    }
+
+    Ok(removed)
+}
+
+/// Moves the file at `path`, if it exists, to the trash and returns its original path
+fn trash_file(
+    path: Result<PathBuf, io::Error>,
+    server_state: &ServerState,
+) -> Result<Option<PathBuf>, io::Error> {
+    let path = match path {
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        res => res?,
+    };
+    match move_to_trash(&path, server_state.durability) {
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => {
+            log::error!("Error while moving '{:?}' to the trash: {}", path, err);
+            Err(err)
+        }
+        Ok(_) => Ok(Some(path)),
+    }
+}
+
+/// Where a file of an image is stored
+#[derive(Clone, Copy, PartialEq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum StorageLocation {
+    Pending,
+    Unapproved,
+    Flagged,
+    Approved,
+    Raw,
+    Cache,
+}
+
+impl StorageLocation {
+    /// Returns the location of the file at `path`, if it is stored in one of the data directories
+    /// of `tenant`
+    pub fn of(path: &Path, tenant: Option<&str>) -> Option<StorageLocation> {
+        let dir = path.parent()?;
+        if dir == ImageState::Pending.path(tenant) {
+            Some(StorageLocation::Pending)
+        } else if dir == ImageState::Unapproved.path(tenant) {
+            Some(StorageLocation::Unapproved)
+        } else if dir == ImageState::Flagged.path(tenant) {
+            Some(StorageLocation::Flagged)
+        } else if dir == ImageState::Approved.path(tenant) {
+            Some(StorageLocation::Approved)
+        } else if dir == get_raw_path(tenant) {
+            Some(StorageLocation::Raw)
+        } else if dir == get_cache_path(tenant) {
+            Some(StorageLocation::Cache)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ImageInfo {
+    /// Shorter alternative to the UUID, accepted by `/image/:id`
+    pub short_id: String,
+    pub state: ImageState,
+    pub width: i32,
+    pub height: i32,
+    pub cache_variants: Vec<CacheVariant>,
+    /// Provided along with the upload
+    pub metadata: ImageMetadata,
+    /// Compact placeholder of approved images, see <https://blurha.sh>. Computed in the
+    /// background, so it is missing right after the approval.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub blurhash: Option<String>,
+}
+
+/// Returns metadata of the image with `uuid`, if it exists.
+/// Unless `authorized`, only approved images are returned.
+/// `cache_variants` are the cached renditions of all images, as returned by
+/// `list_cache_variants`.
+pub fn image_info(
+    uuid: Uuid,
+    authorized: bool,
+    cache_variants: &HashMap<Uuid, Vec<CacheVariant>>,
+    metadata_index: &MetadataIndex,
+) -> Option<ImageInfo> {
+    let (state, path) = find_image(uuid, metadata_index.tenant(uuid).as_deref())?;
+    if !authorized && state != ImageState::Approved {
+        return None;
+    }
+
+    let (width, height) = determine_img_dim(path.to_str().unwrap()).ok()?;
+    Some(ImageInfo {
+        short_id: to_short_id(uuid),
+        state: state,
+        width: width,
+        height: height,
+        cache_variants: cache_variants.get(&uuid).cloned().unwrap_or_default(),
+        metadata: metadata_index.metadata(uuid),
+        blurhash: metadata_index.blurhash(uuid),
+    })
+}
+
+fn check_id(uuid: Uuid) -> Result<(), Error> {
+    if uuid.is_nil() {
+        return Err(Error::BadRequest("Invalid ID".to_owned()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, thread, time::Duration};
+
+    use config::Config;
+
+    use super::*;
+    use crate::util::{
+        avif::parse_avif_profiles,
+        input_limits::tests::{limits, png_header, start_vips},
+        path::{get_quarantine_path, get_root_path},
+        watchdog::parse_watchdog,
+    };
+
+    /// Saves `data` as raw file of a new upload in the root of a tenant of its own, which is
+    /// removed by `remove_upload`
+    fn save_upload(data: &Bytes) -> (Uuid, String) {
+        let uuid = Uuid::new_v4();
+        let tenant = format!("test-{}", uuid);
+        create_root(Some(&tenant)).unwrap();
+        save_raw(data, uuid, Some(&tenant), Durability::None).unwrap();
+        (uuid, tenant)
+    }
+
+    fn remove_upload(uuid: Uuid, tenant: &str) {
+        let _ = fs::remove_dir_all(get_root_path(Some(tenant)));
+        for name in [
+            format!("timeout-{}.raw", uuid),
+            format!("timeout-{}.raw.json", uuid),
+        ] {
+            let _ = fs::remove_file(get_quarantine_path().join(name));
+        }
+    }
+
+    #[tokio::test]
+    async fn discard_failed_upload_removes_raw_file_after_timeout() {
+        let (uuid, tenant) = save_upload(&png_header(640, 480));
+        let raw_path = get_raw_file(uuid, Some(&tenant));
+        let config = Config::builder()
+            .set_override("VIPS_TIMEOUT_SECS", 0)
+            .unwrap()
+            .build()
+            .unwrap();
+        let err = parse_watchdog(&config)
+            .with_timeout(uuid, &raw_path, "Encoding upload", || {
+                thread::sleep(Duration::from_millis(100));
+                Ok(())
+            })
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::Timeout(_)));
+
+        discard_failed_upload(uuid, Some(&tenant), "png", &err);
+        let removed = !raw_path.exists();
+        // The watchdog keeps a copy to investigate the timeout
+        let copied = get_quarantine_path()
+            .join(format!("timeout-{}.raw", uuid))
+            .exists();
+        remove_upload(uuid, &tenant);
+        assert!(removed);
+        assert!(copied);
+    }
+
+    #[test]
+    fn discard_failed_upload_removes_raw_file_of_unprocessable_upload() {
+        start_vips();
+        let data = png_header(1, 100_000);
+        let (uuid, tenant) = save_upload(&data);
+        let avif = parse_avif_profiles(&Config::default()).unwrap().pending;
+        let Err(err) = encode_pending(&data, uuid, Some(&tenant), 0.0, &avif, &limits()) else {
+            panic!("Encoded an image exceeding the input limits");
+        };
+        assert!(matches!(err, Error::Unprocessable(_)));
+
+        discard_failed_upload(uuid, Some(&tenant), "png", &err);
+        let removed = !get_raw_file(uuid, Some(&tenant)).exists();
+        remove_upload(uuid, &tenant);
+        assert!(removed);
+    }
+}
@@ -0,0 +1,134 @@
+use std::{
+    path::PathBuf,
+    time::{Duration, SystemTime},
+};
+
+use axum::{body::Bytes, http::StatusCode};
+use config::Config;
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::{
+    constants::{DEFAULT_PROXY_CACHE_TTL_SECS, DEFAULT_PROXY_MAX_SIZE, DEFAULT_PROXY_TIMEOUT_SECS},
+    error::Error,
+    import::Importer,
+    util::{
+        durability::Durability,
+        image::{check_upload_header, determine_file_type},
+        input_limits::InputLimits,
+        path::{get_proxy_cache_path, write_atomically},
+    },
+};
+
+/// Fetches external images for `/proxy`, which are served through the same pipeline as stored
+/// images without being stored as originals. Fetched images are kept in the proxy cache
+/// directory for a while, so they are not fetched for every request.
+pub struct Proxy {
+    downloader: Importer,
+    cache_ttl: Duration,
+}
+
+/// Parses the proxy settings `PROXY_ALLOWED_HOSTS`, `PROXY_MAX_SIZE`, `PROXY_TIMEOUT_SECS` and
+/// `PROXY_CACHE_TTL_SECS`. Returns `None` if no hosts are allowed, i.e. the proxy is disabled.
+pub fn parse_proxy(config: &Config) -> Option<Proxy> {
+    let allowed_hosts = config
+        .get::<Vec<String>>("PROXY_ALLOWED_HOSTS")
+        .unwrap_or_default();
+    if allowed_hosts.is_empty() {
+        return None;
+    }
+
+    let timeout = Duration::from_secs(
+        config
+            .get::<u64>("PROXY_TIMEOUT_SECS")
+            .unwrap_or(DEFAULT_PROXY_TIMEOUT_SECS),
+    );
+    let max_size = config
+        .get::<usize>("PROXY_MAX_SIZE")
+        .unwrap_or(DEFAULT_PROXY_MAX_SIZE);
+    Some(Proxy {
+        downloader: Importer::new(allowed_hosts, timeout, max_size),
+        cache_ttl: Duration::from_secs(
+            config
+                .get::<u64>("PROXY_CACHE_TTL_SECS")
+                .unwrap_or(DEFAULT_PROXY_CACHE_TTL_SECS),
+        ),
+    })
+}
+
+impl Proxy {
+    /// Returns the ID the image at `url` is served under (derived from the URL) and the path of
+    /// the fetched image. It is only fetched, if it is not cached or the cached one has expired.
+    /// Images exceeding `input_limits` are neither cached nor decoded.
+    /// Returns the HTTP status code and message to respond with otherwise.
+    pub async fn fetch(
+        &self,
+        url: &str,
+        input_limits: &InputLimits,
+    ) -> Result<(Uuid, PathBuf), (StatusCode, String)> {
+        let hash = Sha256::digest(url.as_bytes());
+        let uuid = Uuid::from_slice(&hash[..16]).unwrap();
+        let path = get_proxy_cache_path().join(format!("{:x}", hash));
+
+        let modified = path.metadata().and_then(|metadata| metadata.modified());
+        if modified.is_ok_and(|modified| {
+            SystemTime::now()
+                .duration_since(modified)
+                .is_ok_and(|age| age < self.cache_ttl)
+        }) {
+            return Ok((uuid, path));
+        }
+
+        let data = self.downloader.download(url).await?;
+        if let Err(err) = check_download(&data, input_limits) {
+            log::warn!("Not proxying {}: {}", url, err.1);
+            return Err(err);
+        }
+        // Written atomically, as concurrent requests of the same URL may read it already
+        if let Err(err) = write_atomically(&path, &data, Durability::None) {
+            return Err(
+                Error::Internal(format!("Could not cache proxied image {}: {}", url, err)).into(),
+            );
+        }
+
+        Ok((uuid, path))
+    }
+}
+
+/// Checks that the downloaded `data` is a supported image within `input_limits`. Proxied images
+/// are rendered without being stored, so this is the only check before they are decoded.
+fn check_download(data: &Bytes, input_limits: &InputLimits) -> Result<(), (StatusCode, String)> {
+    if determine_file_type(data).is_none() {
+        return Err((
+            StatusCode::BAD_GATEWAY,
+            "The file is no supported image!".to_owned(),
+        ));
+    }
+    check_upload_header(data, input_limits)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::input_limits::tests::{limits, png_header, start_vips};
+
+    #[test]
+    fn check_download_accepts_images_within_limits() {
+        start_vips();
+        assert_eq!(check_download(&png_header(640, 480), &limits()), Ok(()));
+    }
+
+    #[test]
+    fn check_download_rejects_oversized_images() {
+        start_vips();
+        let (status, _) = check_download(&png_header(100_000, 100_000), &limits()).unwrap_err();
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    #[test]
+    fn check_download_rejects_unsupported_files() {
+        let (status, _) = check_download(&Bytes::from_static(b"<html>"), &limits()).unwrap_err();
+        assert_eq!(status, StatusCode::BAD_GATEWAY);
+    }
+}
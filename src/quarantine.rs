@@ -0,0 +1,223 @@
+use std::{
+    fs::{self, create_dir_all, rename},
+    io,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::util::{
+    image::RemovalBehavior,
+    path::{get_quarantine_path, get_raw_path, list_files},
+};
+
+/// Why an upload was quarantined (it could not be decoded, is infected or its processing timed
+/// out), written next to the quarantined file as `<file name>.json`
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct QuarantineReason {
+    id: Uuid,
+    // Type of the file according to its header, e.g. `jpeg`
+    file_type: String,
+    size: u64,
+    error: String,
+    quarantined_at: DateTime<Utc>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct QuarantinedFile {
+    // File name in the quarantine directory, prefixed by its origin, e.g. `upload-`, `infected-`,
+    // `timeout-` or `raw-`
+    name: String,
+    size: u64,
+    modified: Option<DateTime<Utc>>,
+    // Only recorded for uploads, files quarantined during recovery are listed in its report
+    reason: Option<QuarantineReason>,
+}
+
+/// Moves `path` to the quarantine directory as `name`, creating the directory if necessary.
+/// Returns the new path.
+pub fn move_to_quarantine(path: &Path, name: &str) -> Result<PathBuf, io::Error> {
+    let quarantine_path = get_quarantine_path();
+    create_dir_all(&quarantine_path)?;
+    let target = quarantine_path.join(name);
+    rename(path, &target)?;
+    Ok(target)
+}
+
+/// Moves the raw file of the upload `uuid` of `tenant`, which passed the header check as
+/// `file_type` but could not be decoded with `error`, to the quarantine directory along with the
+/// reason, so recurring encoding bugs of clients can be investigated. Failures are only logged.
+pub fn quarantine_upload(uuid: Uuid, tenant: Option<&str>, file_type: &str, error: &str) {
+    let raw_path = get_raw_path(tenant).join(format!("{}.raw", uuid));
+    let name = format!("upload-{}.raw", uuid);
+    let target = match move_to_quarantine(&raw_path, &name) {
+        Err(err) => {
+            log::error!("Unable to quarantine {:?}: {}", raw_path, err);
+            return;
+        }
+        Ok(target) => target,
+    };
+
+    let size = fs::metadata(&target)
+        .map(|metadata| metadata.len())
+        .unwrap_or(0);
+    write_reason(&name, uuid, file_type, size, error);
+    log::warn!("Quarantined upload {} as {:?}", uuid, target);
+}
+
+/// Writes the upload `uuid` (which was not saved yet), in which clamd found `signature`, to the
+/// quarantine directory along with the reason. Failures are only logged.
+pub fn quarantine_infected(uuid: Uuid, data: &[u8], file_type: &str, signature: &str) {
+    let name = format!("infected-{}.raw", uuid);
+    let target = get_quarantine_path().join(&name);
+    let res = create_dir_all(get_quarantine_path()).and_then(|_| fs::write(&target, data));
+    if let Err(err) = res {
+        log::error!("Unable to quarantine infected upload {}: {}", uuid, err);
+        return;
+    }
+
+    let error = format!("Infected with {}", signature);
+    write_reason(&name, uuid, file_type, data.len() as u64, &error);
+    log::warn!("Quarantined infected upload {} as {:?}", uuid, target);
+}
+
+/// Copies `source` of the image `uuid`, whose processing did not finish in time (see
+/// `with_timeout`), to the quarantine directory along with the reason, so the offending file can
+/// be investigated. The image itself is left in place. Failures are only logged.
+pub fn record_timeout(uuid: Uuid, source: &Path, error: &str) {
+    let file_type = source
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or("raw");
+    let name = format!("timeout-{}.{}", uuid, file_type);
+    let target = get_quarantine_path().join(&name);
+    // Each request of the image times out again, but one copy suffices
+    if target.exists() {
+        return;
+    }
+    let res = create_dir_all(get_quarantine_path()).and_then(|_| fs::copy(source, &target));
+    let size = match res {
+        Err(err) => {
+            log::error!("Unable to quarantine {:?} after a timeout: {}", source, err);
+            return;
+        }
+        Ok(size) => size,
+    };
+
+    write_reason(&name, uuid, file_type, size, error);
+    log::warn!(
+        "Copied {:?} of timed out image {} to {:?}",
+        source,
+        uuid,
+        target
+    );
+}
+
+/// Writes the reason the file `name` was quarantined next to it
+fn write_reason(name: &str, uuid: Uuid, file_type: &str, size: u64, error: &str) {
+    let reason = QuarantineReason {
+        id: uuid,
+        file_type: file_type.to_owned(),
+        size: size,
+        error: error.to_owned(),
+        quarantined_at: Utc::now(),
+    };
+    let reason_path = get_quarantine_path().join(format!("{}.json", name));
+    let res = serde_json::to_vec_pretty(&reason)
+        .map_err(|err| err.to_string())
+        .and_then(|json| fs::write(&reason_path, json).map_err(|err| err.to_string()));
+    if let Err(err) = res {
+        log::error!("Could not write reason to {:?}: {}", reason_path, err);
+    }
+}
+
+/// Lists the quarantined files with their reason, if recorded, most recently modified first.
+/// JSON files (reasons and recovery reports) are not listed themselves.
+pub fn list_quarantine() -> Result<Vec<QuarantinedFile>, io::Error> {
+    let quarantine_path = get_quarantine_path();
+    if !quarantine_path.exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut files = Vec::new();
+    for name in list_files(&quarantine_path)? {
+        if name.ends_with(".json") {
+            continue;
+        }
+        let Ok(metadata) = fs::metadata(quarantine_path.join(&name)) else {
+            continue;
+        };
+        let reason = fs::read(quarantine_path.join(format!("{}.json", name)))
+            .ok()
+            .and_then(|json| serde_json::from_slice(&json).ok());
+        files.push(QuarantinedFile {
+            name: name,
+            size: metadata.len(),
+            modified: metadata.modified().ok().map(DateTime::<Utc>::from),
+            reason: reason,
+        });
+    }
+    files.sort_by(|a, b| b.modified.cmp(&a.modified).then(a.name.cmp(&b.name)));
+    Ok(files)
+}
+
+/// Deletes the least recently modified quarantined files (along with their reasons) until the
+/// quarantine directory takes up at most `max_bytes`, so e.g. a flood of infected uploads can't
+/// fill the data volume. Returns the number of deleted files.
+pub fn enforce_quarantine_size(
+    max_bytes: u64,
+    removal_behavior: RemovalBehavior,
+) -> Result<usize, String> {
+    let quarantine_path = get_quarantine_path();
+    if !quarantine_path.exists() {
+        return Ok(0);
+    }
+    let names = list_files(&quarantine_path)
+        .map_err(|err| format!("Unable to read {:?}: {}", quarantine_path, err))?;
+
+    // Reasons are deleted with their file, other JSON files (recovery reports) on their own
+    let mut files: Vec<(SystemTime, u64, String)> = names
+        .iter()
+        .filter(|name| {
+            !name
+                .strip_suffix(".json")
+                .is_some_and(|file_name| names.iter().any(|name| name == file_name))
+        })
+        .filter_map(|name| {
+            let metadata = fs::metadata(quarantine_path.join(name)).ok()?;
+            let reason_size = fs::metadata(quarantine_path.join(format!("{}.json", name)))
+                .map_or(0, |metadata| metadata.len());
+            Some((
+                metadata.modified().ok()?,
+                metadata.len() + reason_size,
+                name.clone(),
+            ))
+        })
+        .collect();
+    files.sort();
+
+    let mut total: u64 = files.iter().map(|(_, size, _)| size).sum();
+    let mut deleted = 0;
+    for (_, size, name) in files {
+        if total <= max_bytes {
+            break;
+        }
+        let path = quarantine_path.join(&name);
+        if removal_behavior == RemovalBehavior::DryRun {
+            log::info!("Dry run: Would delete {:?}", path);
+        } else if let Err(err) = fs::remove_file(&path) {
+            log::error!("Unable to delete '{:?}': {}", path, err);
+            continue;
+        } else {
+            let _ = fs::remove_file(quarantine_path.join(format!("{}.json", name)));
+            log::info!("Deleted {:?}", path);
+        }
+        total -= size;
+        deleted += 1;
+    }
+    Ok(deleted)
+}
@@ -0,0 +1,226 @@
+use std::{
+    fs::{self, read_dir, remove_file},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use config::Config;
+use libvips::{ops, VipsImage};
+use serde::Serialize;
+
+use crate::{
+    constants::DEFAULT_RECOVERY_WINDOW_SECS,
+    quarantine::move_to_quarantine,
+    util::{
+        image::RemovalBehavior,
+        path::{
+            get_cache_path, get_data_path, get_data_paths, get_proxy_cache_path,
+            get_quarantine_path, get_running_marker_path, list_roots,
+        },
+    },
+    ServerState,
+};
+
+/// Settings of the recovery after an unclean shutdown
+#[derive(Clone, Copy)]
+pub struct RecoveryConfig {
+    pub enabled: bool,
+    // Files modified within this window before the start are checked
+    pub window: Duration,
+}
+
+/// Parses the recovery settings from the config properties `RECOVERY_ENABLED` and
+/// `RECOVERY_WINDOW_SECS`
+pub fn parse_recovery_config(config: &Config) -> RecoveryConfig {
+    RecoveryConfig {
+        enabled: config.get_bool("RECOVERY_ENABLED").unwrap_or(true),
+        window: Duration::from_secs(
+            config
+                .get::<u64>("RECOVERY_WINDOW_SECS")
+                .unwrap_or(DEFAULT_RECOVERY_WINDOW_SECS),
+        ),
+    }
+}
+
+#[derive(Default, Serialize)]
+pub struct RecoveryReport {
+    // Whether the previous run was not shut down gracefully, only then files are checked
+    pub unclean_shutdown: bool,
+    // Temporary files of writes that were interrupted
+    pub removed_temp_files: Vec<PathBuf>,
+    // Number of recently modified files that were decoded
+    pub checked: usize,
+    // Cache entries that could not be decoded, which are simply rendered again
+    pub removed_cache_entries: Vec<PathBuf>,
+    // Images and raw files that could not be decoded, moved to the quarantine directory
+    pub quarantined: Vec<PathBuf>,
+}
+
+/// Cleans up after an unclean shutdown, before the service starts serving:
+/// - temporary files of interrupted atomic writes are removed
+/// - images, raw files and cache entries modified within the recovery window are decoded, and
+///   broken ones moved to the quarantine directory (cache entries are removed instead)
+///
+/// Files are only checked, if the marker of the previous run still exists, i.e. it was not shut
+/// down gracefully. Images stranded in multiple states are left to the consistency check, as
+/// moves are atomic. Nothing is deleted or moved if `MAINTENANCE_DRY_RUN` is enabled.
+pub fn recover(recovery_config: RecoveryConfig, server_state: &ServerState) -> RecoveryReport {
+    let apply = server_state.maintenance_behavior == RemovalBehavior::Delete;
+    let mut report = RecoveryReport {
+        unclean_shutdown: get_running_marker_path().exists(),
+        ..RecoveryReport::default()
+    };
+
+    let since = SystemTime::now()
+        .checked_sub(recovery_config.window)
+        .unwrap_or(UNIX_EPOCH);
+    // The indices are written atomically to the parent of the data directories, whose other
+    // files are not checked
+    let roots = list_roots();
+    // Both are fetched or rendered again
+    let mut cache_dirs: Vec<PathBuf> = roots
+        .iter()
+        .map(|tenant| get_cache_path(tenant.as_deref()))
+        .collect();
+    cache_dirs.push(get_proxy_cache_path());
+    let mut dirs: Vec<(PathBuf, bool)> = get_data_paths(&roots)
+        .into_iter()
+        .map(|dir| (dir, true))
+        .collect();
+    dirs.push((get_data_path(), false));
+    for (dir, check) in dirs.iter() {
+        let entries = match read_dir(dir) {
+            Err(err) => {
+                log::error!("RECOVERY: Unable to read {:?}: {}", dir, err);
+                continue;
+            }
+            Ok(entries) => entries,
+        };
+
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let name = entry.file_name().to_string_lossy().to_string();
+            if !path.is_file() {
+                continue;
+            }
+
+            if name.starts_with('.') && name.ends_with(".tmp") {
+                if !apply || remove(&path) {
+                    report.removed_temp_files.push(path);
+                }
+                continue;
+            }
+            // Hidden files are not written by this service, see `list_files`
+            if !report.unclean_shutdown || !check || name.starts_with('.') {
+                continue;
+            }
+
+            let modified = entry.metadata().and_then(|metadata| metadata.modified());
+            if modified.is_ok_and(|modified| modified < since) {
+                continue;
+            }
+            report.checked += 1;
+            if decodes(&path) {
+                continue;
+            }
+
+            log::warn!("RECOVERY: {:?} could not be decoded", path);
+            if cache_dirs.contains(dir) {
+                if !apply || remove(&path) {
+                    report.removed_cache_entries.push(path);
+                }
+            } else if !apply || quarantine(&path, dir) {
+                report.quarantined.push(path);
+            }
+        }
+    }
+
+    log::info!(
+        "RECOVERY: Removed {} temporary file(s), checked {} file(s), removed {} broken cache \
+         entries and quarantined {} file(s)",
+        report.removed_temp_files.len(),
+        report.checked,
+        report.removed_cache_entries.len(),
+        report.quarantined.len()
+    );
+    if apply && !report.quarantined.is_empty() {
+        write_report(&report);
+        server_state.notifier.notify(
+            "Broken images quarantined",
+            &format!(
+                "After an unclean shutdown, {} file(s) could not be decoded and were moved to {:?}.",
+                report.quarantined.len(),
+                get_quarantine_path()
+            ),
+        );
+    }
+
+    report
+}
+
+/// Marks the service as running until `mark_shut_down`, so an unclean shutdown is detected by
+/// the next start
+pub fn mark_running() {
+    if let Err(err) = fs::write(get_running_marker_path(), []) {
+        log::error!("RECOVERY: Could not write the running marker: {}", err);
+    }
+}
+
+/// Removes the marker of `mark_running` after a graceful shutdown
+pub fn mark_shut_down() {
+    if let Err(err) = remove_file(get_running_marker_path()) {
+        log::error!("RECOVERY: Could not remove the running marker: {}", err);
+    }
+}
+
+/// Returns whether the image at `path` can be decoded completely
+fn decodes(path: &Path) -> bool {
+    // Loading is lazy, computing the average reads all pixels
+    path.to_str().is_some_and(|path| {
+        VipsImage::new_from_file(path)
+            .and_then(|image| ops::avg(&image))
+            .is_ok()
+    })
+}
+
+/// Moves `path` from `dir` to the quarantine directory, prefixed by the name of `dir`
+fn quarantine(path: &Path, dir: &Path) -> bool {
+    let prefix = dir.file_name().unwrap_or_default().to_string_lossy();
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    match move_to_quarantine(path, &format!("{}-{}", prefix, name)) {
+        Err(err) => {
+            log::error!("RECOVERY: Unable to quarantine {:?}: {}", path, err);
+            false
+        }
+        Ok(target) => {
+            log::warn!("RECOVERY: Quarantined {:?} as {:?}", path, target);
+            true
+        }
+    }
+}
+
+/// Writes `report` to the quarantine directory, so the quarantined files can be traced back
+fn write_report(report: &RecoveryReport) {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let path = get_quarantine_path().join(format!("recovery-{}.json", timestamp));
+    let res = serde_json::to_vec_pretty(report)
+        .map_err(|err| err.to_string())
+        .and_then(|json| fs::write(&path, json).map_err(|err| err.to_string()));
+    if let Err(err) = res {
+        log::error!("RECOVERY: Could not write report to {:?}: {}", path, err);
+    }
+}
+
+/// Removes `path` and returns whether that was successful
+fn remove(path: &Path) -> bool {
+    match remove_file(path) {
+        Err(err) => {
+            log::error!("RECOVERY: Unable to delete {:?}: {}", path, err);
+            false
+        }
+        Ok(_) => true,
+    }
+}
@@ -0,0 +1,317 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    sync::Arc,
+    time::Duration,
+};
+
+use config::Config;
+use reqwest::{header, Client, Url};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::{runtime::Handle, sync::mpsc, task, time::sleep};
+use uuid::Uuid;
+
+use crate::{
+    constants::{
+        DEFAULT_REPLICATION_MAX_RETRIES, MAX_LIST_LIMIT, MAX_VERIFY_IDS,
+        REPLICATION_QUEUE_CAPACITY, REPLICATION_RETRY_DELAY_SECS, REPLICATION_TIMEOUT_SECS,
+    },
+    handlers::export::write_archive,
+    util::{
+        image::{find_stored_image, list_images, ImageState, StoredImage},
+        metadata_index::MetadataIndex,
+    },
+};
+
+/// Mirrors writes to a peer instance (e.g. a warm standby), if `REPLICATION_PEER_URL` is set.
+/// Changed images are replicated by a background task, which retries failed replications.
+/// As replications can still fail, the peer is regularly reconciled with all images.
+#[derive(Clone)]
+pub struct Replicator {
+    peer: Option<Arc<Peer>>,
+    sender: Option<mpsc::Sender<Uuid>>,
+}
+
+/// Another image service, whose API is used to replicate images
+struct Peer {
+    // Base URL of the peer's API, e.g. `https://standby.example.com/v1`
+    url: String,
+    api_key: String,
+    client: Client,
+}
+
+#[derive(Deserialize)]
+struct PeerVerifyResult {
+    exists: bool,
+    state: Option<ImageState>,
+    sha256: Option<String>,
+}
+
+#[derive(Serialize)]
+struct PeerListQuery {
+    state: ImageState,
+    limit: usize,
+    offset: usize,
+}
+
+#[derive(Deserialize)]
+struct PeerPage {
+    items: Vec<PeerListEntry>,
+    total: usize,
+}
+
+#[derive(Deserialize)]
+struct PeerListEntry {
+    id: Uuid,
+}
+
+impl Replicator {
+    /// Starts replicating to the peer configured by `REPLICATION_PEER_URL`, which is
+    /// authenticated with the API key `REPLICATION_API_KEY`. Failed replications are retried
+    /// up to `REPLICATION_MAX_RETRIES` times.
+    pub fn start(config: &Config, metadata_index: MetadataIndex) -> Self {
+        let disabled = Self {
+            peer: None,
+            sender: None,
+        };
+        let Ok(url) = config.get_string("REPLICATION_PEER_URL") else {
+            log::info!("REPLICATION: Not replicating images (REPLICATION_PEER_URL not set)");
+            return disabled;
+        };
+        let (Ok(_), Ok(api_key)) = (Url::parse(&url), config.get_string("REPLICATION_API_KEY"))
+        else {
+            log::error!("REPLICATION: Not replicating images, as the peer is misconfigured");
+            return disabled;
+        };
+        let max_retries = config
+            .get::<u32>("REPLICATION_MAX_RETRIES")
+            .unwrap_or(DEFAULT_REPLICATION_MAX_RETRIES);
+
+        log::info!("REPLICATION: Replicating images to {}", url);
+        let peer = Arc::new(Peer {
+            url: url.trim_end_matches('/').to_owned(),
+            api_key: api_key,
+            client: Client::builder()
+                .timeout(Duration::from_secs(REPLICATION_TIMEOUT_SECS))
+                .build()
+                .expect("Could not build HTTP client"),
+        });
+        let (sender, receiver) = mpsc::channel(REPLICATION_QUEUE_CAPACITY);
+        tokio::spawn(run_replication(
+            peer.clone(),
+            metadata_index,
+            max_retries,
+            receiver,
+        ));
+
+        Self {
+            peer: Some(peer),
+            sender: Some(sender),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.peer.is_some()
+    }
+
+    /// Queues the image `uuid` for replication after it was written, e.g. changed its state.
+    /// The current state of the image is replicated, i.e. it is deleted on the peer if it
+    /// doesn't exist anymore.
+    pub fn replicate(&self, uuid: Uuid) {
+        let Some(sender) = &self.sender else {
+            return;
+        };
+        if sender.try_send(uuid).is_err() {
+            log::warn!(
+                "REPLICATION: Not replicating {} until the next reconciliation, as the queue is full",
+                uuid
+            );
+        }
+    }
+
+    /// Compares all images with the peer and queues those that are missing or differ on the
+    /// peer or only exist there. Returns the number of queued images.
+    /// Has to be called from a blocking task, like the ones of the scheduler.
+    pub fn reconcile(&self, metadata_index: &MetadataIndex) -> Result<usize, String> {
+        let (Some(peer), Some(sender)) = (&self.peer, &self.sender) else {
+            return Ok(0);
+        };
+        let runtime = Handle::current();
+
+        let images = list_images(&ImageState::ALL, metadata_index)
+            .map_err(|err| format!("Could not list images: {}", err))?;
+        let mut outdated = Vec::new();
+        for chunk in images.chunks(MAX_VERIFY_IDS) {
+            let ids: Vec<Uuid> = chunk.iter().map(|image| image.uuid).collect();
+            let results = runtime.block_on(peer.verify(&ids))?;
+            outdated.extend(
+                chunk
+                    .iter()
+                    .filter(|image| match results.get(&image.uuid) {
+                        None => true,
+                        Some(result) => {
+                            !result.exists
+                                || result.state != Some(image.state)
+                                || result.sha256 != local_sha256(image)
+                        }
+                    })
+                    .map(|image| image.uuid),
+            );
+        }
+
+        let local: HashSet<Uuid> = images.iter().map(|image| image.uuid).collect();
+        for state in ImageState::ALL {
+            let ids = runtime.block_on(peer.list(state))?;
+            outdated.extend(ids.into_iter().filter(|uuid| !local.contains(uuid)));
+        }
+
+        // Waits for space in the queue, unlike replications of single writes
+        for uuid in &outdated {
+            sender
+                .blocking_send(*uuid)
+                .map_err(|_| "Replication task has stopped".to_owned())?;
+        }
+        Ok(outdated.len())
+    }
+}
+
+/// Replicates all queued images to `peer`
+async fn run_replication(
+    peer: Arc<Peer>,
+    metadata_index: MetadataIndex,
+    max_retries: u32,
+    mut queue: mpsc::Receiver<Uuid>,
+) {
+    while let Some(uuid) = queue.recv().await {
+        let mut retries = 0;
+        loop {
+            match peer.replicate(uuid, &metadata_index).await {
+                Ok(()) => {
+                    log::debug!("REPLICATION: Replicated {}", uuid);
+                    break;
+                }
+                Err(err) if retries < max_retries => {
+                    let delay = REPLICATION_RETRY_DELAY_SECS.saturating_mul(1 << retries.min(16));
+                    log::warn!(
+                        "REPLICATION: Replicating {} failed, retrying in {}s: {}",
+                        uuid,
+                        delay,
+                        err
+                    );
+                    sleep(Duration::from_secs(delay)).await;
+                    retries += 1;
+                }
+                Err(err) => {
+                    log::error!(
+                        "REPLICATION: Replicating {} failed {} times, leaving it to the reconciliation: {}",
+                        uuid,
+                        retries + 1,
+                        err
+                    );
+                    break;
+                }
+            }
+        }
+    }
+}
+
+impl Peer {
+    /// Replicates the current state of the image `uuid`: If it exists, it is restored on the
+    /// peer (together with its raw file and times) from an archive, otherwise it is deleted.
+    async fn replicate(&self, uuid: Uuid, metadata_index: &MetadataIndex) -> Result<(), String> {
+        let index = metadata_index.clone();
+        let archive = task::spawn_blocking(move || archive_image(uuid, &index))
+            .await
+            .map_err(|err| err.to_string())??;
+
+        let request = match archive {
+            None => self.client.delete(format!("{}/image/{}", self.url, uuid)),
+            Some(archive) => self
+                .client
+                .post(format!("{}/restore?conflict=overwrite", self.url))
+                .header(header::CONTENT_TYPE, "application/x-tar")
+                .body(archive),
+        };
+        request
+            .bearer_auth(&self.api_key)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|err| err.to_string())?;
+        Ok(())
+    }
+
+    /// Returns the results of `/verify` on the peer for `ids`
+    async fn verify(&self, ids: &[Uuid]) -> Result<HashMap<Uuid, PeerVerifyResult>, String> {
+        self.client
+            .post(format!("{}/verify", self.url))
+            .bearer_auth(&self.api_key)
+            .json(&serde_json::json!({ "ids": ids }))
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|err| format!("Could not verify images on peer: {}", err))?
+            .json()
+            .await
+            .map_err(|err| format!("Invalid response of peer to /verify: {}", err))
+    }
+
+    /// Returns the IDs of all images in `state` on the peer
+    async fn list(&self, state: ImageState) -> Result<Vec<Uuid>, String> {
+        let mut ids = Vec::new();
+        loop {
+            let page: PeerPage = self
+                .client
+                .get(format!("{}/images", self.url))
+                .bearer_auth(&self.api_key)
+                .query(&PeerListQuery {
+                    state: state,
+                    limit: MAX_LIST_LIMIT,
+                    offset: ids.len(),
+                })
+                .send()
+                .await
+                .and_then(|response| response.error_for_status())
+                .map_err(|err| format!("Could not list images on peer: {}", err))?
+                .json()
+                .await
+                .map_err(|err| format!("Invalid response of peer to /images: {}", err))?;
+
+            let done = page.items.is_empty() || ids.len() + page.items.len() >= page.total;
+            ids.extend(page.items.into_iter().map(|item| item.id));
+            if done {
+                return Ok(ids);
+            }
+        }
+    }
+}
+
+/// Returns an archive (like `/export` does) of the image `uuid` with its raw file,
+/// or none if the image doesn't exist
+fn archive_image(uuid: Uuid, metadata_index: &MetadataIndex) -> Result<Option<Vec<u8>>, String> {
+    let Some(image) = find_stored_image(uuid, metadata_index) else {
+        return Ok(None);
+    };
+
+    let aliases = metadata_index
+        .alias(uuid)
+        .map(|alias| (uuid, alias))
+        .into_iter()
+        .collect();
+    let mut archive = Vec::new();
+    write_archive(&mut archive, &[image], &aliases, true, true)
+        .map_err(|err| format!("Could not archive {}: {}", uuid, err))?;
+    Ok(Some(archive))
+}
+
+/// Returns the hex encoded SHA-256 hash of the stored `image`, like `/verify` does
+fn local_sha256(image: &StoredImage) -> Option<String> {
+    let path = image
+        .state
+        .path(image.tenant.as_deref())
+        .join(format!("{}.avif", image.uuid));
+    fs::read(path)
+        .ok()
+        .map(|data| format!("{:x}", Sha256::digest(data)))
+}
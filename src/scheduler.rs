@@ -0,0 +1,369 @@
+use std::{
+    collections::{hash_map::RandomState, HashMap, VecDeque},
+    fmt,
+    hash::{BuildHasher, Hasher},
+    str::FromStr,
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, Instant, SystemTime},
+};
+
+use chrono::Utc;
+use config::Config;
+use cron::Schedule;
+use serde::Serialize;
+use tokio::{
+    task::{self, JoinHandle},
+    time::MissedTickBehavior,
+};
+use tokio_util::sync::CancellationToken;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::constants::MAX_JOB_RUNS;
+
+/// The work of a job. Returns the number of processed items or a description of the error.
+/// Jobs are run on the blocking thread pool, as they usually do filesystem work.
+pub type JobFn = Arc<dyn Fn() -> Result<usize, String> + Send + Sync>;
+
+/// When a job is run
+pub enum JobSchedule {
+    Interval {
+        // Time between the starts of two runs. If a run takes longer, the next one is delayed.
+        interval: Duration,
+        // Maximum random delay before each run but the first, so jobs don't always run at
+        // the same time
+        jitter: Duration,
+    },
+    // Run at the times matching a cron expression (in UTC)
+    Cron(Box<Schedule>),
+}
+
+impl fmt::Display for JobSchedule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JobSchedule::Interval { interval, .. } => write!(f, "every {:?}", interval),
+            JobSchedule::Cron(schedule) => write!(f, "at '{}' (UTC)", schedule.source()),
+        }
+    }
+}
+
+/// Parses a cron expression. Besides the six or seven fields (starting with seconds)
+/// supported by the `cron` crate, the five fields of a standard crontab are accepted.
+pub fn parse_cron(expression: &str) -> Result<Schedule, String> {
+    let expression = match expression.split_whitespace().count() {
+        5 => format!("0 {}", expression.trim()),
+        _ => expression.trim().to_owned(),
+    };
+    Schedule::from_str(&expression).map_err(|err| err.to_string())
+}
+
+/// Parses the schedule of a job from the optional cron expression in the config property `key`.
+/// If it is not set (or invalid), the job is run every `interval` with some jitter.
+pub fn parse_job_schedule(config: &Config, key: &str, interval: Duration) -> JobSchedule {
+    let fallback = JobSchedule::Interval {
+        interval: interval,
+        jitter: interval / 10,
+    };
+    let Ok(expression) = config.get_string(key) else {
+        return fallback;
+    };
+
+    match parse_cron(&expression) {
+        Err(err) => {
+            log::warn!(
+                "{} is not a valid cron expression, running {} instead: {}",
+                key,
+                fallback,
+                err
+            );
+            fallback
+        }
+        Ok(schedule) => JobSchedule::Cron(Box::new(schedule)),
+    }
+}
+
+/// A job that is run regularly by the `Scheduler`
+pub struct Job {
+    pub name: &'static str,
+    pub schedule: JobSchedule,
+    pub run: JobFn,
+}
+
+/// Status of the last run of a job
+#[derive(Clone, Default)]
+pub struct JobStatus {
+    // Human-readable description of the job's schedule
+    pub schedule: String,
+    pub running: bool,
+    pub runs: u64,
+    pub last_run: Option<SystemTime>,
+    pub last_duration: Option<Duration>,
+    pub last_items_processed: usize,
+    pub last_error: Option<String>,
+}
+
+/// State of a single run of a job
+#[derive(Clone, Copy, PartialEq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum JobRunState {
+    Running,
+    Succeeded,
+    Failed,
+}
+
+/// A single run of a job, either scheduled or triggered manually
+#[derive(Clone)]
+pub struct JobRun {
+    pub id: Uuid,
+    pub job: &'static str,
+    pub state: JobRunState,
+    pub started_at: SystemTime,
+    pub duration: Option<Duration>,
+    pub items_processed: usize,
+    pub error: Option<String>,
+}
+
+/// Why a job could not be triggered manually
+pub enum TriggerError {
+    UnknownJob,
+    AlreadyRunning,
+}
+
+/// Runs jobs in their own tokio task and keeps track of their status
+#[derive(Clone, Default)]
+pub struct Scheduler {
+    // Cancelled on shutdown, which stops all jobs once their current run is finished
+    shutdown: CancellationToken,
+    // Tasks of all spawned jobs and manually triggered runs
+    tasks: Arc<Mutex<Vec<JoinHandle<()>>>>,
+    jobs: Arc<RwLock<HashMap<&'static str, JobFn>>>,
+    statuses: Arc<RwLock<HashMap<&'static str, JobStatus>>>,
+    // The most recent runs of all jobs, oldest first
+    runs: Arc<RwLock<VecDeque<JobRun>>>,
+}
+
+impl Scheduler {
+    /// Spawns a tokio task that runs `job` according to its schedule.
+    /// Jobs with an interval are run right away, cron jobs at their first matching time.
+    /// A panicking run is recorded as error and does not stop the job.
+    /// If a run is due while the previous one (e.g. a manually triggered one) is still running,
+    /// it is skipped.
+    pub fn spawn(&self, job: Job) {
+        self.jobs.write().unwrap().insert(job.name, job.run.clone());
+        self.statuses.write().unwrap().insert(
+            job.name,
+            JobStatus {
+                schedule: job.schedule.to_string(),
+                ..Default::default()
+            },
+        );
+
+        let scheduler = self.clone();
+        self.track(tokio::spawn(async move {
+            let shutdown = scheduler.shutdown.clone();
+            match &job.schedule {
+                JobSchedule::Interval { interval, jitter } => {
+                    let mut ticker = tokio::time::interval(*interval);
+                    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+                    let mut first = true;
+                    loop {
+                        let delay = if first {
+                            Duration::ZERO
+                        } else {
+                            random_jitter(*jitter)
+                        };
+                        first = false;
+                        tokio::select! {
+                            _ = shutdown.cancelled() => break,
+                            _ = async {
+                                ticker.tick().await;
+                                tokio::time::sleep(delay).await;
+                            } => scheduler.run_scheduled(&job).await,
+                        }
+                    }
+                }
+                JobSchedule::Cron(schedule) => loop {
+                    let Some(next) = schedule.upcoming(Utc).next() else {
+                        log::warn!("JOBS: '{}' has no upcoming runs, stopping", job.name);
+                        break;
+                    };
+                    let delay = (next - Utc::now()).to_std().unwrap_or(Duration::ZERO);
+                    tokio::select! {
+                        _ = shutdown.cancelled() => break,
+                        _ = tokio::time::sleep(delay) => scheduler.run_scheduled(&job).await,
+                    }
+                },
+            }
+            log::info!("JOBS: Stopped '{}'", job.name);
+        }));
+    }
+
+    /// Stops all jobs and waits until their current runs are finished
+    pub async fn shutdown(&self) {
+        self.shutdown.cancel();
+        let tasks = std::mem::take(&mut *self.tasks.lock().unwrap());
+        for task in tasks {
+            if let Err(err) = task.await {
+                log::error!("JOBS: Task failed during shutdown: {}", err);
+            }
+        }
+    }
+
+    /// Runs the job called `name` right away in a new tokio task.
+    /// Returns the id of the run, which can be looked up with `run`.
+    pub fn trigger(&self, name: &str) -> Result<Uuid, TriggerError> {
+        let Some((name, run)) = self
+            .jobs
+            .read()
+            .unwrap()
+            .get_key_value(name)
+            .map(|(name, run)| (*name, run.clone()))
+        else {
+            return Err(TriggerError::UnknownJob);
+        };
+
+        let Some(id) = self.begin(name) else {
+            return Err(TriggerError::AlreadyRunning);
+        };
+        log::info!("JOBS: '{}' triggered manually", name);
+
+        let scheduler = self.clone();
+        self.track(tokio::spawn(async move {
+            scheduler.execute(name, id, run).await
+        }));
+        Ok(id)
+    }
+
+    /// Returns the status of all jobs, sorted by name
+    pub fn statuses(&self) -> Vec<(&'static str, JobStatus)> {
+        let mut statuses: Vec<(&'static str, JobStatus)> = self
+            .statuses
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(name, status)| (*name, status.clone()))
+            .collect();
+        statuses.sort_by_key(|(name, _)| *name);
+        statuses
+    }
+
+    /// Returns the run with `id`, if it is one of the most recent runs
+    pub fn run(&self, id: Uuid) -> Option<JobRun> {
+        self.runs
+            .read()
+            .unwrap()
+            .iter()
+            .find(|run| run.id == id)
+            .cloned()
+    }
+
+    fn track(&self, task: JoinHandle<()>) {
+        let mut tasks = self.tasks.lock().unwrap();
+        tasks.retain(|task| !task.is_finished());
+        tasks.push(task);
+    }
+
+    async fn run_scheduled(&self, job: &Job) {
+        match self.begin(job.name) {
+            None => log::warn!("JOBS: '{}' is still running, skipping this run", job.name),
+            Some(id) => self.execute(job.name, id, job.run.clone()).await,
+        }
+    }
+
+    /// Marks the job called `name` as running and records a new run.
+    /// Returns the id of the run, or `None` if the job is already running.
+    fn begin(&self, name: &'static str) -> Option<Uuid> {
+        let mut statuses = self.statuses.write().unwrap();
+        let status = statuses.entry(name).or_default();
+        if status.running {
+            return None;
+        }
+        status.running = true;
+
+        let id = Uuid::new_v4();
+        let mut runs = self.runs.write().unwrap();
+        if runs.len() >= MAX_JOB_RUNS {
+            runs.pop_front();
+        }
+        runs.push_back(JobRun {
+            id: id,
+            job: name,
+            state: JobRunState::Running,
+            started_at: SystemTime::now(),
+            duration: None,
+            items_processed: 0,
+            error: None,
+        });
+        Some(id)
+    }
+
+    async fn execute(&self, name: &'static str, id: Uuid, run: JobFn) {
+        log::info!("JOBS: Starting '{}'", name);
+        let started_at = SystemTime::now();
+        let start = Instant::now();
+
+        let res = match task::spawn_blocking(move || run()).await {
+            Err(err) => Err(format!("Job panicked: {}", err)),
+            Ok(res) => res,
+        };
+
+        let duration = start.elapsed();
+        match &res {
+            Err(err) => log::error!("JOBS: '{}' failed after {:?}: {}", name, duration, err),
+            Ok(items) => log::info!(
+                "JOBS: '{}' finished after {:?}, processed {} item(s)",
+                name,
+                duration,
+                items
+            ),
+        }
+
+        if let Some(job_run) = self
+            .runs
+            .write()
+            .unwrap()
+            .iter_mut()
+            .find(|run| run.id == id)
+        {
+            job_run.duration = Some(duration);
+            match &res {
+                Err(err) => {
+                    job_run.state = JobRunState::Failed;
+                    job_run.error = Some(err.clone());
+                }
+                Ok(items) => {
+                    job_run.state = JobRunState::Succeeded;
+                    job_run.items_processed = *items;
+                }
+            }
+        }
+
+        let mut statuses = self.statuses.write().unwrap();
+        let status = statuses.entry(name).or_default();
+        status.running = false;
+        status.runs += 1;
+        status.last_run = Some(started_at);
+        status.last_duration = Some(duration);
+        match res {
+            Err(err) => {
+                status.last_items_processed = 0;
+                status.last_error = Some(err);
+            }
+            Ok(items) => {
+                status.last_items_processed = items;
+                status.last_error = None;
+            }
+        }
+    }
+}
+
+/// Returns a random duration between zero and `max`
+fn random_jitter(max: Duration) -> Duration {
+    if max.is_zero() {
+        return Duration::ZERO;
+    }
+
+    // RandomState is randomly seeded, which is good enough for jitter
+    let random = RandomState::new().build_hasher().finish();
+    Duration::from_millis(random % (max.as_millis() as u64 + 1))
+}
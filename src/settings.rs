@@ -0,0 +1,816 @@
+use std::{
+    collections::HashMap,
+    net::{SocketAddr, ToSocketAddrs},
+    path::Path,
+    str::FromStr,
+};
+
+use argon2::{password_hash::PasswordHashString, ARGON2ID_IDENT};
+use axum::http::{HeaderName, HeaderValue, Method};
+use config::{Config, ConfigError};
+use reqwest::Url;
+
+use crate::{
+    cleaner::CLEANERS,
+    constants::{DEFAULT_PROXY_CACHE_MAX_AGE_SECS, DEFAULT_PROXY_CACHE_TTL_SECS},
+    notifier::validate_email_channel,
+    scheduler::parse_cron,
+    util::{
+        auth::parse_hashes,
+        avif::parse_avif_profiles,
+        client_ip::parse_network,
+        cors::{parse_cors_policies, parse_origins, parse_policy_origins, RouteGroup, WILDCARD},
+        image::{ColorProfile, OutputFormat},
+        path::{get_data_paths, prepare_data_dir},
+        recipe::{parse_recipes, Recipe},
+        tenant::{parse_tenants, Tenant},
+        webp::parse_webp_tuning,
+    },
+};
+
+/// Loads the config from `config_path`.
+/// Options set in environment variables override the properties from the config file.
+pub fn load_config(config_path: &str) -> Result<Config, ConfigError> {
+    // Use ';' as separator, as argon hashes contain commas
+    let env_source = config::Environment::default()
+        .list_separator(";")
+        .with_list_parse_key("API_KEY_HASHES")
+        .with_list_parse_key("CORS_ALLOWED_ORIGINS")
+        .with_list_parse_key("CORS_ALLOWED_METHODS")
+        .with_list_parse_key("CORS_ALLOWED_HEADERS")
+        .with_list_parse_key("CORS_EXPOSED_HEADERS")
+        .with_list_parse_key("LISTEN_ADDRS")
+        .with_list_parse_key("TRUSTED_PROXIES")
+        .with_list_parse_key("CALLBACK_ALLOWED_URLS")
+        .with_list_parse_key("IMPORT_ALLOWED_HOSTS")
+        .with_list_parse_key("PROXY_ALLOWED_HOSTS")
+        .with_list_parse_key("NOTIFY_EMAIL_TO")
+        .with_list_parse_key("MODERATION_COMMAND")
+        .try_parsing(true);
+
+    Config::builder()
+        .add_source(config::File::with_name(config_path).required(false))
+        .add_source(env_source)
+        .build()
+}
+
+/// The parts of the configuration that can be swapped at runtime via `/reload`
+pub struct ReloadableConfig {
+    pub api_key_hashes: Vec<PasswordHashString>,
+    pub cors_origins: Vec<HeaderValue>,
+    // Of the route groups with their own origins in `CORS_POLICIES`
+    pub cors_policy_origins: HashMap<RouteGroup, Vec<HeaderValue>>,
+    // Named transformations requested via `?recipe=`
+    pub recipes: HashMap<String, Recipe>,
+    // Organizations managing their images under `/t/<name>`
+    pub tenants: HashMap<String, Tenant>,
+}
+
+impl ReloadableConfig {
+    /// Parses all reloadable options from `config`.
+    /// Returns a message describing the problem, if any option is invalid.
+    pub fn from_config(config: &Config) -> Result<Self, String> {
+        Ok(Self {
+            api_key_hashes: parse_hashes(config)?,
+            cors_origins: parse_origins(config)?,
+            cors_policy_origins: parse_policy_origins(config)?,
+            recipes: parse_recipes(config)?,
+            tenants: parse_tenants(config)?,
+        })
+    }
+}
+
+/// Checks all config values and data directories. Missing data directories are created.
+/// Returns a list of human-readable problems, which is empty if everything is fine.
+pub fn validate_config(config: &Config) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    validate_hashes(config, &mut problems);
+    validate_origins(config, &mut problems);
+    validate_methods(config, &mut problems);
+    validate_header_names(config, "CORS_ALLOWED_HEADERS", true, &mut problems);
+    validate_header_names(config, "CORS_EXPOSED_HEADERS", false, &mut problems);
+    validate_positive(config, "CORS_MAX_AGE_SECS", &mut problems);
+    validate_cors_credentials(config, &mut problems);
+    validate_cors_policies(config, &mut problems);
+    validate_listen_addrs(config, &mut problems);
+    validate_trusted_proxies(config, &mut problems);
+    validate_grpc_listen_addr(config, &mut problems);
+    validate_url(config, "PUBLIC_URL", &mut problems);
+    validate_recipes(config, &mut problems);
+    validate_tenants(config, &mut problems);
+    validate_placeholder(config, &mut problems);
+    validate_shadow_read_path(config, &mut problems);
+    validate_positive(config, "MAX_OUTPUT_WIDTH", &mut problems);
+    validate_positive(config, "MAX_OUTPUT_HEIGHT", &mut problems);
+    validate_positive(config, "MAX_OUTPUT_PIXELS", &mut problems);
+    validate_output_limit_behavior(config, &mut problems);
+    validate_positive(config, "MAX_INPUT_WIDTH", &mut problems);
+    validate_positive(config, "MAX_INPUT_HEIGHT", &mut problems);
+    validate_positive(config, "MAX_INPUT_BIT_DEPTH", &mut problems);
+    validate_positive(config, "MAX_INPUT_FRAMES", &mut problems);
+    validate_positive(config, "MAX_INPUT_PIXELS", &mut problems);
+    validate_positive(config, "VIPS_TIMEOUT_SECS", &mut problems);
+    validate_positive(config, "VIPS_CONCURRENCY", &mut problems);
+    validate_positive(config, "CACHE_TTL_SECS", &mut problems);
+    validate_bool(config, "PROGRESSIVE_ENCODING", &mut problems);
+    validate_color_profile(config, &mut problems);
+    validate_output_format(config, &mut problems);
+    validate_avif(config, &mut problems);
+    validate_webp(config, &mut problems);
+    validate_bool(config, "CLIENT_HINTS_ENABLED", &mut problems);
+    validate_positive(config, "SAVE_DATA_QUALITY", &mut problems);
+    validate_bool(config, "LQIP_BLUR", &mut problems);
+    validate_durability(config, &mut problems);
+    validate_positive(config, "CLAMD_TIMEOUT_SECS", &mut problems);
+    validate_positive(config, "MIN_FREE_DISK_BYTES", &mut problems);
+    validate_positive(config, "DISK_SPACE_CHECK_INTERVAL_SECS", &mut problems);
+    validate_schedule(config, "DISK_SPACE_CHECK_SCHEDULE", &mut problems);
+    validate_positive(config, "PREVIEW_TOKEN_TTL_SECS", &mut problems);
+    validate_positive(config, "IDEMPOTENCY_KEY_TTL_SECS", &mut problems);
+    validate_bool(config, "UUID_V7_ENABLED", &mut problems);
+    validate_bool(config, "CAS_ENABLED", &mut problems);
+    validate_bool(config, "TRASH_ENABLED", &mut problems);
+    validate_url(config, "UPLOAD_WEBHOOK_URL", &mut problems);
+    validate_callback_urls(config, &mut problems);
+    validate_events_nats_addr(config, &mut problems);
+    validate_events_subject(config, &mut problems);
+    validate_allowed_hosts(config, "IMPORT_ALLOWED_HOSTS", &mut problems);
+    validate_allowed_hosts(config, "PROXY_ALLOWED_HOSTS", &mut problems);
+    validate_replication(config, &mut problems);
+    // 0 disables retries
+    validate_non_negative(config, "REPLICATION_MAX_RETRIES", &mut problems);
+    validate_positive(config, "REPLICATION_RECONCILE_INTERVAL_SECS", &mut problems);
+    validate_schedule(config, "REPLICATION_RECONCILE_SCHEDULE", &mut problems);
+    validate_pair(
+        config,
+        "CLOUDFLARE_ZONE_ID",
+        "CLOUDFLARE_API_TOKEN",
+        &mut problems,
+    );
+    validate_pair(
+        config,
+        "FASTLY_SERVICE_ID",
+        "FASTLY_API_TOKEN",
+        &mut problems,
+    );
+    validate_notifications(config, &mut problems);
+    validate_moderation(config, &mut problems);
+    validate_positive(config, "IMPORT_TIMEOUT_SECS", &mut problems);
+    validate_positive(config, "PROXY_TIMEOUT_SECS", &mut problems);
+    validate_positive(config, "PROXY_MAX_SIZE", &mut problems);
+    validate_positive(config, "PROXY_CACHE_TTL_SECS", &mut problems);
+    validate_proxy_cache_max_age(config, &mut problems);
+    validate_positive(config, "QUARANTINE_MAX_BYTES", &mut problems);
+    validate_bool(config, "MAINTENANCE_DRY_RUN", &mut problems);
+    validate_bool(config, "CONSISTENCY_CHECK_ENABLED", &mut problems);
+    validate_positive(config, "CONSISTENCY_CHECK_INTERVAL_SECS", &mut problems);
+    validate_schedule(config, "CONSISTENCY_CHECK_SCHEDULE", &mut problems);
+    validate_bool(config, "CONSISTENCY_CHECK_REPAIR", &mut problems);
+    validate_bool(config, "RECOVERY_ENABLED", &mut problems);
+    validate_positive(config, "RECOVERY_WINDOW_SECS", &mut problems);
+    for cleaner in CLEANERS.iter() {
+        validate_bool(config, cleaner.enabled_key, &mut problems);
+        validate_positive(config, cleaner.interval_key, &mut problems);
+        validate_schedule(config, cleaner.schedule_key, &mut problems);
+        if cleaner.allow_zero_max_age {
+            validate_non_negative(config, cleaner.max_age_key, &mut problems);
+        } else {
+            validate_positive(config, cleaner.max_age_key, &mut problems);
+        }
+        if let Some(approved_max_age_key) = cleaner.approved_max_age_key {
+            validate_non_negative(config, approved_max_age_key, &mut problems);
+        }
+    }
+    validate_data_dirs(config, &mut problems);
+
+    problems
+}
+
+/// Formats the problems returned by `validate_config` as a consolidated report
+pub fn format_report(problems: &[String]) -> String {
+    let mut report = format!(
+        "Found {} problem(s) with the configuration:",
+        problems.len()
+    );
+    for problem in problems {
+        report.push_str(&format!("\n  - {}", problem));
+    }
+    report
+}
+
+fn validate_hashes(config: &Config, problems: &mut Vec<String>) {
+    let values = match config.get::<Vec<String>>("API_KEY_HASHES") {
+        Err(err) => {
+            problems.push(format!(
+                "API_KEY_HASHES: Must be set to a list of hashes ({})",
+                err
+            ));
+            return;
+        }
+        Ok(values) => values,
+    };
+
+    if values.is_empty() {
+        problems.push("API_KEY_HASHES: Must contain at least one hash".to_owned());
+    }
+
+    for (i, value) in values.iter().enumerate() {
+        match PasswordHashString::new(value) {
+            Err(err) => problems.push(format!(
+                "API_KEY_HASHES[{}]: '{}' is not a hash in encoded form ({})",
+                i, value, err
+            )),
+            Ok(hash) if hash.algorithm() != ARGON2ID_IDENT => problems.push(format!(
+                "API_KEY_HASHES[{}]: Expected an {} hash, but got {}",
+                i,
+                ARGON2ID_IDENT,
+                hash.algorithm()
+            )),
+            Ok(_) => (),
+        }
+    }
+}
+
+fn validate_origins(config: &Config, problems: &mut Vec<String>) {
+    let values = match config.get::<Vec<String>>("CORS_ALLOWED_ORIGINS") {
+        Err(err) => {
+            problems.push(format!(
+                "CORS_ALLOWED_ORIGINS: Must be set to a list of origins ({})",
+                err
+            ));
+            return;
+        }
+        Ok(values) => values,
+    };
+
+    validate_origin_values("CORS_ALLOWED_ORIGINS", &values, problems);
+}
+
+fn validate_origin_values(key: &str, values: &[String], problems: &mut Vec<String>) {
+    for (i, value) in values.iter().enumerate() {
+        if value == WILDCARD {
+            continue;
+        }
+        if HeaderValue::from_str(value).is_err() {
+            problems.push(format!(
+                "{}[{}]: '{}' contains invalid characters",
+                key, i, value
+            ));
+        } else if !value.starts_with("http://") && !value.starts_with("https://") {
+            problems.push(format!(
+                "{}[{}]: '{}' must start with 'http://' or 'https://'",
+                key, i, value
+            ));
+        } else if value.ends_with('/') {
+            problems.push(format!("{}[{}]: '{}' must not end with '/'", key, i, value));
+        }
+    }
+}
+
+fn validate_methods(config: &Config, problems: &mut Vec<String>) {
+    // Optional, a missing value is handled by `parse_methods`
+    let Ok(values) = config.get::<Vec<String>>("CORS_ALLOWED_METHODS") else {
+        return;
+    };
+
+    validate_method_values("CORS_ALLOWED_METHODS", &values, problems);
+}
+
+fn validate_method_values(key: &str, values: &[String], problems: &mut Vec<String>) {
+    for (i, value) in values.iter().enumerate() {
+        if Method::from_str(value).is_err() {
+            problems.push(format!(
+                "{}[{}]: '{}' is not a valid HTTP method",
+                key, i, value
+            ));
+        }
+    }
+}
+
+/// Checks that the optional list `key` contains valid header names (or the wildcard, if
+/// `wildcard_allowed`)
+fn validate_header_names(
+    config: &Config,
+    key: &str,
+    wildcard_allowed: bool,
+    problems: &mut Vec<String>,
+) {
+    let Ok(values) = config.get::<Vec<String>>(key) else {
+        return;
+    };
+
+    validate_header_name_values(key, &values, wildcard_allowed, problems);
+}
+
+fn validate_header_name_values(
+    key: &str,
+    values: &[String],
+    wildcard_allowed: bool,
+    problems: &mut Vec<String>,
+) {
+    for (i, value) in values.iter().enumerate() {
+        if wildcard_allowed && value == WILDCARD {
+            continue;
+        }
+        if HeaderName::from_str(value).is_err() {
+            problems.push(format!(
+                "{}[{}]: '{}' is not a valid header name",
+                key, i, value
+            ));
+        }
+    }
+}
+
+fn validate_cors_credentials(config: &Config, problems: &mut Vec<String>) {
+    validate_bool(config, "CORS_ALLOW_CREDENTIALS", problems);
+    if !config.get_bool("CORS_ALLOW_CREDENTIALS").unwrap_or(false) {
+        return;
+    }
+
+    // Any website could make requests with the cookies of the user otherwise
+    let origins = config
+        .get::<Vec<String>>("CORS_ALLOWED_ORIGINS")
+        .unwrap_or_default();
+    if origins.iter().any(|origin| origin == WILDCARD) {
+        problems.push(format!(
+            "CORS_ALLOW_CREDENTIALS: Cannot be enabled while CORS_ALLOWED_ORIGINS contains '{}'",
+            WILDCARD
+        ));
+    }
+}
+
+fn validate_cors_policies(config: &Config, problems: &mut Vec<String>) {
+    let policies = match parse_cors_policies(config) {
+        Err(err) => {
+            problems.push(err);
+            return;
+        }
+        Ok(policies) => policies,
+    };
+
+    let global_origins = config
+        .get::<Vec<String>>("CORS_ALLOWED_ORIGINS")
+        .unwrap_or_default();
+    let global_credentials = config.get_bool("CORS_ALLOW_CREDENTIALS").unwrap_or(false);
+    for group in RouteGroup::ALL {
+        let Some(policy) = policies.get(&group) else {
+            continue;
+        };
+        let key = format!("CORS_POLICIES.{:?}", group).to_lowercase();
+
+        if let Some(origins) = &policy.allowed_origins {
+            validate_origin_values(&format!("{}.allowed_origins", key), origins, problems);
+        }
+        if let Some(methods) = &policy.allowed_methods {
+            validate_method_values(&format!("{}.allowed_methods", key), methods, problems);
+        }
+        if let Some(headers) = &policy.allowed_headers {
+            let key = format!("{}.allowed_headers", key);
+            validate_header_name_values(&key, headers, true, problems);
+        }
+        if let Some(headers) = &policy.exposed_headers {
+            let key = format!("{}.exposed_headers", key);
+            validate_header_name_values(&key, headers, false, problems);
+        }
+        if policy.max_age_secs == Some(0) {
+            problems.push(format!("{}.max_age_secs: Must be greater than 0", key));
+        }
+
+        // Like for the global options, but the policy may override either of them
+        let origins = policy.allowed_origins.as_ref().unwrap_or(&global_origins);
+        if policy.allow_credentials.unwrap_or(global_credentials)
+            && origins.iter().any(|origin| origin == WILDCARD)
+        {
+            problems.push(format!(
+                "{}: Credentials cannot be allowed for the '{}' origin",
+                key, WILDCARD
+            ));
+        }
+    }
+}
+
+fn validate_trusted_proxies(config: &Config, problems: &mut Vec<String>) {
+    // Optional, no proxies are trusted if it is not set
+    let Ok(values) = config.get::<Vec<String>>("TRUSTED_PROXIES") else {
+        return;
+    };
+
+    for (i, value) in values.iter().enumerate() {
+        if parse_network(value).is_none() {
+            problems.push(format!(
+                "TRUSTED_PROXIES[{}]: '{}' is not an IP address or network (e.g. '10.0.0.0/8')",
+                i, value
+            ));
+        }
+    }
+}
+
+fn validate_listen_addrs(config: &Config, problems: &mut Vec<String>) {
+    // Optional, a missing value is handled by `parse_listen_addrs`
+    let Ok(values) = config.get::<Vec<String>>("LISTEN_ADDRS") else {
+        return;
+    };
+
+    if values.is_empty() {
+        problems.push("LISTEN_ADDRS: Must contain at least one address".to_owned());
+    }
+
+    for (i, value) in values.iter().enumerate() {
+        if let Err(err) = value.to_socket_addrs() {
+            problems.push(format!(
+                "LISTEN_ADDRS[{}]: '{}' is not a valid 'host:port' address ({})",
+                i, value, err
+            ));
+        }
+    }
+}
+
+fn validate_grpc_listen_addr(config: &Config, problems: &mut Vec<String>) {
+    // Optional, the gRPC server is only started if it is set
+    let Ok(value) = config.get_string("GRPC_LISTEN_ADDR") else {
+        return;
+    };
+
+    if cfg!(not(feature = "grpc")) {
+        problems.push(
+            "GRPC_LISTEN_ADDR: gRPC support is not enabled, build with `--features grpc`"
+                .to_owned(),
+        );
+    } else if let Err(err) = SocketAddr::from_str(&value) {
+        problems.push(format!(
+            "GRPC_LISTEN_ADDR: '{}' is not a valid 'ip:port' address ({})",
+            value, err
+        ));
+    }
+}
+
+fn validate_callback_urls(config: &Config, problems: &mut Vec<String>) {
+    // Optional, callbacks are rejected if it is not set
+    let Ok(values) = config.get::<Vec<String>>("CALLBACK_ALLOWED_URLS") else {
+        return;
+    };
+
+    if config.get_string("WEBHOOK_SECRET").is_err() {
+        problems.push(
+            "CALLBACK_ALLOWED_URLS: WEBHOOK_SECRET must be set, as callbacks are signed".to_owned(),
+        );
+    }
+
+    for (i, value) in values.iter().enumerate() {
+        if !value.starts_with("http://") && !value.starts_with("https://") {
+            problems.push(format!(
+                "CALLBACK_ALLOWED_URLS[{}]: '{}' must start with 'http://' or 'https://'",
+                i, value
+            ));
+        } else if value.matches('/').count() < 3 {
+            // Otherwise e.g. 'https://example.com' would also allow 'https://example.com.evil.net'
+            problems.push(format!(
+                "CALLBACK_ALLOWED_URLS[{}]: '{}' must contain a path, e.g. end with '/'",
+                i, value
+            ));
+        }
+    }
+}
+
+fn validate_allowed_hosts(config: &Config, key: &str, problems: &mut Vec<String>) {
+    // Optional, imports (or proxied requests) are rejected if it is not set
+    let Ok(values) = config.get::<Vec<String>>(key) else {
+        return;
+    };
+
+    for (i, value) in values.iter().enumerate() {
+        if value.is_empty() || value.contains(['/', ':']) {
+            problems.push(format!(
+                "{}[{}]: '{}' must be a host name without scheme, port or path",
+                key, i, value
+            ));
+        }
+    }
+}
+
+fn validate_events_nats_addr(config: &Config, problems: &mut Vec<String>) {
+    // Optional, events are only published if it is set
+    let Ok(value) = config.get_string("EVENTS_NATS_ADDR") else {
+        return;
+    };
+
+    // Not resolved here, as the broker may not be reachable yet when the service starts
+    let valid = match value.rsplit_once(':') {
+        None => false,
+        Some((host, port)) => !host.is_empty() && port.parse::<u16>().is_ok(),
+    };
+    if !valid {
+        problems.push(format!(
+            "EVENTS_NATS_ADDR: '{}' is not a valid 'host:port' address",
+            value
+        ));
+    }
+}
+
+fn validate_events_subject(config: &Config, problems: &mut Vec<String>) {
+    let Ok(value) = config.get_string("EVENTS_SUBJECT") else {
+        return;
+    };
+
+    // Events are published on '<subject>.<event>', so the subject must consist of non-empty
+    // tokens without whitespace and wildcards
+    let valid = value.split('.').all(|token| {
+        !token.is_empty()
+            && token
+                .chars()
+                .all(|c| !c.is_whitespace() && c != '*' && c != '>')
+    });
+    if !valid {
+        problems.push(format!(
+            "EVENTS_SUBJECT: '{}' is not a valid NATS subject without wildcards",
+            value
+        ));
+    }
+}
+
+fn validate_recipes(config: &Config, problems: &mut Vec<String>) {
+    if let Err(err) = parse_recipes(config) {
+        problems.push(err);
+    }
+}
+
+fn validate_tenants(config: &Config, problems: &mut Vec<String>) {
+    if let Err(err) = parse_tenants(config) {
+        problems.push(err);
+    }
+}
+
+fn validate_placeholder(config: &Config, problems: &mut Vec<String>) {
+    match config.get::<u16>("PLACEHOLDER_STATUS") {
+        Err(ConfigError::NotFound(_)) | Ok(200 | 404) => (),
+        Err(err) => problems.push(format!("PLACEHOLDER_STATUS: Must be 404 or 200 ({})", err)),
+        Ok(status) => problems.push(format!(
+            "PLACEHOLDER_STATUS: Must be 404 or 200, not {}",
+            status
+        )),
+    }
+
+    if let Ok(path) = config.get_string("PLACEHOLDER_IMAGE_PATH") {
+        if !Path::new(&path).is_file() {
+            problems.push(format!("PLACEHOLDER_IMAGE_PATH: '{}' is not a file", path));
+        }
+    }
+}
+
+fn validate_shadow_read_path(config: &Config, problems: &mut Vec<String>) {
+    if let Ok(path) = config.get_string("SHADOW_READ_PATH") {
+        if !Path::new(&path).is_dir() {
+            problems.push(format!("SHADOW_READ_PATH: '{}' is not a directory", path));
+        }
+    }
+}
+
+fn validate_durability(config: &Config, problems: &mut Vec<String>) {
+    match config.get_string("DURABILITY").as_deref() {
+        Err(_) | Ok("none") | Ok("files") | Ok("full") => (),
+        Ok(value) => problems.push(format!(
+            "DURABILITY: Must be 'none', 'files' or 'full', not '{}'",
+            value
+        )),
+    }
+    // Deprecated alias of `DURABILITY: files`
+    validate_bool(config, "FSYNC_WRITES", problems);
+}
+
+fn validate_output_limit_behavior(config: &Config, problems: &mut Vec<String>) {
+    match config.get_string("OUTPUT_LIMIT_BEHAVIOR").as_deref() {
+        Err(_) | Ok("reject") | Ok("clamp") => (),
+        Ok(value) => problems.push(format!(
+            "OUTPUT_LIMIT_BEHAVIOR: Must be 'reject' or 'clamp', not '{}'",
+            value
+        )),
+    }
+}
+
+fn validate_color_profile(config: &Config, problems: &mut Vec<String>) {
+    match config.get::<ColorProfile>("COLOR_PROFILE") {
+        Err(ConfigError::NotFound(_)) | Ok(_) => (),
+        Err(err) => problems.push(format!(
+            "COLOR_PROFILE: Must be 'srgb', 'preserve' or 'display_p3' ({})",
+            err
+        )),
+    }
+}
+
+fn validate_output_format(config: &Config, problems: &mut Vec<String>) {
+    match config.get::<OutputFormat>("DEFAULT_OUTPUT_FORMAT") {
+        Err(ConfigError::NotFound(_)) | Ok(_) => (),
+        Err(err) => problems.push(format!(
+            "DEFAULT_OUTPUT_FORMAT: Must be 'webp', 'avif', 'jpeg' or 'png' ({})",
+            err
+        )),
+    }
+}
+
+fn validate_avif(config: &Config, problems: &mut Vec<String>) {
+    validate_bool(config, "LOSSLESS_ARCHIVAL", problems);
+    if let Err(err) = parse_avif_profiles(config) {
+        problems.push(err);
+    }
+}
+
+fn validate_webp(config: &Config, problems: &mut Vec<String>) {
+    validate_bool(config, "WEBP_SMART_SUBSAMPLE", problems);
+    validate_bool(config, "WEBP_NEAR_LOSSLESS", problems);
+    if let Err(err) = parse_webp_tuning(config) {
+        problems.push(err);
+    }
+}
+
+fn validate_replication(config: &Config, problems: &mut Vec<String>) {
+    // Optional, images are only replicated if it is set
+    if config.get_string("REPLICATION_PEER_URL").is_err() {
+        return;
+    }
+
+    validate_url(config, "REPLICATION_PEER_URL", problems);
+    if config.get_string("REPLICATION_API_KEY").is_err() {
+        problems.push(
+            "REPLICATION_PEER_URL: REPLICATION_API_KEY must be set to an API key of the peer"
+                .to_owned(),
+        );
+    }
+}
+
+fn validate_notifications(config: &Config, problems: &mut Vec<String>) {
+    validate_url(config, "NOTIFY_SLACK_WEBHOOK_URL", problems);
+
+    validate_url(config, "NOTIFY_MATRIX_HOMESERVER_URL", problems);
+    validate_pair(
+        config,
+        "NOTIFY_MATRIX_HOMESERVER_URL",
+        "NOTIFY_MATRIX_ROOM_ID",
+        problems,
+    );
+    validate_pair(
+        config,
+        "NOTIFY_MATRIX_HOMESERVER_URL",
+        "NOTIFY_MATRIX_ACCESS_TOKEN",
+        problems,
+    );
+
+    validate_pair(
+        config,
+        "NOTIFY_EMAIL_SMTP_URL",
+        "NOTIFY_EMAIL_FROM",
+        problems,
+    );
+    validate_pair(config, "NOTIFY_EMAIL_SMTP_URL", "NOTIFY_EMAIL_TO", problems);
+    if let (Ok(smtp_url), Ok(from), Ok(to)) = (
+        config.get_string("NOTIFY_EMAIL_SMTP_URL"),
+        config.get_string("NOTIFY_EMAIL_FROM"),
+        config.get::<Vec<String>>("NOTIFY_EMAIL_TO"),
+    ) {
+        if let Err(err) = validate_email_channel(&smtp_url, &from, &to) {
+            problems.push(format!("NOTIFY_EMAIL_SMTP_URL: {}", err));
+        }
+    }
+
+    validate_positive(config, "NOTIFY_UNAPPROVED_THRESHOLD", problems);
+    validate_positive(config, "NOTIFY_BACKLOG_INTERVAL_SECS", problems);
+    validate_schedule(config, "NOTIFY_BACKLOG_SCHEDULE", problems);
+}
+
+/// Checks that fetched images are not deleted while they are still served from the proxy cache
+fn validate_proxy_cache_max_age(config: &Config, problems: &mut Vec<String>) {
+    let ttl = config
+        .get::<u64>("PROXY_CACHE_TTL_SECS")
+        .unwrap_or(DEFAULT_PROXY_CACHE_TTL_SECS);
+    let max_age = config
+        .get::<u64>("PROXY_CACHE_MAX_AGE_SECS")
+        .unwrap_or(DEFAULT_PROXY_CACHE_MAX_AGE_SECS);
+    if max_age < ttl {
+        problems.push(format!(
+            "PROXY_CACHE_MAX_AGE_SECS: Must not be less than PROXY_CACHE_TTL_SECS ({})",
+            ttl
+        ));
+    }
+}
+
+fn validate_moderation(config: &Config, problems: &mut Vec<String>) {
+    validate_url(config, "MODERATION_URL", problems);
+    match config.get::<Vec<String>>("MODERATION_COMMAND") {
+        Err(ConfigError::NotFound(_)) => (),
+        Err(err) => problems.push(format!("MODERATION_COMMAND: Must be a list ({})", err)),
+        Ok(command) if command.is_empty() => {
+            problems.push("MODERATION_COMMAND: Must contain the program".to_owned())
+        }
+        Ok(_) if config.get_string("MODERATION_URL").is_ok() => problems
+            .push("MODERATION_COMMAND: Must not be set together with MODERATION_URL".to_owned()),
+        Ok(_) => (),
+    }
+    match config.get_float("MODERATION_THRESHOLD") {
+        Err(ConfigError::NotFound(_)) => (),
+        Err(err) => problems.push(format!("MODERATION_THRESHOLD: Must be a number ({})", err)),
+        Ok(value) if !(0.0..=1.0).contains(&value) => problems.push(format!(
+            "MODERATION_THRESHOLD: Must be between 0 and 1, but is {}",
+            value
+        )),
+        Ok(_) => (),
+    }
+    validate_positive(config, "MODERATION_TIMEOUT_SECS", problems);
+}
+
+/// Checks that the optional properties `first` and `second` are either both set or both unset.
+/// They may be of any type, e.g. lists like `NOTIFY_EMAIL_TO`.
+fn validate_pair(config: &Config, first: &str, second: &str, problems: &mut Vec<String>) {
+    match (is_set(config, first), is_set(config, second)) {
+        (true, false) => problems.push(format!("{}: {} must be set, too", first, second)),
+        (false, true) => problems.push(format!("{}: {} must be set, too", second, first)),
+        _ => (),
+    }
+}
+
+/// Returns whether the property `key` is set, regardless of its type
+fn is_set(config: &Config, key: &str) -> bool {
+    !matches!(
+        config.get::<config::Value>(key),
+        Err(ConfigError::NotFound(_))
+    )
+}
+
+/// Checks that the optional property `key` is an HTTP(S) URL, if it is set
+fn validate_url(config: &Config, key: &str, problems: &mut Vec<String>) {
+    let Ok(value) = config.get_string(key) else {
+        return;
+    };
+
+    match Url::parse(&value) {
+        Err(err) => problems.push(format!("{}: '{}' is not a valid URL ({})", key, value, err)),
+        Ok(url) if url.scheme() != "http" && url.scheme() != "https" => problems.push(format!(
+            "{}: '{}' must start with 'http://' or 'https://'",
+            key, value
+        )),
+        Ok(_) => (),
+    }
+}
+
+/// Checks that the optional property `key` is a boolean, if it is set
+fn validate_bool(config: &Config, key: &str, problems: &mut Vec<String>) {
+    match config.get_bool(key) {
+        Err(ConfigError::NotFound(_)) | Ok(_) => (),
+        Err(err) => problems.push(format!("{}: Must be true or false ({})", key, err)),
+    }
+}
+
+/// Checks that the optional property `key` is a positive integer, if it is set
+fn validate_positive(config: &Config, key: &str, problems: &mut Vec<String>) {
+    match config.get_int(key) {
+        Err(ConfigError::NotFound(_)) => (),
+        Err(err) => problems.push(format!("{}: Must be an integer ({})", key, err)),
+        Ok(value) if value <= 0 => {
+            problems.push(format!("{}: Must be greater than 0, but is {}", key, value))
+        }
+        Ok(_) => (),
+    }
+}
+
+/// Checks that the optional property `key` is a non-negative integer, if it is set
+fn validate_non_negative(config: &Config, key: &str, problems: &mut Vec<String>) {
+    match config.get_int(key) {
+        Err(ConfigError::NotFound(_)) => (),
+        Err(err) => problems.push(format!("{}: Must be an integer ({})", key, err)),
+        Ok(value) if value < 0 => {
+            problems.push(format!("{}: Must not be negative, but is {}", key, value))
+        }
+        Ok(_) => (),
+    }
+}
+
+/// Checks that the optional property `key` is a valid cron expression, if it is set
+fn validate_schedule(config: &Config, key: &str, problems: &mut Vec<String>) {
+    match config.get_string(key) {
+        Err(ConfigError::NotFound(_)) => (),
+        Err(err) => problems.push(format!("{}: Must be a cron expression ({})", key, err)),
+        Ok(expression) => {
+            if let Err(err) = parse_cron(&expression) {
+                problems.push(format!(
+                    "{}: '{}' is not a valid cron expression ({})",
+                    key, expression, err
+                ));
+            }
+        }
+    }
+}
+
+/// Prepares the data directories, including the roots of the configured tenants
+fn validate_data_dirs(config: &Config, problems: &mut Vec<String>) {
+    let mut roots = vec![None];
+    roots.extend(
+        parse_tenants(config)
+            .unwrap_or_default()
+            .into_keys()
+            .map(Some),
+    );
+    for dir in get_data_paths(&roots).iter() {
+        if let Err(problem) = prepare_data_dir(dir) {
+            problems.push(problem);
+        }
+    }
+}
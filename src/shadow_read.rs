@@ -0,0 +1,126 @@
+use std::{
+    fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use axum::body::Bytes;
+use config::Config;
+use serde::Serialize;
+use tokio::{runtime::Handle, sync::Semaphore};
+use utoipa::ToSchema;
+
+use crate::{constants::SHADOW_READ_CONCURRENCY, util::path::get_data_path};
+
+/// Compares files read from the data directory with a secondary copy of it, e.g. the mount of
+/// the object storage bucket it is migrated to, to gain confidence before the cutover.
+/// Responses are always served from the data directory; differences are only logged and
+/// counted, see `ShadowReadStats`.
+pub struct ShadowReader {
+    // Root of the secondary copy, laid out like the data directory
+    root: PathBuf,
+    // Limits the reads in the background, further reads are skipped
+    permits: Arc<Semaphore>,
+    counters: Arc<Counters>,
+}
+
+#[derive(Default)]
+struct Counters {
+    matched: AtomicU64,
+    missing: AtomicU64,
+    mismatched: AtomicU64,
+    failed: AtomicU64,
+    skipped: AtomicU64,
+}
+
+/// Outcomes of the shadow reads since the start
+#[derive(Serialize, ToSchema)]
+pub struct ShadowReadStats {
+    // Root of the secondary copy
+    path: String,
+    // Files that are identical in the secondary copy
+    matched: u64,
+    // Files that don't exist in the secondary copy
+    missing: u64,
+    // Files whose content differs in the secondary copy
+    mismatched: u64,
+    // Files that could not be read from the secondary copy
+    failed: u64,
+    // Reads that were not compared, as too many comparisons were running already
+    skipped: u64,
+}
+
+/// Parses the root of the secondary copy from the config property `SHADOW_READ_PATH`.
+/// Returns `None` if it is not set, i.e. shadow reads are disabled.
+pub fn parse_shadow_reader(config: &Config) -> Option<ShadowReader> {
+    let root = config.get_string("SHADOW_READ_PATH").ok()?;
+    log::info!("SHADOW: Comparing reads with {}", root);
+    Some(ShadowReader {
+        root: PathBuf::from(root),
+        permits: Arc::new(Semaphore::new(SHADOW_READ_CONCURRENCY)),
+        counters: Arc::new(Counters::default()),
+    })
+}
+
+impl ShadowReader {
+    /// Compares `content`, which was just read from the file at `path` in the data directory,
+    /// with the file in the secondary copy in the background. Only originals and raw files are
+    /// compared, as the cache is not migrated. Files outside the data directory (e.g. the
+    /// placeholder) are ignored.
+    pub fn compare(&self, path: &Path, content: Bytes) {
+        let Ok(relative) = path.strip_prefix(get_data_path()) else {
+            return;
+        };
+        let Ok(runtime) = Handle::try_current() else {
+            return;
+        };
+        let Ok(permit) = self.permits.clone().try_acquire_owned() else {
+            self.counters.skipped.fetch_add(1, Ordering::Relaxed);
+            return;
+        };
+
+        let (primary, secondary) = (path.to_owned(), self.root.join(relative));
+        let counters = self.counters.clone();
+        runtime.spawn_blocking(move || {
+            let _permit = permit;
+            let counter = match fs::read(&secondary) {
+                Ok(actual) if actual == content => &counters.matched,
+                Ok(actual) => {
+                    log::warn!(
+                        "SHADOW: {:?} differs from {:?} ({} instead of {} bytes)",
+                        secondary,
+                        primary,
+                        actual.len(),
+                        content.len()
+                    );
+                    &counters.mismatched
+                }
+                Err(err) if err.kind() == ErrorKind::NotFound => {
+                    log::warn!("SHADOW: {:?} is missing", secondary);
+                    &counters.missing
+                }
+                Err(err) => {
+                    log::error!("SHADOW: Could not read {:?}: {}", secondary, err);
+                    &counters.failed
+                }
+            };
+            counter.fetch_add(1, Ordering::Relaxed);
+        });
+    }
+
+    /// Returns the outcomes of the shadow reads since the start
+    pub fn stats(&self) -> ShadowReadStats {
+        ShadowReadStats {
+            path: self.root.display().to_string(),
+            matched: self.counters.matched.load(Ordering::Relaxed),
+            missing: self.counters.missing.load(Ordering::Relaxed),
+            mismatched: self.counters.mismatched.load(Ordering::Relaxed),
+            failed: self.counters.failed.load(Ordering::Relaxed),
+            skipped: self.counters.skipped.load(Ordering::Relaxed),
+        }
+    }
+}
@@ -0,0 +1,37 @@
+use std::{
+    fs::{self, File},
+    io,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use crate::util::{durability::Durability, path::get_trash_path};
+
+// With `TRASH_ENABLED`, deleted images and their raw files are moved to `data/trash` instead of
+// being removed right away, so accidental deletions can be undone by moving them back. The trash
+// cleaner deletes them after `TRASH_MAX_AGE_SECS`.
+
+/// Moves the file at `path` (an image or a raw file) to `data/trash`, prefixed by the directory it
+/// was stored in, e.g. `originals-<id>.avif`. Its modification time is set to now, as the age of
+/// trashed files is counted from their deletion. Returns the path in the trash.
+pub fn move_to_trash(path: &Path, durability: Durability) -> Result<PathBuf, io::Error> {
+    let trash_path = get_trash_path();
+    fs::create_dir_all(&trash_path)?;
+
+    let dir_name = path
+        .parent()
+        .and_then(|dir| dir.file_name())
+        .unwrap_or_default()
+        .to_string_lossy();
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    let target = trash_path.join(format!("{}-{}", dir_name, file_name));
+
+    fs::rename(path, &target)?;
+    File::options()
+        .write(true)
+        .open(&target)?
+        .set_modified(SystemTime::now())?;
+    durability.sync_parent_dir(&target)?;
+    log::info!("Moved {:?} to {:?}", path, target);
+    Ok(target)
+}
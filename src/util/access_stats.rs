@@ -0,0 +1,189 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    hash::Hash,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    constants::ACCESS_STATS_RETENTION_SECS,
+    util::{
+        durability::Durability,
+        path::{read_json_or_default, write_atomically},
+    },
+};
+
+const SECS_PER_HOUR: u64 = 60 * 60;
+
+#[derive(Default, Serialize, Deserialize)]
+struct AccessStatsData {
+    // Number of requests of each image by hour (since the unix epoch)
+    hours: BTreeMap<u64, HashMap<Uuid, u64>>,
+    // Bytes served of each image by hour
+    #[serde(default)]
+    image_bytes: BTreeMap<u64, HashMap<Uuid, u64>>,
+    // Bytes served to each API key (see `key_id`) by hour
+    #[serde(default)]
+    key_bytes: BTreeMap<u64, HashMap<String, u64>>,
+    // Bytes served to each API key (`None` without one) since the start, for `/metrics`
+    #[serde(skip)]
+    served: HashMap<Option<String>, u64>,
+    #[serde(skip)]
+    dirty: bool,
+}
+
+/// Counts the requests of approved images and the bytes served per hour, e.g. to find the most
+/// requested images or to attribute egress. Hours older than `ACCESS_STATS_RETENTION_SECS` are
+/// dropped.
+#[derive(Clone)]
+pub struct AccessStats {
+    path: PathBuf,
+    data: Arc<Mutex<AccessStatsData>>,
+}
+
+impl AccessStats {
+    /// Loads the statistics from `path`. Starts without requests if they cannot be read.
+    pub fn load(path: PathBuf) -> Self {
+        let data = read_json_or_default(&path, "access statistics");
+        Self {
+            path: path,
+            data: Arc::new(Mutex::new(data)),
+        }
+    }
+
+    /// Records a request of the image `uuid` just now
+    pub fn record(&self, uuid: Uuid) {
+        let hour = current_hour();
+        let mut data = self.data.lock().unwrap();
+        // Expired hours are dropped once a new hour begins
+        if !data.hours.contains_key(&hour) {
+            data.prune(hour);
+        }
+        *data.hours.entry(hour).or_default().entry(uuid).or_default() += 1;
+        data.dirty = true;
+    }
+
+    /// Records that `bytes` of the image `uuid` were served just now, to the API key with the ID
+    /// `key` if the request was authenticated
+    pub fn record_bytes(&self, uuid: Uuid, key: Option<&str>, bytes: u64) {
+        let hour = current_hour();
+        let mut data = self.data.lock().unwrap();
+        if !data.image_bytes.contains_key(&hour) {
+            data.prune(hour);
+        }
+        *data
+            .image_bytes
+            .entry(hour)
+            .or_default()
+            .entry(uuid)
+            .or_default() += bytes;
+        if let Some(key) = key {
+            *data
+                .key_bytes
+                .entry(hour)
+                .or_default()
+                .entry(key.to_owned())
+                .or_default() += bytes;
+        }
+        *data.served.entry(key.map(str::to_owned)).or_default() += bytes;
+        data.dirty = true;
+    }
+
+    /// Returns the (at most) `limit` images requested most often within the last `window`,
+    /// with their number of requests, ordered by it. The window is rounded up to full hours.
+    pub fn top(&self, window: Duration, limit: usize) -> Vec<(Uuid, u64)> {
+        let data = self.data.lock().unwrap();
+        let mut top = totals(&data.hours, since(window));
+        top.truncate(limit);
+        top
+    }
+
+    /// Returns the bytes served of each image within the last `window`, ordered by them. The
+    /// window is rounded up to full hours.
+    pub fn image_bytes(&self, window: Duration) -> Vec<(Uuid, u64)> {
+        totals(&self.data.lock().unwrap().image_bytes, since(window))
+    }
+
+    /// Returns the bytes served to each API key within the last `window`, like `image_bytes`
+    pub fn key_bytes(&self, window: Duration) -> Vec<(String, u64)> {
+        totals(&self.data.lock().unwrap().key_bytes, since(window))
+    }
+
+    /// Returns the bytes served to each API key (`None` without one) since the start, ordered by
+    /// key. Unlike the other totals, they never decrease, as counters of metrics have to.
+    pub fn served_since_start(&self) -> Vec<(Option<String>, u64)> {
+        let mut served: Vec<(Option<String>, u64)> = self
+            .data
+            .lock()
+            .unwrap()
+            .served
+            .iter()
+            .map(|(key, bytes)| (key.clone(), *bytes))
+            .collect();
+        served.sort();
+        served
+    }
+
+    /// Writes the statistics to disk, if they have changed since they were last written
+    pub fn save(&self) -> Result<(), String> {
+        let mut data = self.data.lock().unwrap();
+        if !data.dirty {
+            return Ok(());
+        }
+
+        let json = match serde_json::to_vec(&*data) {
+            Err(err) => return Err(format!("Could not serialize access statistics: {}", err)),
+            Ok(json) => json,
+        };
+        write_atomically(&self.path, &json, Durability::None)?;
+
+        data.dirty = false;
+        Ok(())
+    }
+}
+
+impl AccessStatsData {
+    /// Drops the hours that are expired at `hour`
+    fn prune(&mut self, hour: u64) {
+        let oldest = hour.saturating_sub(ACCESS_STATS_RETENTION_SECS / SECS_PER_HOUR);
+        self.hours.retain(|h, _| *h >= oldest);
+        self.image_bytes.retain(|h, _| *h >= oldest);
+        self.key_bytes.retain(|h, _| *h >= oldest);
+    }
+}
+
+/// Sums the counts of each key in the hours from `since` on, ordered by the sum
+fn totals<K: Clone + Eq + Hash + Ord>(
+    hours: &BTreeMap<u64, HashMap<K, u64>>,
+    since: u64,
+) -> Vec<(K, u64)> {
+    let mut totals: HashMap<K, u64> = HashMap::new();
+    for (_, counts) in hours.range(since..) {
+        for (key, count) in counts {
+            *totals.entry(key.clone()).or_default() += count;
+        }
+    }
+
+    let mut totals: Vec<(K, u64)> = totals.into_iter().collect();
+    // Ties are ordered by key, so the order is stable
+    totals.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    totals
+}
+
+/// Returns the first hour within the last `window`, rounded up to full hours
+fn since(window: Duration) -> u64 {
+    let hours = window.as_secs().div_ceil(SECS_PER_HOUR).max(1);
+    (current_hour() + 1).saturating_sub(hours)
+}
+
+fn current_hour() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+        / SECS_PER_HOUR
+}
@@ -4,14 +4,38 @@ use axum_extra::{
     headers::{authorization::Bearer, Authorization},
     TypedHeader,
 };
+use config::Config;
+use sha2::{Digest, Sha256};
+
+/// Parses the Argon2 hashes of valid API keys from the config property `API_KEY_HASHES`
+pub fn parse_hashes(config: &Config) -> Result<Vec<PasswordHashString>, String> {
+    let hash_values: Vec<String> = match config.get("API_KEY_HASHES") {
+        Err(err) => return Err(format!("$API_KEY_HASHES is not set ({})", err)),
+        Ok(val) => val,
+    };
+
+    hash_values
+        .iter()
+        .map(|hv| {
+            PasswordHashString::new(hv).map_err(|err| format!("Failed to parse hash ({})", err))
+        })
+        .collect()
+}
+
+/// Returns an ID of the API key with the given hash that can be shown, e.g. in statistics, without
+/// revealing the key or its hash. It stays the same as long as the hash is configured.
+pub fn key_id(hash: &PasswordHashString) -> String {
+    let digest = format!("{:x}", Sha256::digest(hash.as_str()));
+    digest[..12].to_owned()
+}
 
 /// Checks if user is authorized by checking if the given Bearer Token or query parameter matches
-/// the given hashes.
+/// the given hashes. Returns the ID of the matching key, see `key_id`.
 pub fn check_auth(
     auth_query: Option<&String>,
     auth_header: Option<TypedHeader<Authorization<Bearer>>>,
     hashes: &Vec<PasswordHashString>,
-) -> Result<(), (StatusCode, String)> {
+) -> Result<String, (StatusCode, String)> {
     if let Some(auth) = auth_query {
         return check_auth_key(auth.as_bytes(), hashes);
     }
@@ -24,22 +48,24 @@ pub fn check_auth(
 }
 
 /// Checks if user is authorized by checking if the given Bearer Token matches the given hashes.
+/// Returns the ID of the matching key, see `key_id`.
 pub fn check_auth_header(
     authorization: Authorization<Bearer>,
     hashes: &Vec<PasswordHashString>,
-) -> Result<(), (StatusCode, String)> {
-    return check_auth_key(authorization.token().as_bytes(), hashes);
+) -> Result<String, (StatusCode, String)> {
+    check_auth_key(authorization.token().as_bytes(), hashes)
 }
 
 /// Checks authorization by checking if a (raw) key matches a given hash  
-/// Returns 401 (UNAUTHORIZED) with appropriate message if they do not match
+/// Returns the ID of the matching key (see `key_id`) or 401 (UNAUTHORIZED) with appropriate
+/// message if they do not match
 pub fn check_auth_key(
     key: &[u8],
     hashes: &Vec<PasswordHashString>,
-) -> Result<(), (StatusCode, String)> {
+) -> Result<String, (StatusCode, String)> {
     for hash in hashes {
         match (Argon2::default()).verify_password(key, &hash.password_hash()) {
-            Ok(_) => return Ok(()),
+            Ok(_) => return Ok(key_id(hash)),
             Err(err) => {
                 match err {
                     password_hash::errors::Error::Password => {
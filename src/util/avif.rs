@@ -0,0 +1,124 @@
+use config::{Config, ConfigError};
+use libvips::ops::{self, ForeignHeifCompression, ForeignSubsample};
+use serde::Deserialize;
+
+use crate::constants::{DEFAULT_AVIF_EFFORT, PENDING_QUALITY, ROTATION_QUALITY};
+
+/// Chroma subsampling of stored images
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChromaSubsampling {
+    // Decided by libvips, i.e. 4:2:0 unless the quality is very high
+    #[default]
+    Auto,
+    // Always 4:2:0
+    On,
+    // Always 4:4:4, keeping sharp colored edges at the cost of size
+    Off,
+}
+
+/// How images are encoded when they are stored as AVIF
+#[derive(Clone, Copy, Debug)]
+pub struct AvifSettings {
+    pub quality: i32,
+    // CPU effort from 0 (fastest) to 9 (smallest files)
+    pub effort: i32,
+    pub chroma_subsampling: ChromaSubsampling,
+    // 8, 10 or 12, defaults to the one of libvips
+    pub bit_depth: Option<i32>,
+    // Lossless encoding (ignoring the quality), see `LOSSLESS_ARCHIVAL`
+    pub lossless: bool,
+}
+
+/// Encoder settings of each way images are stored, so operators can trade CPU for size
+#[derive(Clone, Copy, Debug)]
+pub struct AvifProfiles {
+    // Uploads saved as pending images
+    pub pending: AvifSettings,
+    // Images saved after a rotation, which are not pending
+    pub rotation: AvifSettings,
+    // Images rebuilt from their raw file by `/regenerate/:id`
+    pub regenerate: AvifSettings,
+}
+
+/// Settings as written in the config
+#[derive(Default, Deserialize)]
+struct AvifSettingsConfig {
+    quality: Option<i32>,
+    effort: Option<i32>,
+    chroma_subsampling: Option<ChromaSubsampling>,
+    bit_depth: Option<i32>,
+}
+
+impl AvifSettings {
+    /// Options of `heifsave` encoding with these settings
+    pub fn heifsave_options(&self) -> ops::HeifsaveOptions {
+        let defaults = ops::HeifsaveOptions::default();
+        ops::HeifsaveOptions {
+            q: self.quality,
+            compression: ForeignHeifCompression::Av1,
+            effort: self.effort,
+            subsample_mode: match self.chroma_subsampling {
+                ChromaSubsampling::Auto => ForeignSubsample::Auto,
+                ChromaSubsampling::On => ForeignSubsample::On,
+                ChromaSubsampling::Off => ForeignSubsample::Off,
+            },
+            bitdepth: self.bit_depth.unwrap_or(defaults.bitdepth),
+            lossless: self.lossless,
+            ..defaults
+        }
+    }
+}
+
+/// Parses the encoder settings from the config properties `AVIF_PENDING`, `AVIF_ROTATION` and
+/// `AVIF_REGENERATE`, each optionally with `quality`, `effort`, `chroma_subsampling` (`auto`, `on`
+/// or `off`) and `bit_depth`, e.g. `AVIF_PENDING: { quality: 75, effort: 4 }`.
+/// With `LOSSLESS_ARCHIVAL`, all of them are lossless, as approved originals are the uploads
+/// (or their rotations) moved between the states.
+/// Returns a message describing the problem, if any of them is invalid.
+pub fn parse_avif_profiles(config: &Config) -> Result<AvifProfiles, String> {
+    let lossless = config.get_bool("LOSSLESS_ARCHIVAL").unwrap_or(false);
+    Ok(AvifProfiles {
+        pending: parse_avif_settings(config, "AVIF_PENDING", PENDING_QUALITY, lossless)?,
+        // Highest quality by default, to keep the loss of repeated rotations low
+        rotation: parse_avif_settings(config, "AVIF_ROTATION", ROTATION_QUALITY, lossless)?,
+        // Like uploads by default
+        regenerate: parse_avif_settings(config, "AVIF_REGENERATE", PENDING_QUALITY, lossless)?,
+    })
+}
+
+fn parse_avif_settings(
+    config: &Config,
+    key: &str,
+    default_quality: i32,
+    lossless: bool,
+) -> Result<AvifSettings, String> {
+    let settings = match config.get::<AvifSettingsConfig>(key) {
+        Err(ConfigError::NotFound(_)) => AvifSettingsConfig::default(),
+        Err(err) => return Err(format!("{}: Invalid encoder settings ({})", key, err)),
+        Ok(settings) => settings,
+    };
+
+    let quality = settings.quality.unwrap_or(default_quality);
+    if !(1..=100).contains(&quality) {
+        return Err(format!("{}: quality must be between 1 and 100", key));
+    }
+    let effort = settings.effort.unwrap_or(DEFAULT_AVIF_EFFORT);
+    if !(0..=9).contains(&effort) {
+        return Err(format!("{}: effort must be between 0 and 9", key));
+    }
+    if settings
+        .bit_depth
+        .is_some_and(|bit_depth| ![8, 10, 12].contains(&bit_depth))
+    {
+        return Err(format!("{}: bit_depth must be 8, 10 or 12", key));
+    }
+
+    Ok(AvifSettings {
+        quality: quality,
+        effort: effort,
+        chroma_subsampling: settings.chroma_subsampling.unwrap_or_default(),
+        bit_depth: settings.bit_depth,
+        lossless: lossless,
+    })
+}
@@ -0,0 +1,180 @@
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use config::Config;
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+use uuid::Uuid;
+
+use crate::util::{
+    durability::Durability,
+    image::parse_cache_entry,
+    path::{read_json_or_default, write_atomically},
+};
+
+/// Parses the age after which cache entries are stale and rendered again from the config property
+/// `CACHE_TTL_SECS`. Cache entries are never stale, if it is not set.
+pub fn parse_cache_ttl(config: &Config) -> Option<Duration> {
+    config
+        .get::<u64>("CACHE_TTL_SECS")
+        .ok()
+        .map(Duration::from_secs)
+}
+
+/// Renditions of an image that are never evicted, e.g. of the hero images of the homepage.
+/// Without `width` or `height`, renditions of any width or height are pinned.
+#[derive(Clone, PartialEq, Serialize, Deserialize, ToSchema, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct CachePin {
+    /// ID of the image
+    pub id: Uuid,
+    /// Width of the pinned renditions, as in their cache entry
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub width: Option<i32>,
+    /// Height of the pinned renditions, as in their cache entry
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub height: Option<i32>,
+}
+
+impl CachePin {
+    /// Returns whether the cache entry `file_name` is pinned by this pin
+    fn matches(&self, file_name: &str) -> bool {
+        parse_cache_entry(file_name).is_some_and(|(uuid, variant)| {
+            uuid == self.id
+                && self.width.map_or(true, |width| width == variant.width)
+                && self.height.map_or(true, |height| height == variant.height)
+        })
+    }
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct CacheIndexData {
+    // Last access of each cache entry (by file name) in seconds since the unix epoch
+    last_access: HashMap<String, u64>,
+    // Renditions exempt from eviction
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pinned: Vec<CachePin>,
+    #[serde(skip)]
+    dirty: bool,
+}
+
+/// Keeps track of when cache entries were last accessed and which are pinned.
+/// File access times are not reliable for this, as atime is often disabled.
+#[derive(Clone)]
+pub struct CacheIndex {
+    path: PathBuf,
+    data: Arc<Mutex<CacheIndexData>>,
+}
+
+impl CacheIndex {
+    /// Loads the index from `path`. Starts with an empty index if it cannot be read.
+    pub fn load(path: PathBuf) -> Self {
+        let data = read_json_or_default(&path, "cache index");
+        Self {
+            path: path,
+            data: Arc::new(Mutex::new(data)),
+        }
+    }
+
+    /// Records that the cache entry `file_name` was accessed just now
+    pub fn record_access(&self, file_name: &str) {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+
+        let mut data = self.data.lock().unwrap();
+        data.last_access.insert(file_name.to_owned(), now);
+        data.dirty = true;
+    }
+
+    /// Returns when the cache entry `file_name` was last accessed, if known
+    pub fn last_access(&self, file_name: &str) -> Option<SystemTime> {
+        let data = self.data.lock().unwrap();
+        data.last_access
+            .get(file_name)
+            .map(|secs| UNIX_EPOCH + Duration::from_secs(*secs))
+    }
+
+    /// Removes all entries, for which `exists` returns false when called with their file name.
+    /// Returns the number of removed entries.
+    pub fn prune(&self, mut exists: impl FnMut(&str) -> bool) -> usize {
+        let mut data = self.data.lock().unwrap();
+        let before = data.last_access.len();
+        data.last_access.retain(|file_name, _| exists(file_name));
+
+        let removed = before - data.last_access.len();
+        if removed > 0 {
+            data.dirty = true;
+        }
+        removed
+    }
+
+    /// Pins the renditions of `pin`, so they are never evicted.
+    /// Returns whether it was not pinned yet.
+    pub fn pin(&self, pin: CachePin) -> bool {
+        let mut data = self.data.lock().unwrap();
+        if data.pinned.contains(&pin) {
+            return false;
+        }
+        data.pinned.push(pin);
+        data.dirty = true;
+        true
+    }
+
+    /// Removes `pin`. Returns whether it was pinned.
+    pub fn unpin(&self, pin: &CachePin) -> bool {
+        let mut data = self.data.lock().unwrap();
+        let before = data.pinned.len();
+        data.pinned.retain(|pinned| pinned != pin);
+        let removed = data.pinned.len() < before;
+        data.dirty |= removed;
+        removed
+    }
+
+    /// Removes all pins of the image `uuid`, e.g. after it was deleted
+    pub fn unpin_image(&self, uuid: Uuid) {
+        let mut data = self.data.lock().unwrap();
+        let before = data.pinned.len();
+        data.pinned.retain(|pinned| pinned.id != uuid);
+        data.dirty |= data.pinned.len() < before;
+    }
+
+    /// Returns all pins, in the order they were pinned
+    pub fn pins(&self) -> Vec<CachePin> {
+        self.data.lock().unwrap().pinned.clone()
+    }
+
+    /// Returns whether the cache entry `file_name` is pinned
+    pub fn is_pinned(&self, file_name: &str) -> bool {
+        let data = self.data.lock().unwrap();
+        data.pinned.iter().any(|pin| pin.matches(file_name))
+    }
+
+    /// Returns the number of entries whose last access is known
+    pub fn len(&self) -> usize {
+        self.data.lock().unwrap().last_access.len()
+    }
+
+    /// Writes the index to disk, if it has changed since it was last written.
+    /// The index is written to a temporary file first, so a crash can't leave a corrupt index.
+    pub fn save(&self) -> Result<(), String> {
+        let mut data = self.data.lock().unwrap();
+        if !data.dirty {
+            return Ok(());
+        }
+
+        let json = match serde_json::to_vec(&*data) {
+            Err(err) => return Err(format!("Could not serialize cache index: {}", err)),
+            Ok(json) => json,
+        };
+        write_atomically(&self.path, &json, Durability::None)?;
+
+        data.dirty = false;
+        Ok(())
+    }
+}
@@ -0,0 +1,50 @@
+use axum::http::HeaderMap;
+
+use crate::constants::MAX_CLIENT_HINT_DPR;
+
+/// Client Hints in request headers, see
+/// https://developer.mozilla.org/en-US/docs/Web/HTTP/Client_hints.
+/// Browsers only send them, if the page opted in via `Accept-CH: Sec-CH-Width, Sec-CH-DPR`
+/// (`Save-Data` is always sent).
+#[derive(Clone, Copy, Default)]
+pub struct ClientHints {
+    // Width of the image on the page in physical pixels (`Sec-CH-Width`)
+    pub width: Option<i32>,
+    // Device pixel ratio (`Sec-CH-DPR`)
+    pub dpr: Option<f64>,
+    // Whether the user asked for reduced data usage (`Save-Data: on`)
+    pub save_data: bool,
+}
+
+/// Names of the request headers that are considered, for the `Vary` header of responses
+pub const CLIENT_HINT_HEADERS: &str = "Sec-CH-Width, Sec-CH-DPR, Save-Data";
+
+/// Parses the Client Hints from `headers`. Invalid hints are ignored.
+pub fn parse_client_hints(headers: &HeaderMap) -> ClientHints {
+    let value = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(str::trim)
+    };
+    ClientHints {
+        width: value("sec-ch-width")
+            .and_then(|width| width.parse::<i32>().ok())
+            .filter(|width| *width > 0),
+        dpr: value("sec-ch-dpr")
+            .and_then(|dpr| dpr.parse::<f64>().ok())
+            .filter(|dpr| *dpr > 0.0)
+            .map(|dpr| dpr.min(MAX_CLIENT_HINT_DPR)),
+        save_data: value("save-data").is_some_and(|save_data| save_data == "on"),
+    }
+}
+
+impl ClientHints {
+    /// Scales dimensions given in CSS pixels to physical pixels
+    pub fn scale(&self, value: i32) -> i32 {
+        match self.dpr {
+            None => value,
+            Some(dpr) => (value as f64 * dpr).round() as i32,
+        }
+    }
+}
@@ -0,0 +1,140 @@
+use std::{
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+};
+
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::{header, HeaderMap, StatusCode},
+    middleware::Next,
+    response::Response,
+};
+use config::Config;
+use ipnet::IpNet;
+
+use crate::ServerState;
+
+/// IP address of the client of a request, with forwarding by trusted proxies resolved.
+/// Added to the extensions of every request by `resolve_client_ip`.
+#[derive(Clone, Copy, Debug)]
+pub struct ClientIp(pub IpAddr);
+
+/// Reverse proxies whose `Forwarded`/`X-Forwarded-For` headers are trusted. Headers of other
+/// peers are ignored, as any client could set them.
+#[derive(Default)]
+pub struct TrustedProxies {
+    networks: Vec<IpNet>,
+}
+
+/// Parses the config property `TRUSTED_PROXIES`, a list of addresses (`10.0.0.1`) or networks
+/// (`10.0.0.0/8`). No proxies are trusted, if it is not set.
+pub fn parse_trusted_proxies(config: &Config) -> TrustedProxies {
+    TrustedProxies {
+        networks: config
+            .get::<Vec<String>>("TRUSTED_PROXIES")
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|value| parse_network(value))
+            .collect(),
+    }
+}
+
+/// Parses an address or network, returns none if it is neither
+pub fn parse_network(value: &str) -> Option<IpNet> {
+    IpNet::from_str(value)
+        .ok()
+        .or_else(|| IpAddr::from_str(value).ok().map(IpNet::from))
+}
+
+impl TrustedProxies {
+    fn is_trusted(&self, ip: IpAddr) -> bool {
+        self.networks.iter().any(|network| network.contains(&ip))
+    }
+
+    /// Returns the IP address of the client that connected from `peer`. If the peer is a trusted
+    /// proxy, the forwarding chain in `headers` is followed from the right (i.e. the last proxy)
+    /// until the first address that is not trusted, which is the client.
+    pub fn client_ip(&self, peer: IpAddr, headers: &HeaderMap) -> IpAddr {
+        let mut client = peer.to_canonical();
+        if !self.is_trusted(client) {
+            return client;
+        }
+        for hop in forwarded_for(headers).into_iter().rev() {
+            // Obfuscated or unknown addresses can't be followed further
+            let Some(hop) = hop else {
+                break;
+            };
+            client = hop.to_canonical();
+            if !self.is_trusted(client) {
+                break;
+            }
+        }
+        client
+    }
+}
+
+/// Returns the addresses in the `for` parameters of the `Forwarded` headers or, if there are
+/// none, in the `X-Forwarded-For` headers, starting with the original client. Entries that are
+/// not an address (e.g. `unknown`) are none.
+fn forwarded_for(headers: &HeaderMap) -> Vec<Option<IpAddr>> {
+    let forwarded: Vec<Option<IpAddr>> = header_entries(headers, header::FORWARDED)
+        .iter()
+        .filter_map(|element| {
+            element.split(';').find_map(|pair| {
+                let (name, value) = pair.split_once('=')?;
+                name.trim()
+                    .eq_ignore_ascii_case("for")
+                    .then(|| parse_node(value))
+            })
+        })
+        .collect();
+    if !forwarded.is_empty() {
+        return forwarded;
+    }
+
+    header_entries(headers, "x-forwarded-for")
+        .iter()
+        .map(|entry| parse_node(entry))
+        .collect()
+}
+
+/// Returns the comma-separated entries of all headers `name`
+fn header_entries(headers: &HeaderMap, name: impl header::AsHeaderName) -> Vec<String> {
+    headers
+        .get_all(name)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|entry| entry.trim().to_owned())
+        .collect()
+}
+
+/// Parses a node, e.g. `192.0.2.60`, `"192.0.2.60:4711"` or `"[2001:db8::1]:4711"`
+fn parse_node(node: &str) -> Option<IpAddr> {
+    let node = node.trim().trim_matches('"');
+    IpAddr::from_str(node)
+        .ok()
+        .or_else(|| SocketAddr::from_str(node).ok().map(|addr| addr.ip()))
+        .or_else(|| IpAddr::from_str(node.trim_start_matches('[').trim_end_matches(']')).ok())
+}
+
+/// Middleware adding the `ClientIp` to the extensions of the request. Rejected authentications
+/// are logged with it.
+pub async fn resolve_client_ip(
+    State(server_state): State<ServerState>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let client_ip = server_state
+        .trusted_proxies
+        .client_ip(peer.ip(), request.headers());
+    request.extensions_mut().insert(ClientIp(client_ip));
+
+    let (method, path) = (request.method().clone(), request.uri().path().to_owned());
+    let response = next.run(request).await;
+    if response.status() == StatusCode::UNAUTHORIZED {
+        log::warn!("AUTH: Rejected {} {} from {}", method, path, client_ip);
+    }
+    response
+}
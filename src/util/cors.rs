@@ -1,7 +1,156 @@
-use std::str::FromStr;
+use std::{
+    collections::HashMap,
+    str::FromStr,
+    task::{Context, Poll},
+    time::Duration,
+};
 
-use axum::http::{HeaderValue, Method};
-use config::Config;
+use axum::http::{header, HeaderName, HeaderValue, Method, Request};
+use config::{Config, ConfigError};
+use serde::Deserialize;
+use tower::{Layer, Service};
+use tower_http::cors::{AllowHeaders, AllowOrigin, Cors, CorsLayer};
+
+use crate::{
+    handlers::image::{IMAGE_HEIGHT, IMAGE_WIDTH, ORIGINAL_HEIGHT, ORIGINAL_WIDTH},
+    ServerState,
+};
+
+/// Allows any origin (or header), if listed in `CORS_ALLOWED_ORIGINS` (or `CORS_ALLOWED_HEADERS`)
+pub const WILDCARD: &str = "*";
+
+/// Groups of routes that can have their own CORS policy in `CORS_POLICIES`, e.g. so the
+/// moderation endpoints are only callable from the admin origin while images are open to any.
+/// Mutating methods of image routes (e.g. `DELETE /image/:id`) belong to `Admin`, see
+/// `SplitCorsLayer`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RouteGroup {
+    // Delivery of images and their metadata, e.g. `/image/:id` and `/images/info`
+    Images,
+    // Uploads and submissions by clients, e.g. `/upload` and `/submit/:id`
+    Upload,
+    // Moderation and maintenance, e.g. `/approve/:id`, `/export` and `/admin`
+    Admin,
+}
+
+impl RouteGroup {
+    pub const ALL: [RouteGroup; 3] = [RouteGroup::Images, RouteGroup::Upload, RouteGroup::Admin];
+}
+
+/// The CORS policy of a route group as written in the config. Unset options fall back to the
+/// global `CORS_*` options.
+#[derive(Default, Deserialize)]
+pub struct CorsPolicyConfig {
+    pub allowed_origins: Option<Vec<String>>,
+    pub allowed_methods: Option<Vec<String>>,
+    pub allowed_headers: Option<Vec<String>>,
+    pub exposed_headers: Option<Vec<String>>,
+    pub max_age_secs: Option<u64>,
+    pub allow_credentials: Option<bool>,
+}
+
+/// Parses the policies of route groups from the config property `CORS_POLICIES`, which maps
+/// groups (`images`, `upload` or `admin`) to policies, e.g.
+/// `admin: { allowed_origins: ["https://admin.example.com"], allow_credentials: true }`.
+/// Returns a message describing the problem, if the policies can't be parsed.
+pub fn parse_cors_policies(
+    config: &Config,
+) -> Result<HashMap<RouteGroup, CorsPolicyConfig>, String> {
+    match config.get::<HashMap<RouteGroup, CorsPolicyConfig>>("CORS_POLICIES") {
+        Err(ConfigError::NotFound(_)) => Ok(HashMap::new()),
+        Err(err) => Err(format!("CORS_POLICIES: Invalid policies ({})", err)),
+        Ok(policies) => Ok(policies),
+    }
+}
+
+/// The CORS options that are fixed at startup, origins are reloadable (see `reloadable_origins`)
+#[derive(Clone)]
+pub struct CorsConfig {
+    pub methods: Vec<Method>,
+    // Request headers besides the CORS-safelisted ones, none allows any header
+    pub allowed_headers: Option<Vec<HeaderName>>,
+    // Response headers readable by scripts, besides the dimension headers of `/image/:id`
+    pub exposed_headers: Vec<HeaderName>,
+    // How long browsers may cache preflight responses
+    pub max_age: Option<Duration>,
+    // Whether requests may include cookies and Authorization headers
+    pub allow_credentials: bool,
+}
+
+/// Parses the config properties `CORS_ALLOWED_METHODS`, `CORS_ALLOWED_HEADERS` (`*` allows any
+/// header), `CORS_EXPOSED_HEADERS`, `CORS_MAX_AGE_SECS` and `CORS_ALLOW_CREDENTIALS`
+pub fn parse_cors_config(config: &Config) -> CorsConfig {
+    CorsConfig {
+        methods: parse_methods(config),
+        allowed_headers: parse_allowed_headers(
+            &config
+                .get::<Vec<String>>("CORS_ALLOWED_HEADERS")
+                .unwrap_or_default(),
+        ),
+        exposed_headers: parse_header_names(
+            &config
+                .get::<Vec<String>>("CORS_EXPOSED_HEADERS")
+                .unwrap_or_default(),
+        ),
+        max_age: config
+            .get::<u64>("CORS_MAX_AGE_SECS")
+            .ok()
+            .map(Duration::from_secs),
+        allow_credentials: config.get_bool("CORS_ALLOW_CREDENTIALS").unwrap_or(false),
+    }
+}
+
+impl CorsConfig {
+    /// Returns this config with the options set in `policy` overridden, except for the origins
+    pub fn with_policy(&self, policy: &CorsPolicyConfig) -> CorsConfig {
+        CorsConfig {
+            methods: match &policy.allowed_methods {
+                None => self.methods.clone(),
+                Some(methods) => methods
+                    .iter()
+                    .filter_map(|method| Method::from_str(method).ok())
+                    .collect(),
+            },
+            allowed_headers: match &policy.allowed_headers {
+                None => self.allowed_headers.clone(),
+                Some(headers) => parse_allowed_headers(headers),
+            },
+            exposed_headers: match &policy.exposed_headers {
+                None => self.exposed_headers.clone(),
+                Some(headers) => parse_header_names(headers),
+            },
+            max_age: policy
+                .max_age_secs
+                .map(Duration::from_secs)
+                .or(self.max_age),
+            allow_credentials: policy.allow_credentials.unwrap_or(self.allow_credentials),
+        }
+    }
+
+    /// Builds the CORS layer of the routes in `group`, which checks origins against the
+    /// currently loaded config. Wildcards are implemented by mirroring the request, as the `*`
+    /// response headers can't be combined with credentials.
+    pub fn layer(&self, server_state: ServerState, group: RouteGroup) -> CorsLayer {
+        let exposed_headers = [IMAGE_WIDTH, IMAGE_HEIGHT, ORIGINAL_WIDTH, ORIGINAL_HEIGHT]
+            .into_iter()
+            .chain(self.exposed_headers.iter().cloned())
+            .collect::<Vec<_>>();
+        let layer = CorsLayer::new()
+            .allow_methods(self.methods.clone())
+            .allow_origin(reloadable_origins(server_state, group))
+            .allow_headers(match &self.allowed_headers {
+                None => AllowHeaders::mirror_request(),
+                Some(headers) => AllowHeaders::list(headers.iter().cloned()),
+            })
+            .expose_headers(exposed_headers)
+            .allow_credentials(self.allow_credentials);
+        match self.max_age {
+            None => layer,
+            Some(max_age) => layer.max_age(max_age),
+        }
+    }
+}
 
 /// Parses Axum HTTP Methods from the config property `CORS_ALLOWED_METHODS`
 pub fn parse_methods(config: &Config) -> Vec<Method> {
@@ -20,15 +169,137 @@ pub fn parse_methods(config: &Config) -> Vec<Method> {
     }
 }
 
-/// Parses Axum Header Value from the config property `CORS_ALLOWED_METHODS`
-pub fn parse_origins(config: &Config) -> Vec<HeaderValue> {
+/// Parses the allowed headers `values`, returns none if they contain the wildcard
+fn parse_allowed_headers(values: &[String]) -> Option<Vec<HeaderName>> {
+    match values.iter().any(|value| value == WILDCARD) {
+        true => None,
+        false => Some(parse_header_names(values)),
+    }
+}
+
+fn parse_header_names(values: &[String]) -> Vec<HeaderName> {
+    values
+        .iter()
+        .filter_map(|value| HeaderName::from_str(value).ok())
+        .collect()
+}
+
+/// Parses Axum Header Value from the config property `CORS_ALLOWED_ORIGINS`.
+/// `*` allows requests from any origin, which is logged as warning.
+pub fn parse_origins(config: &Config) -> Result<Vec<HeaderValue>, String> {
     match config.get::<Vec<String>>("CORS_ALLOWED_ORIGINS") {
-        Err(err) => {
-            panic!("CORS_ALLOWED_ORIGINS not specified. Error was: {}", err);
-        }
-        Ok(vec) => vec
+        Err(err) => Err(format!(
+            "CORS_ALLOWED_ORIGINS not specified. Error was: {}",
+            err
+        )),
+        Ok(vec) => Ok(to_origins("CORS_ALLOWED_ORIGINS", &vec)),
+    }
+}
+
+/// Parses the origins of the policies in `CORS_POLICIES` that set them.
+/// Returns a message describing the problem, if the policies can't be parsed.
+pub fn parse_policy_origins(
+    config: &Config,
+) -> Result<HashMap<RouteGroup, Vec<HeaderValue>>, String> {
+    Ok(parse_cors_policies(config)?
+        .into_iter()
+        .filter_map(|(group, policy)| {
+            let origins = policy.allowed_origins?;
+            let key = format!("CORS_POLICIES.{:?}.allowed_origins", group).to_lowercase();
+            Some((group, to_origins(&key, &origins)))
+        })
+        .collect())
+}
+
+/// Converts the origins of the config property `key`. `*` allows requests from any origin,
+/// which is logged as warning.
+fn to_origins(key: &str, values: &[String]) -> Vec<HeaderValue> {
+    if values.iter().any(|value| value == WILDCARD) {
+        log::warn!(
+            "{} contains '{}'. Requests from any origin are allowed.",
+            key,
+            WILDCARD
+        );
+    }
+    values
+        .iter()
+        .filter_map(|value| HeaderValue::from_str(value).ok())
+        .collect()
+}
+
+/// Builds an `AllowOrigin` that checks origins against the currently loaded config of `group`
+/// (or the global one, if the group has no origins of its own), so that changes to
+/// `CORS_ALLOWED_ORIGINS` and `CORS_POLICIES` take effect on `/reload`
+pub fn reloadable_origins(server_state: ServerState, group: RouteGroup) -> AllowOrigin {
+    AllowOrigin::predicate(move |origin: &HeaderValue, _| {
+        let reloadable = server_state.reloadable();
+        reloadable
+            .cors_policy_origins
+            .get(&group)
+            .unwrap_or(&reloadable.cors_origins)
             .iter()
-            .filter_map(|elem| HeaderValue::from_str(elem).ok())
-            .collect(),
+            .any(|allowed| allowed == origin || allowed == WILDCARD)
+    })
+}
+
+/// Applies the CORS layer `mutating` to requests with one of `methods` (and their preflight
+/// requests) and `other` to all other requests, e.g. so `DELETE /image/:id` gets the policy of
+/// `Admin`, while `GET /image/:id` gets the one of `Images`
+#[derive(Clone)]
+pub struct SplitCorsLayer {
+    pub other: CorsLayer,
+    pub mutating: CorsLayer,
+    pub methods: Vec<Method>,
+}
+
+impl<S: Clone> Layer<S> for SplitCorsLayer {
+    type Service = SplitCors<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        SplitCors {
+            other: self.other.layer(inner.clone()),
+            mutating: self.mutating.layer(inner),
+            methods: self.methods.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct SplitCors<S> {
+    other: Cors<S>,
+    mutating: Cors<S>,
+    methods: Vec<Method>,
+}
+
+impl<S, B> Service<Request<B>> for SplitCors<S>
+where
+    Cors<S>: Service<Request<B>>,
+{
+    type Response = <Cors<S> as Service<Request<B>>>::Response;
+    type Error = <Cors<S> as Service<Request<B>>>::Error;
+    type Future = <Cors<S> as Service<Request<B>>>::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        // Both wrap the same inner service
+        match self.other.poll_ready(cx) {
+            Poll::Ready(Ok(())) => self.mutating.poll_ready(cx),
+            poll => poll,
+        }
+    }
+
+    fn call(&mut self, request: Request<B>) -> Self::Future {
+        // Preflight requests are answered for the method of the actual request
+        let method = match request.method() {
+            &Method::OPTIONS => request
+                .headers()
+                .get(header::ACCESS_CONTROL_REQUEST_METHOD)
+                .and_then(|value| Method::from_bytes(value.as_bytes()).ok())
+                .unwrap_or(Method::OPTIONS),
+            method => method.clone(),
+        };
+        match self.methods.contains(&method) {
+            true => self.mutating.call(request),
+            false => self.other.call(request),
+        }
     }
 }
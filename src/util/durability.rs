@@ -0,0 +1,54 @@
+use std::{fs, io, path::Path};
+
+use config::Config;
+
+/// How thoroughly images are flushed to disk when they are saved or moved between states, trading
+/// write latency for not losing them (or their state transitions) on power loss
+#[derive(Clone, Copy, Debug, Default, PartialEq, PartialOrd)]
+pub enum Durability {
+    // Left to the OS, atomic writes only guarantee there are no partially written files
+    #[default]
+    None,
+    // Saved files are flushed before they are moved into place
+    Files,
+    // Additionally, directories are flushed after files are moved into or out of them, so the
+    // moves (e.g. approvals) are persisted as well
+    Full,
+}
+
+/// Parses the config property `DURABILITY` (`none`, `files` or `full`, defaults to `none`).
+/// If it is not set, the deprecated `FSYNC_WRITES: true` is accepted as `files`.
+pub fn parse_durability(config: &Config) -> Durability {
+    match config.get_string("DURABILITY").as_deref() {
+        Ok("files") => Durability::Files,
+        Ok("full") => Durability::Full,
+        Ok(_) => Durability::None,
+        Err(_) => match config.get_bool("FSYNC_WRITES") {
+            Ok(true) => {
+                log::warn!("FSYNC_WRITES is deprecated, use 'DURABILITY: files' instead");
+                Durability::Files
+            }
+            _ => Durability::None,
+        },
+    }
+}
+
+impl Durability {
+    /// Flushes the file at `path`, if required
+    pub fn sync_file(&self, path: &Path) -> Result<(), io::Error> {
+        if *self >= Durability::Files {
+            fs::File::open(path)?.sync_all()?;
+        }
+        Ok(())
+    }
+
+    /// Flushes the directory containing `path` after it was moved, if required
+    pub fn sync_parent_dir(&self, path: &Path) -> Result<(), io::Error> {
+        if *self == Durability::Full {
+            if let Some(dir) = path.parent() {
+                fs::File::open(dir)?.sync_all()?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,118 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use axum::http::StatusCode;
+use config::Config;
+use uuid::Uuid;
+
+use crate::constants::{DEFAULT_IDEMPOTENCY_KEY_TTL_SECS, MAX_IDEMPOTENCY_KEY_LENGTH};
+
+enum KeyState {
+    // The upload with the key has not finished yet
+    InProgress,
+    Done(Uuid),
+}
+
+struct KeyEntry {
+    state: KeyState,
+    // SHA-256 of the uploaded file (hex), to detect keys reused for another file
+    checksum: String,
+    claimed_at: Instant,
+}
+
+/// Result of claiming an idempotency key
+pub enum Claim {
+    // The key is new, the upload has to be stored and `complete` (or `release`) called afterwards
+    New,
+    // The upload with the key was already stored as this image
+    Done(Uuid),
+}
+
+/// Remembers the `Idempotency-Key` headers of uploads, so clients retrying an upload (e.g. after
+/// a timeout) get the ID of the image stored by the first attempt instead of creating a duplicate.
+/// Keys are kept in memory until they expire, so they don't survive restarts.
+pub struct IdempotencyKeys {
+    ttl: Duration,
+    entries: Mutex<HashMap<String, KeyEntry>>,
+}
+
+/// Parses how long idempotency keys are remembered from the config property
+/// `IDEMPOTENCY_KEY_TTL_SECS`
+pub fn parse_idempotency_keys(config: &Config) -> IdempotencyKeys {
+    IdempotencyKeys {
+        ttl: Duration::from_secs(
+            config
+                .get::<u64>("IDEMPOTENCY_KEY_TTL_SECS")
+                .unwrap_or(DEFAULT_IDEMPOTENCY_KEY_TTL_SECS),
+        ),
+        entries: Mutex::new(HashMap::new()),
+    }
+}
+
+impl IdempotencyKeys {
+    /// Claims `key` for the upload of a file with `checksum`. Fails with 400 if the key is
+    /// invalid, 409 if an upload with the key is still in progress and 422 if the key was used
+    /// for another file.
+    pub fn claim(&self, key: &str, checksum: &str) -> Result<Claim, (StatusCode, String)> {
+        if key.is_empty()
+            || key.len() > MAX_IDEMPOTENCY_KEY_LENGTH
+            || !key.bytes().all(|b| b.is_ascii_graphic())
+        {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                format!(
+                    "Idempotency-Key must be 1 to {} visible ASCII characters!",
+                    MAX_IDEMPOTENCY_KEY_LENGTH
+                ),
+            ));
+        }
+
+        let mut entries = self.entries.lock().unwrap();
+        // Uploads in progress expire as well, in case they never finished
+        entries.retain(|_, entry| entry.claimed_at.elapsed() < self.ttl);
+
+        match entries.get(key) {
+            None => {
+                entries.insert(
+                    key.to_owned(),
+                    KeyEntry {
+                        state: KeyState::InProgress,
+                        checksum: checksum.to_owned(),
+                        claimed_at: Instant::now(),
+                    },
+                );
+                Ok(Claim::New)
+            }
+            Some(entry) if entry.checksum != checksum => Err((
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "Idempotency-Key was already used for another file!".to_owned(),
+            )),
+            Some(KeyEntry {
+                state: KeyState::InProgress,
+                ..
+            }) => Err((
+                StatusCode::CONFLICT,
+                "An upload with this Idempotency-Key is still in progress!".to_owned(),
+            )),
+            Some(KeyEntry {
+                state: KeyState::Done(uuid),
+                ..
+            }) => Ok(Claim::Done(*uuid)),
+        }
+    }
+
+    /// Records that the upload with the claimed `key` was stored as image `uuid`
+    pub fn complete(&self, key: &str, uuid: Uuid) {
+        if let Some(entry) = self.entries.lock().unwrap().get_mut(key) {
+            entry.state = KeyState::Done(uuid);
+        }
+    }
+
+    /// Releases the claimed `key` after the upload failed, so it can be retried with the same key
+    pub fn release(&self, key: &str) {
+        self.entries.lock().unwrap().remove(key);
+    }
+}
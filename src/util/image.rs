@@ -1,21 +1,42 @@
-use core::fmt;
 use std::{
-    fs::{read_dir, remove_file, rename},
+    collections::{HashMap, HashSet},
+    fs::{metadata, read_dir, remove_file, rename},
     io,
     path::{Path, PathBuf},
+    time::{Duration, SystemTime},
 };
 
 use axum::body::Bytes;
 use libvips::{
-    ops::{self, ForeignHeifCompression, HeifsaveOptions},
+    ops::{self, ForeignHeifCompression},
     VipsImage,
 };
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 use uuid::Uuid;
 
-use crate::util::path::get_raw_path;
+use crate::util::{
+    avif::AvifSettings,
+    durability::Durability,
+    image_metadata::ImageMetadata,
+    input_limits::InputLimits,
+    location::scrub_location,
+    metadata_index::{ImageTimes, MetadataIndex},
+    path::{
+        commit_temp_file, get_raw_path, get_temp_path, list_files, list_roots, write_atomically,
+        TempFile,
+    },
+    pipeline::Pipeline,
+    webp::WebpTuning,
+};
 use crate::{
-    constants::PENDING_QUALITY,
-    util::path::{get_cache_path, get_original_path, get_pending_path, get_unapproved_path},
+    constants::{
+        BLURHASH_COMPONENTS, BLURHASH_WIDTH, COMPARE_GAP, LQIP_BLUR_SIGMA, LQIP_QUALITY, LQIP_WIDTH,
+    },
+    error::Error,
+    util::path::{
+        get_cache_path, get_flagged_path, get_original_path, get_pending_path, get_unapproved_path,
+    },
 };
 
 #[allow(clippy::upper_case_acronyms)]
@@ -28,61 +49,432 @@ pub enum FileType {
     AVIF,
 }
 
-#[allow(dead_code)]
 pub struct FileIdentification {
-    file_type: FileType,
+    pub file_type: FileType,
     file_extension: &'static str,
+    content_type: &'static str,
     file_header: &'static [u8],
 }
 
-#[derive(Debug)]
-pub enum SaveError {
-    LibError(libvips::error::Error),
-    IOError(std::io::Error),
+impl FileIdentification {
+    /// Returns the name of this file type, e.g. `jpeg`
+    pub fn name(&self) -> String {
+        format!("{:?}", self.file_type).to_lowercase()
+    }
+
+    /// Returns the usual file extension of this file type, e.g. `jpg`
+    pub fn extension(&self) -> &'static str {
+        self.file_extension
+    }
+
+    /// Returns the MIME type of this file type, e.g. `image/jpeg`
+    pub fn content_type(&self) -> &'static str {
+        self.content_type
+    }
 }
 
-#[derive(PartialEq)]
+#[derive(Clone, Copy, PartialEq)]
 pub enum CacheBehavior {
     Normal,
     Skip,
+    // Render even if cached and overwrite the cache entry before returning, e.g. after an
+    // encoder bug produced artifacts
+    Refresh,
 }
 
-#[allow(dead_code)]
-#[derive(PartialEq)]
-pub enum ImageSearchBehaviour {
-    All,
-    // Valid means, that the image is properly attached to a review, be it unapproved or not
-    Valid,
+#[derive(Clone, Copy, PartialEq)]
+pub enum RemovalBehavior {
+    Delete,
+    // Only log (and report) what would be removed, without touching the filesystem
+    DryRun,
+}
+
+/// The states an image passes through, each stored in its own directory
+#[derive(
+    Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize, Serialize, ToSchema, async_graphql::Enum,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum ImageState {
+    // Uploaded, but review not yet submitted
+    Pending,
+    // Submitted, but not yet approved
+    Unapproved,
+    // Submitted, but flagged by the moderation hook, so held for a closer review. Approved like
+    // unapproved images.
+    Flagged,
+    // Approved and served publicly, stored in the "originals" directory
+    #[serde(alias = "originals")]
+    Approved,
+}
+
+impl ImageState {
+    // Ordered from least to most advanced state
+    pub const ALL: [ImageState; 4] = [
+        ImageState::Pending,
+        ImageState::Unapproved,
+        ImageState::Flagged,
+        ImageState::Approved,
+    ];
+
+    /// Returns the directory images of this state are stored in, in the root of `tenant`
+    pub fn path(&self, tenant: Option<&str>) -> PathBuf {
+        match self {
+            ImageState::Pending => get_pending_path(tenant),
+            ImageState::Unapproved => get_unapproved_path(tenant),
+            ImageState::Flagged => get_flagged_path(tenant),
+            ImageState::Approved => get_original_path(tenant),
+        }
+    }
+}
+
+/// Returns the state of the image with `uuid` of `tenant` and its path, if it exists in any
+/// state. If it exists in multiple states, the most advanced one is returned.
+pub fn find_image(uuid: Uuid, tenant: Option<&str>) -> Option<(ImageState, PathBuf)> {
+    ImageState::ALL.iter().rev().find_map(|state| {
+        determine_img_path(state.path(tenant).to_str().unwrap(), uuid)
+            .ok()
+            .map(|path| (*state, path))
+    })
+}
+
+/// Returns the raw file of the image with `uuid` of `tenant`
+pub fn get_raw_file(uuid: Uuid, tenant: Option<&str>) -> PathBuf {
+    get_raw_path(tenant).join(format!("{}.raw", uuid))
+}
+
+/// Format renditions are encoded in
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    Default,
+    PartialEq,
+    Eq,
+    Deserialize,
+    Serialize,
+    ToSchema,
+    async_graphql::Enum,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputFormat {
+    #[default]
+    Webp,
+    Avif,
+    Jpeg,
+    Png,
+}
+
+impl OutputFormat {
+    pub const ALL: [OutputFormat; 4] = [
+        OutputFormat::Webp,
+        OutputFormat::Avif,
+        OutputFormat::Jpeg,
+        OutputFormat::Png,
+    ];
+
+    /// File extension of cache entries in this format
+    pub fn extension(&self) -> &'static str {
+        match self {
+            OutputFormat::Webp => "webp",
+            OutputFormat::Avif => "avif",
+            OutputFormat::Jpeg => "jpg",
+            OutputFormat::Png => "png",
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            OutputFormat::Webp => "image/webp",
+            OutputFormat::Avif => "image/avif",
+            OutputFormat::Jpeg => "image/jpeg",
+            OutputFormat::Png => "image/png",
+        }
+    }
 }
 
-impl fmt::Display for SaveError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+/// Color profile of renditions
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    Default,
+    PartialEq,
+    Eq,
+    Deserialize,
+    Serialize,
+    ToSchema,
+    async_graphql::Enum,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum ColorProfile {
+    // Converted to sRGB, which squashes wide-gamut colors
+    #[default]
+    Srgb,
+    // Not converted, keeping the embedded ICC profile
+    Preserve,
+    // Converted to Display P3
+    DisplayP3,
+}
+
+impl ColorProfile {
+    pub const ALL: [ColorProfile; 3] = [
+        ColorProfile::Srgb,
+        ColorProfile::Preserve,
+        ColorProfile::DisplayP3,
+    ];
+
+    pub fn name(&self) -> &'static str {
         match self {
-            Self::LibError(err) => err.fmt(f),
-            Self::IOError(err) => err.fmt(f),
+            ColorProfile::Srgb => "srgb",
+            ColorProfile::Preserve => "preserve",
+            ColorProfile::DisplayP3 => "display_p3",
+        }
+    }
+
+    /// Marks cache entries with this profile, empty for the default
+    fn cache_flag(&self) -> &'static str {
+        match self {
+            ColorProfile::Srgb => "",
+            ColorProfile::Preserve => "o",
+            ColorProfile::DisplayP3 => "d",
+        }
+    }
+
+    /// Profile libvips converts renditions to, unless the embedded one is preserved
+    fn export_profile(&self) -> &'static str {
+        match self {
+            ColorProfile::DisplayP3 => "p3",
+            _ => "sRGB",
+        }
+    }
+}
+
+/// How `manipulate_image` brings an image to the requested dimensions
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ResizeMode {
+    // Fits the image into them (only the width or nothing was requested)
+    Fit,
+    // Scales the image to the height (only the height was requested). Thumbnails are always fit
+    // to the width, so this is done with `ops::resize`.
+    Height,
+    // Cuts out the most interesting part of this size (both were requested)
+    Crop,
+}
+
+/// Returns the dimensions of a rendition of an image with `img_dim` and how it is resized to
+/// them, if only `width`, only `height`, both or none of them were requested
+pub fn resize_dimensions(
+    width: Option<i32>,
+    height: Option<i32>,
+    img_dim: (i32, i32),
+) -> (i32, i32, ResizeMode) {
+    match (width, height) {
+        (Some(width), Some(height)) => (width, height, ResizeMode::Crop),
+        // The width is derived from the aspect ratio, so the cache entry is named after the actual dimensions
+        (None, Some(height)) => {
+            let height = height.min(img_dim.1);
+            let width = (img_dim.0 as f64 * height as f64 / img_dim.1 as f64).round() as i32;
+            (width.max(1), height, ResizeMode::Height)
         }
+        (width, None) => (width.unwrap_or(img_dim.0), img_dim.1, ResizeMode::Fit),
     }
 }
 
+/// How a rendition is encoded
+#[derive(Clone, Copy, Debug)]
+pub struct Encoding {
+    pub format: OutputFormat,
+    // Ignored for the lossless PNG
+    pub quality: i32,
+    // Interlaced, so the image is displayed progressively while loading. Only supported by
+    // JPEG and PNG, as WebP and AVIF have no interlaced mode.
+    pub progressive: bool,
+    pub color_profile: ColorProfile,
+    // Ignored for the other formats
+    pub webp: WebpTuning,
+}
+
+impl Encoding {
+    /// Returns whether the rendition is actually interlaced, i.e. progressive encoding is
+    /// requested and supported by the format
+    pub fn is_progressive(&self) -> bool {
+        self.progressive && matches!(self.format, OutputFormat::Jpeg | OutputFormat::Png)
+    }
+
+    pub fn encode(&self, image: &VipsImage) -> Result<Vec<u8>, libvips::error::Error> {
+        match self.format {
+            OutputFormat::Webp => ops::webpsave_buffer_with_opts(
+                image,
+                &ops::WebpsaveBufferOptions {
+                    q: self.quality,
+                    effort: self.webp.effort,
+                    smart_subsample: self.webp.smart_subsample,
+                    // Near-lossless is a preprocessing of lossless encoding
+                    lossless: self.webp.near_lossless,
+                    near_lossless: self.webp.near_lossless,
+                    alpha_q: self.webp.alpha_quality,
+                    ..ops::WebpsaveBufferOptions::default()
+                },
+            ),
+            OutputFormat::Avif => ops::heifsave_buffer_with_opts(
+                image,
+                &ops::HeifsaveBufferOptions {
+                    q: self.quality,
+                    compression: ForeignHeifCompression::Av1,
+                    effort: 0,
+                    ..ops::HeifsaveBufferOptions::default()
+                },
+            ),
+            OutputFormat::Jpeg => ops::jpegsave_buffer_with_opts(
+                image,
+                &ops::JpegsaveBufferOptions {
+                    q: self.quality,
+                    interlace: self.progressive,
+                    ..ops::JpegsaveBufferOptions::default()
+                },
+            ),
+            OutputFormat::Png => ops::pngsave_buffer_with_opts(
+                image,
+                &ops::PngsaveBufferOptions {
+                    interlace: self.progressive,
+                    ..ops::PngsaveBufferOptions::default()
+                },
+            ),
+        }
+    }
+}
+
+/// Checks requested parameters of a rendition before they are passed to vips: dimensions must be
+/// positive and the quality between 1 and 100. Upper bounds of dimensions are `OutputLimits`.
+pub fn validate_rendition(
+    width: Option<i32>,
+    height: Option<i32>,
+    quality: Option<i32>,
+) -> Result<(), String> {
+    for (name, value) in [("width", width), ("height", height)] {
+        if value.is_some_and(|value| value <= 0) {
+            return Err(format!("{} must be positive", name));
+        }
+    }
+    if quality.is_some_and(|quality| !(1..=100).contains(&quality)) {
+        return Err("quality must be between 1 and 100".to_owned());
+    }
+    Ok(())
+}
+
+/// Dimensions and quality of a cached rendition of an image
+#[derive(Clone, Serialize, ToSchema, async_graphql::SimpleObject)]
+pub struct CacheVariant {
+    pub width: i32,
+    pub height: i32,
+    pub quality: i32,
+    /// Key of the transformation pipeline (`ops`) the rendition was created with, if any
+    pub pipeline: Option<String>,
+    pub format: OutputFormat,
+    /// Whether the rendition is interlaced
+    pub progressive: bool,
+    pub color_profile: ColorProfile,
+}
+
+/// Parses a cache entry file name as created by `get_cache_entry`
+pub fn parse_cache_entry(name: &str) -> Option<(Uuid, CacheVariant)> {
+    // '<uuid>-<width>x<height>-<quality>[w<tuning>][p][o|d][-<pipeline key>].<extension>',
+    // where 'w' marks WebP renditions with a tuning (see `WebpTuning::cache_flag`), 'p'
+    // progressive renditions and 'o'/'d' the color profile
+    let uuid = Uuid::parse_str(name.get(..36)?).ok()?;
+    let (rest, extension) = name.get(37..)?.rsplit_once('.')?;
+    let format = OutputFormat::ALL
+        .into_iter()
+        .find(|format| format.extension() == extension)?;
+    let (dimensions, rest) = rest.split_once('-')?;
+    let (width, height) = dimensions.split_once('x')?;
+    let (quality, pipeline) = match rest.split_once('-') {
+        None => (rest, None),
+        Some((quality, pipeline)) => (quality, Some(pipeline.to_owned())),
+    };
+    let (quality, color_profile) = ColorProfile::ALL.into_iter().rev().find_map(|profile| {
+        quality
+            .strip_suffix(profile.cache_flag())
+            .map(|quality| (quality, profile))
+    })?;
+    let (quality, progressive) = match quality.strip_suffix('p') {
+        None => (quality, false),
+        Some(quality) => (quality, true),
+    };
+    // The tuning is not part of the variant
+    let quality = quality
+        .split_once('w')
+        .map_or(quality, |(quality, _)| quality);
+    Some((
+        uuid,
+        CacheVariant {
+            width: width.parse().ok()?,
+            height: height.parse().ok()?,
+            quality: quality.parse().ok()?,
+            pipeline: pipeline,
+            format: format,
+            progressive: progressive,
+            color_profile: color_profile,
+        },
+    ))
+}
+
+/// Lists the cached renditions of all images of all tenants
+pub fn list_cache_variants() -> Result<HashMap<Uuid, Vec<CacheVariant>>, io::Error> {
+    let mut variants: HashMap<Uuid, Vec<CacheVariant>> = HashMap::new();
+    for tenant in list_roots() {
+        for name in list_files(&get_cache_path(tenant.as_deref()))? {
+            if let Some((uuid, variant)) = parse_cache_entry(&name) {
+                variants.entry(uuid).or_default().push(variant);
+            }
+        }
+    }
+    Ok(variants)
+}
+
+/// An image found in one of the state directories
+pub struct StoredImage {
+    pub uuid: Uuid,
+    pub state: ImageState,
+    // Time of the upload
+    pub created_at: SystemTime,
+    // Time the image entered its current state
+    pub state_changed_at: SystemTime,
+    pub metadata: ImageMetadata,
+    // Name of the tenant the image belongs to, if any
+    pub tenant: Option<String>,
+}
+
+#[derive(PartialEq)]
+pub enum ImageSearchBehaviour {
+    All,
+    // Valid means, that the image is properly attached to a review, be it unapproved or not
+    Valid,
+}
+
 const FILE_MAPPINGS: [FileIdentification; 5] = [
     FileIdentification {
         file_type: FileType::JPEG,
         file_extension: "jpg",
+        content_type: "image/jpeg",
         file_header: &[0xff, 0xd8, 0xff],
     },
     FileIdentification {
         file_type: FileType::PNG,
         file_extension: "png",
+        content_type: "image/png",
         file_header: &[0x89, 0x50, 0x4e, 0x47, 0x0d, 0x0a, 0x1a, 0x0a],
     },
     FileIdentification {
         file_type: FileType::WEBP,
         file_extension: "webp",
+        content_type: "image/webp",
         file_header: &[0x52, 0x49, 0x46, 0x46],
     },
     FileIdentification {
         file_type: FileType::HEIF,
         file_extension: "heic",
+        content_type: "image/heic",
         file_header: &[
             0x00, 0x00, 0x00, 0x18, 0x66, 0x74, 0x79, 0x70, 0x68, 0x65, 0x69, 0x63,
         ],
@@ -90,6 +482,7 @@ const FILE_MAPPINGS: [FileIdentification; 5] = [
     FileIdentification {
         file_type: FileType::AVIF,
         file_extension: "avif",
+        content_type: "image/avif",
         file_header: &[
             0x00, 0x00, 0x00, 0x1c, 0x66, 0x74, 0x79, 0x70, 0x61, 0x76, 0x69, 0x66,
         ],
@@ -102,78 +495,137 @@ pub fn determine_file_type(image: &Bytes) -> Option<&FileIdentification> {
         .find(|&mapping| image.starts_with(mapping.file_header))
 }
 
-pub fn save_raw(data: &Bytes, uuid: Uuid) -> Result<(), SaveError> {
-    let path = get_raw_path().join(format!("{}.raw", uuid));
-    let path_str = match path.to_str() {
-        Some(str) => str,
-        None => {
-            return Err(SaveError::IOError(io::Error::new(
-                io::ErrorKind::InvalidData,
-                "Could not determine path string",
-            )));
-        }
-    };
+/// Saves the uploaded `data` unmodified (atomically, see `commit_temp_file`), except for location
+/// metadata, which is removed, see `scrub_location`
+pub fn save_raw(
+    data: &Bytes,
+    uuid: Uuid,
+    tenant: Option<&str>,
+    durability: Durability,
+) -> Result<(), Error> {
+    let path = get_raw_file(uuid, tenant);
+
+    let mut data = data.to_vec();
+    let removed = scrub_location(&mut data);
+    if !removed.is_empty() {
+        log::info!(
+            "Removed location metadata from raw image {}: {}",
+            uuid,
+            removed.join(", ")
+        );
+    }
 
-    log::info!("Saving raw image to {}", path_str);
-    std::fs::write(path_str, data.as_ref()).map_err(SaveError::IOError)
+    log::info!("Saving raw image to {:?}", path);
+    std::fs::write(get_temp_path(&path), &data)?;
+    commit_temp_file(&path, durability)?;
+    Ok(())
 }
 
-pub fn save_pending(data: &Bytes, uuid: Uuid, angle: f64) -> Result<(), SaveError> {
-    let path = get_pending_path().join(format!("{}.avif", uuid));
-    let path_str = match path.to_str() {
-        Some(str) => str,
-        None => {
-            return Err(SaveError::IOError(io::Error::new(
-                io::ErrorKind::InvalidData,
-                "Could not determine path string",
-            )));
-        }
+/// Checks the header of the uploaded `data` against `input_limits` (see `check_header`), before
+/// anything of it is stored or decoded
+pub fn check_upload_header(data: &Bytes, input_limits: &InputLimits) -> Result<(), Error> {
+    // Files whose header can't be read are quarantined when they are decoded
+    let Ok(image) = VipsImage::new_from_buffer(data, "") else {
+        return Ok(());
     };
-    log::info!("Saving pending image to {}", path_str);
+    input_limits
+        .check_header(&image)
+        .map_err(Error::Unprocessable)?;
+    Ok(())
+}
+
+/// Encodes the upload `data` as pending image `uuid`, see `encode_upload`
+pub fn encode_pending(
+    data: &Bytes,
+    uuid: Uuid,
+    tenant: Option<&str>,
+    angle: f64,
+    avif: &AvifSettings,
+    input_limits: &InputLimits,
+) -> Result<TempFile, Error> {
+    let path = get_pending_path(tenant).join(format!("{}.avif", uuid));
+    log::info!("Encoding pending image {:?}", path);
+    encode_upload(data, &path, angle, avif, input_limits)
+}
+
+/// Decodes the uploaded `data`, rotates it by `angle` degrees and encodes it as AVIF into a
+/// temporary file of `path`, like uploads are saved as pending images (or regenerated from their
+/// raw file). It is only moved to `path` once it is committed.
+/// Only the first frame of animated images is saved. Images exceeding `input_limits` are
+/// rejected before they are decoded, see `check_header`.
+pub fn encode_upload(
+    data: &Bytes,
+    path: &Path,
+    angle: f64,
+    avif: &AvifSettings,
+    input_limits: &InputLimits,
+) -> Result<TempFile, Error> {
+    let path_str = path
+        .to_str()
+        .ok_or_else(|| Error::Internal(format!("Could not determine path string of {:?}", path)))?;
 
     let image = match VipsImage::new_from_buffer(data, "") {
         Err(err) => {
             log::error!("Error while reading image from buffer: {}", err);
-            return Err(SaveError::LibError(err));
+            return Err(Error::Vips(err));
         }
         Ok(img) => img,
     };
 
+    // Only the header has been read so far
+    input_limits
+        .check_header(&image)
+        .map_err(Error::Unprocessable)?;
+
     let rotated = match ops::rotate(&image, angle) {
         Err(err) => {
             log::error!("Error while rotating '{}': {}", path_str, err);
-            return Err(SaveError::LibError(err));
+            return Err(Error::Vips(err));
         }
         Ok(img) => img,
     };
 
-    save_image(&rotated, path_str, PENDING_QUALITY)?;
+    encode_image(&rotated, path, avif)
+}
 
-    Ok(())
+/// Rotates the stored image at `path` by `angle` degrees and encodes it as AVIF with `avif` into
+/// a temporary file of `path`, which replaces the image once it is committed
+pub fn encode_rotated(path: &Path, angle: f64, avif: &AvifSettings) -> Result<TempFile, Error> {
+    let path_str = path
+        .to_str()
+        .ok_or_else(|| Error::Internal(format!("Could not determine path string of {:?}", path)))?;
+    let image = VipsImage::new_from_file(path_str)?;
+    let rotated = ops::rotate(&image, angle)?;
+    encode_image(&rotated, path, avif)
 }
 
-pub fn save_image(image: &VipsImage, path_str: &str, quality: i32) -> Result<(), SaveError> {
-    let heifsave_options = HeifsaveOptions {
-        q: quality,
-        compression: ForeignHeifCompression::Av1,
-        effort: 0,
-        ..Default::default()
-    };
+/// Encodes `image` as AVIF with `avif` into a temporary file of `path`
+fn encode_image(image: &VipsImage, path: &Path, avif: &AvifSettings) -> Result<TempFile, Error> {
+    let tmp_file = TempFile::new(path);
+    heifsave(image, tmp_file.path().to_str().unwrap(), avif)?;
+    Ok(tmp_file)
+}
+
+fn heifsave(image: &VipsImage, path_str: &str, avif: &AvifSettings) -> Result<(), Error> {
+    let heifsave_options = avif.heifsave_options();
 
     match ops::heifsave_with_opts(image, path_str, &heifsave_options) {
-        Err(err) => {
-            log::error!("Error while saving '{}': {}", path_str, err);
-            // TODO: heifsave lib has changed and returns "error" on success
-            // See:
-            //  - https://github.com/libvips/libvips/issues/3718#issuecomment-1771494570
-            //  - https://github.com/libvips/libvips/pull/3724
-            //  - https://github.com/olxgroup-oss/libvips-rust-bindings/pull/35
-            // return Err(SaveError::LibError(err));
+        Ok(_) => Ok(()),
+        // Some libvips versions return an error even though the image was saved, see
+        //  - https://github.com/libvips/libvips/issues/3718#issuecomment-1771494570
+        //  - https://github.com/libvips/libvips/pull/3724
+        //  - https://github.com/olxgroup-oss/libvips-rust-bindings/pull/35
+        // So it is only a warning, if the file was written
+        Err(err) if metadata(path_str).is_ok_and(|metadata| metadata.len() > 0) => {
+            log::warn!(
+                "heifsave reported an error, but saved '{}': {}",
+                path_str,
+                err
+            );
+            Ok(())
         }
-        Ok(_) => log::info!("Saved '{}'", path_str),
+        Err(err) => Err(Error::Vips(err)),
     }
-
-    Ok(())
 }
 
 pub fn determine_img_dim(path: &str) -> Result<(i32, i32), libvips::error::Error> {
@@ -186,6 +638,12 @@ pub fn determine_img_dim(path: &str) -> Result<(i32, i32), libvips::error::Error
     }
 }
 
+/// Returns the dimensions of the encoded image `buffer`, e.g. a rendition, reading only its header
+pub fn determine_buffer_dim(buffer: &[u8]) -> Result<(i32, i32), libvips::error::Error> {
+    let img = VipsImage::new_from_buffer(buffer, "")?;
+    Ok((img.get_width(), img.get_height()))
+}
+
 pub fn determine_img_path(folder: &str, uuid: Uuid) -> Result<PathBuf, io::Error> {
     let buf = PathBuf::from(folder).join(format!("{}.avif", uuid));
     if buf.exists() {
@@ -198,18 +656,122 @@ pub fn determine_img_path(folder: &str, uuid: Uuid) -> Result<PathBuf, io::Error
     ))
 }
 
+/// Lists all images in the directories of `states` of all tenants, with their times from
+/// `metadata_index`. Files that are not named like images are ignored. Images stranded in
+/// multiple states (e.g. by a crash while moving them) are listed once, in the most advanced
+/// state like `find_image`.
+pub fn list_images(
+    states: &[ImageState],
+    metadata_index: &MetadataIndex,
+) -> Result<Vec<StoredImage>, io::Error> {
+    let mut images = Vec::new();
+    for tenant in list_roots() {
+        images.extend(list_tenant_images(
+            states,
+            tenant.as_deref(),
+            metadata_index,
+        )?);
+    }
+    Ok(images)
+}
+
+/// Lists the images of `tenant` like `list_images`
+pub fn list_tenant_images(
+    states: &[ImageState],
+    tenant: Option<&str>,
+    metadata_index: &MetadataIndex,
+) -> Result<Vec<StoredImage>, io::Error> {
+    let mut images = Vec::new();
+    let mut listed = HashSet::new();
+    // Most advanced states first, so they take precedence
+    let states = ImageState::ALL
+        .iter()
+        .rev()
+        .filter(|state| states.contains(state));
+    for state in states {
+        let state_path = state.path(tenant);
+        for name in list_files(&state_path)? {
+            let Some(uuid) = name
+                .strip_suffix(".avif")
+                .and_then(|stem| Uuid::parse_str(stem).ok())
+            else {
+                continue;
+            };
+            if !listed.insert(uuid) {
+                continue;
+            }
+
+            images.push(stored_image(
+                uuid,
+                *state,
+                tenant,
+                &state_path.join(&name),
+                metadata_index,
+            )?);
+        }
+    }
+    Ok(images)
+}
+
+/// Returns the image with `uuid`, if it exists in any state
+pub fn find_stored_image(uuid: Uuid, metadata_index: &MetadataIndex) -> Option<StoredImage> {
+    let tenant = metadata_index.tenant(uuid);
+    let (state, path) = find_image(uuid, tenant.as_deref())?;
+    stored_image(uuid, state, tenant.as_deref(), &path, metadata_index).ok()
+}
+
+/// Takes the times and metadata of the image from `metadata_index`, its tenant from the root it
+/// is stored in.
+/// Images that are not indexed (yet) fall back to the modification time of their raw file
+/// (or of the image, if there is no raw file) for both times.
+fn stored_image(
+    uuid: Uuid,
+    state: ImageState,
+    tenant: Option<&str>,
+    image_path: &Path,
+    metadata_index: &MetadataIndex,
+) -> Result<StoredImage, io::Error> {
+    let times = match metadata_index.get(uuid) {
+        Some(times) => times,
+        None => {
+            let upload_time = metadata(get_raw_file(uuid, tenant))
+                .or_else(|_| metadata(image_path))
+                .and_then(|metadata| metadata.modified())?;
+            ImageTimes {
+                created_at: upload_time,
+                state_changed_at: upload_time,
+            }
+        }
+    };
+
+    Ok(StoredImage {
+        uuid: uuid,
+        state: state,
+        created_at: times.created_at,
+        state_changed_at: times.state_changed_at,
+        metadata: metadata_index.metadata(uuid),
+        tenant: tenant.map(str::to_owned),
+    })
+}
+
 pub fn determine_img_dir(
     uuid: Uuid,
+    tenant: Option<&str>,
     search_behaviour: ImageSearchBehaviour,
 ) -> Result<PathBuf, io::Error> {
     // Search unapproved
-    if determine_img_path(get_unapproved_path().to_str().unwrap(), uuid).is_ok() {
-        return Ok(get_unapproved_path());
+    if determine_img_path(get_unapproved_path(tenant).to_str().unwrap(), uuid).is_ok() {
+        return Ok(get_unapproved_path(tenant));
+    }
+
+    // Search flagged
+    if determine_img_path(get_flagged_path(tenant).to_str().unwrap(), uuid).is_ok() {
+        return Ok(get_flagged_path(tenant));
     }
 
     // Search original
-    if determine_img_path(get_original_path().to_str().unwrap(), uuid).is_ok() {
-        return Ok(get_original_path());
+    if determine_img_path(get_original_path(tenant).to_str().unwrap(), uuid).is_ok() {
+        return Ok(get_original_path(tenant));
     }
 
     if search_behaviour == ImageSearchBehaviour::Valid {
@@ -220,8 +782,8 @@ pub fn determine_img_dir(
     }
 
     // Search pending
-    if determine_img_path(get_pending_path().to_str().unwrap(), uuid).is_ok() {
-        return Ok(get_pending_path());
+    if determine_img_path(get_pending_path(tenant).to_str().unwrap(), uuid).is_ok() {
+        return Ok(get_pending_path(tenant));
     }
 
     Err(io::Error::new(
@@ -230,86 +792,323 @@ pub fn determine_img_dir(
     ))
 }
 
+/// Applies `pipeline` to the image `content`, resizes the result to `width` x
+/// `height` according to `mode` and encodes it with `encoding`. Input limits are not checked, as
+/// they were checked when the image was uploaded.
 pub fn manipulate_image(
-    path: &str,
+    content: &[u8],
     height: i32,
     width: i32,
-    quality: i32,
-    cache_behavior: CacheBehavior,
-) -> Result<Vec<u8>, libvips::error::Error> {
+    mode: ResizeMode,
+    pipeline: &Pipeline,
+    encoding: Encoding,
+) -> Result<Vec<u8>, Error> {
     let mut thumb_opts = ops::ThumbnailImageOptions {
         // See https://github.com/olxgroup-oss/libvips-rust-bindings/issues/42
         height: height,
         import_profile: "sRGB".into(),
-        export_profile: "sRGB".into(),
+        export_profile: encoding.color_profile.export_profile().into(),
         size: ops::Size::Down,
         ..ops::ThumbnailImageOptions::default()
     };
-
-    let orig_image = VipsImage::new_from_file(path)?;
-
-    // When a height was specified in the request (then it was not replaced by the original height)
-    // TODO: Don't hack around it like this, but instead pass in the proper arguments
-    if height != orig_image.get_height() {
+    if mode == ResizeMode::Crop {
         thumb_opts.crop = ops::Interesting::Attention;
     }
 
-    let image = match ops::thumbnail_image_with_opts(&orig_image, width, &thumb_opts) {
-        Err(err) => {
-            log::error!("{}", err);
-            return Err(err);
+    let orig_image = VipsImage::new_from_buffer(content, "")?;
+    let orig_image = pipeline.apply(orig_image)?;
+
+    let image = match (mode, encoding.color_profile) {
+        (ResizeMode::Height, color_profile) => scale_to_height(orig_image, height)
+            .and_then(|image| convert_color_profile(image, color_profile)),
+        // Thumbnails always convert to the export profile
+        (_, ColorProfile::Preserve) => {
+            resize_image(orig_image, width, height, mode == ResizeMode::Crop)
         }
-        Ok(img) => img,
+        _ => ops::thumbnail_image_with_opts(&orig_image, width, &thumb_opts),
     };
+    let image = image?;
+    Ok(encoding.encode(&image)?)
+}
 
-    let webpsave_buffer_options = ops::WebpsaveBufferOptions {
-        q: quality,
-        ..ops::WebpsaveBufferOptions::default()
-    };
-    let buffer: Vec<u8> = match ops::webpsave_buffer_with_opts(&image, &webpsave_buffer_options) {
-        Err(err) => {
-            log::error!("{}", err);
-            return Err(err);
+/// Writes the rendition `buffer` to `cache_entry` according to `cache_behavior`, without
+/// delaying the response unless it is refreshed
+pub fn cache_rendition(
+    cache_entry: PathBuf,
+    buffer: &[u8],
+    cache_behavior: CacheBehavior,
+) -> Result<(), Error> {
+    match cache_behavior {
+        CacheBehavior::Normal => write_cache_entry_in_background(cache_entry, buffer.to_vec()),
+        CacheBehavior::Refresh => write_atomically(&cache_entry, buffer, Durability::None)
+            .map_err(|err| {
+                Error::Internal(format!(
+                    "Could not write cache entry {:?}: {}",
+                    cache_entry, err
+                ))
+            })?,
+        CacheBehavior::Skip => (),
+    }
+    Ok(())
+}
+
+/// Writes the encoded rendition `buffer` to `cache_entry` on a blocking thread. It is written to
+/// a temporary file first (see `write_atomically`), so a crash can't leave a corrupt cache entry
+/// and concurrent requests either find the whole entry or none. The rendition is returned anyway,
+/// it is just not cached if this fails.
+fn write_cache_entry_in_background(cache_entry: PathBuf, buffer: Vec<u8>) {
+    tokio::task::spawn_blocking(move || {
+        if let Err(err) = write_atomically(&cache_entry, &buffer, Durability::None) {
+            log::error!("Could not write cache entry {:?}: {}", cache_entry, err);
         }
-        Ok(vec) => vec,
+    });
+}
+
+/// Scales `image` down to `height`, keeping its aspect ratio
+fn scale_to_height(image: VipsImage, height: i32) -> Result<VipsImage, libvips::error::Error> {
+    let scale = height as f64 / image.get_height() as f64;
+    match scale < 1.0 {
+        true => ops::resize(&image, scale),
+        false => Ok(image),
+    }
+}
+
+/// Converts `image` to the export profile of `color_profile` like thumbnails do, treating images
+/// without an embedded profile as sRGB
+fn convert_color_profile(
+    image: VipsImage,
+    color_profile: ColorProfile,
+) -> Result<VipsImage, libvips::error::Error> {
+    if color_profile == ColorProfile::Preserve {
+        return Ok(image);
+    }
+    ops::icc_transform_with_opts(
+        &image,
+        color_profile.export_profile(),
+        &ops::IccTransformOptions {
+            embedded: true,
+            input_profile: "sRGB".into(),
+            ..ops::IccTransformOptions::default()
+        },
+    )
+}
+
+/// Resizes `image` to fit into `width` x `height` without upscaling, like a thumbnail without color
+/// management. With `crop`, the most interesting area of the requested size is cut out instead.
+fn resize_image(
+    image: VipsImage,
+    width: i32,
+    height: i32,
+    crop: bool,
+) -> Result<VipsImage, libvips::error::Error> {
+    let scale_x = width as f64 / image.get_width() as f64;
+    let scale_y = height as f64 / image.get_height() as f64;
+    let scale = match crop {
+        false => scale_x.min(scale_y),
+        true => scale_x.max(scale_y),
     };
+    let image = match scale < 1.0 {
+        true => ops::resize(&image, scale)?,
+        false => image,
+    };
+    if !crop {
+        return Ok(image);
+    }
 
-    // Write image to cache if desired
-    if cache_behavior == CacheBehavior::Normal {
-        let cache_entry = get_cache_entry(
-            PathBuf::from(path).file_stem().unwrap().to_str().unwrap(),
+    let (crop_width, crop_height) = (width.min(image.get_width()), height.min(image.get_height()));
+    ops::smartcrop_with_opts(
+        &image,
+        crop_width,
+        crop_height,
+        &ops::SmartcropOptions {
+            interesting: ops::Interesting::Attention,
+            ..ops::SmartcropOptions::default()
+        },
+    )
+}
+
+/// Returns the cache entry of a rendition of the image with `uuid` of `tenant`
+pub fn get_cache_entry(
+    uuid: &str,
+    tenant: Option<&str>,
+    height: i32,
+    width: i32,
+    pipeline: &Pipeline,
+    encoding: Encoding,
+) -> PathBuf {
+    let webp_flag = match encoding.format {
+        OutputFormat::Webp => encoding.webp.cache_flag(),
+        _ => String::new(),
+    };
+    let quality = format!(
+        "{}{}{}{}",
+        encoding.quality,
+        webp_flag,
+        if encoding.is_progressive() { "p" } else { "" },
+        encoding.color_profile.cache_flag()
+    );
+    match pipeline.cache_key() {
+        None => get_cache_path(tenant).join(format!(
+            "{}-{}x{}-{}.{}",
+            uuid,
+            width,
             height,
+            quality,
+            encoding.format.extension()
+        )),
+        Some(key) => get_cache_path(tenant).join(format!(
+            "{}-{}x{}-{}-{}.{}",
+            uuid,
             width,
+            height,
             quality,
-        );
+            key,
+            encoding.format.extension()
+        )),
+    }
+}
 
-        let opts = ops::WebpsaveOptions {
-            q: quality,
-            ..ops::WebpsaveOptions::default()
-        };
-        match ops::webpsave_with_opts(&image, cache_entry.to_str().unwrap(), &opts) {
-            Err(err) => {
-                log::error!("{}", err);
-                return Err(err);
+/// Returns whether the rendition is cached and, with a `ttl`, was written within it
+pub fn check_cache(
+    uuid: Uuid,
+    tenant: Option<&str>,
+    height: i32,
+    width: i32,
+    pipeline: &Pipeline,
+    encoding: Encoding,
+    ttl: Option<Duration>,
+) -> bool {
+    let cache_entry = get_cache_entry(&uuid.to_string(), tenant, height, width, pipeline, encoding);
+    let Some(ttl) = ttl else {
+        return cache_entry.exists();
+    };
+    match metadata(&cache_entry).and_then(|metadata| metadata.modified()) {
+        Err(_) => false,
+        Ok(modified) => {
+            // Entries written in the future (according to the clock) are fresh
+            let stale = modified.elapsed().is_ok_and(|age| age >= ttl);
+            if stale {
+                log::debug!("Cache entry {:?} is stale, rendering it again", cache_entry);
             }
-            Ok(img) => img,
-        };
+            !stale
+        }
+    }
+}
+
+/// Returns the cache entry of the low-quality image placeholder of the image with `uuid` of
+/// `tenant`. As it is removed together with the other cache entries, it is regenerated after
+/// rotations.
+pub fn get_lqip_entry(uuid: Uuid, tenant: Option<&str>) -> PathBuf {
+    get_cache_path(tenant).join(format!("{}-lqip.webp", uuid))
+}
+
+/// Creates the low-quality image placeholder of the image at `path`, a tiny, heavily compressed
+/// WebP, blurred if `blur` is set. It is written to the cache by `create_lqip_entry`.
+pub fn create_lqip(path: &str, blur: bool) -> Result<Vec<u8>, Error> {
+    let orig_image = VipsImage::new_from_file(path)?;
+    let thumb_opts = ops::ThumbnailImageOptions {
+        // The height has to be set, see `manipulate_image`
+        height: (orig_image.get_height() * LQIP_WIDTH / orig_image.get_width()).max(1),
+        import_profile: "sRGB".into(),
+        export_profile: "sRGB".into(),
+        size: ops::Size::Down,
+        ..ops::ThumbnailImageOptions::default()
+    };
+    let mut image = ops::thumbnail_image_with_opts(&orig_image, LQIP_WIDTH, &thumb_opts)?;
+    if blur {
+        image = ops::gaussblur(&image, LQIP_BLUR_SIGMA)?;
     }
 
-    Ok(buffer)
+    let encoding = Encoding {
+        format: OutputFormat::Webp,
+        quality: LQIP_QUALITY,
+        progressive: false,
+        color_profile: ColorProfile::Srgb,
+        webp: WebpTuning::default(),
+    };
+    Ok(encoding.encode(&image)?)
 }
 
-pub fn get_cache_entry(uuid: &str, height: i32, width: i32, quality: i32) -> PathBuf {
-    get_cache_path().join(format!("{}-{}x{}-{}.webp", uuid, width, height, quality))
+/// Returns the BlurHash of the image at `path`, a placeholder of a few bytes that clients decode
+/// themselves, computed from a tiny sRGB thumbnail of it
+pub fn create_blurhash(path: &str) -> Result<String, Error> {
+    let orig_image = VipsImage::new_from_file(path)?;
+    let thumb_opts = ops::ThumbnailImageOptions {
+        // The height has to be set, see `manipulate_image`
+        height: (orig_image.get_height() * BLURHASH_WIDTH / orig_image.get_width()).max(1),
+        import_profile: "sRGB".into(),
+        export_profile: "sRGB".into(),
+        size: ops::Size::Down,
+        ..ops::ThumbnailImageOptions::default()
+    };
+    let image = ops::thumbnail_image_with_opts(&orig_image, BLURHASH_WIDTH, &thumb_opts)?;
+    let image = ops::cast(
+        &ops::colourspace(&image, ops::Interpretation::Srgb)?,
+        ops::BandFormat::Uchar,
+    )?;
+    // BlurHash expects RGBA, but ignores the alpha channel
+    let image = match image.get_bands() > 3 {
+        true => ops::extract_band_with_opts(&image, 0, &ops::ExtractBandOptions { n: 3 })?,
+        false => image,
+    };
+    let rgba = ops::bandjoin_const(&image, &mut [255.0])?.image_write_to_memory();
+    blurhash::encode(
+        BLURHASH_COMPONENTS.0,
+        BLURHASH_COMPONENTS.1,
+        image.get_width() as u32,
+        image.get_height() as u32,
+        &rgba,
+    )
+    .map_err(|err| Error::Internal(format!("Could not compute BlurHash: {}", err)))
 }
 
-pub fn check_cache(uuid: Uuid, height: i32, width: i32, quality: i32) -> bool {
-    let cache_entry = get_cache_entry(&uuid.to_string(), height, width, quality);
-    cache_entry.exists()
+/// Returns `left` and `right` next to each other on a white background, both scaled to `height`
+/// and converted to sRGB without alpha, e.g. to compare two renditions of an image
+pub fn compose_side_by_side(
+    left: VipsImage,
+    right: VipsImage,
+    height: i32,
+) -> Result<VipsImage, Error> {
+    let left = prepare_side(left, height)?;
+    let right = prepare_side(right, height)?;
+    Ok(ops::join_with_opts(
+        &left,
+        &right,
+        ops::Direction::Horizontal,
+        &ops::JoinOptions {
+            expand: true,
+            shim: COMPARE_GAP,
+            background: vec![255.0, 255.0, 255.0],
+            ..ops::JoinOptions::default()
+        },
+    )?)
+}
+
+/// Scales `image` to `height` (up or down, so both sides match) and converts it to 3 band sRGB,
+/// as joined images need the same bands
+fn prepare_side(image: VipsImage, height: i32) -> Result<VipsImage, Error> {
+    let image = ops::resize(&image, height as f64 / image.get_height() as f64)?;
+    let image = ops::colourspace(&image, ops::Interpretation::Srgb)?;
+    match image.get_bands() > 3 {
+        true => Ok(ops::extract_band_with_opts(
+            &image,
+            0,
+            &ops::ExtractBandOptions { n: 3 },
+        )?),
+        false => Ok(image),
+    }
 }
 
-pub fn remove_cache_entries(uuid: Uuid) {
-    match read_dir(get_cache_path()) {
+/// Removes all cache entries of the image with `uuid` of `tenant`.
+/// Returns the paths of the removed entries (or the ones that would be removed in a dry run).
+pub fn remove_cache_entries(
+    uuid: Uuid,
+    tenant: Option<&str>,
+    removal_behavior: RemovalBehavior,
+) -> Vec<PathBuf> {
+    let mut removed = Vec::new();
+
+    match read_dir(get_cache_path(tenant)) {
         Err(err) => log::error!("Unable to read pending path: {}", err),
         Ok(iterator) => {
             iterator.for_each(|dir_entry_res| {
@@ -334,6 +1133,12 @@ pub fn remove_cache_entries(uuid: Uuid) {
                                 return;
                             }
 
+                            if removal_behavior == RemovalBehavior::DryRun {
+                                log::info!("Dry run: Would delete '{:?}'", dir_entry.path());
+                                removed.push(dir_entry.path());
+                                return;
+                            }
+
                             match remove_file(dir_entry.path()) {
                                 Err(err) => match err.kind() {
                                     io::ErrorKind::NotFound => (), // Can be ignored
@@ -343,7 +1148,10 @@ pub fn remove_cache_entries(uuid: Uuid) {
                                         err
                                     ),
                                 },
-                                Ok(_) => log::info!("Deleted '{:?}'", dir_entry.path()),
+                                Ok(_) => {
+                                    log::info!("Deleted '{:?}'", dir_entry.path());
+                                    removed.push(dir_entry.path());
+                                }
                             }
                         }
                     }
@@ -351,9 +1159,18 @@ pub fn remove_cache_entries(uuid: Uuid) {
             })
         }
     }
+
+    removed
 }
 
-pub fn move_image(from: &Path, to: &Path, uuid: Uuid) -> Result<(), io::Error> {
+/// Moves the image with `uuid` from the directory `from` to `to`. Depending on `durability`, the
+/// move is flushed to disk as well.
+pub fn move_image(
+    from: &Path,
+    to: &Path,
+    uuid: Uuid,
+    durability: Durability,
+) -> Result<(), io::Error> {
     // Make sure image with given uuid does exist at source path
     let source_path = match determine_img_path(from.to_str().unwrap(), uuid) {
         Err(err) => {
@@ -372,29 +1189,71 @@ pub fn move_image(from: &Path, to: &Path, uuid: Uuid) -> Result<(), io::Error> {
         }
         Ok(_) => log::info!("Moved '{:?}' to '{:?}'", source_path, target_path),
     };
+    // The image was moved already, so failing here would report a state change that happened as
+    // failed. The move is only not guaranteed to survive a power loss.
+    for dir in [&target_path, &source_path] {
+        if let Err(err) = durability.sync_parent_dir(dir) {
+            log::error!("Could not flush the directory of {:?}: {}", dir, err);
+        }
+    }
 
     Ok(())
 }
 
 /// Deletes an image with the specified `uuid` from `from`  
+/// Returns the path of the deleted image (or the one that would be deleted in a dry run), if
+/// there was one.  
 /// Returns an io::Error if an error (apart from file not found - which is the expected state)
 /// was encountered.
-pub fn delete_image(from: &Path, uuid: Uuid) -> Result<(), io::Error> {
+/// Deletes the raw file of the image with `uuid`, which is otherwise left to the raw cleaner.
+/// Returns its path, if it existed (or would be deleted in a dry run).
+pub fn delete_raw(
+    uuid: Uuid,
+    tenant: Option<&str>,
+    removal_behavior: RemovalBehavior,
+) -> Result<Option<PathBuf>, io::Error> {
+    let path = get_raw_file(uuid, tenant);
+    if !path.exists() {
+        return Ok(None);
+    }
+    if removal_behavior == RemovalBehavior::DryRun {
+        log::info!("Dry run: Would delete '{:?}'", path);
+        return Ok(Some(path));
+    }
+    match std::fs::remove_file(&path) {
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => {
+            log::error!("Error while removing '{:?}': {}", path, err);
+            Err(err)
+        }
+        Ok(()) => Ok(Some(path)),
+    }
+}
+
+pub fn delete_image(
+    from: &Path,
+    uuid: Uuid,
+    removal_behavior: RemovalBehavior,
+) -> Result<Option<PathBuf>, io::Error> {
     match determine_img_path(from.to_str().unwrap(), uuid) {
         Err(err) => match err.kind() {
             // If the file is not found, everything is as expected
-            io::ErrorKind::NotFound => (),
+            io::ErrorKind::NotFound => Ok(None),
             // Some other error occurred, we should return it
             _ => {
                 log::error!("Error while getting path for '{}': {}", uuid, err);
-                return Err(err);
+                Err(err)
             }
         },
+        Ok(path) if removal_behavior == RemovalBehavior::DryRun => {
+            log::info!("Dry run: Would delete '{:?}'", path);
+            Ok(Some(path))
+        }
         Ok(path) => {
             if let Err(err) = std::fs::remove_file(&path) {
                 match err.kind() {
                     // If the file is not found, everything is as expected (although this should have returned above)
-                    io::ErrorKind::NotFound => (),
+                    io::ErrorKind::NotFound => return Ok(None),
                     // Some other error occurred, we should return it
                     _ => {
                         log::error!("Error while removing '{:?}': {}", path, err);
@@ -402,7 +1261,93 @@ pub fn delete_image(from: &Path, uuid: Uuid) -> Result<(), io::Error> {
                     }
                 }
             }
+            Ok(Some(path))
         }
-    };
-    Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validate_rendition_accepts_valid_parameters() {
+        assert_eq!(validate_rendition(None, None, None), Ok(()));
+        assert_eq!(validate_rendition(Some(1), Some(100_000), Some(1)), Ok(()));
+        assert_eq!(validate_rendition(Some(800), None, Some(100)), Ok(()));
+    }
+
+    #[test]
+    fn validate_rendition_rejects_non_positive_dimensions() {
+        assert_eq!(
+            validate_rendition(Some(0), None, None),
+            Err("width must be positive".to_owned())
+        );
+        assert_eq!(
+            validate_rendition(None, Some(-10), None),
+            Err("height must be positive".to_owned())
+        );
+    }
+
+    #[test]
+    fn validate_rendition_rejects_quality_out_of_range() {
+        for quality in [i32::MIN, -1, 0, 101, 5000] {
+            assert_eq!(
+                validate_rendition(None, None, Some(quality)),
+                Err("quality must be between 1 and 100".to_owned())
+            );
+        }
+    }
+
+    #[test]
+    fn resize_dimensions_width_only_fits() {
+        assert_eq!(
+            resize_dimensions(Some(400), None, (1600, 1200)),
+            (400, 1200, ResizeMode::Fit)
+        );
+    }
+
+    #[test]
+    fn resize_dimensions_height_only_keeps_aspect_ratio() {
+        assert_eq!(
+            resize_dimensions(None, Some(300), (1600, 1200)),
+            (400, 300, ResizeMode::Height)
+        );
+        assert_eq!(
+            resize_dimensions(None, Some(100), (1000, 3000)),
+            (33, 100, ResizeMode::Height)
+        );
+    }
+
+    #[test]
+    fn resize_dimensions_height_only_does_not_upscale() {
+        assert_eq!(
+            resize_dimensions(None, Some(2400), (1600, 1200)),
+            (1600, 1200, ResizeMode::Height)
+        );
+    }
+
+    #[test]
+    fn resize_dimensions_height_only_keeps_at_least_one_pixel() {
+        assert_eq!(
+            resize_dimensions(None, Some(1), (10, 1000)),
+            (1, 1, ResizeMode::Height)
+        );
+    }
+
+    #[test]
+    fn resize_dimensions_both_crop() {
+        assert_eq!(
+            resize_dimensions(Some(400), Some(400), (1600, 1200)),
+            (400, 400, ResizeMode::Crop)
+        );
+    }
+
+    #[test]
+    fn resize_dimensions_none_keep_original() {
+        assert_eq!(
+            resize_dimensions(None, None, (1600, 1200)),
+            (1600, 1200, ResizeMode::Fit)
+        );
+    }
 }
@@ -0,0 +1,67 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use tokio::sync::{Mutex as AsyncMutex, OwnedMutexGuard};
+use uuid::Uuid;
+
+/// Locks of single images, so mutations of the same image (e.g. a rotation racing with an
+/// approval) are serialized instead of losing updates or moving the image to the wrong state.
+/// Mutations of different images don't block each other, and waiting for a lock doesn't block
+/// the runtime.
+#[derive(Clone, Default)]
+pub struct ImageLocks {
+    // Locks of the images that are currently locked or waited for
+    inner: Arc<Mutex<HashMap<Uuid, Arc<AsyncMutex<()>>>>>,
+}
+
+/// Holds the lock of an image until it is dropped
+pub struct ImageLock {
+    locks: ImageLocks,
+    uuid: Uuid,
+    // Only `None` while it is dropped
+    guard: Option<OwnedMutexGuard<()>>,
+}
+
+impl ImageLocks {
+    /// Locks the image with `uuid`, waiting until it is no longer locked by another mutation.
+    /// The locks are not reentrant, so the lock must not be taken again while it is held.
+    pub async fn lock(&self, uuid: Uuid) -> ImageLock {
+        let guard = self.mutex(uuid).lock_owned().await;
+        self.locked(uuid, guard)
+    }
+
+    /// Like `lock`, but blocks the current thread while waiting, e.g. in jobs or in
+    /// `spawn_blocking`. Must not be called from async code.
+    pub fn blocking_lock(&self, uuid: Uuid) -> ImageLock {
+        let guard = self.mutex(uuid).blocking_lock_owned();
+        self.locked(uuid, guard)
+    }
+
+    fn mutex(&self, uuid: Uuid) -> Arc<AsyncMutex<()>> {
+        self.inner.lock().unwrap().entry(uuid).or_default().clone()
+    }
+
+    fn locked(&self, uuid: Uuid, guard: OwnedMutexGuard<()>) -> ImageLock {
+        ImageLock {
+            locks: self.clone(),
+            uuid: uuid,
+            guard: Some(guard),
+        }
+    }
+}
+
+impl Drop for ImageLock {
+    fn drop(&mut self) {
+        let mut locks = self.locks.inner.lock().unwrap();
+        drop(self.guard.take());
+        // Forget the lock, unless another mutation is waiting for it
+        if locks
+            .get(&self.uuid)
+            .is_some_and(|mutex| Arc::strong_count(mutex) == 1)
+        {
+            locks.remove(&self.uuid);
+        }
+    }
+}
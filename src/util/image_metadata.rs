@@ -0,0 +1,123 @@
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::constants::{MAX_ALT_TEXT_LENGTH, MAX_CAPTURE_SOURCE_LENGTH, MAX_TAGS, MAX_TAG_LENGTH};
+
+/// Point of interest of an image as fractions of its width and height (from 0 to 1, starting at
+/// the top left corner), e.g. to keep it visible when cropping
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct FocalPoint {
+    pub x: f64,
+    pub y: f64,
+}
+
+impl FromStr for FocalPoint {
+    type Err = String;
+
+    /// Parses `<x>,<y>`, e.g. `0.5,0.3`
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid focal point '{}', expected '<x>,<y>'!", value);
+        let (x, y) = value.split_once(',').ok_or_else(invalid)?;
+        Ok(FocalPoint {
+            x: x.trim().parse().map_err(|_| invalid())?,
+            y: y.trim().parse().map_err(|_| invalid())?,
+        })
+    }
+}
+
+/// Descriptive metadata of an image, provided along with the upload or set via
+/// `/image/:id/metadata` and stored in the metadata index. It is kept independently of the state
+/// directories, so it stays with the image when it is moved, and is included in exports (and
+/// thereby replicated).
+#[derive(Clone, Default, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct ImageMetadata {
+    /// Text describing the image, e.g. for the `alt` attribute
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub alt_text: Option<String>,
+    /// Where the image was taken, e.g. the device or app
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub capture_source: Option<String>,
+    /// Lowercase tags without duplicates
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub focal_point: Option<FocalPoint>,
+}
+
+impl ImageMetadata {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Adds the comma-separated `tags`, trimmed and lowercased. Empty and duplicate tags are
+    /// skipped.
+    pub fn add_tags(&mut self, tags: &str) {
+        for tag in tags.split(',') {
+            let tag = tag.trim().to_lowercase();
+            if !tag.is_empty() && !self.tags.contains(&tag) {
+                self.tags.push(tag);
+            }
+        }
+    }
+
+    /// Trims and lowercases the tags and removes empty and duplicate ones, like `add_tags`
+    pub fn normalize_tags(&mut self) {
+        for tag in std::mem::take(&mut self.tags) {
+            self.add_tags(&tag);
+        }
+    }
+
+    /// Checks the fields against their limits and for control characters (line breaks are only
+    /// allowed in the alt text). Returns the problem, if any.
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(alt_text) = &self.alt_text {
+            validate_text("alt_text", alt_text, MAX_ALT_TEXT_LENGTH, true)?;
+        }
+        if let Some(capture_source) = &self.capture_source {
+            validate_text(
+                "capture_source",
+                capture_source,
+                MAX_CAPTURE_SOURCE_LENGTH,
+                false,
+            )?;
+        }
+        if self.tags.len() > MAX_TAGS {
+            return Err(format!("At most {} tags are allowed!", MAX_TAGS));
+        }
+        for tag in &self.tags {
+            validate_text("tags", tag, MAX_TAG_LENGTH, false)?;
+        }
+        if let Some(FocalPoint { x, y }) = self.focal_point {
+            if !(0.0..=1.0).contains(&x) || !(0.0..=1.0).contains(&y) {
+                return Err("The coordinates of 'focal_point' must be from 0 to 1!".to_owned());
+            }
+        }
+        Ok(())
+    }
+}
+
+fn validate_text(
+    field: &str,
+    value: &str,
+    max_length: usize,
+    multiline: bool,
+) -> Result<(), String> {
+    if value.trim().is_empty() {
+        return Err(format!("'{}' must not be empty!", field));
+    }
+    if value.chars().count() > max_length {
+        return Err(format!(
+            "'{}' is limited to {} characters!",
+            field, max_length
+        ));
+    }
+    if value
+        .chars()
+        .any(|c| c.is_control() && !(multiline && (c == '\n' || c == '\r' || c == '\t')))
+    {
+        return Err(format!("'{}' must not contain control characters!", field));
+    }
+    Ok(())
+}
@@ -0,0 +1,176 @@
+use config::Config;
+use libvips::{ops::BandFormat, VipsImage};
+
+use crate::constants::{
+    DEFAULT_MAX_INPUT_BIT_DEPTH, DEFAULT_MAX_INPUT_DIMENSION, DEFAULT_MAX_INPUT_FRAMES,
+    DEFAULT_MAX_INPUT_PIXELS,
+};
+
+/// Limits of inputs (uploads, regenerated raw files and served images), checked from their header
+/// before they are decoded, so e.g. a decompression bomb claiming 100000x100000 pixels or a
+/// GIF-like WebP with hundreds of frames can't make libvips allocate huge buffers
+#[derive(Clone, Copy)]
+pub struct InputLimits {
+    pub max_width: i32,
+    pub max_height: i32,
+    // Bits per sample, e.g. 8 for most JPEGs
+    pub max_bit_depth: i32,
+    pub max_frames: i32,
+    // Of all frames together
+    pub max_pixels: i64,
+}
+
+/// Parses the limits from the config properties `MAX_INPUT_WIDTH`, `MAX_INPUT_HEIGHT`,
+/// `MAX_INPUT_BIT_DEPTH`, `MAX_INPUT_FRAMES` and `MAX_INPUT_PIXELS`
+pub fn parse_input_limits(config: &Config) -> InputLimits {
+    InputLimits {
+        max_width: config
+            .get::<i32>("MAX_INPUT_WIDTH")
+            .unwrap_or(DEFAULT_MAX_INPUT_DIMENSION),
+        max_height: config
+            .get::<i32>("MAX_INPUT_HEIGHT")
+            .unwrap_or(DEFAULT_MAX_INPUT_DIMENSION),
+        max_bit_depth: config
+            .get::<i32>("MAX_INPUT_BIT_DEPTH")
+            .unwrap_or(DEFAULT_MAX_INPUT_BIT_DEPTH),
+        max_frames: config
+            .get::<i32>("MAX_INPUT_FRAMES")
+            .unwrap_or(DEFAULT_MAX_INPUT_FRAMES),
+        max_pixels: config
+            .get::<i64>("MAX_INPUT_PIXELS")
+            .unwrap_or(DEFAULT_MAX_INPUT_PIXELS),
+    }
+}
+
+impl InputLimits {
+    /// Checks the dimensions, bit depth and frames (see `check_frames`) of `image`, which must
+    /// only be loaded (i.e. its header read), against the limits. Returns the problem, if the
+    /// image is rejected.
+    pub fn check_header(&self, image: &VipsImage) -> Result<(), String> {
+        if image.get_width() > self.max_width || image.get_height() > self.max_height {
+            return Err(format!(
+                "Images are limited to {}x{} pixels, but this one has {}x{}",
+                self.max_width,
+                self.max_height,
+                image.get_width(),
+                image.get_height()
+            ));
+        }
+        let bit_depth = match image.get_format() {
+            Ok(BandFormat::Uchar | BandFormat::Char) => 8,
+            Ok(BandFormat::Ushort | BandFormat::Short) => 16,
+            Ok(BandFormat::Uint | BandFormat::Int | BandFormat::Float) => 32,
+            Ok(BandFormat::Complex | BandFormat::Double) => 64,
+            Ok(BandFormat::Dpcomplex) => 128,
+            Ok(BandFormat::Notset | BandFormat::Last) | Err(_) => {
+                return Err("The sample format of this image is unknown".to_owned());
+            }
+        };
+        if bit_depth > self.max_bit_depth {
+            return Err(format!(
+                "Images are limited to {} bits per sample, but this one has {}",
+                self.max_bit_depth, bit_depth
+            ));
+        }
+        self.check_frames(image)
+    }
+
+    /// Checks the frames of `image`, which must only be loaded (i.e. its header read), against
+    /// the limits. Returns the problem, if the image is rejected.
+    fn check_frames(&self, image: &VipsImage) -> Result<(), String> {
+        let frames = image.get_n_pages().max(1);
+        // Images are loaded with their first frame only, so its height is the one of a frame
+        let frame_pixels = (image.get_width() as i64 * image.get_height() as i64).max(1);
+        let allowed = (self.max_frames as i64).min(self.max_pixels / frame_pixels);
+        if frames as i64 > allowed {
+            return Err(format!(
+                "Images are limited to {} frames and {} pixels of all frames, but this one has {} \
+                 frames of {} pixels",
+                self.max_frames, self.max_pixels, frames, frame_pixels
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::sync::Once;
+
+    use axum::body::Bytes;
+    use libvips::VipsApp;
+
+    use super::*;
+
+    /// Starts libvips once for all tests that read images
+    pub fn start_vips() {
+        static START: Once = Once::new();
+        // Never shut down, as tests run in parallel
+        START.call_once(|| std::mem::forget(VipsApp::new("mensatt-tests", false).unwrap()));
+    }
+
+    /// Returns the defaults, i.e. at most 20000x20000 pixels
+    pub fn limits() -> InputLimits {
+        parse_input_limits(&Config::default())
+    }
+
+    /// Returns an RGB PNG claiming `width` x `height` pixels, whose pixel data is empty. Its
+    /// header can be read, but decoding it fails.
+    pub fn png_header(width: u32, height: u32) -> Bytes {
+        let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+        let mut chunk = |kind: &[u8], data: &[u8]| {
+            png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+            let start = png.len();
+            png.extend_from_slice(kind);
+            png.extend_from_slice(data);
+            let crc = crc32fast::hash(&png[start..]);
+            png.extend_from_slice(&crc.to_be_bytes());
+        };
+
+        let mut header = Vec::new();
+        header.extend_from_slice(&width.to_be_bytes());
+        header.extend_from_slice(&height.to_be_bytes());
+        // 8 bits per sample, RGB, default compression, filters and no interlacing
+        header.extend_from_slice(&[8, 2, 0, 0, 0]);
+        chunk(b"IHDR", &header);
+        // Empty zlib stream
+        chunk(b"IDAT", &[0x78, 0x9c, 0x03, 0x00, 0x00, 0x00, 0x00, 0x01]);
+        chunk(b"IEND", &[]);
+        Bytes::from(png)
+    }
+
+    fn check(data: &Bytes, limits: &InputLimits) -> Result<(), String> {
+        start_vips();
+        limits.check_header(&VipsImage::new_from_buffer(data, "").unwrap())
+    }
+
+    #[test]
+    fn check_header_accepts_images_within_limits() {
+        assert_eq!(check(&png_header(20_000, 20_000), &limits()), Ok(()));
+    }
+
+    #[test]
+    fn check_header_rejects_too_wide_or_high_images() {
+        assert!(check(&png_header(20_001, 1), &limits()).is_err());
+        assert!(check(&png_header(1, 20_001), &limits()).is_err());
+    }
+
+    #[test]
+    fn check_header_rejects_too_many_pixels() {
+        let limits = InputLimits {
+            max_pixels: 999,
+            ..limits()
+        };
+        assert_eq!(check(&png_header(10, 99), &limits), Ok(()));
+        assert!(check(&png_header(10, 100), &limits).is_err());
+    }
+
+    #[test]
+    fn check_header_rejects_too_deep_samples() {
+        let limits = InputLimits {
+            max_bit_depth: 4,
+            ..limits()
+        };
+        assert!(check(&png_header(1, 1), &limits).is_err());
+    }
+}
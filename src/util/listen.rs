@@ -0,0 +1,75 @@
+use std::{
+    io,
+    net::{SocketAddr, ToSocketAddrs},
+};
+
+use config::Config;
+use socket2::{Domain, Socket, Type};
+use tokio::net::TcpListener;
+
+use crate::constants::DEFAULT_LISTEN_ADDR;
+
+/// Parses the addresses to listen on from the config property `LISTEN_ADDRS`.
+/// Each entry has the form `host:port`, e.g. `0.0.0.0:3000` or `[::]:3000`.
+/// Falls back to `DEFAULT_LISTEN_ADDR` if the property is not set.
+pub fn parse_listen_addrs(config: &Config) -> Vec<SocketAddr> {
+    let values = match config.get::<Vec<String>>("LISTEN_ADDRS") {
+        Err(err) => {
+            log::info!(
+                "LISTEN_ADDRS not specified. Listening on {}. Error was: {}",
+                DEFAULT_LISTEN_ADDR,
+                err
+            );
+            return Vec::from([DEFAULT_LISTEN_ADDR]);
+        }
+        Ok(vec) => vec,
+    };
+
+    let addrs: Vec<SocketAddr> = values
+        .iter()
+        .flat_map(|value| match value.to_socket_addrs() {
+            Err(err) => panic!(
+                "LISTEN_ADDRS contains invalid address '{}' (expected 'host:port'): {}",
+                value, err
+            ),
+            Ok(iter) => iter,
+        })
+        .collect();
+
+    if addrs.is_empty() {
+        panic!("LISTEN_ADDRS is set, but does not contain any addresses");
+    }
+
+    addrs
+}
+
+/// Binds a `TcpListener` to `addr`.
+/// IPv6 sockets are bound as IPv6-only, so that `[::]` and `0.0.0.0` can be used side by side.
+pub fn bind(addr: SocketAddr) -> io::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, None)?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(true)?;
+    }
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(1024)?;
+
+    TcpListener::from_std(socket.into())
+}
+
+/// Binds to all given addresses and panics with a descriptive message if that is not possible
+pub fn bind_all(addrs: &[SocketAddr]) -> Vec<TcpListener> {
+    addrs
+        .iter()
+        .map(|addr| match bind(*addr) {
+            Err(err) if err.kind() == io::ErrorKind::AddrInUse => panic!(
+                "Could not listen on {}: Port {} is already in use by another process",
+                addr,
+                addr.port()
+            ),
+            Err(err) => panic!("Could not listen on {}: {}", addr, err),
+            Ok(listener) => listener,
+        })
+        .collect()
+}
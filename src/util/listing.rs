@@ -0,0 +1,158 @@
+use std::time::SystemTime;
+
+use axum::http::StatusCode;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+use uuid::Uuid;
+
+use crate::{
+    constants::{DEFAULT_LIST_LIMIT, MAX_LIST_LIMIT},
+    util::image::ImageState,
+};
+
+#[derive(Clone, Copy, Default, PartialEq, Eq, Deserialize, ToSchema, async_graphql::Enum)]
+#[serde(rename_all = "snake_case")]
+pub enum SortKey {
+    #[default]
+    CreatedAt,
+    StateChangedAt,
+    Id,
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Eq, Deserialize, ToSchema, async_graphql::Enum)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    #[default]
+    Asc,
+    Desc,
+}
+
+/// Query parameters shared by all listing endpoints
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ListQuery {
+    /// Maximum number of returned items, defaults to 100, at most 1000
+    pub limit: Option<usize>,
+    /// Number of items to skip, defaults to 0
+    pub offset: Option<usize>,
+    /// Defaults to `created_at`
+    pub sort: Option<SortKey>,
+    /// Defaults to `asc`
+    pub order: Option<SortOrder>,
+    /// Only list items in this state
+    pub state: Option<ImageState>,
+    /// Only list items created at or after this time (RFC 3339)
+    pub after: Option<DateTime<Utc>>,
+    /// Only list items created before this time (RFC 3339)
+    pub before: Option<DateTime<Utc>>,
+    /// Only list items that entered their state at or after this time (RFC 3339)
+    pub changed_after: Option<DateTime<Utc>>,
+    /// Only list items that entered their state before this time (RFC 3339)
+    pub changed_before: Option<DateTime<Utc>>,
+}
+
+/// An item that can be listed with a `ListQuery`
+pub trait ListItem {
+    fn id(&self) -> Uuid;
+    fn state(&self) -> ImageState;
+    fn created_at(&self) -> SystemTime;
+    fn state_changed_at(&self) -> SystemTime;
+}
+
+/// One page of a listing
+#[derive(Serialize, ToSchema)]
+#[aliases(ImagePage = Page<crate::handlers::images::ImageListEntry>)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    // Number of items matching the filters, across all pages
+    pub total: usize,
+    pub limit: usize,
+    pub offset: usize,
+}
+
+impl ListQuery {
+    /// Returns the states to list items of, as requested by the state filter
+    pub fn states(&self) -> Vec<ImageState> {
+        match self.state {
+            None => ImageState::ALL.to_vec(),
+            Some(state) => vec![state],
+        }
+    }
+
+    /// Filters, sorts and paginates `items` as requested.
+    /// Returns 400 (BAD_REQUEST), if the query is invalid.
+    pub fn apply<T: ListItem>(&self, items: Vec<T>) -> Result<Page<T>, (StatusCode, String)> {
+        let limit = self.limit.unwrap_or(DEFAULT_LIST_LIMIT);
+        if limit > MAX_LIST_LIMIT {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                format!("Limit must be at most {}!", MAX_LIST_LIMIT),
+            ));
+        }
+        if let (Some(after), Some(before)) = (self.after, self.before) {
+            if after > before {
+                return Err((
+                    StatusCode::BAD_REQUEST,
+                    "'after' must not be later than 'before'!".to_owned(),
+                ));
+            }
+        }
+        if let (Some(after), Some(before)) = (self.changed_after, self.changed_before) {
+            if after > before {
+                return Err((
+                    StatusCode::BAD_REQUEST,
+                    "'changed_after' must not be later than 'changed_before'!".to_owned(),
+                ));
+            }
+        }
+        let offset = self.offset.unwrap_or(0);
+
+        let after = self.after.map(SystemTime::from);
+        let before = self.before.map(SystemTime::from);
+        let changed_after = self.changed_after.map(SystemTime::from);
+        let changed_before = self.changed_before.map(SystemTime::from);
+        let mut items: Vec<T> = items
+            .into_iter()
+            .filter(|item| match self.state {
+                None => true,
+                Some(state) => item.state() == state,
+            })
+            .filter(|item| match after {
+                None => true,
+                Some(after) => item.created_at() >= after,
+            })
+            .filter(|item| match before {
+                None => true,
+                Some(before) => item.created_at() < before,
+            })
+            .filter(|item| match changed_after {
+                None => true,
+                Some(after) => item.state_changed_at() >= after,
+            })
+            .filter(|item| match changed_before {
+                None => true,
+                Some(before) => item.state_changed_at() < before,
+            })
+            .collect();
+
+        match self.sort.unwrap_or_default() {
+            SortKey::CreatedAt => items.sort_by_key(|item| (item.created_at(), item.id())),
+            SortKey::StateChangedAt => {
+                items.sort_by_key(|item| (item.state_changed_at(), item.id()))
+            }
+            SortKey::Id => items.sort_by_key(|item| item.id()),
+        }
+        if let SortOrder::Desc = self.order.unwrap_or_default() {
+            items.reverse();
+        }
+
+        let total = items.len();
+        Ok(Page {
+            items: items.into_iter().skip(offset).take(limit).collect(),
+            total: total,
+            limit: limit,
+            offset: offset,
+        })
+    }
+}
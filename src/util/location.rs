@@ -0,0 +1,631 @@
+// Tags of the TIFF structure of Exif metadata
+const GPS_IFD_TAG: u16 = 0x8825;
+const EXIF_IFD_TAG: u16 = 0x8769;
+const MAKER_NOTE_TAG: u16 = 0x927c;
+
+const EXIF_PREFIX: &[u8] = b"Exif\0\0";
+const PNG_SIGNATURE: &[u8] = &[0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+
+// Properties of XMP packets that reveal where an image was taken
+const XMP_LOCATION_PROPERTIES: [&str; 8] = [
+    "exif:GPS",
+    "photoshop:City",
+    "photoshop:State",
+    "photoshop:Country",
+    "Iptc4xmpCore:Location",
+    "Iptc4xmpCore:CountryCode",
+    "Iptc4xmpExt:LocationCreated",
+    "Iptc4xmpExt:LocationShown",
+];
+const XMP_START: &[u8] = b"<x:xmpmeta";
+const XMP_END: &[u8] = b"</x:xmpmeta>";
+
+/// Removes location metadata from the image file `data` in place, without changing its length,
+/// so offsets within the file stay valid and the image itself is untouched:
+/// - the entries of GPS IFDs of Exif metadata are zeroed (leaving empty IFDs)
+/// - maker notes are zeroed, as their vendor-specific formats may contain locations
+/// - XMP packets with geotags are replaced by whitespace (the padding of XMP)
+///
+/// Exif metadata is found by its `Exif\0\0` prefix (JPEG, HEIF, AVIF) and in the `eXIf` chunk of
+/// PNGs and the `EXIF` chunk of WebPs. Returns descriptions of what was removed, i.e. nothing if
+/// the file contains no location metadata, so it can be used to verify existing files, too.
+pub fn scrub_location(data: &mut [u8]) -> Vec<String> {
+    let mut findings = Vec::new();
+
+    for start in exif_offsets(data) {
+        scrub_tiff(&mut data[start..], &mut findings);
+    }
+    scrub_xmp(data, &mut findings);
+
+    // Changed chunks of PNGs would fail their checksum otherwise
+    if !findings.is_empty() && data.starts_with(PNG_SIGNATURE) {
+        fix_png_checksums(data);
+    }
+    findings
+}
+
+/// Returns the offsets of all TIFF structures of Exif metadata in `data`
+fn exif_offsets(data: &[u8]) -> Vec<usize> {
+    let mut offsets: Vec<usize> = find_all(data, EXIF_PREFIX)
+        .into_iter()
+        .map(|offset| offset + EXIF_PREFIX.len())
+        .collect();
+
+    if data.starts_with(PNG_SIGNATURE) {
+        offsets.extend(
+            png_chunks(data)
+                .into_iter()
+                .filter(|(chunk_type, _, _)| chunk_type == b"eXIf")
+                .map(|(_, start, _)| start),
+        );
+    }
+    if data.len() >= 12 && &data[..4] == b"RIFF" && &data[8..12] == b"WEBP" {
+        let mut offset = 12;
+        while offset + 8 <= data.len() {
+            let size =
+                u32::from_le_bytes(data[offset + 4..offset + 8].try_into().unwrap()) as usize;
+            if &data[offset..offset + 4] == b"EXIF" && !data[offset + 8..].starts_with(EXIF_PREFIX)
+            {
+                offsets.push(offset + 8);
+            }
+            // Chunks are padded to an even size
+            offset = offset.saturating_add(8 + size + size % 2);
+        }
+    }
+
+    offsets.retain(|offset| is_tiff_header(&data[*offset..]));
+    offsets.sort_unstable();
+    offsets.dedup();
+    offsets
+}
+
+fn is_tiff_header(data: &[u8]) -> bool {
+    data.starts_with(b"II*\0") || data.starts_with(b"MM\0*")
+}
+
+/// Zeroes the GPS IFD and maker note of the TIFF structure at the start of `tiff`
+fn scrub_tiff(tiff: &mut [u8], findings: &mut Vec<String>) {
+    let mut reader = Tiff {
+        big_endian: tiff.starts_with(b"MM"),
+        data: tiff,
+    };
+
+    // IFD0 and the following ones (usually only IFD1 of the thumbnail)
+    let mut ifd = reader.u32(4);
+    let mut visited = Vec::new();
+    while let Some(offset) = ifd.filter(|offset| *offset != 0 && !visited.contains(offset)) {
+        visited.push(offset);
+        for (tag, value) in reader.entries(offset as usize) {
+            match tag {
+                GPS_IFD_TAG => {
+                    let count = reader.clear_ifd(value as usize);
+                    if count > 0 {
+                        findings.push(format!("GPS IFD with {} entries", count));
+                    }
+                }
+                EXIF_IFD_TAG if reader.clear_entry_values(value as usize, MAKER_NOTE_TAG) => {
+                    findings.push("Maker note".to_owned());
+                }
+                _ => (),
+            }
+        }
+        ifd = reader.next_ifd(offset as usize);
+    }
+}
+
+/// Reads and modifies a TIFF structure. All offsets are relative to its start, reads out of
+/// bounds return `None`, so broken metadata is skipped.
+struct Tiff<'a> {
+    data: &'a mut [u8],
+    big_endian: bool,
+}
+
+impl Tiff<'_> {
+    fn u16(&self, offset: usize) -> Option<u16> {
+        let bytes: [u8; 2] = self.data.get(offset..offset + 2)?.try_into().ok()?;
+        Some(match self.big_endian {
+            true => u16::from_be_bytes(bytes),
+            false => u16::from_le_bytes(bytes),
+        })
+    }
+
+    fn u32(&self, offset: usize) -> Option<u32> {
+        let bytes: [u8; 4] = self.data.get(offset..offset + 4)?.try_into().ok()?;
+        Some(match self.big_endian {
+            true => u32::from_be_bytes(bytes),
+            false => u32::from_le_bytes(bytes),
+        })
+    }
+
+    /// Returns the tags and values (or value offsets) of the entries of the IFD at `offset`
+    fn entries(&self, offset: usize) -> Vec<(u16, u32)> {
+        let count = self.u16(offset).unwrap_or(0) as usize;
+        (0..count)
+            .map_while(|i| {
+                let entry = offset + 2 + i * 12;
+                Some((self.u16(entry)?, self.u32(entry + 8)?))
+            })
+            .collect()
+    }
+
+    fn next_ifd(&self, offset: usize) -> Option<u32> {
+        let count = self.u16(offset)? as usize;
+        self.u32(offset + 2 + count * 12)
+    }
+
+    /// Returns the range of the value of the entry at `entry`, if it is stored outside of it
+    fn value_range(&self, entry: usize) -> Option<std::ops::Range<usize>> {
+        let size = match self.u16(entry + 2)? {
+            1 | 2 | 6 | 7 => 1,
+            3 | 8 => 2,
+            4 | 9 | 11 | 13 => 4,
+            5 | 10 | 12 => 8,
+            _ => return None,
+        };
+        let length = size * self.u32(entry + 4)? as usize;
+        if length <= 4 {
+            return None;
+        }
+        let start = self.u32(entry + 8)? as usize;
+        if start >= self.data.len() {
+            return None;
+        }
+        Some(start..start.saturating_add(length).min(self.data.len()))
+    }
+
+    /// Zeroes all entries of the IFD at `offset` and their values, leaving an empty IFD.
+    /// Returns the number of entries it had.
+    fn clear_ifd(&mut self, offset: usize) -> usize {
+        let count = self.u16(offset).unwrap_or(0) as usize;
+        let end = offset + 2 + count * 12;
+        if end + 4 > self.data.len() {
+            return 0;
+        }
+        for i in 0..count {
+            if let Some(range) = self.value_range(offset + 2 + i * 12) {
+                self.data[range].fill(0);
+            }
+        }
+        // The count, all entries and the offset of the next IFD
+        self.data[offset..end + 4].fill(0);
+        count
+    }
+
+    /// Zeroes the values of the entries with `tag` of the IFD at `offset`.
+    /// Returns whether any of them was not zeroed already.
+    fn clear_entry_values(&mut self, offset: usize, tag: u16) -> bool {
+        let count = self.u16(offset).unwrap_or(0) as usize;
+        let mut cleared = false;
+        for i in 0..count {
+            let entry = offset + 2 + i * 12;
+            if self.u16(entry) != Some(tag) {
+                continue;
+            }
+            if let Some(range) = self.value_range(entry) {
+                cleared |= self.data[range.clone()].iter().any(|byte| *byte != 0);
+                self.data[range].fill(0);
+            }
+        }
+        cleared
+    }
+}
+
+/// Replaces XMP packets containing location properties with whitespace
+fn scrub_xmp(data: &mut [u8], findings: &mut Vec<String>) {
+    for start in find_all(data, XMP_START) {
+        let Some(length) = find(&data[start..], XMP_END) else {
+            continue;
+        };
+        let end = start + length + XMP_END.len();
+        let packet = String::from_utf8_lossy(&data[start..end]).into_owned();
+        let properties: Vec<&str> = XMP_LOCATION_PROPERTIES
+            .into_iter()
+            .filter(|property| packet.contains(property))
+            .collect();
+        if !properties.is_empty() {
+            findings.push(format!("XMP with {}", properties.join(", ")));
+            data[start..end].fill(b' ');
+        }
+    }
+}
+
+/// Returns the type, start and end of the data of each chunk of the PNG `data`
+fn png_chunks(data: &[u8]) -> Vec<([u8; 4], usize, usize)> {
+    let mut chunks = Vec::new();
+    let mut offset = PNG_SIGNATURE.len();
+    while offset + 12 <= data.len() {
+        let length = u32::from_be_bytes(data[offset..offset + 4].try_into().unwrap()) as usize;
+        let chunk_type: [u8; 4] = data[offset + 4..offset + 8].try_into().unwrap();
+        let start = offset + 8;
+        let Some(end) = start
+            .checked_add(length)
+            .filter(|end| end + 4 <= data.len())
+        else {
+            break;
+        };
+        chunks.push((chunk_type, start, end));
+        offset = end + 4;
+    }
+    chunks
+}
+
+/// Recomputes the checksum of each chunk of the PNG `data`
+fn fix_png_checksums(data: &mut [u8]) {
+    for (_, start, end) in png_chunks(data) {
+        // The checksum covers the type and the data
+        let crc = crc32fast::hash(&data[start - 4..end]);
+        data[end..end + 4].copy_from_slice(&crc.to_be_bytes());
+    }
+}
+
+/// Returns the offset of the first occurrence of `needle` in `haystack`
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+/// Returns the offsets of all occurrences of `needle` in `haystack`
+fn find_all(haystack: &[u8], needle: &[u8]) -> Vec<usize> {
+    haystack
+        .windows(needle.len())
+        .enumerate()
+        .filter(|(_, window)| *window == needle)
+        .map(|(offset, _)| offset)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAKE: &[u8] = b"Cam\0";
+    const MAKER_NOTE: &[u8] = b"Nikon\0\x02\x10";
+
+    /// Writes integers of a TIFF structure in its byte order
+    struct Writer {
+        data: Vec<u8>,
+        big_endian: bool,
+    }
+
+    impl Writer {
+        fn u16(&mut self, value: u16) {
+            match self.big_endian {
+                true => self.data.extend_from_slice(&value.to_be_bytes()),
+                false => self.data.extend_from_slice(&value.to_le_bytes()),
+            }
+        }
+
+        fn u32(&mut self, value: u32) {
+            match self.big_endian {
+                true => self.data.extend_from_slice(&value.to_be_bytes()),
+                false => self.data.extend_from_slice(&value.to_le_bytes()),
+            }
+        }
+
+        fn entry(&mut self, tag: u16, field_type: u16, count: u32, value: u32) {
+            self.u16(tag);
+            self.u16(field_type);
+            self.u32(count);
+            self.u32(value);
+        }
+    }
+
+    /// Returns a TIFF structure with a make and, if requested, a GPS IFD with a latitude and an
+    /// Exif IFD with a maker note, and the range of the GPS IFD and its values
+    fn tiff(big_endian: bool, gps: bool, maker_note: bool) -> (Vec<u8>, std::ops::Range<usize>) {
+        let ifd0_entries = 1 + gps as u32 + maker_note as u32;
+        let exif_offset = 8 + 2 + 12 * ifd0_entries + 4;
+        let note_offset = exif_offset + if maker_note { 2 + 12 + 4 } else { 0 };
+        let gps_offset = note_offset + if maker_note { 8 } else { 0 };
+        let latitude_offset = gps_offset + 2 + 2 * 12 + 4;
+
+        let mut writer = Writer {
+            data: Vec::new(),
+            big_endian: big_endian,
+        };
+        writer.data.extend_from_slice(match big_endian {
+            true => b"MM\0*",
+            false => b"II*\0",
+        });
+        writer.u32(8);
+
+        writer.u16(ifd0_entries as u16);
+        writer.entry(0x010f, 2, MAKE.len() as u32, 0);
+        writer.data.truncate(writer.data.len() - 4);
+        writer.data.extend_from_slice(MAKE);
+        if maker_note {
+            writer.entry(EXIF_IFD_TAG, 4, 1, exif_offset);
+        }
+        if gps {
+            writer.entry(GPS_IFD_TAG, 4, 1, gps_offset);
+        }
+        writer.u32(0);
+
+        if maker_note {
+            writer.u16(1);
+            writer.entry(MAKER_NOTE_TAG, 7, MAKER_NOTE.len() as u32, note_offset);
+            writer.u32(0);
+            writer.data.extend_from_slice(MAKER_NOTE);
+        }
+        if gps {
+            writer.u16(2);
+            // GPSLatitudeRef "N" (stored in the entry) and GPSLatitude 48/1, 8/1, 0/1
+            writer.entry(0x0001, 2, 2, 0);
+            writer.data.truncate(writer.data.len() - 4);
+            writer.data.extend_from_slice(b"N\0\0\0");
+            writer.entry(0x0002, 5, 3, latitude_offset);
+            writer.u32(0);
+            for (numerator, denominator) in [(48, 1), (8, 1), (0, 1)] {
+                writer.u32(numerator);
+                writer.u32(denominator);
+            }
+        }
+        let end = writer.data.len();
+        (writer.data, gps_offset as usize..end)
+    }
+
+    fn jpeg(tiff: &[u8]) -> Vec<u8> {
+        let mut data = vec![0xff, 0xd8, 0xff, 0xe1];
+        data.extend_from_slice(&((2 + EXIF_PREFIX.len() + tiff.len()) as u16).to_be_bytes());
+        data.extend_from_slice(EXIF_PREFIX);
+        data.extend_from_slice(tiff);
+        data.extend_from_slice(&[0xff, 0xd9]);
+        data
+    }
+
+    fn png_chunk(data: &mut Vec<u8>, chunk_type: &[u8], contents: &[u8]) {
+        data.extend_from_slice(&(contents.len() as u32).to_be_bytes());
+        data.extend_from_slice(chunk_type);
+        data.extend_from_slice(contents);
+        let crc = crc32fast::hash(&data[data.len() - contents.len() - 4..]);
+        data.extend_from_slice(&crc.to_be_bytes());
+    }
+
+    fn png(tiff: &[u8]) -> Vec<u8> {
+        let mut data = PNG_SIGNATURE.to_vec();
+        png_chunk(&mut data, b"IHDR", &[0, 0, 0, 1, 0, 0, 0, 1, 8, 2, 0, 0, 0]);
+        png_chunk(&mut data, b"eXIf", tiff);
+        png_chunk(&mut data, b"IEND", &[]);
+        data
+    }
+
+    fn webp(tiff: &[u8]) -> Vec<u8> {
+        let mut chunks = Vec::new();
+        for (chunk_type, contents) in [(b"VP8X", &[0u8; 10][..]), (b"EXIF", tiff)] {
+            chunks.extend_from_slice(chunk_type);
+            chunks.extend_from_slice(&(contents.len() as u32).to_le_bytes());
+            chunks.extend_from_slice(contents);
+            if contents.len() % 2 == 1 {
+                chunks.push(0);
+            }
+        }
+        let mut data = b"RIFF".to_vec();
+        data.extend_from_slice(&(4 + chunks.len() as u32).to_le_bytes());
+        data.extend_from_slice(b"WEBP");
+        data.extend_from_slice(&chunks);
+        data
+    }
+
+    fn xmp(properties: &str) -> Vec<u8> {
+        let mut data = vec![0xff, 0xd8];
+        data.extend_from_slice(b"http://ns.adobe.com/xap/1.0/\0<?xpacket begin=\"\"?>");
+        data.extend_from_slice(b"<x:xmpmeta xmlns:x=\"adobe:ns:meta/\"><rdf:Description ");
+        data.extend_from_slice(properties.as_bytes());
+        data.extend_from_slice(b"/></x:xmpmeta><?xpacket end=\"w\"?>");
+        data.extend_from_slice(&[0xff, 0xd9]);
+        data
+    }
+
+    /// Returns the offset of the TIFF structure in `data`
+    fn tiff_offset(data: &[u8], tiff: &[u8]) -> usize {
+        find(data, &tiff[..8]).unwrap()
+    }
+
+    fn check_png_checksums(data: &[u8]) {
+        let chunks = png_chunks(data);
+        assert_eq!(chunks.len(), 3);
+        for (_, start, end) in chunks {
+            let crc = u32::from_be_bytes(data[end..end + 4].try_into().unwrap());
+            assert_eq!(crc, crc32fast::hash(&data[start - 4..end]));
+        }
+    }
+
+    #[test]
+    fn scrubs_gps_and_maker_note_of_every_format() {
+        for big_endian in [false, true] {
+            let (tiff, gps_range) = tiff(big_endian, true, true);
+            for (format, original) in [
+                ("jpeg", jpeg(&tiff)),
+                ("png", png(&tiff)),
+                ("webp", webp(&tiff)),
+            ] {
+                let mut data = original.clone();
+                assert_eq!(
+                    scrub_location(&mut data),
+                    vec!["Maker note", "GPS IFD with 2 entries"],
+                    "{}",
+                    format
+                );
+                assert_eq!(data.len(), original.len(), "{}", format);
+
+                let offset = tiff_offset(&data, &tiff);
+                let scrubbed = &data[offset..offset + tiff.len()];
+                assert!(
+                    scrubbed[gps_range.clone()].iter().all(|byte| *byte == 0),
+                    "{}",
+                    format
+                );
+                assert!(find(scrubbed, MAKER_NOTE).is_none(), "{}", format);
+                assert!(find(scrubbed, MAKE).is_some(), "{}", format);
+                if format == "png" {
+                    check_png_checksums(&data);
+                }
+
+                // Scrubbed files are reported as clean
+                assert!(scrub_location(&mut data).is_empty(), "{}", format);
+            }
+        }
+    }
+
+    #[test]
+    fn keeps_files_without_location() {
+        for big_endian in [false, true] {
+            let (tiff, _) = tiff(big_endian, false, false);
+            for original in [jpeg(&tiff), png(&tiff), webp(&tiff)] {
+                let mut data = original.clone();
+                assert!(scrub_location(&mut data).is_empty());
+                assert_eq!(data, original);
+            }
+        }
+    }
+
+    #[test]
+    fn keeps_files_without_metadata() {
+        for original in [
+            vec![],
+            vec![0xff, 0xd8, 0xff, 0xd9],
+            png(b"not tiff"),
+            webp(b""),
+        ] {
+            let mut data = original.clone();
+            assert!(scrub_location(&mut data).is_empty());
+            assert_eq!(data, original);
+        }
+    }
+
+    #[test]
+    fn scrubs_xmp_with_location() {
+        let original = xmp("exif:GPSLatitude=\"48,8.0N\" photoshop:City=\"Stuttgart\"");
+        let mut data = original.clone();
+        assert_eq!(
+            scrub_location(&mut data),
+            vec!["XMP with exif:GPS, photoshop:City"]
+        );
+        assert_eq!(data.len(), original.len());
+        assert!(find(&data, b"GPS").is_none());
+        assert!(find(&data, b"Stuttgart").is_none());
+        assert!(data.ends_with(b"<?xpacket end=\"w\"?>\xff\xd9"));
+    }
+
+    #[test]
+    fn keeps_xmp_without_location() {
+        let original = xmp("xmp:CreatorTool=\"Camera\"");
+        let mut data = original.clone();
+        assert!(scrub_location(&mut data).is_empty());
+        assert_eq!(data, original);
+
+        // Unterminated packets are left alone
+        let mut data = original[..original.len() - 30].to_vec();
+        assert!(scrub_location(&mut data).is_empty());
+    }
+
+    #[test]
+    fn does_not_panic_on_truncated_files() {
+        for big_endian in [false, true] {
+            let (tiff, _) = tiff(big_endian, true, true);
+            let xmp = xmp("exif:GPSLatitude=\"48,8.0N\"");
+            for original in [jpeg(&tiff), png(&tiff), webp(&tiff), xmp] {
+                for length in 0..original.len() {
+                    scrub_location(&mut original[..length].to_vec());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn does_not_panic_on_broken_offsets() {
+        let header = b"II*\0\x08\0\0\0";
+        let mut cases: Vec<Vec<u8>> = Vec::new();
+
+        // GPS IFD and its values out of bounds
+        for (gps_offset, field_type, count, value) in [
+            (u32::MAX, 5, 3, 0),
+            (0xfffffff0, 5, 3, 0),
+            (26, 5, u32::MAX, 0),
+            (26, 12, u32::MAX, u32::MAX),
+            (26, 99, 1, 0),
+        ] {
+            let mut writer = Writer {
+                data: header.to_vec(),
+                big_endian: false,
+            };
+            writer.u16(1);
+            writer.entry(GPS_IFD_TAG, 4, 1, gps_offset);
+            writer.u32(0);
+            writer.u16(1);
+            writer.entry(0x0002, field_type, count, value);
+            writer.u32(0);
+            cases.push(writer.data);
+        }
+
+        // IFDs referring to themselves, huge entry counts and maker notes out of bounds
+        let mut writer = Writer {
+            data: header.to_vec(),
+            big_endian: false,
+        };
+        writer.u16(2);
+        writer.entry(EXIF_IFD_TAG, 4, 1, 8);
+        writer.entry(MAKER_NOTE_TAG, 7, u32::MAX, 8);
+        writer.u32(8);
+        cases.push(writer.data);
+        cases.push(b"II*\0\x08\0\0\0\xff\xff".to_vec());
+        cases.push(b"MM\0*\xff\xff\xff\xff".to_vec());
+
+        for tiff in cases {
+            for mut data in [jpeg(&tiff), png(&tiff), webp(&tiff), tiff.clone()] {
+                scrub_location(&mut data);
+            }
+        }
+    }
+
+    #[test]
+    fn does_not_panic_on_broken_containers() {
+        let mut cases = vec![
+            // Exif prefix at the end
+            EXIF_PREFIX.to_vec(),
+            [b"xx".as_slice(), EXIF_PREFIX, b"II*"].concat(),
+            // WebP chunks larger than the file
+            [b"RIFF\0\0\0\0WEBPEXIF\xff\xff\xff\xffII*\0".as_slice()].concat(),
+            b"RIFF\0\0\0\0WEBPVP8X\xfe\xff\xff\xff".to_vec(),
+            b"RIFF\0\0\0\0WEBP".to_vec(),
+            // PNG chunks larger than the file
+            [PNG_SIGNATURE, b"\xff\xff\xff\xffeXIfII*\0\x08\0\0\0\0\0"].concat(),
+            [PNG_SIGNATURE, b"\0\0\0\x08eXIfII*\0"].concat(),
+        ];
+        // PNG with a scrubbed eXIf chunk followed by a truncated one
+        let (tiff, _) = tiff(false, true, false);
+        let mut data = png(&tiff);
+        data.extend_from_slice(b"\0\0\x10\0eXIf");
+        cases.push(data);
+
+        for mut data in cases {
+            scrub_location(&mut data);
+        }
+    }
+
+    #[test]
+    fn does_not_panic_on_random_data() {
+        // Deterministic pseudo-random bytes (xorshift) with metadata markers in between
+        let mut state = 0x2545f4914f6cdd1du64;
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+        let markers: [&[u8]; 5] = [EXIF_PREFIX, b"II*\0", b"MM\0*", XMP_START, XMP_END];
+        for _ in 0..200 {
+            let mut data = Vec::new();
+            while data.len() < 512 {
+                match next() % 8 {
+                    0 => data.extend_from_slice(markers[(next() % 5) as usize]),
+                    _ => data.extend_from_slice(&next().to_le_bytes()),
+                }
+            }
+            for prefix in [&b""[..], PNG_SIGNATURE, b"RIFF\0\0\0\0WEBP"] {
+                scrub_location(&mut [prefix, &data].concat());
+            }
+        }
+    }
+}
@@ -0,0 +1,438 @@
+use std::{
+    collections::{hash_map::Entry, HashMap, HashSet},
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    constants::MAX_IMAGE_HISTORY,
+    events::ImageEventKind,
+    util::{
+        durability::Durability,
+        image::StoredImage,
+        image_metadata::ImageMetadata,
+        path::{read_json_or_default, write_atomically},
+    },
+};
+
+#[derive(Clone, Serialize, Deserialize)]
+struct IndexEntry {
+    // Times in seconds since the unix epoch
+    created_at: u64,
+    state_changed_at: u64,
+    // SHA-256 of the stored image (hex), unknown for images stored before it was introduced
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    checksum: Option<String>,
+    // Provided along with the upload
+    #[serde(default, skip_serializing_if = "ImageMetadata::is_empty")]
+    metadata: ImageMetadata,
+    // Name of the tenant the image was uploaded by, `None` for images uploaded via `/upload`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tenant: Option<String>,
+    // Of the stored image and its raw file when it was last written, for quotas of tenants.
+    // Unknown for images written before it was introduced.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    bytes: Option<u64>,
+    // Placeholder of the approved image, see `create_blurhash`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    blurhash: Option<String>,
+    // Changes of its state, oldest first, at most `MAX_IMAGE_HISTORY`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    history: Vec<HistoryEntry>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct HistoryEntry {
+    event: ImageEventKind,
+    // In seconds since the unix epoch
+    time: u64,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct MetadataIndexData {
+    images: HashMap<Uuid, IndexEntry>,
+    // Human-readable names of images, e.g. for pretty URLs, at most one per image
+    #[serde(default)]
+    aliases: HashMap<String, Uuid>,
+    #[serde(skip)]
+    dirty: bool,
+}
+
+/// When an image was uploaded and when it entered its current state
+#[derive(Clone, Copy)]
+pub struct ImageTimes {
+    pub created_at: SystemTime,
+    pub state_changed_at: SystemTime,
+}
+
+/// Keeps track of when images were uploaded and changed their state, e.g. were approved (with a
+/// history of these changes), and of
+/// the metadata provided along with uploads, the tenants they belong to and their aliases.
+/// Moving an image to another state keeps its modification time, so that can't be used for this.
+/// The state itself is still determined by the directory an image is stored in.
+#[derive(Clone)]
+pub struct MetadataIndex {
+    path: PathBuf,
+    data: Arc<Mutex<MetadataIndexData>>,
+}
+
+impl MetadataIndex {
+    /// Loads the index from `path`. Starts with an empty index if it cannot be read.
+    pub fn load(path: PathBuf) -> Self {
+        let data = read_json_or_default(&path, "metadata index");
+        Self {
+            path: path,
+            data: Arc::new(Mutex::new(data)),
+        }
+    }
+
+    /// Records that the image `uuid` was uploaded just now, with its `metadata` and the `tenant`
+    /// it belongs to, if any
+    pub fn record_upload(&self, uuid: Uuid, metadata: ImageMetadata, tenant: Option<String>) {
+        let now = now_secs();
+        let mut data = self.data.lock().unwrap();
+        data.images.insert(
+            uuid,
+            IndexEntry {
+                created_at: now,
+                state_changed_at: now,
+                checksum: None,
+                metadata: metadata,
+                tenant: tenant,
+                bytes: None,
+                blurhash: None,
+                history: vec![HistoryEntry {
+                    event: ImageEventKind::Uploaded,
+                    time: now,
+                }],
+            },
+        );
+        data.dirty = true;
+    }
+
+    /// Records that the image `uuid` changed its state just now, by `event`
+    pub fn record_state_change(&self, uuid: Uuid, event: ImageEventKind) {
+        let now = now_secs();
+        let mut data = self.data.lock().unwrap();
+        let entry = data.images.entry(uuid).or_insert(IndexEntry {
+            // Unknown, as the upload happened before the index was introduced
+            created_at: now,
+            state_changed_at: now,
+            checksum: None,
+            metadata: ImageMetadata::default(),
+            tenant: None,
+            bytes: None,
+            blurhash: None,
+            history: Vec::new(),
+        });
+        entry.state_changed_at = now;
+        entry.history.push(HistoryEntry {
+            event: event,
+            time: now,
+        });
+        if entry.history.len() > MAX_IMAGE_HISTORY {
+            entry.history.remove(0);
+        }
+        data.dirty = true;
+    }
+
+    /// Records known `times` and `metadata` of the image `uuid`, e.g. of a restored image
+    pub fn record_times(&self, uuid: Uuid, times: ImageTimes, metadata: ImageMetadata) {
+        let mut data = self.data.lock().unwrap();
+        data.images.insert(
+            uuid,
+            IndexEntry {
+                created_at: to_secs(times.created_at),
+                state_changed_at: to_secs(times.state_changed_at),
+                checksum: None,
+                metadata: metadata,
+                tenant: None,
+                bytes: None,
+                blurhash: None,
+                history: Vec::new(),
+            },
+        );
+        data.dirty = true;
+    }
+
+    /// Records the `checksum` of the stored image `uuid`, after it was written
+    pub fn record_checksum(&self, uuid: Uuid, checksum: String) {
+        let now = now_secs();
+        let mut data = self.data.lock().unwrap();
+        let entry = data.images.entry(uuid).or_insert(IndexEntry {
+            // Unknown, as the upload happened before the index was introduced
+            created_at: now,
+            state_changed_at: now,
+            checksum: None,
+            metadata: ImageMetadata::default(),
+            tenant: None,
+            bytes: None,
+            blurhash: None,
+            history: Vec::new(),
+        });
+        entry.checksum = Some(checksum);
+        data.dirty = true;
+    }
+
+    /// Replaces the `metadata` of the image `uuid`, e.g. after it was edited
+    pub fn record_metadata(&self, uuid: Uuid, metadata: ImageMetadata) {
+        let now = now_secs();
+        let mut data = self.data.lock().unwrap();
+        let entry = data.images.entry(uuid).or_insert(IndexEntry {
+            // Unknown, as the upload happened before the index was introduced
+            created_at: now,
+            state_changed_at: now,
+            checksum: None,
+            metadata: ImageMetadata::default(),
+            tenant: None,
+            bytes: None,
+            blurhash: None,
+            history: Vec::new(),
+        });
+        entry.metadata = metadata;
+        data.dirty = true;
+    }
+
+    /// Records that the image `uuid` belongs to `tenant`, after it was uploaded or restored
+    pub fn record_tenant(&self, uuid: Uuid, tenant: String) {
+        let now = now_secs();
+        let mut data = self.data.lock().unwrap();
+        let entry = data.images.entry(uuid).or_insert(IndexEntry {
+            created_at: now,
+            state_changed_at: now,
+            checksum: None,
+            metadata: ImageMetadata::default(),
+            tenant: None,
+            bytes: None,
+            blurhash: None,
+            history: Vec::new(),
+        });
+        entry.tenant = Some(tenant);
+        data.dirty = true;
+    }
+
+    /// Records the `bytes` the image `uuid` and its raw file take up, after it was written
+    pub fn record_bytes(&self, uuid: Uuid, bytes: u64) {
+        let mut data = self.data.lock().unwrap();
+        if let Some(entry) = data.images.get_mut(&uuid) {
+            entry.bytes = Some(bytes);
+            data.dirty = true;
+        }
+    }
+
+    /// Records the `blurhash` of the image `uuid`, after it was approved or rotated
+    pub fn record_blurhash(&self, uuid: Uuid, blurhash: String) {
+        let mut data = self.data.lock().unwrap();
+        if let Some(entry) = data.images.get_mut(&uuid) {
+            entry.blurhash = Some(blurhash);
+            data.dirty = true;
+        }
+    }
+
+    /// Returns the BlurHash of the image `uuid`, if it was computed already
+    pub fn blurhash(&self, uuid: Uuid) -> Option<String> {
+        let data = self.data.lock().unwrap();
+        data.images
+            .get(&uuid)
+            .and_then(|entry| entry.blurhash.clone())
+    }
+
+    /// Returns the recorded checksum of the stored image `uuid`, if known
+    pub fn checksum(&self, uuid: Uuid) -> Option<String> {
+        let data = self.data.lock().unwrap();
+        data.images
+            .get(&uuid)
+            .and_then(|entry| entry.checksum.clone())
+    }
+
+    /// Returns the metadata of the image `uuid`, which is empty if none was provided
+    pub fn metadata(&self, uuid: Uuid) -> ImageMetadata {
+        let data = self.data.lock().unwrap();
+        data.images
+            .get(&uuid)
+            .map(|entry| entry.metadata.clone())
+            .unwrap_or_default()
+    }
+
+    /// Returns the name of the tenant the image `uuid` belongs to, if any
+    pub fn tenant(&self, uuid: Uuid) -> Option<String> {
+        let data = self.data.lock().unwrap();
+        data.images
+            .get(&uuid)
+            .and_then(|entry| entry.tenant.clone())
+    }
+
+    /// Returns the IDs of all images that belong to `tenant` along with their recorded bytes
+    pub fn tenant_images(&self, tenant: &str) -> Vec<(Uuid, Option<u64>)> {
+        let data = self.data.lock().unwrap();
+        data.images
+            .iter()
+            .filter(|(_, entry)| entry.tenant.as_deref() == Some(tenant))
+            .map(|(uuid, entry)| (*uuid, entry.bytes))
+            .collect()
+    }
+
+    /// Assigns `alias` to the image `uuid`, replacing its previous alias, which is returned.
+    /// Returns the ID of the other image, if the alias is already assigned to one.
+    pub fn set_alias(&self, uuid: Uuid, alias: String) -> Result<Option<String>, Uuid> {
+        let mut data = self.data.lock().unwrap();
+        match data.aliases.get(&alias) {
+            Some(owner) if *owner == uuid => return Ok(None),
+            Some(owner) => return Err(*owner),
+            None => (),
+        }
+        let previous = remove_alias_of(&mut data.aliases, uuid);
+        data.aliases.insert(alias, uuid);
+        data.dirty = true;
+        Ok(previous)
+    }
+
+    /// Removes the alias of the image `uuid` and returns it, if it had one
+    pub fn remove_alias(&self, uuid: Uuid) -> Option<String> {
+        let mut data = self.data.lock().unwrap();
+        let alias = remove_alias_of(&mut data.aliases, uuid);
+        if alias.is_some() {
+            data.dirty = true;
+        }
+        alias
+    }
+
+    /// Returns the alias of the image `uuid`, if any
+    pub fn alias(&self, uuid: Uuid) -> Option<String> {
+        let data = self.data.lock().unwrap();
+        data.aliases
+            .iter()
+            .find(|(_, owner)| **owner == uuid)
+            .map(|(alias, _)| alias.clone())
+    }
+
+    /// Returns the aliases of all images that have one, by image
+    pub fn aliases(&self) -> HashMap<Uuid, String> {
+        let data = self.data.lock().unwrap();
+        data.aliases
+            .iter()
+            .map(|(alias, uuid)| (*uuid, alias.clone()))
+            .collect()
+    }
+
+    /// Returns the ID of the image `alias` is assigned to, if any
+    pub fn resolve_alias(&self, alias: &str) -> Option<Uuid> {
+        let data = self.data.lock().unwrap();
+        data.aliases.get(alias).copied()
+    }
+
+    /// Removes the image `uuid` (and its alias) from the index, e.g. after it was deleted
+    pub fn remove(&self, uuid: Uuid) {
+        let mut data = self.data.lock().unwrap();
+        let alias = remove_alias_of(&mut data.aliases, uuid);
+        if data.images.remove(&uuid).is_some() || alias.is_some() {
+            data.dirty = true;
+        }
+    }
+
+    /// Returns the times of the image `uuid`, if known
+    pub fn get(&self, uuid: Uuid) -> Option<ImageTimes> {
+        let data = self.data.lock().unwrap();
+        data.images.get(&uuid).map(|entry| ImageTimes {
+            created_at: from_secs(entry.created_at),
+            state_changed_at: from_secs(entry.state_changed_at),
+        })
+    }
+
+    /// Returns the changes of the state of the image `uuid` with their times, oldest first.
+    /// Empty for images changed before the history was introduced.
+    pub fn history(&self, uuid: Uuid) -> Vec<(ImageEventKind, SystemTime)> {
+        let data = self.data.lock().unwrap();
+        data.images
+            .get(&uuid)
+            .map(|entry| {
+                entry
+                    .history
+                    .iter()
+                    .map(|change| (change.event, from_secs(change.time)))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Adds the stored `images` that are missing in the index (with the times they have been
+    /// listed with) and removes the entries of images that don't exist anymore.
+    /// Returns the number of added and removed entries.
+    pub fn sync(&self, images: &[StoredImage]) -> (usize, usize) {
+        let mut data = self.data.lock().unwrap();
+
+        let mut added = 0;
+        for image in images {
+            if let Entry::Vacant(entry) = data.images.entry(image.uuid) {
+                entry.insert(IndexEntry {
+                    created_at: to_secs(image.created_at),
+                    state_changed_at: to_secs(image.state_changed_at),
+                    checksum: None,
+                    metadata: ImageMetadata::default(),
+                    tenant: None,
+                    bytes: None,
+                    blurhash: None,
+                    history: Vec::new(),
+                });
+                added += 1;
+            }
+        }
+
+        let before = data.images.len();
+        let existing: HashSet<Uuid> = images.iter().map(|image| image.uuid).collect();
+        data.images.retain(|uuid, _| existing.contains(uuid));
+        data.aliases.retain(|_, uuid| existing.contains(uuid));
+        let removed = before - data.images.len();
+
+        if added > 0 || removed > 0 {
+            data.dirty = true;
+        }
+        (added, removed)
+    }
+
+    /// Writes the index to disk, if it has changed since it was last written.
+    /// The index is written to a temporary file first, so a crash can't leave a corrupt index.
+    pub fn save(&self) -> Result<(), String> {
+        let mut data = self.data.lock().unwrap();
+        if !data.dirty {
+            return Ok(());
+        }
+
+        let json = match serde_json::to_vec(&*data) {
+            Err(err) => return Err(format!("Could not serialize metadata index: {}", err)),
+            Ok(json) => json,
+        };
+        write_atomically(&self.path, &json, Durability::None)?;
+
+        data.dirty = false;
+        Ok(())
+    }
+}
+
+/// Removes the alias of the image `uuid` from `aliases` and returns it, if it had one
+fn remove_alias_of(aliases: &mut HashMap<String, Uuid>, uuid: Uuid) -> Option<String> {
+    let alias = aliases
+        .iter()
+        .find(|(_, owner)| **owner == uuid)
+        .map(|(alias, _)| alias.clone())?;
+    aliases.remove(&alias);
+    Some(alias)
+}
+
+fn now_secs() -> u64 {
+    to_secs(SystemTime::now())
+}
+
+fn to_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+fn from_secs(secs: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(secs)
+}
@@ -1,4 +1,28 @@
+pub mod access_stats;
 pub mod auth;
+pub mod avif;
+pub mod cache_index;
+pub mod client_hints;
+pub mod client_ip;
 pub mod cors;
+pub mod durability;
+pub mod idempotency;
 pub mod image;
+pub mod image_lock;
+pub mod image_metadata;
+pub mod input_limits;
+pub mod listen;
+pub mod listing;
+pub mod location;
+pub mod metadata_index;
+pub mod output_limits;
 pub mod path;
+pub mod pipeline;
+pub mod placeholder;
+pub mod preview_token;
+pub mod recipe;
+pub mod short_id;
+pub mod tenant;
+pub mod watchdog;
+pub mod webp;
+pub mod zip;
@@ -0,0 +1,118 @@
+use config::Config;
+
+use crate::constants::{DEFAULT_MAX_OUTPUT_DIMENSION, DEFAULT_MAX_OUTPUT_PIXELS};
+
+/// What happens to requests for renditions exceeding the `OutputLimits`
+#[derive(Clone, Copy, PartialEq)]
+pub enum LimitBehavior {
+    // Respond with 400
+    Reject,
+    // Scale the requested dimensions down (keeping their aspect ratio) until they are within
+    Clamp,
+}
+
+/// Maximum dimensions of renditions, so requests like `?width=100000` can't make libvips
+/// allocate huge buffers
+#[derive(Clone, Copy)]
+pub struct OutputLimits {
+    pub max_width: i32,
+    pub max_height: i32,
+    pub max_pixels: i64,
+    pub behavior: LimitBehavior,
+}
+
+/// Parses the limits from the config properties `MAX_OUTPUT_WIDTH`, `MAX_OUTPUT_HEIGHT`,
+/// `MAX_OUTPUT_PIXELS` and `OUTPUT_LIMIT_BEHAVIOR` (`reject` or `clamp`, defaults to `reject`)
+pub fn parse_output_limits(config: &Config) -> OutputLimits {
+    OutputLimits {
+        max_width: config
+            .get::<i32>("MAX_OUTPUT_WIDTH")
+            .unwrap_or(DEFAULT_MAX_OUTPUT_DIMENSION),
+        max_height: config
+            .get::<i32>("MAX_OUTPUT_HEIGHT")
+            .unwrap_or(DEFAULT_MAX_OUTPUT_DIMENSION),
+        max_pixels: config
+            .get::<i64>("MAX_OUTPUT_PIXELS")
+            .unwrap_or(DEFAULT_MAX_OUTPUT_PIXELS),
+        behavior: match config.get_string("OUTPUT_LIMIT_BEHAVIOR").as_deref() {
+            Ok("clamp") => LimitBehavior::Clamp,
+            _ => LimitBehavior::Reject,
+        },
+    }
+}
+
+impl OutputLimits {
+    /// Checks the requested dimensions `(width, height)` against the limits. Returns the
+    /// dimensions to use, which are clamped if necessary and configured that way, or the
+    /// problem, if they are rejected.
+    pub fn apply(&self, (width, height): (i32, i32)) -> Result<(i32, i32), String> {
+        if width <= 0 || height <= 0 {
+            return Err("Width and height must be positive".to_owned());
+        }
+
+        let pixels = width as i64 * height as i64;
+        if width <= self.max_width && height <= self.max_height && pixels <= self.max_pixels {
+            return Ok((width, height));
+        }
+        if self.behavior == LimitBehavior::Reject {
+            return Err(format!(
+                "Renditions are limited to {}x{} and {} pixels",
+                self.max_width, self.max_height, self.max_pixels
+            ));
+        }
+
+        let scale = (self.max_width as f64 / width as f64)
+            .min(self.max_height as f64 / height as f64)
+            .min((self.max_pixels as f64 / pixels as f64).sqrt());
+        Ok((
+            ((width as f64 * scale).floor() as i32).max(1),
+            ((height as f64 * scale).floor() as i32).max(1),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limits(behavior: LimitBehavior) -> OutputLimits {
+        OutputLimits {
+            max_width: 4000,
+            max_height: 3000,
+            max_pixels: 6_000_000,
+            behavior: behavior,
+        }
+    }
+
+    #[test]
+    fn apply_accepts_dimensions_within_limits() {
+        assert_eq!(
+            limits(LimitBehavior::Reject).apply((4000, 1500)),
+            Ok((4000, 1500))
+        );
+    }
+
+    #[test]
+    fn apply_rejects_non_positive_dimensions() {
+        for behavior in [LimitBehavior::Reject, LimitBehavior::Clamp] {
+            assert!(limits(behavior).apply((0, 100)).is_err());
+            assert!(limits(behavior).apply((100, -1)).is_err());
+        }
+    }
+
+    #[test]
+    fn apply_rejects_excessive_dimensions() {
+        let limits = limits(LimitBehavior::Reject);
+        assert!(limits.apply((4001, 100)).is_err());
+        assert!(limits.apply((100, 3001)).is_err());
+        assert!(limits.apply((3000, 3000)).is_err());
+    }
+
+    #[test]
+    fn apply_clamps_excessive_dimensions_keeping_aspect_ratio() {
+        let limits = limits(LimitBehavior::Clamp);
+        assert_eq!(limits.apply((8000, 2000)), Ok((4000, 1000)));
+        assert_eq!(limits.apply((3000, 3000)), Ok((2449, 2449)));
+        assert_eq!(limits.apply((8000, 1)), Ok((4000, 1)));
+    }
+}
@@ -1,27 +1,336 @@
-use std::path::PathBuf;
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
 
-use crate::constants::{CACHE_PATH, ORIGINAL_PATH, PENDING_PATH, RAW_PATH, UNAPPROVED_PATH};
+use serde::de::DeserializeOwned;
+use uuid::Uuid;
+
+use crate::constants::{
+    ACCESS_STATS_PATH, CACHE_INDEX_PATH, CACHE_PATH, DATA_PATH, FLAGGED_PATH, METADATA_INDEX_PATH,
+    OBJECTS_PATH, ORIGINAL_PATH, PENDING_PATH, PROXY_CACHE_PATH, QUARANTINE_PATH, RAW_PATH,
+    RUNNING_MARKER_PATH, TENANTS_PATH, TRASH_PATH, UNAPPROVED_PATH,
+};
+use crate::util::{durability::Durability, tenant::is_valid_tenant_name};
+
+// Path all data is stored in
+pub fn get_data_path() -> PathBuf {
+    DATA_PATH.iter().collect()
+}
+
+// Root of the images of `tenant`, which has the same layout as the data path, or the data path
+// itself for images without tenant
+pub fn get_root_path(tenant: Option<&str>) -> PathBuf {
+    match tenant {
+        None => get_data_path(),
+        Some(tenant) => TENANTS_PATH.iter().collect::<PathBuf>().join(tenant),
+    }
+}
+
+// Returns `path` (one of the image paths) in the root of `tenant`
+fn in_root(path: &[&str], tenant: Option<&str>) -> PathBuf {
+    get_root_path(tenant).join(path[DATA_PATH.len()..].iter().collect::<PathBuf>())
+}
 
 // Path of images that are not yet assigned to a review
-pub fn get_pending_path() -> PathBuf {
-    PENDING_PATH.iter().collect()
+pub fn get_pending_path(tenant: Option<&str>) -> PathBuf {
+    in_root(&PENDING_PATH, tenant)
 }
 
 // Path where images of unapproved reviews are stored
-pub fn get_unapproved_path() -> PathBuf {
-    UNAPPROVED_PATH.iter().collect()
+pub fn get_unapproved_path(tenant: Option<&str>) -> PathBuf {
+    in_root(&UNAPPROVED_PATH, tenant)
+}
+
+// Path of submitted images the moderation hook flagged, which are held for review
+pub fn get_flagged_path(tenant: Option<&str>) -> PathBuf {
+    in_root(&FLAGGED_PATH, tenant)
 }
 
 // Path where public servable images are stored
-pub fn get_original_path() -> PathBuf {
-    ORIGINAL_PATH.iter().collect()
+pub fn get_original_path(tenant: Option<&str>) -> PathBuf {
+    in_root(&ORIGINAL_PATH, tenant)
 }
 
 // Path for image cache
-pub fn get_cache_path() -> PathBuf {
-    CACHE_PATH.iter().collect()
+pub fn get_cache_path(tenant: Option<&str>) -> PathBuf {
+    in_root(&CACHE_PATH, tenant)
+}
+
+// Path where raw images are stored as uploaded
+pub fn get_raw_path(tenant: Option<&str>) -> PathBuf {
+    in_root(&RAW_PATH, tenant)
+}
+
+// Tenants that have a root (including ones that are no longer configured) and `None` for the
+// images without tenant, i.e. all roots images are stored in
+pub fn list_roots() -> Vec<Option<String>> {
+    let mut roots = vec![None];
+    let tenants_path: PathBuf = TENANTS_PATH.iter().collect();
+    if let Ok(entries) = fs::read_dir(&tenants_path) {
+        let mut tenants = entries
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.path().is_dir())
+            .filter_map(|entry| entry.file_name().into_string().ok())
+            .filter(|name| is_valid_tenant_name(name))
+            .collect::<Vec<_>>();
+        tenants.sort();
+        roots.extend(tenants.into_iter().map(Some));
+    }
+    roots
+}
+
+// Path of external images fetched by the proxy, which are not stored as originals
+pub fn get_proxy_cache_path() -> PathBuf {
+    PROXY_CACHE_PATH.iter().collect()
+}
+
+// Path of the index of last accesses of cache entries
+pub fn get_cache_index_path() -> PathBuf {
+    CACHE_INDEX_PATH.iter().collect()
+}
+
+// Path of approved images stored under their content hash, see `content_store`
+pub fn get_objects_path() -> PathBuf {
+    OBJECTS_PATH.iter().collect()
+}
+
+// Path of the statistics of requests of images
+pub fn get_access_stats_path() -> PathBuf {
+    ACCESS_STATS_PATH.iter().collect()
+}
+
+// Path of the index of upload and state change times of images
+pub fn get_metadata_index_path() -> PathBuf {
+    METADATA_INDEX_PATH.iter().collect()
+}
+
+// Path where uploads and files are moved to, that could not be decoded
+pub fn get_quarantine_path() -> PathBuf {
+    QUARANTINE_PATH.iter().collect()
+}
+
+// Path where deleted images are moved to with `TRASH_ENABLED`, see `trash`
+pub fn get_trash_path() -> PathBuf {
+    TRASH_PATH.iter().collect()
+}
+
+// Path of the marker that exists while the service is running, to detect unclean shutdowns
+pub fn get_running_marker_path() -> PathBuf {
+    RUNNING_MARKER_PATH.iter().collect()
+}
+
+// Directories of the root of `tenant`
+pub fn get_root_paths(tenant: Option<&str>) -> [PathBuf; 6] {
+    [
+        get_pending_path(tenant),
+        get_unapproved_path(tenant),
+        get_flagged_path(tenant),
+        get_original_path(tenant),
+        get_cache_path(tenant),
+        get_raw_path(tenant),
+    ]
+}
+
+// All directories images are stored in, in the `roots` of tenants (see `list_roots`)
+pub fn get_data_paths(roots: &[Option<String>]) -> Vec<PathBuf> {
+    let mut paths = Vec::from([get_proxy_cache_path(), get_objects_path()]);
+    for tenant in roots {
+        paths.extend(get_root_paths(tenant.as_deref()));
+    }
+    paths
+}
+
+/// Creates the directories of the root of `tenant`, if they don't exist yet, e.g. for tenants
+/// added by a reload of the config
+pub fn create_root(tenant: Option<&str>) -> Result<(), io::Error> {
+    for dir in get_root_paths(tenant) {
+        fs::create_dir_all(dir)?;
+    }
+    Ok(())
+}
+
+/// Creates `dir` if it does not exist yet and checks that it is writable by writing
+/// (and removing) a hidden probe file.
+/// Returns a message naming the offending path otherwise.
+pub fn prepare_data_dir(dir: &Path) -> Result<(), String> {
+    if !dir.exists() {
+        if let Err(err) = fs::create_dir_all(dir) {
+            return Err(format!(
+                "Data directory '{}': Could not create it ({})",
+                dir.display(),
+                err
+            ));
+        }
+        log::info!("Created missing data directory '{}'", dir.display());
+    }
+
+    if !dir.is_dir() {
+        return Err(format!(
+            "Data directory '{}': Not a directory",
+            dir.display()
+        ));
+    }
+
+    let probe = dir.join(".write-probe");
+    if let Err(err) = fs::write(&probe, []) {
+        return Err(format!(
+            "Data directory '{}': Not writable ({})",
+            dir.display(),
+            err
+        ));
+    }
+    if let Err(err) = fs::remove_file(&probe) {
+        return Err(format!(
+            "Data directory '{}': Could not remove probe file ({})",
+            dir.display(),
+            err
+        ));
+    }
+
+    Ok(())
+}
+
+/// Returns the names of all regular, non-hidden files in `dir`
+pub fn list_files(dir: &Path) -> Result<Vec<String>, io::Error> {
+    let mut names = Vec::new();
+    for dir_entry in fs::read_dir(dir)? {
+        let dir_entry = dir_entry?;
+        if !dir_entry.path().is_file() {
+            continue;
+        }
+
+        match dir_entry.file_name().into_string() {
+            Err(name) => log::error!("Unable to get file name as string for: '{:?}'", name),
+            Ok(name) if name.starts_with('.') => (),
+            Ok(name) => names.push(name),
+        }
+    }
+    Ok(names)
+}
+
+/// Returns the path files are written to before they are moved to `path` by `commit_temp_file`.
+/// It is next to `path` (so the move is atomic) and hidden (so `list_files` ignores it).
+pub fn get_temp_path(path: &Path) -> PathBuf {
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!(".{}.tmp", file_name))
+}
+
+/// Moves the temporary file of `path` (see `get_temp_path`) to `path`, so a crash can't leave a
+/// partially written file behind. Depending on `durability`, it is flushed to disk as well.
+pub fn commit_temp_file(path: &Path, durability: Durability) -> Result<(), io::Error> {
+    let tmp_path = get_temp_path(path);
+    durability.sync_file(&tmp_path)?;
+    fs::rename(&tmp_path, path)?;
+    durability.sync_parent_dir(path)
+}
+
+/// A uniquely named temporary file next to `path`, which is moved to `path` by `commit` and
+/// removed if it is dropped without being committed, e.g. when the operation writing it was
+/// abandoned by the watchdog
+pub struct TempFile {
+    // Where the file is moved to
+    target: PathBuf,
+    path: PathBuf,
+    committed: bool,
+}
+
+impl TempFile {
+    /// Reserves a temporary file for `path`. Like the one of `get_temp_path`, it is hidden and
+    /// removed by the crash recovery, if it is left behind.
+    pub fn new(path: &Path) -> Self {
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+        TempFile {
+            target: path.to_path_buf(),
+            path: path.with_file_name(format!(".{}.{}.tmp", file_name, Uuid::new_v4().simple())),
+            committed: false,
+        }
+    }
+
+    /// Path the file is written to
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Moves the file to `path`, see `commit_temp_file`
+    pub fn commit(mut self, durability: Durability) -> Result<(), io::Error> {
+        durability.sync_file(&self.path)?;
+        fs::rename(&self.path, &self.target)?;
+        self.committed = true;
+        durability.sync_parent_dir(&self.target)
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        if !self.committed {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// Writes `contents` to the temporary file of `path` first and then moves it to `path`,
+/// see `commit_temp_file`.
+pub fn write_atomically(
+    path: &Path,
+    contents: &[u8],
+    durability: Durability,
+) -> Result<(), String> {
+    let tmp_path = get_temp_path(path);
+    if let Err(err) = fs::write(&tmp_path, contents) {
+        return Err(format!("Could not write {:?}: {}", tmp_path, err));
+    }
+    if let Err(err) = commit_temp_file(path, durability) {
+        let _ = fs::remove_file(&tmp_path);
+        return Err(format!(
+            "Could not move {:?} to {:?}: {}",
+            tmp_path, path, err
+        ));
+    }
+    Ok(())
+}
+
+/// Reads the JSON file at `path`, e.g. one of the indices.
+/// Returns the default value, if it cannot be read or is invalid. `description` is used for logging.
+pub fn read_json_or_default<T: DeserializeOwned + Default>(path: &Path, description: &str) -> T {
+    match fs::read(path) {
+        Err(err) => {
+            log::info!(
+                "Starting with empty {}, as {:?} could not be read: {}",
+                description,
+                path,
+                err
+            );
+            T::default()
+        }
+        Ok(bytes) => match serde_json::from_slice(&bytes) {
+            Err(err) => {
+                log::error!(
+                    "Starting with empty {}, as {:?} is invalid: {}",
+                    description,
+                    path,
+                    err
+                );
+                T::default()
+            }
+            Ok(data) => data,
+        },
+    }
+}
+
+/// Returns the number and total size in bytes of the files in `dir`, as listed by `list_files`
+pub fn dir_usage(dir: &Path) -> Result<(usize, u64), io::Error> {
+    let names = list_files(dir)?;
+    let bytes = names
+        .iter()
+        .filter_map(|name| fs::metadata(dir.join(name)).ok())
+        .map(|metadata| metadata.len())
+        .sum();
+    Ok((names.len(), bytes))
 }
 
-pub fn get_raw_path() -> PathBuf {
-    RAW_PATH.iter().collect()
+/// Returns the number of bytes available to the service on the file system `path` is on
+pub fn available_space(path: &Path) -> Result<u64, io::Error> {
+    let stat = rustix::fs::statvfs(path)?;
+    Ok(stat.f_bavail * stat.f_frsize)
 }
@@ -0,0 +1,191 @@
+use std::{fmt, str::FromStr};
+
+use libvips::{ops, VipsImage};
+use sha2::{Digest, Sha256};
+
+use crate::constants::{MAX_BLUR_SIGMA, MAX_PIPELINE_OPERATIONS, MAX_SHARPEN_SIGMA};
+
+/// A single operation of a `Pipeline`
+#[derive(Clone, Copy, Debug)]
+pub enum Operation {
+    // Clockwise, by 90, 180 or 270 degrees
+    Rotate(i32),
+    // Mirrors the image horizontally (`h`) or vertically (`v`)
+    Flip(ops::Direction),
+    // Keeps the centered area of `width` x `height` pixels (at most the whole image)
+    Crop { width: i32, height: i32 },
+    // Gaussian blur with the given sigma
+    Blur(f64),
+    // Sharpens with the given sigma
+    Sharpen(f64),
+}
+
+/// Ordered transformations requested via `?ops=`, e.g. `rotate:90,crop:800x600,blur:2`.
+/// They are applied to the original before it is resized. As libvips evaluates lazily, all
+/// operations and the resize are executed in a single pass when the result is encoded.
+#[derive(Clone, Default, Debug)]
+pub struct Pipeline {
+    operations: Vec<Operation>,
+}
+
+impl FromStr for Pipeline {
+    type Err = String;
+
+    /// Parses and validates comma separated `<name>:<argument>` operations
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let operations = value
+            .split(',')
+            .filter(|operation| !operation.trim().is_empty())
+            .map(parse_operation)
+            .collect::<Result<Vec<_>, _>>()?;
+        if operations.len() > MAX_PIPELINE_OPERATIONS {
+            return Err(format!(
+                "At most {} operations are allowed",
+                MAX_PIPELINE_OPERATIONS
+            ));
+        }
+        Ok(Self {
+            operations: operations,
+        })
+    }
+}
+
+fn parse_operation(operation: &str) -> Result<Operation, String> {
+    let invalid = || format!("Invalid operation '{}'", operation);
+    let (name, argument) = operation.trim().split_once(':').ok_or_else(invalid)?;
+    let parse_sigma = |max: f64| match argument.parse::<f64>() {
+        Ok(sigma) if sigma > 0.0 && sigma <= max => Ok(sigma),
+        _ => Err(format!(
+            "Invalid operation '{}': sigma must be greater than 0 and at most {}",
+            operation, max
+        )),
+    };
+
+    match name {
+        "rotate" => match argument.parse::<i32>() {
+            Ok(angle) if angle == 90 || angle == 180 || angle == 270 => {
+                Ok(Operation::Rotate(angle))
+            }
+            _ => Err(format!(
+                "Invalid operation '{}': angle must be one of {{90, 180, 270}}",
+                operation
+            )),
+        },
+        "flip" => match argument {
+            "h" => Ok(Operation::Flip(ops::Direction::Horizontal)),
+            "v" => Ok(Operation::Flip(ops::Direction::Vertical)),
+            _ => Err(format!(
+                "Invalid operation '{}': direction must be 'h' or 'v'",
+                operation
+            )),
+        },
+        "crop" => {
+            let (width, height) = argument.split_once('x').ok_or_else(invalid)?;
+            match (width.parse::<i32>(), height.parse::<i32>()) {
+                (Ok(width), Ok(height)) if width > 0 && height > 0 => Ok(Operation::Crop {
+                    width: width,
+                    height: height,
+                }),
+                _ => Err(format!(
+                    "Invalid operation '{}': expected '<width>x<height>'",
+                    operation
+                )),
+            }
+        }
+        "blur" => parse_sigma(MAX_BLUR_SIGMA).map(Operation::Blur),
+        "sharpen" => parse_sigma(MAX_SHARPEN_SIGMA).map(Operation::Sharpen),
+        _ => Err(format!(
+            "Unknown operation '{}', expected one of rotate, flip, crop, blur or sharpen",
+            name
+        )),
+    }
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Operation::Rotate(angle) => write!(f, "rotate:{}", angle),
+            Operation::Flip(ops::Direction::Vertical) => write!(f, "flip:v"),
+            Operation::Flip(_) => write!(f, "flip:h"),
+            Operation::Crop { width, height } => write!(f, "crop:{}x{}", width, height),
+            Operation::Blur(sigma) => write!(f, "blur:{}", sigma),
+            Operation::Sharpen(sigma) => write!(f, "sharpen:{}", sigma),
+        }
+    }
+}
+
+/// Canonical form of the pipeline, e.g. `rotate:90,blur:2` for `rotate:90,blur:2.0`
+impl fmt::Display for Pipeline {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let operations: Vec<String> = self.operations.iter().map(|op| op.to_string()).collect();
+        write!(f, "{}", operations.join(","))
+    }
+}
+
+impl Pipeline {
+    pub fn is_empty(&self) -> bool {
+        self.operations.is_empty()
+    }
+
+    /// Identifies the pipeline in names of cache entries, i.e. renditions with the same
+    /// operations share the key. Returns none for the empty pipeline.
+    pub fn cache_key(&self) -> Option<String> {
+        if self.is_empty() {
+            return None;
+        }
+        let hash = format!("{:x}", Sha256::digest(self.to_string()));
+        Some(hash[..16].to_owned())
+    }
+
+    /// Returns the dimensions of an image of `width` x `height` after the pipeline
+    pub fn output_dimensions(&self, (mut width, mut height): (i32, i32)) -> (i32, i32) {
+        for operation in &self.operations {
+            match *operation {
+                Operation::Rotate(90 | 270) => (width, height) = (height, width),
+                Operation::Crop {
+                    width: crop_width,
+                    height: crop_height,
+                } => (width, height) = (width.min(crop_width), height.min(crop_height)),
+                _ => (),
+            }
+        }
+        (width, height)
+    }
+
+    /// Applies all operations to `image` in order
+    pub fn apply(&self, mut image: VipsImage) -> Result<VipsImage, libvips::error::Error> {
+        for operation in &self.operations {
+            image = match *operation {
+                Operation::Rotate(angle) => ops::rot(
+                    &image,
+                    match angle {
+                        90 => ops::Angle::D90,
+                        180 => ops::Angle::D180,
+                        _ => ops::Angle::D270,
+                    },
+                )?,
+                Operation::Flip(direction) => ops::flip(&image, direction)?,
+                Operation::Crop { width, height } => {
+                    let width = width.min(image.get_width());
+                    let height = height.min(image.get_height());
+                    ops::extract_area(
+                        &image,
+                        (image.get_width() - width) / 2,
+                        (image.get_height() - height) / 2,
+                        width,
+                        height,
+                    )?
+                }
+                Operation::Blur(sigma) => ops::gaussblur(&image, sigma)?,
+                Operation::Sharpen(sigma) => ops::sharpen_with_opts(
+                    &image,
+                    &ops::SharpenOptions {
+                        sigma: sigma,
+                        ..ops::SharpenOptions::default()
+                    },
+                )?,
+            };
+        }
+        Ok(image)
+    }
+}
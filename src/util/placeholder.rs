@@ -0,0 +1,26 @@
+use std::path::PathBuf;
+
+use axum::http::StatusCode;
+use config::Config;
+
+/// Image served by `/image/:id` instead of a plain-text error, if the image doesn't exist
+/// (or isn't visible to the client)
+#[derive(Clone)]
+pub struct Placeholder {
+    pub path: PathBuf,
+    // Status of responses with the placeholder, either 404 or 200
+    pub status: StatusCode,
+}
+
+/// Parses the placeholder from the config properties `PLACEHOLDER_IMAGE_PATH` and
+/// `PLACEHOLDER_STATUS` (defaults to 404). Returns none if no placeholder is configured.
+pub fn parse_placeholder(config: &Config) -> Option<Placeholder> {
+    let path = config.get_string("PLACEHOLDER_IMAGE_PATH").ok()?;
+    Some(Placeholder {
+        path: PathBuf::from(path),
+        status: match config.get::<u16>("PLACEHOLDER_STATUS") {
+            Ok(200) => StatusCode::OK,
+            _ => StatusCode::NOT_FOUND,
+        },
+    })
+}
@@ -0,0 +1,77 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use config::Config;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use uuid::Uuid;
+
+use crate::constants::DEFAULT_PREVIEW_TOKEN_TTL_SECS;
+
+/// Issues and verifies signed tokens that grant access to a single unapproved or pending image
+/// via `/image/:id?token=<token>`, e.g. so the uploading client can preview its image without
+/// knowing an API key. Tokens have the form `<expiry as unix timestamp>.<hex encoded signature>`.
+pub struct PreviewTokens {
+    secret: String,
+    ttl: Duration,
+}
+
+/// Parses the config properties `PREVIEW_TOKEN_SECRET` and `PREVIEW_TOKEN_TTL_SECS`.
+/// Returns none, if no secret is set, as tokens are disabled then.
+pub fn parse_preview_tokens(config: &Config) -> Option<PreviewTokens> {
+    Some(PreviewTokens {
+        secret: config.get_string("PREVIEW_TOKEN_SECRET").ok()?,
+        ttl: Duration::from_secs(
+            config
+                .get::<u64>("PREVIEW_TOKEN_TTL_SECS")
+                .unwrap_or(DEFAULT_PREVIEW_TOKEN_TTL_SECS),
+        ),
+    })
+}
+
+impl PreviewTokens {
+    /// Returns a new token for the image with `uuid` and the time it expires at
+    pub fn issue(&self, uuid: Uuid) -> (String, SystemTime) {
+        let expires_at = SystemTime::now() + self.ttl;
+        let expiry = expires_at
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let signature = self.mac(uuid, expiry).finalize().into_bytes();
+        (format!("{}.{:x}", expiry, signature), expires_at)
+    }
+
+    /// Returns whether `token` was issued for the image with `uuid` and has not expired yet
+    pub fn verify(&self, uuid: Uuid, token: &str) -> bool {
+        let Some((expiry, signature)) = token.split_once('.') else {
+            return false;
+        };
+        let (Ok(expiry), Some(signature)) = (expiry.parse::<u64>(), decode_hex(signature)) else {
+            return false;
+        };
+        // Expiries beyond what `SystemTime` can represent are invalid
+        match UNIX_EPOCH.checked_add(Duration::from_secs(expiry)) {
+            Some(expires_at) if expires_at >= SystemTime::now() => (),
+            _ => return false,
+        }
+        // Compares in constant time
+        self.mac(uuid, expiry).verify_slice(&signature).is_ok()
+    }
+
+    fn mac(&self, uuid: Uuid, expiry: u64) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(self.secret.as_bytes())
+            .expect("HMAC accepts any key length");
+        mac.update(format!("{}.{}", uuid, expiry).as_bytes());
+        mac
+    }
+}
+
+fn decode_hex(value: &str) -> Option<Vec<u8>> {
+    value
+        .as_bytes()
+        .chunks(2)
+        .map(|pair| match pair {
+            [_, _] => u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok(),
+            _ => None,
+        })
+        .collect()
+}
@@ -0,0 +1,98 @@
+use std::collections::HashMap;
+
+use config::{Config, ConfigError};
+use serde::Deserialize;
+
+use crate::util::{
+    image::{validate_rendition, ColorProfile, OutputFormat},
+    pipeline::Pipeline,
+};
+
+/// A named transformation configured by the operators and requested via `?recipe=<name>`
+#[derive(Clone, Debug)]
+pub struct Recipe {
+    pub pipeline: Pipeline,
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+    pub quality: Option<i32>,
+    // Defaults to `DEFAULT_OUTPUT_FORMAT`
+    pub format: Option<OutputFormat>,
+    pub progressive: Option<bool>,
+    pub color_profile: Option<ColorProfile>,
+    // Tuning of the WebP encoder, defaults to `WEBP_*`
+    pub webp_effort: Option<i32>,
+    pub webp_smart_subsample: Option<bool>,
+    pub webp_near_lossless: Option<bool>,
+    pub webp_alpha_quality: Option<i32>,
+}
+
+/// A recipe as written in the config
+#[derive(Deserialize)]
+struct RecipeConfig {
+    // Operations in the syntax of `?ops=`
+    ops: Option<String>,
+    width: Option<i32>,
+    height: Option<i32>,
+    quality: Option<i32>,
+    format: Option<OutputFormat>,
+    progressive: Option<bool>,
+    color_profile: Option<ColorProfile>,
+    webp_effort: Option<i32>,
+    webp_smart_subsample: Option<bool>,
+    webp_near_lossless: Option<bool>,
+    webp_alpha_quality: Option<i32>,
+}
+
+/// Parses the recipes from the config property `RECIPES`, which maps names to recipes, e.g.
+/// `hero: { ops: "crop:1600x900,sharpen:1", width: 1600, quality: 85, format: avif }`.
+/// Returns a message describing the problem, if any recipe is invalid.
+pub fn parse_recipes(config: &Config) -> Result<HashMap<String, Recipe>, String> {
+    let recipes = match config.get::<HashMap<String, RecipeConfig>>("RECIPES") {
+        Err(ConfigError::NotFound(_)) => return Ok(HashMap::new()),
+        Err(err) => return Err(format!("RECIPES: Invalid recipes ({})", err)),
+        Ok(recipes) => recipes,
+    };
+
+    recipes
+        .into_iter()
+        .map(|(name, recipe)| {
+            parse_recipe(recipe)
+                .map(|recipe| (name.clone(), recipe))
+                .map_err(|err| format!("RECIPES.{}: {}", name, err))
+        })
+        .collect()
+}
+
+fn parse_recipe(recipe: RecipeConfig) -> Result<Recipe, String> {
+    let pipeline = match &recipe.ops {
+        None => Pipeline::default(),
+        Some(ops) => ops.parse::<Pipeline>()?,
+    };
+    validate_rendition(recipe.width, recipe.height, recipe.quality)?;
+    if recipe
+        .webp_effort
+        .is_some_and(|effort| !(0..=6).contains(&effort))
+    {
+        return Err("webp_effort must be between 0 and 6".to_owned());
+    }
+    if recipe
+        .webp_alpha_quality
+        .is_some_and(|quality| !(0..=100).contains(&quality))
+    {
+        return Err("webp_alpha_quality must be between 0 and 100".to_owned());
+    }
+
+    Ok(Recipe {
+        pipeline: pipeline,
+        width: recipe.width,
+        height: recipe.height,
+        quality: recipe.quality,
+        format: recipe.format,
+        progressive: recipe.progressive,
+        color_profile: recipe.color_profile,
+        webp_effort: recipe.webp_effort,
+        webp_smart_subsample: recipe.webp_smart_subsample,
+        webp_near_lossless: recipe.webp_near_lossless,
+        webp_alpha_quality: recipe.webp_alpha_quality,
+    })
+}
@@ -0,0 +1,49 @@
+use serde::{de, Deserialize, Deserializer};
+use uuid::Uuid;
+
+// Bitcoin alphabet, without look-alikes like 0 and O
+const ALPHABET: &[u8; 58] = b"123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+// Digits needed for 128 bits, short IDs are padded to it so each UUID has exactly one
+const SHORT_ID_LENGTH: usize = 22;
+
+/// Returns the short, URL-friendly ID of the image with `uuid`: the UUID in base58, e.g.
+/// `2P2PeP88yP6VPVKSEF8jJN` instead of `0b2c5bc5-3c3e-4d6e-9f6b-1b0a3e0d5c2f`
+pub fn to_short_id(uuid: Uuid) -> String {
+    let mut value = uuid.as_u128();
+    let mut digits = [ALPHABET[0]; SHORT_ID_LENGTH];
+    for digit in digits.iter_mut().rev() {
+        *digit = ALPHABET[(value % 58) as usize];
+        value /= 58;
+    }
+    String::from_utf8(digits.to_vec()).unwrap()
+}
+
+/// Parses a short ID as returned by `to_short_id`
+pub fn parse_short_id(short_id: &str) -> Option<Uuid> {
+    if short_id.len() != SHORT_ID_LENGTH {
+        return None;
+    }
+    let mut value: u128 = 0;
+    for character in short_id.bytes() {
+        let digit = ALPHABET.iter().position(|c| *c == character)?;
+        value = value.checked_mul(58)?.checked_add(digit as u128)?;
+    }
+    Some(Uuid::from_u128(value))
+}
+
+/// Parses the ID of an image, either a UUID or a short ID
+pub fn parse_image_id(id: &str) -> Option<Uuid> {
+    Uuid::parse_str(id).ok().or_else(|| parse_short_id(id))
+}
+
+/// ID of an image in a path, either a UUID or a short ID
+pub struct ImageIdParam(pub Uuid);
+
+impl<'de> Deserialize<'de> for ImageIdParam {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let id = String::deserialize(deserializer)?;
+        parse_image_id(&id)
+            .map(ImageIdParam)
+            .ok_or_else(|| de::Error::custom(format!("Invalid image ID '{}'", id)))
+    }
+}
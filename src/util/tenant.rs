@@ -0,0 +1,153 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use argon2::{password_hash::PasswordHashString, ARGON2ID_IDENT};
+use config::{Config, ConfigError};
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::{constants::MAX_TENANT_NAME_LENGTH, util::image::get_raw_file};
+
+/// An organization (e.g. a canteen) hosted by this deployment, which manages its images with its
+/// own API keys under `/t/<name>`
+#[derive(Clone)]
+pub struct Tenant {
+    pub api_key_hashes: Vec<PasswordHashString>,
+    // Bytes of the stored images and raw files of the tenant, unlimited if not set
+    pub quota_bytes: Option<u64>,
+}
+
+/// Bytes reserved for uploads of tenants that are in progress, so concurrent uploads can't
+/// exceed a quota together, see `reserve`
+#[derive(Clone, Default)]
+pub struct QuotaReservations {
+    inner: Arc<Mutex<HashMap<String, u64>>>,
+}
+
+/// Holds bytes reserved for an upload of a tenant until it is dropped, i.e. until the upload is
+/// recorded as image of the tenant (or failed)
+pub struct QuotaReservation {
+    reservations: QuotaReservations,
+    tenant: String,
+    bytes: u64,
+}
+
+impl QuotaReservations {
+    /// Reserves `bytes` for an upload of `tenant`, if they fit into its `quota` along with its
+    /// `usage` and the bytes reserved for its other uploads. `usage` is determined while the
+    /// reservations are locked, so no other upload can be recorded or reserved meanwhile.
+    pub fn reserve(
+        &self,
+        tenant: &str,
+        bytes: u64,
+        quota: u64,
+        usage: impl FnOnce() -> u64,
+    ) -> Option<QuotaReservation> {
+        let mut inner = self.inner.lock().unwrap();
+        let reserved = inner.get(tenant).copied().unwrap_or(0);
+        if usage() + reserved + bytes > quota {
+            return None;
+        }
+        inner.insert(tenant.to_owned(), reserved + bytes);
+        Some(QuotaReservation {
+            reservations: self.clone(),
+            tenant: tenant.to_owned(),
+            bytes: bytes,
+        })
+    }
+}
+
+impl Drop for QuotaReservation {
+    fn drop(&mut self) {
+        let mut inner = self.reservations.inner.lock().unwrap();
+        if let Some(reserved) = inner.get_mut(&self.tenant) {
+            *reserved = reserved.saturating_sub(self.bytes);
+            if *reserved == 0 {
+                inner.remove(&self.tenant);
+            }
+        }
+    }
+}
+
+/// Returns the bytes the image `uuid` of `tenant` stored at `path` and its raw file take up,
+/// which count towards the quota of its tenant
+pub fn stored_bytes(uuid: Uuid, tenant: Option<&str>, path: &Path) -> u64 {
+    [path.to_path_buf(), get_raw_file(uuid, tenant)]
+        .iter()
+        .filter_map(|path| fs::metadata(path).ok())
+        .map(|metadata| metadata.len())
+        .sum()
+}
+
+/// A tenant as written in the config
+#[derive(Deserialize)]
+struct TenantConfig {
+    api_key_hashes: Vec<String>,
+    quota_bytes: Option<u64>,
+}
+
+/// Parses the tenants from the config property `TENANTS`, which maps names to tenants, e.g.
+/// `mensa-nord: { api_key_hashes: ["$argon2id$..."], quota_bytes: 1073741824 }`.
+/// Returns a message describing the problem, if any tenant is invalid.
+pub fn parse_tenants(config: &Config) -> Result<HashMap<String, Tenant>, String> {
+    let tenants = match config.get::<HashMap<String, TenantConfig>>("TENANTS") {
+        Err(ConfigError::NotFound(_)) => return Ok(HashMap::new()),
+        Err(err) => return Err(format!("TENANTS: Invalid tenants ({})", err)),
+        Ok(tenants) => tenants,
+    };
+
+    tenants
+        .into_iter()
+        .map(|(name, tenant)| {
+            parse_tenant(&name, tenant)
+                .map(|tenant| (name.clone(), tenant))
+                .map_err(|err| format!("TENANTS.{}: {}", name, err))
+        })
+        .collect()
+}
+
+fn parse_tenant(name: &str, tenant: TenantConfig) -> Result<Tenant, String> {
+    if !is_valid_tenant_name(name) {
+        return Err(format!(
+            "Names must consist of up to {} lowercase letters, digits and dashes",
+            MAX_TENANT_NAME_LENGTH
+        ));
+    }
+    if tenant.api_key_hashes.is_empty() {
+        return Err("api_key_hashes must contain at least one hash".to_owned());
+    }
+    let api_key_hashes = tenant
+        .api_key_hashes
+        .iter()
+        .map(|value| match PasswordHashString::new(value) {
+            Err(err) => Err(format!(
+                "'{}' is not a hash in encoded form ({})",
+                value, err
+            )),
+            Ok(hash) if hash.algorithm() != ARGON2ID_IDENT => Err(format!(
+                "Expected an {} hash, but got {}",
+                ARGON2ID_IDENT,
+                hash.algorithm()
+            )),
+            Ok(hash) => Ok(hash),
+        })
+        .collect::<Result<_, _>>()?;
+
+    Ok(Tenant {
+        api_key_hashes: api_key_hashes,
+        quota_bytes: tenant.quota_bytes,
+    })
+}
+
+/// Returns whether `name` can be used as name of a tenant (and in paths), e.g. `mensa-nord`
+pub fn is_valid_tenant_name(name: &str) -> bool {
+    (1..=MAX_TENANT_NAME_LENGTH).contains(&name.len())
+        && !name.starts_with('-')
+        && name
+            .bytes()
+            .all(|byte| byte.is_ascii_lowercase() || byte.is_ascii_digit() || byte == b'-')
+}
@@ -0,0 +1,104 @@
+use std::{path::Path, sync::Arc, thread, time::Duration};
+
+use config::Config;
+use tokio::sync::Semaphore;
+use uuid::Uuid;
+
+use crate::{constants::DEFAULT_VIPS_TIMEOUT_SECS, error::Error, quarantine::record_timeout};
+
+/// Runs libvips operations on the blocking thread pool, at most `concurrency` at the same time
+/// and each for at most `timeout`, see `with_timeout`
+#[derive(Clone)]
+pub struct Watchdog {
+    pub timeout: Duration,
+    pub concurrency: usize,
+    // Held by running operations, including abandoned ones, as they still occupy their thread
+    permits: Arc<Semaphore>,
+}
+
+/// Parses how long a vips operation may run from the config property `VIPS_TIMEOUT_SECS` and how
+/// many may run at once from `VIPS_CONCURRENCY`, which defaults to the number of CPUs
+pub fn parse_watchdog(config: &Config) -> Watchdog {
+    let timeout = Duration::from_secs(
+        config
+            .get::<u64>("VIPS_TIMEOUT_SECS")
+            .unwrap_or(DEFAULT_VIPS_TIMEOUT_SECS),
+    );
+    let concurrency = config.get::<usize>("VIPS_CONCURRENCY").unwrap_or_else(|_| {
+        thread::available_parallelism()
+            .map(|parallelism| parallelism.get())
+            .unwrap_or(1)
+    });
+
+    Watchdog {
+        timeout: timeout,
+        concurrency: concurrency,
+        permits: Arc::new(Semaphore::new(concurrency)),
+    }
+}
+
+impl Watchdog {
+    /// Runs `operation`, which processes the image `uuid` read from `source` with libvips, on the
+    /// blocking thread pool and waits at most `timeout` for it, so a pathological input can't tie
+    /// up a worker for minutes. libvips operations can't be cancelled, so after the timeout the
+    /// operation is abandoned: it finishes in the background and its result is dropped, so it must
+    /// not commit anything itself (see `TempFile`). The offending file is recorded in the
+    /// quarantine directory and 504 is returned. If `concurrency` operations are already running,
+    /// it waits for one of them to finish, but also at most `timeout`.
+    /// `action` describes the operation for logging, e.g. `rendering`.
+    pub async fn with_timeout<T: Send + 'static>(
+        &self,
+        uuid: Uuid,
+        source: &Path,
+        action: &str,
+        operation: impl FnOnce() -> Result<T, Error> + Send + 'static,
+    ) -> Result<T, Error> {
+        let permit =
+            match tokio::time::timeout(self.timeout, self.permits.clone().acquire_owned()).await {
+                Ok(Ok(permit)) => permit,
+                Ok(Err(err)) => return Err(Error::Internal(err.to_string())),
+                Err(_) => {
+                    log::warn!(
+                        "WATCHDOG: {} {} did not start within {:?}, as {} operations are running",
+                        action,
+                        uuid,
+                        self.timeout,
+                        self.concurrency
+                    );
+                    return Err(Error::Timeout(
+                        "Too many images are being processed, try again later".to_owned(),
+                    ));
+                }
+            };
+        let handle = tokio::task::spawn_blocking(move || {
+            let _permit = permit;
+            operation()
+        });
+
+        match tokio::time::timeout(self.timeout, handle).await {
+            Ok(Ok(res)) => res,
+            // The operation panicked, which has already been reported
+            Ok(Err(err)) => Err(Error::Internal(format!(
+                "{} {} panicked: {}",
+                action, uuid, err
+            ))),
+            Err(_) => {
+                log::error!(
+                    "WATCHDOG: {} {} did not finish within {:?}, abandoning it",
+                    action,
+                    uuid,
+                    self.timeout
+                );
+                record_timeout(
+                    uuid,
+                    source,
+                    &format!("{} did not finish within {:?}", action, self.timeout),
+                );
+                Err(Error::Timeout(format!(
+                    "Processing the image took longer than {}s",
+                    self.timeout.as_secs()
+                )))
+            }
+        }
+    }
+}
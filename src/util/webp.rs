@@ -0,0 +1,80 @@
+use config::Config;
+
+/// Tuning of the WebP encoder beyond the quality, defaults to the ones of libvips
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WebpTuning {
+    // CPU effort from 0 (fastest) to 6 (smallest files)
+    pub effort: i32,
+    // High quality chroma subsampling, which keeps colored edges sharper
+    pub smart_subsample: bool,
+    // Lossless encoding after lossy preprocessing with the quality, instead of lossy encoding
+    pub near_lossless: bool,
+    // Quality of the alpha channel from 0 to 100
+    pub alpha_quality: i32,
+}
+
+impl Default for WebpTuning {
+    fn default() -> Self {
+        WebpTuning {
+            effort: 4,
+            smart_subsample: false,
+            near_lossless: false,
+            alpha_quality: 100,
+        }
+    }
+}
+
+/// Parses the tuning from the config properties `WEBP_EFFORT`, `WEBP_SMART_SUBSAMPLE`,
+/// `WEBP_NEAR_LOSSLESS` and `WEBP_ALPHA_QUALITY`. Unset properties default to the ones of libvips.
+/// Returns a message describing the problem, if any of them is invalid.
+pub fn parse_webp_tuning(config: &Config) -> Result<WebpTuning, String> {
+    let defaults = WebpTuning::default();
+    let tuning = WebpTuning {
+        effort: config.get::<i32>("WEBP_EFFORT").unwrap_or(defaults.effort),
+        smart_subsample: config
+            .get_bool("WEBP_SMART_SUBSAMPLE")
+            .unwrap_or(defaults.smart_subsample),
+        near_lossless: config
+            .get_bool("WEBP_NEAR_LOSSLESS")
+            .unwrap_or(defaults.near_lossless),
+        alpha_quality: config
+            .get::<i32>("WEBP_ALPHA_QUALITY")
+            .unwrap_or(defaults.alpha_quality),
+    };
+    if !(0..=6).contains(&tuning.effort) {
+        return Err("WEBP_EFFORT: Must be between 0 and 6".to_owned());
+    }
+    if !(0..=100).contains(&tuning.alpha_quality) {
+        return Err("WEBP_ALPHA_QUALITY: Must be between 0 and 100".to_owned());
+    }
+    Ok(tuning)
+}
+
+impl WebpTuning {
+    /// Checks the ranges of the effort and the alpha quality, as requested via `webp_effort` and
+    /// `webp_alpha_quality`
+    pub fn validate(&self) -> Result<(), String> {
+        if !(0..=6).contains(&self.effort) {
+            return Err("webp_effort must be between 0 and 6".to_owned());
+        }
+        if !(0..=100).contains(&self.alpha_quality) {
+            return Err("webp_alpha_quality must be between 0 and 100".to_owned());
+        }
+        Ok(())
+    }
+
+    /// Flag of cache entries encoded with this tuning, e.g. `w6sa100` (effort 6 with smart
+    /// subsampling). Empty for the default tuning, so existing cache entries stay valid.
+    pub fn cache_flag(&self) -> String {
+        if *self == WebpTuning::default() {
+            return String::new();
+        }
+        format!(
+            "w{}{}{}a{}",
+            self.effort,
+            if self.smart_subsample { "s" } else { "" },
+            if self.near_lossless { "n" } else { "" },
+            self.alpha_quality
+        )
+    }
+}
@@ -0,0 +1,220 @@
+use std::io::{self, Write};
+
+use chrono::{Datelike, Timelike, Utc};
+
+// Minimal writer of zip archives with uncompressed entries, which is all thumbnail archives need
+// (encoded images don't compress further). Entries are written as they are added, so archives can
+// be streamed. Without ZIP64, entries and the archive are limited to 4 GiB and 65535 entries.
+
+const LOCAL_HEADER_SIGNATURE: u32 = 0x04034b50;
+const CENTRAL_HEADER_SIGNATURE: u32 = 0x02014b50;
+const END_OF_CENTRAL_DIRECTORY_SIGNATURE: u32 = 0x06054b50;
+// Version 2.0, the first one supporting directories and deflate
+const VERSION: u16 = 20;
+// File names are UTF-8
+const FLAGS: u16 = 1 << 11;
+
+struct CentralEntry {
+    name: String,
+    crc: u32,
+    size: u32,
+    offset: u32,
+}
+
+/// Writes a zip archive to `W`
+pub struct ZipWriter<W: Write> {
+    inner: W,
+    // Bytes written to `inner` so far
+    written: u64,
+    entries: Vec<CentralEntry>,
+    // Modification time of all entries in MS-DOS format
+    time: u16,
+    date: u16,
+}
+
+impl<W: Write> ZipWriter<W> {
+    pub fn new(inner: W) -> Self {
+        let now = Utc::now();
+        Self {
+            inner: inner,
+            written: 0,
+            entries: Vec::new(),
+            time: ((now.hour() << 11) | (now.minute() << 5) | (now.second() / 2)) as u16,
+            date: (((now.year() - 1980).max(0) as u32) << 9 | (now.month() << 5) | now.day())
+                as u16,
+        }
+    }
+
+    /// Returns the writer the archive is written to, e.g. to take what was written so far
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    /// Writes a file named `name` with `contents` to the archive
+    pub fn add_file(&mut self, name: &str, contents: &[u8]) -> Result<(), io::Error> {
+        let too_large = || too_large(&format!("Zip archive too large for '{}'", name));
+        let size = u32::try_from(contents.len()).map_err(|_| too_large())?;
+        let offset = u32::try_from(self.written).map_err(|_| too_large())?;
+        let name_len = u16::try_from(name.len()).map_err(|_| too_large())?;
+        if self.entries.len() >= u16::MAX as usize {
+            return Err(too_large());
+        }
+        let crc = crc32fast::hash(contents);
+
+        let mut header = Vec::new();
+        put_u32(&mut header, LOCAL_HEADER_SIGNATURE);
+        put_u16(&mut header, VERSION);
+        put_u16(&mut header, FLAGS);
+        // Stored, i.e. not compressed
+        put_u16(&mut header, 0);
+        put_u16(&mut header, self.time);
+        put_u16(&mut header, self.date);
+        put_u32(&mut header, crc);
+        // Compressed and uncompressed size
+        put_u32(&mut header, size);
+        put_u32(&mut header, size);
+        put_u16(&mut header, name_len);
+        // Length of extra fields
+        put_u16(&mut header, 0);
+        header.extend_from_slice(name.as_bytes());
+        self.write(&header)?;
+        self.write(contents)?;
+
+        self.entries.push(CentralEntry {
+            name: name.to_owned(),
+            crc: crc,
+            size: size,
+            offset: offset,
+        });
+        Ok(())
+    }
+
+    /// Writes the central directory and returns the writer
+    pub fn finish(mut self) -> Result<W, io::Error> {
+        let central_directory_offset =
+            u32::try_from(self.written).map_err(|_| too_large("Zip archive too large"))?;
+
+        let mut directory = Vec::new();
+        for entry in self.entries.iter() {
+            put_u32(&mut directory, CENTRAL_HEADER_SIGNATURE);
+            // Version made by and needed to extract
+            put_u16(&mut directory, VERSION);
+            put_u16(&mut directory, VERSION);
+            put_u16(&mut directory, FLAGS);
+            put_u16(&mut directory, 0);
+            put_u16(&mut directory, self.time);
+            put_u16(&mut directory, self.date);
+            put_u32(&mut directory, entry.crc);
+            put_u32(&mut directory, entry.size);
+            put_u32(&mut directory, entry.size);
+            put_u16(&mut directory, entry.name.len() as u16);
+            // Length of extra fields and comment, disk number, internal and external attributes
+            put_u16(&mut directory, 0);
+            put_u16(&mut directory, 0);
+            put_u16(&mut directory, 0);
+            put_u16(&mut directory, 0);
+            put_u32(&mut directory, 0);
+            put_u32(&mut directory, entry.offset);
+            directory.extend_from_slice(entry.name.as_bytes());
+        }
+        let central_directory_size =
+            u32::try_from(directory.len()).map_err(|_| too_large("Zip archive too large"))?;
+
+        put_u32(&mut directory, END_OF_CENTRAL_DIRECTORY_SIGNATURE);
+        // Number of this disk and of the disk with the central directory
+        put_u16(&mut directory, 0);
+        put_u16(&mut directory, 0);
+        // Entries on this disk and in total
+        put_u16(&mut directory, self.entries.len() as u16);
+        put_u16(&mut directory, self.entries.len() as u16);
+        put_u32(&mut directory, central_directory_size);
+        put_u32(&mut directory, central_directory_offset);
+        // Length of the comment
+        put_u16(&mut directory, 0);
+        self.write(&directory)?;
+
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<(), io::Error> {
+        self.inner.write_all(data)?;
+        self.written += data.len() as u64;
+        Ok(())
+    }
+}
+
+fn put_u16(buffer: &mut Vec<u8>, value: u16) {
+    buffer.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_u32(buffer: &mut Vec<u8>, value: u32) {
+    buffer.extend_from_slice(&value.to_le_bytes());
+}
+
+fn too_large(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Read};
+
+    use super::*;
+
+    fn read_entries(archive: Vec<u8>) -> Vec<(String, Vec<u8>)> {
+        let mut archive = ::zip::ZipArchive::new(Cursor::new(archive)).unwrap();
+        (0..archive.len())
+            .map(|index| {
+                let mut file = archive.by_index(index).unwrap();
+                let mut contents = Vec::new();
+                file.read_to_end(&mut contents).unwrap();
+                (file.name().to_owned(), contents)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn round_trip() {
+        let files = vec![
+            ("first.webp".to_owned(), b"RIFF\0\0\0\0WEBP".to_vec()),
+            ("empty.webp".to_owned(), Vec::new()),
+            (
+                "ümlaut.avif".to_owned(),
+                (0..=255).cycle().take(70_000).collect(),
+            ),
+        ];
+
+        let mut zip = ZipWriter::new(Vec::new());
+        for (name, contents) in files.iter() {
+            zip.add_file(name, contents).unwrap();
+        }
+
+        assert_eq!(read_entries(zip.finish().unwrap()), files);
+    }
+
+    #[test]
+    fn round_trip_empty() {
+        let zip = ZipWriter::new(Vec::new());
+        assert_eq!(read_entries(zip.finish().unwrap()), Vec::new());
+    }
+
+    #[test]
+    fn round_trip_taken_in_parts() {
+        let mut zip = ZipWriter::new(Vec::new());
+        let mut archive = Vec::new();
+        for name in ["a.webp", "b.webp"] {
+            zip.add_file(name, name.as_bytes()).unwrap();
+            archive.append(zip.get_mut());
+        }
+        archive.append(&mut zip.finish().unwrap());
+
+        assert_eq!(
+            read_entries(archive),
+            vec![
+                ("a.webp".to_owned(), b"a.webp".to_vec()),
+                ("b.webp".to_owned(), b"b.webp".to_vec())
+            ]
+        );
+    }
+}
@@ -0,0 +1,107 @@
+use std::{io, path::PathBuf, time::Duration};
+
+use config::Config;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::{TcpStream, UnixStream},
+    time::timeout,
+};
+
+use crate::constants::{CLAMD_CHUNK_SIZE, DEFAULT_CLAMD_TIMEOUT_SECS};
+
+/// Where clamd listens
+enum ClamdAddr {
+    Unix(PathBuf),
+    // `host:port`
+    Tcp(String),
+}
+
+pub enum ScanResult {
+    Clean,
+    // Name of the signature that matched
+    Infected(String),
+}
+
+/// Scans uploads for viruses with clamd before they are processed
+pub struct VirusScanner {
+    addr: ClamdAddr,
+    timeout: Duration,
+}
+
+/// Parses the clamd socket from the config property `CLAMD_SOCKET`, either the path of a unix
+/// socket or `host:port`, and the timeout from `CLAMD_TIMEOUT_SECS`.
+/// Returns `None` if uploads are not scanned.
+pub fn parse_virus_scanner(config: &Config) -> Option<VirusScanner> {
+    let socket = config.get_string("CLAMD_SOCKET").ok()?;
+    let addr = match socket.starts_with('/') {
+        true => ClamdAddr::Unix(PathBuf::from(socket)),
+        false => ClamdAddr::Tcp(socket),
+    };
+    Some(VirusScanner {
+        addr: addr,
+        timeout: Duration::from_secs(
+            config
+                .get::<u64>("CLAMD_TIMEOUT_SECS")
+                .unwrap_or(DEFAULT_CLAMD_TIMEOUT_SECS),
+        ),
+    })
+}
+
+impl VirusScanner {
+    /// Describes the socket, e.g. for logging
+    pub fn describe(&self) -> String {
+        match &self.addr {
+            ClamdAddr::Unix(path) => path.display().to_string(),
+            ClamdAddr::Tcp(addr) => addr.clone(),
+        }
+    }
+
+    /// Sends `data` to clamd and returns whether it is infected. The whole scan, including
+    /// connecting, is aborted after the timeout.
+    pub async fn scan(&self, data: &[u8]) -> Result<ScanResult, String> {
+        let scan = async {
+            match &self.addr {
+                ClamdAddr::Unix(path) => instream(UnixStream::connect(path).await?, data).await,
+                ClamdAddr::Tcp(addr) => instream(TcpStream::connect(addr).await?, data).await,
+            }
+        };
+        let reply = match timeout(self.timeout, scan).await {
+            Err(_) => Err(io::Error::new(io::ErrorKind::TimedOut, "Timed out")),
+            Ok(res) => res,
+        }
+        .map_err(|err| format!("Could not scan with clamd at {}: {}", self.describe(), err))?;
+
+        // E.g. `stream: OK` or `stream: Eicar-Signature FOUND`
+        let result = reply.strip_prefix("stream: ").unwrap_or(&reply);
+        if result == "OK" {
+            Ok(ScanResult::Clean)
+        } else if let Some(signature) = result.strip_suffix(" FOUND") {
+            Ok(ScanResult::Infected(signature.to_owned()))
+        } else {
+            Err(format!("clamd replied '{}'", reply))
+        }
+    }
+}
+
+/// Streams `data` to clamd with the `INSTREAM` command and returns its reply
+async fn instream<S: AsyncRead + AsyncWrite + Unpin>(
+    mut stream: S,
+    data: &[u8],
+) -> Result<String, io::Error> {
+    stream.write_all(b"zINSTREAM\0").await?;
+    // Each chunk is prefixed by its length, an empty chunk ends the stream
+    for chunk in data.chunks(CLAMD_CHUNK_SIZE) {
+        stream
+            .write_all(&(chunk.len() as u32).to_be_bytes())
+            .await?;
+        stream.write_all(chunk).await?;
+    }
+    stream.write_all(&0u32.to_be_bytes()).await?;
+    stream.flush().await?;
+
+    let mut reply = Vec::new();
+    stream.read_to_end(&mut reply).await?;
+    Ok(String::from_utf8_lossy(&reply)
+        .trim_end_matches(['\0', '\n'])
+        .to_owned())
+}
@@ -0,0 +1,137 @@
+use std::time::Duration;
+
+use axum::http::StatusCode;
+use config::Config;
+use hmac::{Hmac, Mac};
+use reqwest::{Client, Url};
+use serde::Serialize;
+use sha2::Sha256;
+use uuid::Uuid;
+
+use crate::constants::WEBHOOK_TIMEOUT_SECS;
+
+/// Configuration of the webhooks and callbacks the service calls
+pub struct WebhookConfig {
+    // Called after each successful upload
+    pub upload_url: Option<Url>,
+    // If set, request bodies are signed with HMAC-SHA256 using this secret
+    pub secret: Option<String>,
+    // Prefixes callback URLs passed by clients have to start with
+    pub allowed_callback_urls: Vec<String>,
+}
+
+/// Parses the webhook configuration from the config properties `UPLOAD_WEBHOOK_URL`,
+/// `WEBHOOK_SECRET` and `CALLBACK_ALLOWED_URLS`
+pub fn parse_webhook_config(config: &Config) -> WebhookConfig {
+    WebhookConfig {
+        upload_url: config
+            .get_string("UPLOAD_WEBHOOK_URL")
+            .ok()
+            .and_then(|value| Url::parse(&value).ok()),
+        secret: config.get_string("WEBHOOK_SECRET").ok(),
+        allowed_callback_urls: config
+            .get::<Vec<String>>("CALLBACK_ALLOWED_URLS")
+            .unwrap_or_default(),
+    }
+}
+
+impl WebhookConfig {
+    /// Parses a callback URL passed by a client.
+    /// Returns 400 (BAD_REQUEST), if it is invalid or not allowed, as otherwise clients could
+    /// make the service send requests to arbitrary (e.g. internal) hosts.
+    pub fn parse_callback_url(&self, value: &str) -> Result<Url, (StatusCode, String)> {
+        if self.secret.is_none() {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                "Callbacks are not enabled!".to_owned(),
+            ));
+        }
+
+        let url = Url::parse(value)
+            .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid callback URL!".to_owned()))?;
+        if !self
+            .allowed_callback_urls
+            .iter()
+            .any(|prefix| url.as_str().starts_with(prefix))
+        {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                "Callback URL is not allowed!".to_owned(),
+            ));
+        }
+        Ok(url)
+    }
+}
+
+/// Builds the HTTP client used for all outgoing requests. Redirects are not followed, as they
+/// could lead callbacks to URLs that are not allowed by `CALLBACK_ALLOWED_URLS`.
+pub fn build_http_client() -> Client {
+    Client::builder()
+        .timeout(Duration::from_secs(WEBHOOK_TIMEOUT_SECS))
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .expect("Could not build HTTP client")
+}
+
+/// Sent to the upload webhook right after an image was uploaded, before it is submitted
+#[derive(Serialize)]
+pub struct UploadEvent {
+    // Always "upload"
+    pub event: &'static str,
+    pub id: Uuid,
+    // Type detected from the file header, e.g. "jpeg"
+    pub file_type: String,
+    pub width: i32,
+    pub height: i32,
+    // Hex encoded SHA-256 hash of the file as uploaded
+    pub sha256: String,
+}
+
+/// Sent to the callback URL passed to `/submit/:id`, once the submitted image was validated
+#[derive(Serialize)]
+pub struct SubmitCallback {
+    // Always "submitted"
+    pub event: &'static str,
+    pub id: Uuid,
+    // Whether the submitted image could be read, i.e. can be approved and served
+    pub valid: bool,
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+}
+
+/// POSTs `payload` as JSON to `url` in the background.
+/// If `secret` is set, the body is signed with it and the signature is sent as
+/// `X-Signature: sha256=<hex encoded HMAC-SHA256>` header.
+/// Failures are only logged, as the request that triggered the webhook has already succeeded.
+pub fn send_webhook<T: Serialize>(client: &Client, url: Url, secret: Option<&str>, payload: T) {
+    let body = match serde_json::to_vec(&payload) {
+        Err(err) => {
+            log::error!("Could not serialize webhook payload: {}", err);
+            return;
+        }
+        Ok(body) => body,
+    };
+
+    let mut request = client
+        .post(url.clone())
+        .header("Content-Type", "application/json");
+    if let Some(secret) = secret {
+        request = request.header("X-Signature", format!("sha256={}", sign(secret, &body)));
+    }
+    let request = request.body(body);
+
+    tokio::spawn(async move {
+        match request.send().await.and_then(|res| res.error_for_status()) {
+            Err(err) => log::error!("Webhook {} failed: {}", url, err),
+            Ok(res) => log::debug!("Webhook {} responded with {}", url, res.status()),
+        }
+    });
+}
+
+/// Returns the hex encoded HMAC-SHA256 of `body` with `secret` as key
+fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(body);
+    format!("{:x}", mac.finalize().into_bytes())
+}